@@ -0,0 +1,77 @@
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Instant;
+
+// Compares inserting the prefixes with `insert_bulk` and `par_insert_bulk`
+// to inserting them one at a time.
+
+fn get_first_arg() -> Result<OsString, Box<dyn Error>> {
+    match env::args_os().nth(1) {
+        None => Err(From::from("expected 1 argument, but got none")),
+        Some(file_path) => Ok(file_path),
+    }
+}
+
+type Records = Vec<(Prefix, Record<Asn>)>;
+
+fn load_records() -> Result<Records, Box<dyn Error>> {
+    let file = File::open(get_first_arg()?)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(file);
+    let mut records = vec![];
+    for result in rdr.records() {
+        let record = result?;
+        let net: Ipv4Addr = record[0].parse()?;
+        let len: u8 = record[1].parse()?;
+        let asn: u32 = record[2].parse()?;
+        records.push((
+            Prefix::new(IpAddr::V4(net), len)?,
+            Record::new(1, 0, RouteStatus::Active, Asn::from(asn)),
+        ));
+    }
+    Ok(records)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let records = load_records()?;
+    println!("prefixes in file: {}", records.len());
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let start = Instant::now();
+    for (pfx, record) in records.iter() {
+        store.insert(pfx, record.clone(), None)?;
+    }
+    let dur_insert = start.elapsed();
+    println!("prefixes in store: {}", store.prefixes_count());
+
+    let bulk = MultiThreadedStore::<Asn>::new()?;
+    let start = Instant::now();
+    let report = bulk.insert_bulk(records.clone())?;
+    let dur_bulk = start.elapsed();
+    assert_eq!(bulk.prefixes_count(), store.prefixes_count());
+    println!("insert_bulk: {:?}", report);
+
+    let par_bulk = MultiThreadedStore::<Asn>::new()?;
+    let start = Instant::now();
+    let report = par_bulk.par_insert_bulk(records, threads)?;
+    let dur_par_bulk = start.elapsed();
+    assert_eq!(par_bulk.prefixes_count(), store.prefixes_count());
+    println!("par_insert_bulk: {:?}", report);
+
+    println!("insert one at a time: {:?}", dur_insert);
+    println!("insert_bulk: {:?}", dur_bulk);
+    println!("par_insert_bulk ({} threads): {:?}", threads, dur_par_bulk);
+
+    Ok(())
+}
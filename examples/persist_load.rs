@@ -0,0 +1,70 @@
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Instant;
+
+// Compares loading a store that was written with `save_to` to building it
+// again by inserting the prefixes one at a time.
+
+fn get_first_arg() -> Result<OsString, Box<dyn Error>> {
+    match env::args_os().nth(1) {
+        None => Err(From::from("expected 1 argument, but got none")),
+        Some(file_path) => Ok(file_path),
+    }
+}
+
+fn load_prefixes() -> Result<Vec<(Prefix, Asn)>, Box<dyn Error>> {
+    let file = File::open(get_first_arg()?)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(file);
+    let mut pfxs = vec![];
+    for result in rdr.records() {
+        let record = result?;
+        let net: Ipv4Addr = record[0].parse()?;
+        let len: u8 = record[1].parse()?;
+        let asn: u32 = record[2].parse()?;
+        pfxs.push((Prefix::new(IpAddr::V4(net), len)?, Asn::from(asn)));
+    }
+    Ok(pfxs)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let pfxs = load_prefixes()?;
+    println!("prefixes in file: {}", pfxs.len());
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let start = Instant::now();
+    for (pfx, asn) in pfxs.iter() {
+        store.insert(
+            pfx,
+            Record::new(1, 0, RouteStatus::Active, *asn),
+            Some(()),
+        )?;
+    }
+    let dur_insert = start.elapsed();
+    println!("prefixes in store: {}", store.prefixes_count());
+
+    let mut buf = vec![];
+    let start = Instant::now();
+    store.save_to(&mut buf)?;
+    let dur_save = start.elapsed();
+
+    let start = Instant::now();
+    let loaded = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice())?;
+    let dur_load = start.elapsed();
+    assert_eq!(loaded.prefixes_count(), store.prefixes_count());
+
+    println!("snapshot size: {} bytes", buf.len());
+    println!("insert one at a time: {:?}", dur_insert);
+    println!("save_to: {:?}", dur_save);
+    println!("load_from: {:?}", dur_load);
+
+    Ok(())
+}
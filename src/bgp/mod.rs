@@ -0,0 +1,39 @@
+//! Exchanging routes with BGP
+//!
+//! This module has the glue between the meta-data of the records in a store
+//! and the BGP types of `routecore`.
+
+use std::net::IpAddr;
+
+use routecore::bgp::path_attributes::PaMap;
+
+use crate::prefix_record::Meta;
+
+mod multipath;
+mod updates;
+
+pub use multipath::is_multipath_equivalent;
+pub use updates::{
+    adj_rib_updates, loc_rib_updates, UpdateError, UpdateOptions,
+};
+
+//------------ BgpMeta -------------------------------------------------------
+
+/// Meta-data that carries the BGP path attributes of a route
+///
+/// Meta-data types that implement this trait can be turned into BGP
+/// routes, e.g. when exporting the store to an MRT file, or when generating
+/// BGP UPDATE messages with [loc_rib_updates] or [adj_rib_updates].
+pub trait BgpMeta: Meta {
+    /// The path attributes of the route. The MP_REACH_NLRI and
+    /// MP_UNREACH_NLRI attributes are not part of these, since they depend
+    /// on the prefixes the attributes are sent with.
+    fn path_attributes(&self) -> PaMap;
+
+    /// The next hop of the route, for routes that can't carry their next
+    /// hop in the conventional NEXT_HOP attribute of the path attributes,
+    /// i.e. routes for IPv6 prefixes.
+    fn next_hop(&self) -> Option<IpAddr> {
+        None
+    }
+}
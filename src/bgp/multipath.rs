@@ -0,0 +1,68 @@
+use routecore::bgp::aspath::HopPath;
+use routecore::bgp::path_attributes::PaMap;
+use routecore::bgp::types::{LocalPref, MultiExitDisc, Origin};
+
+use crate::prefix_record::MultipathPolicy;
+
+use super::BgpMeta;
+
+//------------ is_multipath_equivalent ---------------------------------------
+
+/// Returns whether the route with the meta-data `candidate` can be used
+/// together with the best route, with the meta-data `best`, in a multipath
+/// set under `policy`.
+///
+/// For `MultipathPolicy::Equal` this compares the orderables of the routes.
+/// The relaxed policies compare the path attributes of the routes in the
+/// steps of the BGP decision process (RFC 4271, section 9.1.2.2) up to the
+/// IGP cost: the LOCAL_PREF, the AS path, the ORIGIN, and, for routes from
+/// the same neighbouring AS, the MULTI_EXIT_DISC. `AsPathRelax` only
+/// compares the length of the AS paths. The route source, EBGP or IBGP, is
+/// not part of the path attributes, so callers that mix these should check
+/// it themselves.
+///
+/// This can be used to implement [Meta::is_multipath_equivalent] for
+/// meta-data that carries the BGP path attributes:
+///
+/// ```ignore
+/// fn is_multipath_equivalent(
+///     &self,
+///     best: &Self,
+///     tbi: Self::TBI,
+///     policy: MultipathPolicy,
+/// ) -> bool {
+///     rotonda_store::bgp::is_multipath_equivalent(self, best, tbi, policy)
+/// }
+/// ```
+///
+/// [Meta::is_multipath_equivalent]: crate::Meta::is_multipath_equivalent
+pub fn is_multipath_equivalent<M: BgpMeta>(
+    candidate: &M,
+    best: &M,
+    tbi: M::TBI,
+    policy: MultipathPolicy,
+) -> bool {
+    let same_as_path = match policy {
+        MultipathPolicy::Equal => {
+            return candidate.as_orderable(tbi) == best.as_orderable(tbi);
+        }
+        MultipathPolicy::UpToIgpCost => |a: &HopPath, b: &HopPath| a == b,
+        MultipathPolicy::AsPathRelax => |a: &HopPath, b: &HopPath| {
+            a.hop_count_path_selection() == b.hop_count_path_selection()
+        },
+    };
+
+    let (c, b) = (candidate.path_attributes(), best.path_attributes());
+    let as_path = |pa: &PaMap| pa.get::<HopPath>().unwrap_or_default();
+    let (c_path, b_path) = (as_path(&c), as_path(&b));
+    // A missing MULTI_EXIT_DISC counts as the lowest possible value.
+    let med =
+        |pa: &PaMap| pa.get::<MultiExitDisc>().unwrap_or(MultiExitDisc(0));
+
+    c.get::<LocalPref>() == b.get::<LocalPref>()
+        && same_as_path(&c_path, &b_path)
+        && c.get::<Origin>() == b.get::<Origin>()
+        && (c_path.neighbor_path_selection()
+            != b_path.neighbor_path_selection()
+            || med(&c) == med(&b))
+}
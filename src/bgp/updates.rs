@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+
+use inetnum::addr::Prefix;
+use log::trace;
+use routecore::bgp::message::{SessionConfig, UpdateMessage};
+
+use crate::prelude::multi::{epoch, PrefixStoreError, Record, RouteStatus};
+use crate::MultiThreadedStore;
+
+use super::BgpMeta;
+
+// The type codes of the path attributes that are composed here.
+const NEXT_HOP: u8 = 3;
+const MP_REACH_NLRI: u8 = 14;
+const MP_UNREACH_NLRI: u8 = 15;
+
+// The length of the marker, length and type of a BGP message, and the
+// lengths of the withdrawn routes and path attributes of an UPDATE.
+const UPDATE_HEADER_LEN: usize = 19 + 2 + 2;
+
+// The length of the value of an MP_REACH_NLRI attribute for IPv6 unicast,
+// without the NLRI: AFI, SAFI, next hop length, next hop and reserved.
+const MP_REACH_IPV6_LEN: usize = 2 + 1 + 1 + 16 + 1;
+
+//------------ UpdateOptions -------------------------------------------------
+
+/// Options for generating BGP UPDATE messages from a store
+#[derive(Clone, Copy, Debug)]
+pub struct UpdateOptions {
+    /// The maximum size of a message in octets: 4096 for a regular BGP
+    /// session, 65535 for a session with the Extended Message capability.
+    /// Sizes over 65535 are treated as 65535.
+    pub max_pdu_size: usize,
+}
+
+impl Default for UpdateOptions {
+    fn default() -> Self {
+        Self { max_pdu_size: 4096 }
+    }
+}
+
+//------------ UpdateError ---------------------------------------------------
+
+/// An error that occurred while generating BGP UPDATE messages
+#[derive(Debug)]
+pub enum UpdateError {
+    /// The path attributes of the route for the prefix don't fit in a
+    /// message of the maximum size.
+    PduTooLarge(Prefix),
+    /// The route for the prefix doesn't have a next hop: an IPv6 next hop
+    /// for an IPv6 prefix, or a NEXT_HOP attribute or an IPv4 next hop for
+    /// an IPv4 prefix.
+    MissingNextHop(Prefix),
+    /// The path attributes of the route for the prefix are invalid.
+    InvalidAttributes(Prefix),
+    Store(PrefixStoreError),
+}
+
+impl std::error::Error for UpdateError {}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::PduTooLarge(prefix) => write!(
+                f,
+                "Error: The path attributes for {} don't fit in a BGP \
+                 message.",
+                prefix
+            ),
+            UpdateError::MissingNextHop(prefix) => {
+                write!(f, "Error: No next hop for {}.", prefix)
+            }
+            UpdateError::InvalidAttributes(prefix) => {
+                write!(f, "Error: Invalid path attributes for {}.", prefix)
+            }
+            UpdateError::Store(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<PrefixStoreError> for UpdateError {
+    fn from(value: PrefixStoreError) -> Self {
+        UpdateError::Store(value)
+    }
+}
+
+//------------ Generating UPDATEs --------------------------------------------
+
+/// Generate the BGP UPDATE messages that announce the Loc-RIB of `store`:
+/// the best path for every prefix.
+///
+/// The best path of a prefix is calculated again with `tbi` if the path
+/// selection for the prefix is outdated, or hasn't been done yet. The best
+/// and backup path that are calculated are stored with the prefix, like
+/// `calculate_and_store_best_and_backup_path` does, so generating the
+/// messages updates the path selections in `store`. Prefixes without an
+/// active best path, or whose best path is from a multi_uniq_id that is
+/// withdrawn, are left out.
+///
+/// See [adj_rib_updates] for how the messages are built.
+pub fn loc_rib_updates<M: BgpMeta>(
+    store: &MultiThreadedStore<M>,
+    tbi: &M::TBI,
+    options: &UpdateOptions,
+) -> Result<Vec<UpdateMessage<Vec<u8>>>, UpdateError> {
+    let guard = &epoch::pin();
+    let mut packer = Packer::default();
+
+    for pfx in store.prefixes_iter(guard) {
+        let needs_selection = match store.best_path(&pfx.prefix, guard) {
+            Some(Err(PrefixStoreError::BestPathNotFound)) => true,
+            _ => store.is_ps_outdated(&pfx.prefix, guard)?,
+        };
+        if needs_selection {
+            store.calculate_and_store_best_and_backup_path(
+                &pfx.prefix,
+                tbi,
+                guard,
+            )?;
+        }
+        if let Some(Ok(rec)) = store.best_path(&pfx.prefix, guard) {
+            if is_announced(store, &pfx.prefix, &rec) {
+                packer.add(&pfx.prefix, &rec.meta, options)?;
+            }
+        }
+    }
+
+    packer.into_messages(options)
+}
+
+/// Generate the BGP UPDATE messages that announce the Adj-RIB of `mui` in
+/// `store`: the active routes with that multi_uniq_id.
+///
+/// The path attributes of the routes come from the [BgpMeta]
+/// implementation of the meta-data. Prefixes with the same path
+/// attributes and next hop are packed into the same message, as many as
+/// fit into a message of `options.max_pdu_size`. IPv4 prefixes are
+/// announced in the NLRI field of the message, with the NEXT_HOP attribute
+/// from the path attributes, or from the next hop of the meta-data if there
+/// isn't one. IPv6 prefixes are announced in an MP_REACH_NLRI attribute,
+/// with the next hop of the meta-data. AS numbers are encoded as four octet
+/// AS numbers.
+///
+/// The messages for prefixes with the same path attributes follow each
+/// other. These groups of messages are in the order their first prefix
+/// has in `prefixes_iter`.
+pub fn adj_rib_updates<M: BgpMeta>(
+    store: &MultiThreadedStore<M>,
+    mui: u32,
+    options: &UpdateOptions,
+) -> Result<Vec<UpdateMessage<Vec<u8>>>, UpdateError> {
+    let guard = &epoch::pin();
+    let mut packer = Packer::default();
+
+    for pfx in store.prefixes_iter(guard) {
+        if let Some(rec) = pfx.meta.iter().find(|r| r.multi_uniq_id == mui) {
+            if is_announced(store, &pfx.prefix, rec) {
+                packer.add(&pfx.prefix, &rec.meta, options)?;
+            }
+        }
+    }
+
+    packer.into_messages(options)
+}
+
+// Whether the record is active, and not from a withdrawn mui.
+fn is_announced<M: BgpMeta>(
+    store: &MultiThreadedStore<M>,
+    prefix: &Prefix,
+    rec: &Record<M>,
+) -> bool {
+    rec.status == RouteStatus::Active
+        && !match prefix.addr() {
+            IpAddr::V4(_) => store.mui_is_withdrawn_v4(rec.multi_uniq_id),
+            IpAddr::V6(_) => store.mui_is_withdrawn_v6(rec.multi_uniq_id),
+        }
+}
+
+//------------ Packer --------------------------------------------------------
+
+// The composed path attributes for a group of prefixes, split around the
+// place where the MP_REACH_NLRI attribute goes for IPv6 prefixes, so that
+// all the attributes are ordered by type code.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Attributes {
+    before_mp_reach: Vec<u8>,
+    after_mp_reach: Vec<u8>,
+    // The next hop for the MP_REACH_NLRI attribute of IPv6 prefixes.
+    mp_next_hop: Option<Ipv6Addr>,
+}
+
+// Groups prefixes with the same path attributes.
+#[derive(Default)]
+struct Packer {
+    groups: Vec<(Attributes, Vec<Prefix>)>,
+    index: HashMap<Attributes, usize>,
+}
+
+impl Packer {
+    fn add<M: BgpMeta>(
+        &mut self,
+        prefix: &Prefix,
+        meta: &M,
+        options: &UpdateOptions,
+    ) -> Result<(), UpdateError> {
+        let attributes = compose_attributes(prefix, meta)?;
+        if pdu_len(&attributes, nlri_len(prefix)) > max_pdu_size(options) {
+            return Err(UpdateError::PduTooLarge(*prefix));
+        }
+        match self.index.get(&attributes) {
+            Some(idx) => self.groups[*idx].1.push(*prefix),
+            None => {
+                self.index.insert(attributes.clone(), self.groups.len());
+                self.groups.push((attributes, vec![*prefix]));
+            }
+        }
+        Ok(())
+    }
+
+    fn into_messages(
+        self,
+        options: &UpdateOptions,
+    ) -> Result<Vec<UpdateMessage<Vec<u8>>>, UpdateError> {
+        let mut msgs = vec![];
+        for (attributes, prefixes) in self.groups {
+            let mut start = 0;
+            while start < prefixes.len() {
+                // Take as many prefixes as fit.
+                let mut nlri = 0;
+                let mut end = start;
+                while end < prefixes.len() {
+                    let len = nlri + nlri_len(&prefixes[end]);
+                    if pdu_len(&attributes, len) > max_pdu_size(options) {
+                        break;
+                    }
+                    nlri = len;
+                    end += 1;
+                }
+                msgs.push(compose_update(
+                    &attributes,
+                    &prefixes[start..end],
+                )?);
+                start = end;
+            }
+        }
+        trace!("generated {} UPDATE messages", msgs.len());
+        Ok(msgs)
+    }
+}
+
+impl Attributes {
+    fn len(&self) -> usize {
+        self.before_mp_reach.len() + self.after_mp_reach.len()
+    }
+}
+
+fn compose_attributes<M: BgpMeta>(
+    prefix: &Prefix,
+    meta: &M,
+) -> Result<Attributes, UpdateError> {
+    let mp_next_hop = match (prefix.addr(), meta.next_hop()) {
+        (IpAddr::V4(_), _) => None,
+        (IpAddr::V6(_), Some(IpAddr::V6(next_hop))) => Some(next_hop),
+        (IpAddr::V6(_), _) => {
+            return Err(UpdateError::MissingNextHop(*prefix))
+        }
+    };
+    let mut conventional_next_hop = match (prefix.addr(), meta.next_hop()) {
+        (IpAddr::V4(_), Some(IpAddr::V4(next_hop))) => Some(next_hop),
+        _ => None,
+    };
+    let pa_map = meta.path_attributes();
+    // Without a next hop the UPDATE would be malformed.
+    if prefix.is_v4()
+        && conventional_next_hop.is_none()
+        && !pa_map.attributes().contains_key(&NEXT_HOP)
+    {
+        return Err(UpdateError::MissingNextHop(*prefix));
+    }
+
+    let mut attributes = Attributes {
+        before_mp_reach: vec![],
+        after_mp_reach: vec![],
+        mp_next_hop,
+    };
+    for (type_code, pa) in pa_map.attributes().iter() {
+        let type_code = *type_code;
+        if type_code == MP_REACH_NLRI || type_code == MP_UNREACH_NLRI {
+            continue;
+        }
+        // The NEXT_HOP attribute is only for IPv4 prefixes.
+        if type_code == NEXT_HOP {
+            if mp_next_hop.is_some() {
+                continue;
+            }
+            conventional_next_hop = None;
+        }
+        let buf = if type_code < MP_REACH_NLRI {
+            &mut attributes.before_mp_reach
+        } else {
+            &mut attributes.after_mp_reach
+        };
+        if type_code > NEXT_HOP {
+            if let Some(next_hop) = conventional_next_hop.take() {
+                // Well-known, transitive.
+                buf.extend_from_slice(&[0x40, NEXT_HOP, 4]);
+                buf.extend_from_slice(&next_hop.octets());
+            }
+        }
+        pa.compose(buf).unwrap_or_else(|e| match e {});
+    }
+    if let Some(next_hop) = conventional_next_hop {
+        attributes
+            .after_mp_reach
+            .extend_from_slice(&[0x40, NEXT_HOP, 4]);
+        attributes
+            .after_mp_reach
+            .extend_from_slice(&next_hop.octets());
+    }
+
+    if attributes.len() > u16::MAX as usize {
+        return Err(UpdateError::PduTooLarge(*prefix));
+    }
+    Ok(attributes)
+}
+
+// The length of an UPDATE message with `attributes` and `nlri` octets of
+// NLRI, in the MP_REACH_NLRI attribute for IPv6 prefixes.
+fn pdu_len(attributes: &Attributes, nlri: usize) -> usize {
+    UPDATE_HEADER_LEN
+        + attributes.len()
+        + match attributes.mp_next_hop {
+            Some(_) => {
+                let value_len = MP_REACH_IPV6_LEN + nlri;
+                if value_len > u8::MAX as usize {
+                    4 + value_len
+                } else {
+                    3 + value_len
+                }
+            }
+            None => nlri,
+        }
+}
+
+fn max_pdu_size(options: &UpdateOptions) -> usize {
+    options.max_pdu_size.min(u16::MAX as usize)
+}
+
+fn nlri_len(prefix: &Prefix) -> usize {
+    1 + (prefix.len() as usize + 7) / 8
+}
+
+fn compose_nlri(buf: &mut Vec<u8>, prefix: &Prefix) {
+    let n = (prefix.len() as usize + 7) / 8;
+    buf.push(prefix.len());
+    match prefix.addr() {
+        IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()[..n]),
+        IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()[..n]),
+    }
+}
+
+fn compose_update(
+    attributes: &Attributes,
+    prefixes: &[Prefix],
+) -> Result<UpdateMessage<Vec<u8>>, UpdateError> {
+    let mut nlri = vec![];
+    for prefix in prefixes {
+        compose_nlri(&mut nlri, prefix);
+    }
+
+    let mut path_attributes = attributes.before_mp_reach.clone();
+    if let Some(next_hop) = attributes.mp_next_hop {
+        let value_len = MP_REACH_IPV6_LEN + nlri.len();
+        // Optional, non-transitive, with an extended length if necessary.
+        if value_len > u8::MAX as usize {
+            path_attributes.extend_from_slice(&[0x90, MP_REACH_NLRI]);
+            path_attributes
+                .extend_from_slice(&(value_len as u16).to_be_bytes());
+        } else {
+            path_attributes.extend_from_slice(&[
+                0x80,
+                MP_REACH_NLRI,
+                value_len as u8,
+            ]);
+        }
+        path_attributes.extend_from_slice(&[0, 2, 1, 16]);
+        path_attributes.extend_from_slice(&next_hop.octets());
+        path_attributes.push(0);
+        path_attributes.append(&mut nlri);
+    }
+    path_attributes.extend_from_slice(&attributes.after_mp_reach);
+
+    let len = UPDATE_HEADER_LEN + path_attributes.len() + nlri.len();
+    let mut msg = Vec::with_capacity(len);
+    msg.extend_from_slice(&[0xff; 16]);
+    msg.extend_from_slice(&(len as u16).to_be_bytes());
+    // The type of an UPDATE, and no withdrawn routes.
+    msg.extend_from_slice(&[2, 0, 0]);
+    msg.extend_from_slice(&(path_attributes.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&path_attributes);
+    msg.extend_from_slice(&nlri);
+
+    UpdateMessage::from_octets(msg, &SessionConfig::modern())
+        .map_err(|_| UpdateError::InvalidAttributes(prefixes[0]))
+}
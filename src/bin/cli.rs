@@ -24,37 +24,17 @@ fn get_first_arg() -> Result<OsString, Box<dyn Error>> {
 fn load_prefixes(
     pfxs: &mut Vec<PrefixRecord<PrefixAs>>,
 ) -> Result<(), Box<dyn Error>> {
-    // Build the CSV reader and iterate over each record.
     let file_path = get_first_arg()?;
     println!("file path {:?}", file_path);
     let file = File::open(file_path)?;
-    let mut rdr = csv::Reader::from_reader(file);
-    for result in rdr.records() {
-        // The iterator yields Result<StringRecord, Error>, so we check the
-        // error here.
-        let record = result?;
-
-        let ip = record[0].parse::<std::net::IpAddr>()?;
-
-        let len: u8 = record[1].parse().unwrap();
-        let asn: u32 = record[2].parse().unwrap();
-        let pfx = PrefixRecord::new(
-            Prefix::new(ip, len)?,
-            vec![Record::new(0, 0, RouteStatus::Active, PrefixAs(asn))],
-        );
-
-        // let ip: Vec<_> = record[0]
-        //     .split('.')
-        //     .map(|o| -> u8 { o.parse().unwrap() })
-        //     .collect();
-        // let net = std::net::Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]);
-        // let len: u8 = record[1].parse().unwrap();
-        // let asn: u32 = record[2].parse().unwrap();
-        // let pfx = PrefixRecord::new_with_local_meta(
-        //     Prefix::new(net.into(), len)?,
-        //     PrefixAs(asn),
-        // );
-        pfxs.push(pfx);
+    let routes = rotonda_store::csv::routes(
+        file,
+        &rotonda_store::csv::CsvOptions::default(),
+        |fields| Ok(PrefixAs(fields.get(2).unwrap_or_default().parse()?)),
+    );
+    for route in routes {
+        let (prefix, record) = route?;
+        pfxs.push(PrefixRecord::new(prefix, vec![record]));
     }
     Ok(())
 }
@@ -70,9 +50,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("finished loading {} prefixes...", pfxs.len());
     let start = std::time::Instant::now();
 
-    for pfx in pfxs.into_iter() {
-        tree_bitmap.insert(&pfx.prefix, pfx.meta[0].clone(), None)?;
-    }
+    tree_bitmap.par_insert_bulk(
+        pfxs.into_iter().map(|pfx| (pfx.prefix, pfx.meta[0].clone())),
+        0,
+    )?;
     let ready = std::time::Instant::now();
     // println!("{:#?}", tree_bitmap.store.prefixes);
     println!(
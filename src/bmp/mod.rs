@@ -0,0 +1,313 @@
+//! Feeding a store from BMP
+//!
+//! This module has an adaptor that applies the messages of a BMP session,
+//! as described in RFC 7854, to a
+//! [MultiThreadedStore](crate::MultiThreadedStore). Every monitored peer
+//! gets its own multi_uniq_id. The messages are parsed with `routecore`.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use log::trace;
+use routecore::bgp::message::update::FourOctetAsns;
+use routecore::bgp::message::SessionConfig;
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+use routecore::bgp::types::AfiSafiType;
+use routecore::bmp::message::{Message, PerPeerHeader};
+use routecore::Octets;
+
+use crate::prelude::multi::{BgpUpdateReport, PrefixStoreError};
+use crate::prelude::Meta;
+use crate::MultiThreadedStore;
+
+//------------ BmpPeer -------------------------------------------------------
+
+/// A monitored peer, as identified by the per-peer header of a BMP message
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BmpPeer {
+    /// The address of the peer
+    pub addr: IpAddr,
+    /// The AS number of the peer
+    pub asn: Asn,
+    /// The peer distinguisher, the route distinguisher of the VRF the peer
+    /// is in, or all zeroes for peers in the global instance
+    pub distinguisher: [u8; 8],
+    /// Whether the routes of the peer are post-policy, or pre-policy
+    pub post_policy: bool,
+}
+
+impl<O: AsRef<[u8]>> From<&PerPeerHeader<O>> for BmpPeer {
+    fn from(pph: &PerPeerHeader<O>) -> Self {
+        BmpPeer {
+            addr: pph.address(),
+            asn: pph.asn(),
+            distinguisher: pph.distinguisher().try_into().unwrap(),
+            post_policy: pph.is_post_policy(),
+        }
+    }
+}
+
+//------------ BmpAdaptor ----------------------------------------------------
+
+// The state of a monitored peer. A peer that came back up waits for the
+// End-of-RIB for IPv4 and IPv6 unicast before its mui is marked as active
+// again for that address family.
+#[derive(Debug)]
+struct PeerState {
+    mui: u32,
+    session_config: SessionConfig,
+    up: bool,
+    pending_eor_v4: bool,
+    pending_eor_v6: bool,
+}
+
+/// Applies the messages of a BMP session to a store
+///
+/// The adaptor keeps a table with a multi_uniq_id for every monitored
+/// peer. Every peer that shows up in a Peer Up Notification or a Route
+/// Monitoring message gets the next multi_uniq_id, starting at the
+/// `mui_base` the adaptor was created with. A peer keeps its multi_uniq_id
+/// when it goes down and comes back up again.
+///
+/// An adaptor is meant for one BMP session. Adaptors for the sessions with
+/// different routers that feed the same store should have their own range
+/// of multi_uniq_ids.
+#[derive(Debug)]
+pub struct BmpAdaptor {
+    peers: HashMap<BmpPeer, PeerState>,
+    next_mui: u32,
+}
+
+impl BmpAdaptor {
+    /// Create an adaptor that hands out multi_uniq_ids from `mui_base`
+    /// upwards.
+    pub fn new(mui_base: u32) -> Self {
+        Self {
+            peers: HashMap::new(),
+            next_mui: mui_base,
+        }
+    }
+
+    /// Apply a BMP message to `store`.
+    ///
+    /// * A Peer Up Notification adds the peer to the table, with the
+    ///   session parameters from the BGP OPEN messages in it. If the peer
+    ///   was down, the multi_uniq_id of the peer is marked as stale, and it
+    ///   stays withdrawn, so that none of its old routes are used again,
+    ///   until the peer sends the End-of-RIB marker for an address family.
+    /// * A Route Monitoring message is applied with
+    ///   [apply_bgp_update](crate::MultiThreadedStore::apply_bgp_update),
+    ///   with the timestamp in seconds from the per-peer header as the
+    ///   ltime. `meta_builder` is called for every announced prefix, with
+    ///   the peer, the prefix and the path attributes. A peer that was not
+    ///   in a Peer Up Notification is added to the table first, and a peer
+    ///   that was down is brought back up like with a Peer Up
+    ///   Notification, with the session parameters it had before.
+    /// * An End-of-RIB marker for IPv4 or IPv6 unicast from a peer that
+    ///   came back up removes the routes for that address family that the
+    ///   peer did not announce again, with `sweep_stale_v4` or
+    ///   `sweep_stale_v6` on the store, and then marks the multi_uniq_id
+    ///   of the peer as active again for that address family. Routers send
+    ///   the End-of-RIB markers once they are done sending the routes of a
+    ///   peer, see RFC 7854. If a router does not send them, the user has
+    ///   to do this instead, to use the routes of the peer again.
+    /// * A Peer Down Notification marks the multi_uniq_id of the peer as
+    ///   withdrawn for both IPv4 and IPv6.
+    /// * A Termination message marks the multi_uniq_ids of all the peers
+    ///   that are up as withdrawn.
+    ///
+    /// All the other messages are ignored. The report has the changes to
+    /// the store for a Route Monitoring message, and is empty for all the
+    /// other messages.
+    pub fn process<M, O, F>(
+        &mut self,
+        store: &MultiThreadedStore<M>,
+        msg: &Message<O>,
+        mut meta_builder: F,
+    ) -> Result<BgpUpdateReport, PrefixStoreError>
+    where
+        M: Meta,
+        O: Octets,
+        F: FnMut(&BmpPeer, &Prefix, &OwnedPathAttributes) -> Option<M>,
+    {
+        match msg {
+            Message::PeerUpNotification(peer_up) => {
+                let pph = peer_up.per_peer_header();
+                let (session_config, _) = peer_up.pph_session_config();
+                self.peer_up(store, BmpPeer::from(&pph), session_config)?;
+                Ok(BgpUpdateReport::default())
+            }
+            Message::RouteMonitoring(route_monitoring) => {
+                let pph = route_monitoring.per_peer_header();
+                let peer = BmpPeer::from(&pph);
+                let (mui, session_config) = match self.peers.get(&peer) {
+                    Some(state) if state.up => {
+                        (state.mui, state.session_config.clone())
+                    }
+                    Some(state) => {
+                        let session_config = state.session_config.clone();
+                        let mui = self.peer_up(
+                            store,
+                            peer,
+                            session_config.clone(),
+                        )?;
+                        (mui, session_config)
+                    }
+                    None => {
+                        let mut session_config = SessionConfig::modern();
+                        session_config.set_four_octet_asns(FourOctetAsns(
+                            !pph.is_legacy_format(),
+                        ));
+                        let mui = self.peer_up(
+                            store,
+                            peer,
+                            session_config.clone(),
+                        )?;
+                        (mui, session_config)
+                    }
+                };
+                let update = route_monitoring
+                    .bgp_update(&session_config)
+                    .map_err(|_| PrefixStoreError::InvalidBgpUpdate)?;
+                let ltime =
+                    u64::try_from(pph.timestamp().timestamp()).unwrap_or(0);
+                let report = store.apply_bgp_update(
+                    mui,
+                    ltime,
+                    &update,
+                    |prefix, attributes| {
+                        meta_builder(&peer, prefix, attributes)
+                    },
+                )?;
+                if let Ok(Some(afi_safi)) = update.is_eor() {
+                    if let Some(state) = self.peers.get_mut(&peer) {
+                        end_of_rib_for(store, state, afi_safi)?;
+                    }
+                }
+                Ok(report)
+            }
+            Message::PeerDownNotification(peer_down) => {
+                let peer = BmpPeer::from(&peer_down.per_peer_header());
+                if let Some(state) = self.peers.get_mut(&peer) {
+                    peer_down_for(store, state)?;
+                }
+                Ok(BgpUpdateReport::default())
+            }
+            Message::TerminationMessage(_) => {
+                for state in self.peers.values_mut() {
+                    peer_down_for(store, state)?;
+                }
+                Ok(BgpUpdateReport::default())
+            }
+            _ => Ok(BgpUpdateReport::default()),
+        }
+    }
+
+    /// The multi_uniq_id for `peer`, if it is in the table.
+    pub fn mui_for(&self, peer: &BmpPeer) -> Option<u32> {
+        self.peers.get(peer).map(|state| state.mui)
+    }
+
+    /// The peer with the multi_uniq_id `mui`, if it is in the table.
+    pub fn peer_for(&self, mui: u32) -> Option<&BmpPeer> {
+        self.peers
+            .iter()
+            .find(|(_, state)| state.mui == mui)
+            .map(|(peer, _)| peer)
+    }
+
+    /// Whether `peer` is in the table, and up.
+    pub fn is_up(&self, peer: &BmpPeer) -> bool {
+        self.peers.get(peer).is_some_and(|state| state.up)
+    }
+
+    /// All the peers in the table, with their multi_uniq_ids, in no
+    /// particular order.
+    pub fn peers(&self) -> impl Iterator<Item = (&BmpPeer, u32)> {
+        self.peers.iter().map(|(peer, state)| (peer, state.mui))
+    }
+
+    // Add the peer to the table, or bring it back up, and return its mui.
+    fn peer_up<M: Meta>(
+        &mut self,
+        store: &MultiThreadedStore<M>,
+        peer: BmpPeer,
+        session_config: SessionConfig,
+    ) -> Result<u32, PrefixStoreError> {
+        match self.peers.get_mut(&peer) {
+            Some(state) => {
+                if !state.up {
+                    trace!(
+                        "peer {:?} with mui {} is up again",
+                        peer,
+                        state.mui
+                    );
+                    store.mark_mui_as_stale(state.mui)?;
+                    state.up = true;
+                    state.pending_eor_v4 = true;
+                    state.pending_eor_v6 = true;
+                }
+                state.session_config = session_config;
+                Ok(state.mui)
+            }
+            None => {
+                let mui = self.next_mui;
+                trace!("new peer {:?} with mui {}", peer, mui);
+                self.next_mui += 1;
+                self.peers.insert(
+                    peer,
+                    PeerState {
+                        mui,
+                        session_config,
+                        up: true,
+                        pending_eor_v4: false,
+                        pending_eor_v6: false,
+                    },
+                );
+                Ok(mui)
+            }
+        }
+    }
+}
+
+// Mark the mui of the peer as withdrawn, if it is up.
+fn peer_down_for<M: Meta>(
+    store: &MultiThreadedStore<M>,
+    state: &mut PeerState,
+) -> Result<(), PrefixStoreError> {
+    if state.up {
+        trace!("peer with mui {} is down", state.mui);
+        store.mark_mui_as_withdrawn_v4(state.mui)?;
+        store.mark_mui_as_withdrawn_v6(state.mui)?;
+        state.up = false;
+    }
+    Ok(())
+}
+
+// Sweep the routes of a peer that came back up that it did not announce
+// again, and mark its mui as active again, for the address family of the
+// End-of-RIB marker.
+fn end_of_rib_for<M: Meta>(
+    store: &MultiThreadedStore<M>,
+    state: &mut PeerState,
+    afi_safi: AfiSafiType,
+) -> Result<(), PrefixStoreError> {
+    match afi_safi {
+        AfiSafiType::Ipv4Unicast if state.pending_eor_v4 => {
+            trace!("end of rib for ipv4 for mui {}", state.mui);
+            store.sweep_stale_v4(state.mui, true)?;
+            store.mark_mui_as_active_v4(state.mui)?;
+            state.pending_eor_v4 = false;
+        }
+        AfiSafiType::Ipv6Unicast if state.pending_eor_v6 => {
+            trace!("end of rib for ipv6 for mui {}", state.mui);
+            store.sweep_stale_v6(state.mui, true)?;
+            store.mark_mui_as_active_v6(state.mui)?;
+            state.pending_eor_v6 = false;
+        }
+        _ => {}
+    }
+    Ok(())
+}
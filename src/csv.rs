@@ -0,0 +1,361 @@
+//! Loading routes from CSV files
+//!
+//! This module has a loader for CSV files with a route on every line, for
+//! both the [MultiThreadedStore](crate::MultiThreadedStore) and the
+//! [SingleThreadedStore](crate::SingleThreadedStore). Which columns hold
+//! the prefix, the multi_uniq_id, the ltime and the status of a route is
+//! configured with [CsvOptions], and the meta-data of a route is created
+//! from the fields of its line by a closure.
+//!
+//! This module is only available with the `csv` feature.
+
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use ::csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter, Trim};
+use inetnum::addr::Prefix;
+use log::trace;
+
+use crate::prelude::multi::{Record, RouteStatus};
+use crate::prelude::Meta;
+use crate::{MultiThreadedStore, SingleThreadedStore};
+
+/// The result of the closure that creates the meta-data for a route
+pub type MetaResult<M> = Result<M, Box<dyn Error + Send + Sync>>;
+
+//------------ PrefixColumns -------------------------------------------------
+
+/// The column(s) that hold the prefix of a route
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PrefixColumns {
+    /// One column with the prefix in CIDR notation, e.g. `192.0.2.0/24`
+    Cidr(usize),
+    /// A column with the address, and a column with the prefix length
+    AddrLen(usize, usize),
+}
+
+//------------ CsvOptions ----------------------------------------------------
+
+/// Options for loading a CSV file
+///
+/// Columns are numbered from zero. The multi_uniq_id, ltime and status of
+/// the routes are taken from their column, if there is one, or else from
+/// the `default_*` fields. Statuses are written like the `Display`
+/// implementation of [RouteStatus] writes them, ignoring case.
+///
+/// The default options match the `ip,len,asn` files in the `data`
+/// directory of this crate: a header line, the address and prefix length
+/// in the first two columns, and the routes stored as Active under
+/// multi_uniq_id 0 with ltime 0.
+#[derive(Clone, Copy, Debug)]
+pub struct CsvOptions {
+    /// The column(s) with the prefix
+    pub prefix: PrefixColumns,
+    /// The column with the multi_uniq_id
+    pub mui: Option<usize>,
+    /// The column with the ltime
+    pub ltime: Option<usize>,
+    /// The column with the status
+    pub status: Option<usize>,
+    /// The multi_uniq_id of routes if there is no column for it
+    pub default_mui: u32,
+    /// The ltime of routes if there is no column for it
+    pub default_ltime: u64,
+    /// The status of routes if there is no column for it
+    pub default_status: RouteStatus,
+    /// Whether the first line has the names of the columns, instead of a
+    /// route
+    pub has_headers: bool,
+    /// The character that separates the fields
+    pub delimiter: u8,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            prefix: PrefixColumns::AddrLen(0, 1),
+            mui: None,
+            ltime: None,
+            status: None,
+            default_mui: 0,
+            default_ltime: 0,
+            default_status: RouteStatus::Active,
+            has_headers: true,
+            delimiter: b',',
+        }
+    }
+}
+
+//------------ CsvError ------------------------------------------------------
+
+/// An error that occurred while loading a CSV file
+///
+/// The variants that are tied to a line of the file carry its line number,
+/// starting at 1, and the variants that are tied to a field carry its
+/// column number as well.
+#[derive(Debug)]
+pub enum CsvError {
+    /// The file cannot be read, or a line is not valid CSV
+    Csv(::csv::Error),
+    /// A line doesn't have a configured column
+    MissingColumn(u64, usize),
+    /// A field cannot be parsed
+    InvalidField(u64, usize),
+    /// The closure could not create the meta-data for a line
+    Meta(u64, Box<dyn Error + Send + Sync>),
+    /// The route of a line cannot be inserted into the store
+    Store(u64, Box<dyn Error + Send + Sync>),
+}
+
+impl Error for CsvError {}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Csv(err) => write!(f, "Error: Invalid CSV: {}", err),
+            CsvError::MissingColumn(line, column) => write!(
+                f,
+                "Error: Line {} does not have column {}.",
+                line, column
+            ),
+            CsvError::InvalidField(line, column) => write!(
+                f,
+                "Error: Invalid field in column {} on line {}.",
+                column, line
+            ),
+            CsvError::Meta(line, err) => write!(
+                f,
+                "Error: Cannot create meta-data for line {}: {}",
+                line, err
+            ),
+            CsvError::Store(line, err) => {
+                write!(f, "Error: Cannot store line {}: {}", line, err)
+            }
+        }
+    }
+}
+
+impl From<::csv::Error> for CsvError {
+    fn from(value: ::csv::Error) -> Self {
+        CsvError::Csv(value)
+    }
+}
+
+//------------ CsvRoutes -----------------------------------------------------
+
+/// An iterator over the routes in a CSV file
+///
+/// Created by [routes].
+pub struct CsvRoutes<R, F> {
+    records: StringRecordsIntoIter<R>,
+    options: CsvOptions,
+    meta_parser: F,
+    line: u64,
+}
+
+impl<R, F> CsvRoutes<R, F> {
+    /// The line number of the last route that was read.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<R, F, M> Iterator for CsvRoutes<R, F>
+where
+    R: Read,
+    F: FnMut(&StringRecord) -> MetaResult<M>,
+{
+    type Item = Result<(Prefix, Record<M>), CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fields = match self.records.next()? {
+            Ok(fields) => fields,
+            Err(err) => return Some(Err(err.into())),
+        };
+        self.line = fields.position().map_or(self.line + 1, |p| p.line());
+        Some(self.route(&fields))
+    }
+}
+
+impl<R, F, M> CsvRoutes<R, F>
+where
+    F: FnMut(&StringRecord) -> MetaResult<M>,
+{
+    // Create the route from the fields of a line.
+    fn route(
+        &mut self,
+        fields: &StringRecord,
+    ) -> Result<(Prefix, Record<M>), CsvError> {
+        let prefix = match self.options.prefix {
+            PrefixColumns::Cidr(column) => {
+                self.parse(fields, column, |s| Prefix::from_str(s).ok())?
+            }
+            PrefixColumns::AddrLen(addr_column, len_column) => {
+                let addr = self.parse(fields, addr_column, |s| {
+                    s.parse::<IpAddr>().ok()
+                })?;
+                let len =
+                    self.parse(fields, len_column, |s| s.parse::<u8>().ok())?;
+                Prefix::new(addr, len).map_err(|_| {
+                    CsvError::InvalidField(self.line, len_column)
+                })?
+            }
+        };
+        let mui = match self.options.mui {
+            Some(column) => self.parse(fields, column, |s| s.parse().ok())?,
+            None => self.options.default_mui,
+        };
+        let ltime = match self.options.ltime {
+            Some(column) => self.parse(fields, column, |s| s.parse().ok())?,
+            None => self.options.default_ltime,
+        };
+        let status = match self.options.status {
+            Some(column) => self.parse(fields, column, parse_status)?,
+            None => self.options.default_status,
+        };
+        let meta = (self.meta_parser)(fields)
+            .map_err(|err| CsvError::Meta(self.line, err))?;
+
+        Ok((prefix, Record::new(mui, ltime, status, meta)))
+    }
+
+    // Parse the field in `column` with `parse`.
+    fn parse<T>(
+        &self,
+        fields: &StringRecord,
+        column: usize,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<T, CsvError> {
+        let field = fields
+            .get(column)
+            .ok_or(CsvError::MissingColumn(self.line, column))?;
+        parse(field).ok_or(CsvError::InvalidField(self.line, column))
+    }
+}
+
+// Parse a status, as written by the Display implementation of RouteStatus.
+fn parse_status(s: &str) -> Option<RouteStatus> {
+    match s.to_ascii_lowercase().as_str() {
+        "active" => Some(RouteStatus::Active),
+        "inactive" => Some(RouteStatus::InActive),
+        "withdrawn" => Some(RouteStatus::Withdrawn),
+        "stale" => Some(RouteStatus::Stale),
+        _ => None,
+    }
+}
+
+//------------ Loading -------------------------------------------------------
+
+/// Read the routes from a CSV file from `reader`.
+///
+/// Leading and trailing whitespace is removed from all the fields. For
+/// every line `meta_parser` is called with its fields, to create the
+/// meta-data of the route. Lines that cannot be turned into a route
+/// produce an error, after which the iterator can be used to read the
+/// next lines.
+pub fn routes<M, R, F>(
+    reader: R,
+    options: &CsvOptions,
+    meta_parser: F,
+) -> CsvRoutes<R, F>
+where
+    R: Read,
+    F: FnMut(&StringRecord) -> MetaResult<M>,
+{
+    let records = ReaderBuilder::new()
+        .has_headers(options.has_headers)
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(reader)
+        .into_records();
+    CsvRoutes {
+        records,
+        options: *options,
+        meta_parser,
+        line: 0,
+    }
+}
+
+/// Load the routes from the CSV file at `path` into `store`.
+///
+/// See [load] for details.
+pub fn load_file<M, P, F>(
+    store: &MultiThreadedStore<M>,
+    path: P,
+    options: &CsvOptions,
+    meta_parser: F,
+) -> Result<usize, CsvError>
+where
+    M: Meta,
+    P: AsRef<Path>,
+    F: FnMut(&StringRecord) -> MetaResult<M>,
+{
+    let file = std::fs::File::open(path).map_err(::csv::Error::from)?;
+    load(store, file, options, meta_parser)
+}
+
+/// Load the routes from a CSV file from `reader` into `store`.
+///
+/// See [routes] for how the lines are turned into routes. Loading stops at
+/// the first line that cannot be turned into a route, or that cannot be
+/// stored, leaving the routes of the lines before it in the store. Returns
+/// the number of routes that were inserted. Best path selection is not run
+/// for the inserted prefixes.
+pub fn load<M, R, F>(
+    store: &MultiThreadedStore<M>,
+    reader: R,
+    options: &CsvOptions,
+    meta_parser: F,
+) -> Result<usize, CsvError>
+where
+    M: Meta,
+    R: Read,
+    F: FnMut(&StringRecord) -> MetaResult<M>,
+{
+    let mut routes = routes(reader, options, meta_parser);
+    let mut count = 0;
+    while let Some(route) = routes.next() {
+        let (prefix, record) = route?;
+        store
+            .insert(&prefix, record, None)
+            .map_err(|err| CsvError::Store(routes.line(), err.into()))?;
+        count += 1;
+    }
+    trace!("loaded {} routes from CSV", count);
+    Ok(count)
+}
+
+/// Load the routes from a CSV file from `reader` into the single-threaded
+/// `store`.
+///
+/// This works like [load], except that the records in a single-threaded
+/// store only have meta-data, so the multi_uniq_id, ltime and status of
+/// the routes are not stored.
+pub fn load_single<M, R, F>(
+    store: &mut SingleThreadedStore<M>,
+    reader: R,
+    options: &CsvOptions,
+    meta_parser: F,
+) -> Result<usize, CsvError>
+where
+    M: Meta,
+    R: Read,
+    F: FnMut(&StringRecord) -> MetaResult<M>,
+{
+    let mut routes = routes(reader, options, meta_parser);
+    let mut count = 0;
+    while let Some(route) = routes.next() {
+        let (prefix, record) = route?;
+        store.insert(&prefix, record.meta).map_err(|err| {
+            CsvError::Store(routes.line(), err.to_string().into())
+        })?;
+        count += 1;
+    }
+    trace!("loaded {} routes from CSV", count);
+    Ok(count)
+}
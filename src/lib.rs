@@ -32,6 +32,17 @@ pub mod stats;
 /// Some simple metadata implementations
 pub mod meta_examples;
 
+/// Glue between the meta-data in a store and BGP
+pub mod bgp;
+
+pub mod bmp;
+
+#[cfg(feature = "mrt")]
+pub mod mrt;
+
+#[cfg(feature = "csv")]
+pub mod csv;
+
 /// The publicly available devices
 pub use crate::rotonda_store::*;
 
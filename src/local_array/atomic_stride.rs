@@ -33,7 +33,8 @@ pub trait AtomicBitmap {
         + Zero
         + PartialOrd
         + std::ops::BitAnd<Output = Self::InnerType>
-        + std::ops::BitOr<Output = Self::InnerType>;
+        + std::ops::BitOr<Output = Self::InnerType>
+        + std::ops::Not<Output = Self::InnerType>;
 
     fn new() -> Self;
     fn inner(self) -> Self::InnerType;
@@ -15,6 +15,7 @@ macro_rules! insert_match {
         $pfx: ident; // the whole search prefix
         $record: ident; // the record holding the metadata
         $update_path_selections: ident; // boolean indicate whether to update the path selections for this route
+        $mode: ident; // how to treat an existing record for the same mui
         $truncate_len: ident; // the start of the length of this stride
         $stride_len: ident; // the length of this stride
         $cur_i: expr; // the id of the current node in this stride
@@ -51,7 +52,7 @@ macro_rules! insert_match {
             let mut local_retry_count = 0;
             loop {
                 // retrieve_node_mut_with_guard updates the bitmap index if necessary.
-                if let Some(current_node) = $self.store.retrieve_node_mut_with_guard($cur_i, $record.multi_uniq_id, $guard) {
+                if let Some(current_node) = $self.store.retrieve_node_mut_with_guard($cur_i, $record.mui(), $guard) {
                     match current_node {
                         $(
                             SizedStrideRefMut::$variant(current_node) => {
@@ -80,7 +81,7 @@ macro_rules! insert_match {
                                         // store. It returns the created id
                                         // and the number of retries before
                                         // success.
-                                        match $self.store.store_node(new_id, $record.multi_uniq_id, n, $guard) {
+                                        match $self.store.store_node(new_id, $record.mui(), n, $guard) {
                                             Ok((node_id, s_retry_count)) => {
                                                 break Ok((node_id, $acc_retry_count + s_retry_count + retry_count));
                                             },
@@ -101,7 +102,7 @@ macro_rules! insert_match {
                                         break Ok((node_id, $acc_retry_count + local_retry_count + retry_count))
                                     },
                                     (NewNodeOrIndex::NewPrefix, retry_count) => {
-                                        return $self.store.upsert_prefix($pfx, $record, $update_path_selections, $guard)
+                                        return $self.store.upsert_prefix($pfx, $record, $update_path_selections, $mode, $guard)
                                             .and_then(|mut r| {
                                                 r.cas_count += $acc_retry_count as usize + local_retry_count as usize + retry_count as usize;
                                                 Ok(r)
@@ -110,7 +111,7 @@ macro_rules! insert_match {
                                         // $self.stats[$stats_level].inc_prefix_count($level);
                                     }
                                     (NewNodeOrIndex::ExistingPrefix, retry_count) => {
-                                        return $self.store.upsert_prefix($pfx, $record, $update_path_selections, $guard)
+                                        return $self.store.upsert_prefix($pfx, $record, $update_path_selections, $mode, $guard)
                                             .and_then(|mut r| { 
                                                 r.cas_count += $acc_retry_count as usize + local_retry_count as usize + retry_count as usize;
                                                 Ok(r) 
@@ -130,9 +131,11 @@ macro_rules! insert_match {
                             );
                     }
                     local_retry_count += 1;
-                    // THIS IS A FAIRLY ARBITRARY NUMBER.
-                    // We're giving up after a number of tries.
-                    if local_retry_count >= 8 {
+                    // We're giving up after the back off is completed. The
+                    // back off yields to other threads for its last
+                    // attempts, so that a creating thread that got
+                    // preempted gets a chance to finish.
+                    if $back_off.is_completed() {
                         if log_enabled!(log::Level::Trace) {
                             debug!("{} contention: Max. retry count reached. Giving up for id {} from store l{} after {} attempts.", 
                                 std::thread::current().name().unwrap(),
@@ -143,7 +146,7 @@ macro_rules! insert_match {
                         }
                         return Err(PrefixStoreError::NodeCreationMaxRetryError);
                     }
-                    $back_off.spin();
+                    $back_off.snooze();
                 }
             }
         }
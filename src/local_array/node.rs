@@ -307,6 +307,97 @@ where
         ), retry_count)
     }
 
+    // Sets the bit for the prefix at (nibble, nibble_len) in the pfxbitarr
+    // of this node, e.g. for a prefix that was removed from the store, and
+    // that got a record again.
+    //
+    // Returns a tuple of which the first element indicates whether this
+    // call actually set the bit (false means it was set already), and the
+    // second element is the number of retries for the compare_exchange of
+    // the pfxbitarr.
+    pub(crate) fn insert_pfx_at(
+        &self,
+        nibble: u32,
+        nibble_len: u8,
+    ) -> (bool, u32) {
+        let mut retry_count = 0;
+        let bit_pos = S::get_bit_pos(nibble, nibble_len);
+        let mut pfxbitarr = self.pfxbitarr.load();
+        let backoff = Backoff::new();
+
+        // THE CRITICAL SECTION
+        //
+        // UPDATING pfxbitarr
+        //
+        // See `remove_pfx_at`.
+        loop {
+            if pfxbitarr & bit_pos
+                != <<<S as Stride>::AtomicPfxSize as AtomicBitmap>::InnerType as std::ops::BitAnd>::Output::zero()
+            {
+                return (false, retry_count);
+            }
+
+            match self.pfxbitarr.compare_exchange(
+                pfxbitarr, pfxbitarr | bit_pos
+            ) {
+                CasResult(Ok(_)) => {
+                    return (true, retry_count);
+                }
+                CasResult(Err(newer_array)) => {
+                    retry_count += 1;
+                    pfxbitarr = newer_array;
+                }
+            };
+            backoff.spin();
+        }
+    }
+
+    // Clears the bit for the prefix at (nibble, nibble_len) in the pfxbitarr
+    // of this node.
+    //
+    // Returns a tuple of which the first element indicates whether this
+    // call actually cleared the bit (false means it wasn't set, possibly
+    // because another thread beat us to it), and the second element is the
+    // number of retries for the compare_exchange of the pfxbitarr.
+    pub(crate) fn remove_pfx_at(
+        &self,
+        nibble: u32,
+        nibble_len: u8,
+    ) -> (bool, u32) {
+        let mut retry_count = 0;
+        let bit_pos = S::get_bit_pos(nibble, nibble_len);
+        let mut pfxbitarr = self.pfxbitarr.load();
+        let backoff = Backoff::new();
+
+        // THE CRITICAL SECTION
+        //
+        // UPDATING pfxbitarr
+        //
+        // Same as for setting a bit, we're preventing the use of an old
+        // pfxbitarr that would overwrite bits set (or cleared) in the
+        // meantime elsewhere in the bitarray.
+        loop {
+            if pfxbitarr & bit_pos
+                == <<<S as Stride>::AtomicPfxSize as AtomicBitmap>::InnerType as std::ops::BitAnd>::Output::zero()
+            {
+                return (false, retry_count);
+            }
+
+            match self.pfxbitarr.compare_exchange(
+                pfxbitarr, pfxbitarr & !bit_pos
+            ) {
+                CasResult(Ok(_)) => {
+                    return (true, retry_count);
+                }
+                CasResult(Err(newer_array)) => {
+                    retry_count += 1;
+                    pfxbitarr = newer_array;
+                }
+            };
+            backoff.spin();
+        }
+    }
+
     //-------- Search nibble functions --------------------------------------
 
     // This function looks for the longest marching prefix in the provided
@@ -757,10 +848,18 @@ impl<AF: AddressFamily, S: Stride> std::iter::Iterator for
             return None;
         }
 
+        // The child nodes in the ptrbitarr all have nibbles of the full
+        // stride size, so the start_bit_span has to be extended to that
+        // size. The more-specifics for the start_bit_span then lie between
+        // the start_bit_span padded with zeros and the start_bit_span padded
+        // with ones (inclusive).
+        let unused_len = S::STRIDE_LEN - self.start_bit_span.len;
+        let stop = ((self.start_bit_span.bits + 1) << unused_len) - 1;
+
         // Previous iteration incremented the cursor beyond the stride size.
         if let Some(cursor) = self.cursor { 
-            if cursor >= (1 << (S::STRIDE_LEN - self.start_bit_span.len)) {
-                trace!("cursor.bits >= (1 << (S::STRIDE_LEN - self.start_bit_span.len))");
+            if cursor > stop {
+                trace!("cursor > stop");
                 trace!("cursor: {}", cursor);
                 trace!("start_bit_span: {} {}", self.start_bit_span.bits, self.start_bit_span.len);
                 return None;
@@ -776,11 +875,11 @@ impl<AF: AddressFamily, S: Stride> std::iter::Iterator for
         trace!("          x1  4   8  12  16  20  24  28  32");
         trace!("ptrbitarr {:032b}", self.ptrbitarr);
 
-        let start = if let Some(bits) = self.cursor { bits } else { self.start_bit_span.bits };
-        // We either stop if we have reached the maximum number of bits that
-        // we should check for this bit_span or we stop at the end of the
-        // stride (in case of a start_bit_span.bits == 0).
-        let stop = <u32>::min((1 << (S::STRIDE_LEN - self.start_bit_span.len)) + start, (1 << S::STRIDE_LEN) - 1); 
+        let start = if let Some(bits) = self.cursor {
+            bits
+        } else {
+            self.start_bit_span.bits << unused_len
+        };
 
         trace!("start {:?} stop {}", start, stop);
         for cursor in start..=stop {
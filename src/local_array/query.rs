@@ -35,7 +35,10 @@ where
     ) -> QueryResult<M> {
         let result = self
             .store
-            .non_recursive_retrieve_prefix_with_guard(prefix_id, guard);
+            .non_recursive_retrieve_stored_prefix_with_guard(
+                prefix_id,
+                guard,
+            );
         let prefix = result.0;
         let more_specifics_vec =
             self.store.more_specific_prefix_iter_from(prefix_id, mui, include_withdrawn, guard);
@@ -68,7 +71,10 @@ where
     ) -> QueryResult<M> {
         let result = self
             .store
-            .non_recursive_retrieve_prefix_with_guard(prefix_id, guard);
+            .non_recursive_retrieve_stored_prefix_with_guard(
+                prefix_id,
+                guard,
+            );
 
         let prefix = result.0;
         let less_specifics_vec = result.1.map(
@@ -103,7 +109,7 @@ where
         include_withdrawn: bool,
         guard: &'a Guard,
     ) -> Result<
-        impl Iterator<Item = (PrefixId<AF>, Vec<PublicRecord<M>>)> + '_,
+        impl Iterator<Item = (PrefixId<AF>, Vec<PublicRecord<M>>)> + 'a,
         std::io::Error,
     > {
         Ok(self.store.more_specific_prefix_iter_from(prefix_id, mui, include_withdrawn, guard))
@@ -116,11 +122,14 @@ where
         mui: Option<u32>,
         guard: &'a Guard,
     ) -> QueryResult<M> {
-        // `non_recursive_retrieve_prefix_with_guard` returns an exact match
-        // only, so no longest matching prefix!
+        // `non_recursive_retrieve_stored_prefix_with_guard` returns an exact
+        // match only, so no longest matching prefix!
         let mut stored_prefix = self
             .store
-            .non_recursive_retrieve_prefix_with_guard(search_pfx, guard)
+            .non_recursive_retrieve_stored_prefix_with_guard(
+                search_pfx,
+                guard,
+            )
             .0
             .map(|pfx| (
             pfx.prefix, 
@@ -139,7 +148,12 @@ where
                                     Ordering::Acquire, guard
                                 ).deref() 
                             },
-                            RouteStatus::Withdrawn
+                            RouteStatus::Withdrawn,
+                            unsafe {
+                                self.store.stale_muis_bmin.load(
+                                    Ordering::Acquire, guard
+                                ).deref()
+                            },
                         )
                     }
                 )
@@ -217,6 +231,91 @@ where
         }
     }
 
+    // Like `match_prefix_by_store_direct`, but the result reflects the store
+    // as it was at `ltime`. The records for each (prefix, mui) are taken
+    // from their history, so how far back this goes depends on the
+    // HistoryRetention of the store. Records that were removed from the
+    // store, and the global withdrawn status of muis, are not part of the
+    // history, so they are not taken into account.
+    pub fn match_prefix_as_of(
+        &'a self,
+        search_pfx: PrefixId<AF>,
+        options: &MatchOptions,
+        ltime: u64,
+        guard: &'a Guard,
+    ) -> QueryResult<M> {
+        let mui = options.mui;
+        let records_as_of = |prefix_id: PrefixId<AF>| {
+            self.store
+                .non_recursive_retrieve_stored_prefix_with_guard(
+                    prefix_id,
+                    guard,
+                )
+                .0
+                .map(|sp| {
+                    sp.record_map.as_records_at(
+                        ltime,
+                        mui,
+                        options.include_withdrawn,
+                    )
+                })
+                .filter(|recs| !recs.is_empty())
+                .map(|recs| (prefix_id, recs))
+        };
+
+        let mut stored_prefix = records_as_of(search_pfx);
+
+        // The iterators below select the candidate prefixes on their current
+        // records, so they should not filter out any current statuses.
+
+        let match_type = match (&options.match_type, &stored_prefix) {
+            (_, Some(_)) => MatchType::ExactMatch,
+            (MatchType::LongestMatch | MatchType::EmptyMatch, None) => {
+                stored_prefix = self
+                    .store
+                    .less_specific_prefix_iter(search_pfx, mui, true, guard)
+                    .filter_map(|p| records_as_of(p.0))
+                    .max_by(|p0, p1| p0.0.get_len().cmp(&p1.0.get_len()));
+                if stored_prefix.is_some() {
+                    MatchType::LongestMatch
+                } else {
+                    MatchType::EmptyMatch
+                }
+            }
+            (MatchType::ExactMatch, None) => MatchType::EmptyMatch,
+        };
+
+        let start_pfx = stored_prefix.as_ref().map_or(search_pfx, |p| p.0);
+
+        QueryResult {
+            prefix: stored_prefix.as_ref().map(|p| p.0.into_pub()),
+            less_specifics: if options.include_less_specifics {
+                Some(
+                    self.store
+                        .less_specific_prefix_iter(start_pfx, mui, true, guard)
+                        .filter_map(|p| records_as_of(p.0))
+                        .collect(),
+                )
+            } else {
+                None
+            },
+            more_specifics: if options.include_more_specifics {
+                Some(
+                    self.store
+                        .more_specific_prefix_iter_from(
+                            start_pfx, mui, true, guard,
+                        )
+                        .filter_map(|p| records_as_of(p.0))
+                        .collect(),
+                )
+            } else {
+                None
+            },
+            prefix_meta: stored_prefix.map(|p| p.1).unwrap_or_default(),
+            match_type,
+        }
+    }
+
     // In a LMP search we have to go over all the nibble lengths in the
     // stride up until the value of the actual nibble length were looking for
     // (until we reach stride length for all strides that aren't the last)
@@ -800,13 +899,18 @@ where
     }
 
     // Helper to filter out records that are not-active (Inactive or
-    // Withdrawn), or whose mui appears in the global withdrawn index.
+    // Withdrawn), or whose mui appears in the global withdrawn index. Stale
+    // records are returned with the Stale status.
     fn get_filtered_records(&self, pfx: &StoredPrefix<AF, M>, mui: Option<u32>, guard: &Guard) -> Vec<PublicRecord<M>> {
         let bmin = unsafe { 
             self.store.withdrawn_muis_bmin.load(
                 Ordering::Acquire, guard).as_ref()
             }.unwrap();
-        
-        pfx.record_map.get_filtered_records(mui, bmin)
+        let stale_bmin = unsafe {
+            self.store.stale_muis_bmin.load(
+                Ordering::Acquire, guard).as_ref()
+            }.unwrap();
+
+        pfx.record_map.get_filtered_records(mui, bmin, stale_bmin)
     }
 }
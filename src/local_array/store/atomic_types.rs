@@ -2,22 +2,27 @@ use flurry::HashMap;
 use std::{
     fmt::{Debug, Display},
     mem::MaybeUninit,
-    sync::atomic::Ordering,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use crossbeam_epoch::{self as epoch, Atomic};
 
 use log::{debug, log_enabled, trace};
 
-use epoch::{Guard, Owned};
+use epoch::{Guard, Owned, Shared};
 use roaring::RoaringBitmap;
 
 use crate::local_array::tree::*;
 use crate::prefix_record::PublicRecord;
-use crate::prelude::Meta;
+use crate::prelude::{Meta, MultipathPolicy};
+use crate::HistoryRetention;
 use crate::AddressFamily;
 
 use super::errors::PrefixStoreError;
+use super::selector::{PathCandidate, PathSelector};
 
 // ----------- Node related structs -----------------------------------------
 
@@ -171,6 +176,10 @@ impl PathSelections {
     }
 }
 
+/// A predicate for the records that are eligible for path selection, that
+/// gets the multi_uniq_id and the meta-data of a record.
+pub type EligibleFn<'a, M> = &'a dyn Fn(u32, &M) -> bool;
+
 // ----------- StoredPrefix -------------------------------------------------
 // This is the top-level struct that's linked from the slots in the buckets.
 // It contains a super_agg_record that is supposed to hold counters for the
@@ -186,6 +195,20 @@ pub struct StoredPrefix<AF: AddressFamily, M: crate::prefix_record::Meta> {
     pub record_map: MultiMap<M>,
     // (mui of best path entry, mui of backup path entry) from the record_map
     path_selections: Atomic<PathSelections>,
+    // The muis of all the eligible paths, best first, if they were ranked.
+    ranked_paths: Atomic<RankedPaths>,
+    // Bumped by every change to the records, after the change. A ranking
+    // is only up to date if it was calculated in the current generation.
+    generation: AtomicUsize,
+    // The number of threads that are adding a record to this prefix, or a
+    // prefix to its next_bucket, see `AtomicStoredPrefix::register_writer`.
+    writers: AtomicUsize,
+    // The number of prefixes in the next_bucket.
+    children: AtomicUsize,
+    // Whether the prefix was removed from the store, i.e. its bit in the
+    // pfxbitarr was cleared and it isn't counted anymore, while it stays
+    // in its slot, because it has prefixes in its next_bucket.
+    retired: AtomicBool,
     // the reference to the next set of records for this prefix, if any.
     pub next_bucket: PrefixSet<AF, M>,
 }
@@ -228,6 +251,13 @@ impl<AF: AddressFamily, M: crate::prefix_record::Meta> StoredPrefix<AF, M> {
             path_selections: Atomic::init(PathSelections {
                 path_selection_muis: (None, None),
             }),
+            ranked_paths: Atomic::null(),
+            generation: AtomicUsize::new(0),
+            // A new prefix is registered for the writer that stores it in
+            // its slot, see `PrefixWriter::adopt`.
+            writers: AtomicUsize::new(1),
+            children: AtomicUsize::new(0),
+            retired: AtomicBool::new(false),
             record_map: MultiMap::new(rec_map),
             next_bucket,
         }
@@ -277,6 +307,13 @@ impl<AF: AddressFamily, M: crate::prefix_record::Meta> StoredPrefix<AF, M> {
             path_selections: Atomic::new(PathSelections {
                 path_selection_muis: (Some(mui), None),
             }),
+            ranked_paths: Atomic::null(),
+            generation: AtomicUsize::new(0),
+            // A new prefix is registered for the writer that stores it in
+            // its slot, see `PrefixWriter::adopt`.
+            writers: AtomicUsize::new(1),
+            children: AtomicUsize::new(0),
+            retired: AtomicBool::new(false),
             record_map: MultiMap::new(rec_map),
             next_bucket,
         }
@@ -286,6 +323,36 @@ impl<AF: AddressFamily, M: crate::prefix_record::Meta> StoredPrefix<AF, M> {
         self.prefix
     }
 
+    pub(crate) fn has_writers(&self) -> bool {
+        self.writers.load(Ordering::SeqCst) > 0
+    }
+
+    pub(crate) fn has_children(&self) -> bool {
+        self.children.load(Ordering::SeqCst) > 0
+    }
+
+    pub(crate) fn add_child(&self) {
+        self.children.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn remove_child(&self) {
+        self.children.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+
+    // Returns whether the prefix was in the store until now.
+    pub(crate) fn retire(&self) -> bool {
+        !self.retired.swap(true, Ordering::SeqCst)
+    }
+
+    // Returns whether the prefix was removed from the store until now.
+    pub(crate) fn revive(&self) -> bool {
+        self.retired.swap(false, Ordering::SeqCst)
+    }
+
     pub fn get_path_selections(
         &self,
         guard: &Guard,
@@ -326,10 +393,14 @@ impl<AF: AddressFamily, M: crate::prefix_record::Meta> StoredPrefix<AF, M> {
         Ok(())
     }
 
+    // Mark the path selections and the ranking as outdated. This should be
+    // called after the records were changed, not before, so that a ranking
+    // that was calculated from the old records can't pass as up to date.
     pub fn set_ps_outdated(
         &self,
         guard: &Guard,
     ) -> Result<(), PrefixStoreError> {
+        self.set_ranking_outdated();
         self.path_selections
             .fetch_update(Ordering::Acquire, Ordering::Acquire, guard, |p| {
                 Some(p.with_tag(1))
@@ -338,17 +409,33 @@ impl<AF: AddressFamily, M: crate::prefix_record::Meta> StoredPrefix<AF, M> {
             .map_err(|_| PrefixStoreError::StoreNotReadyError)
     }
 
+    // Mark only the ranking as outdated, for changes to the records that
+    // leave the path selections as they are.
+    pub(crate) fn set_ranking_outdated(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
     pub fn is_ps_outdated(&self, guard: &Guard) -> bool {
         self.path_selections.load(Ordering::Acquire, guard).tag() == 1
     }
 
-    pub fn calculate_and_store_best_backup<'a>(
+    // Calculate the best and backup path from the eligible records with
+    // `selector`, see `MultiMap::best_backup`, and store them.
+    pub(crate) fn calculate_and_store_eligible_best_backup<'a>(
         &'a self,
         tbi: &M::TBI,
+        withdrawn_muis_bmin: &RoaringBitmap,
+        selector: &dyn PathSelector<M>,
+        eligible: Option<EligibleFn<'_, M>>,
         guard: &'a Guard,
     ) -> Result<(Option<u32>, Option<u32>), super::errors::PrefixStoreError>
     {
-        let path_selection_muis = self.record_map.best_backup(*tbi);
+        let path_selection_muis = self.record_map.best_backup(
+            *tbi,
+            withdrawn_muis_bmin,
+            selector,
+            eligible,
+        );
 
         self.set_path_selections(
             PathSelections {
@@ -359,13 +446,194 @@ impl<AF: AddressFamily, M: crate::prefix_record::Meta> StoredPrefix<AF, M> {
 
         Ok(path_selection_muis)
     }
+
+    // Returns the muis of all the eligible paths, see
+    // `MultiMap::ranked_paths`, best first. The ranking is cached, together
+    // with the `tbi` and the generation it was calculated with, until the
+    // records change. A ranking for another `tbi` replaces the cached one. `withdrawn_muis_bmin` and `selector` are only used if the
+    // ranking has to be calculated, so the cache is meant for the path
+    // selector of the store only.
+    pub(crate) fn ranked_paths(
+        &self,
+        tbi: &M::TBI,
+        withdrawn_muis_bmin: &RoaringBitmap,
+        selector: &dyn PathSelector<M>,
+        guard: &Guard,
+    ) -> Vec<u32>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        // The generation is read before the records, so that a change to
+        // the records while we rank them leaves our ranking outdated.
+        let generation = self.generation.load(Ordering::SeqCst);
+        let current = self.ranked_paths.load(Ordering::Acquire, guard);
+        if let Some(ranked) = unsafe { current.as_ref() } {
+            if ranked.generation == generation
+                && ranked.tbi.downcast_ref::<M::TBI>() == Some(tbi)
+            {
+                return ranked.muis.clone();
+            }
+        }
+
+        let ranked =
+            self.record_map
+                .ranked_paths(*tbi, withdrawn_muis_bmin, selector);
+        // If another thread stored its ranking in the meantime, we don't
+        // store ours. If the records changed in the meantime, we do, but
+        // the next caller will find it in an old generation.
+        let stored = self.ranked_paths.compare_exchange(
+            current,
+            Owned::new(RankedPaths {
+                tbi: Box::new(*tbi),
+                generation,
+                muis: ranked.clone(),
+            }),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            guard,
+        );
+        if stored.is_ok() && !current.is_null() {
+            unsafe { guard.defer_destroy(current) };
+        }
+
+        ranked
+    }
+}
+
+// ----------- RankedPaths --------------------------------------------------
+// The cached ranking of the eligible paths for a prefix, with the
+// tiebreaker info and the generation of the records it was calculated
+// with. The tiebreaker info is boxed, so that the prefix stays Send and
+// Sync, whatever the type of it is.
+#[derive(Debug)]
+pub(crate) struct RankedPaths {
+    tbi: Box<dyn std::any::Any + Send + Sync>,
+    generation: usize,
+    muis: Vec<u32>,
+}
+
+// A StoredPrefix is only dropped if it never made it into a slot, or if it
+// was unlinked from its slot, without any prefixes in its next_bucket, and
+// all the threads that could see it are gone. In both cases nobody else
+// refers to what its atomics point to anymore.
+impl<AF: AddressFamily, M: Meta> Drop for StoredPrefix<AF, M> {
+    fn drop(&mut self) {
+        let guard = unsafe { epoch::unprotected() };
+        let path_selections =
+            self.path_selections.load(Ordering::Relaxed, guard);
+        if !path_selections.is_null() {
+            drop(unsafe { path_selections.into_owned() });
+        }
+        let ranked_paths = self.ranked_paths.load(Ordering::Relaxed, guard);
+        if !ranked_paths.is_null() {
+            drop(unsafe { ranked_paths.into_owned() });
+        }
+        let next_bucket = self.next_bucket.0.load(Ordering::Relaxed, guard);
+        if !next_bucket.is_null() {
+            drop(unsafe { next_bucket.into_owned() });
+        }
+    }
+}
+
+// ----------- PrefixWriter -------------------------------------------------
+// The registration of a thread as a writer for a StoredPrefix, see
+// `AtomicStoredPrefix::register_writer`. The registration ends when it is
+// dropped.
+pub(crate) struct PrefixWriter<'a, AF: AddressFamily, M: Meta>(
+    &'a StoredPrefix<AF, M>,
+);
+
+impl<'a, AF: AddressFamily, M: Meta> PrefixWriter<'a, AF, M> {
+    // Take over the registration that a new prefix is created with, after
+    // it was stored in its slot.
+    pub(crate) fn adopt(stored_prefix: &'a StoredPrefix<AF, M>) -> Self {
+        PrefixWriter(stored_prefix)
+    }
+}
+
+impl<'a, AF: AddressFamily, M: Meta> Drop for PrefixWriter<'a, AF, M> {
+    fn drop(&mut self) {
+        self.0.writers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// ----------- StoredPrefixRef ----------------------------------------------
+// A StoredPrefix as it is handed out by the store, together with the
+// global withdrawn muis and the path selector of the store, so that path
+// selections on it leave out the records of those muis, and rank the
+// records like the store does. It derefs to the StoredPrefix for
+// everything else.
+pub struct StoredPrefixRef<'a, AF: AddressFamily, M: Meta> {
+    stored_prefix: &'a StoredPrefix<AF, M>,
+    withdrawn_muis_bmin: &'a RoaringBitmap,
+    selector: &'a dyn PathSelector<M>,
+}
+
+impl<'a, AF: AddressFamily, M: Meta> StoredPrefixRef<'a, AF, M> {
+    pub(crate) fn new(
+        stored_prefix: &'a StoredPrefix<AF, M>,
+        withdrawn_muis_bmin: &'a RoaringBitmap,
+        selector: &'a dyn PathSelector<M>,
+    ) -> Self {
+        Self {
+            stored_prefix,
+            withdrawn_muis_bmin,
+            selector,
+        }
+    }
+
+    // Calculate the best and backup path from the records that the path
+    // selector of the store finds eligible, with the status of the muis
+    // that are withdrawn globally rewritten to Withdrawn, and store them.
+    pub fn calculate_and_store_best_backup(
+        &self,
+        tbi: &M::TBI,
+        guard: &Guard,
+    ) -> Result<(Option<u32>, Option<u32>), super::errors::PrefixStoreError>
+    {
+        self.stored_prefix.calculate_and_store_eligible_best_backup(
+            tbi,
+            self.withdrawn_muis_bmin,
+            self.selector,
+            None,
+            guard,
+        )
+    }
+}
+
+// Deriving this would require the path selector to be Debug.
+impl<'a, AF: AddressFamily, M: Meta> std::fmt::Debug
+    for StoredPrefixRef<'a, AF, M>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("StoredPrefixRef")
+            .field("stored_prefix", self.stored_prefix)
+            .field("withdrawn_muis_bmin", self.withdrawn_muis_bmin)
+            .finish()
+    }
+}
+
+impl<'a, AF: AddressFamily, M: Meta> std::ops::Deref
+    for StoredPrefixRef<'a, AF, M>
+{
+    type Target = StoredPrefix<AF, M>;
+
+    fn deref(&self) -> &Self::Target {
+        self.stored_prefix
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RouteStatus {
     Active,
     InActive,
     Withdrawn,
+    // The record was learned from a mui that was marked as stale, e.g.
+    // during a BGP Graceful Restart, and it hasn't been refreshed since.
+    // Records are not stored with this status, queries report it for the
+    // Active records of a stale mui, until these are swept.
+    Stale,
 }
 
 impl std::fmt::Display for RouteStatus {
@@ -374,6 +642,7 @@ impl std::fmt::Display for RouteStatus {
             RouteStatus::Active => write!(f, "active"),
             RouteStatus::InActive => write!(f, "inactive"),
             RouteStatus::Withdrawn => write!(f, "withdrawn"),
+            RouteStatus::Stale => write!(f, "stale"),
         }
     }
 }
@@ -383,6 +652,12 @@ pub(crate) struct MultiMapValue<M> {
     pub meta: M,
     pub ltime: u64,
     pub status: RouteStatus,
+    // Whether this record was inserted while its mui was marked as stale,
+    // i.e. whether it survives the next sweep of stale records for the mui.
+    pub refreshed: bool,
+    // The records that were replaced by this one, most recent first. Only
+    // kept if the store has a HistoryRetention other than Off.
+    pub history: RecordHistory<M>,
 }
 
 impl<M: Clone> MultiMapValue<M> {
@@ -391,8 +666,22 @@ impl<M: Clone> MultiMapValue<M> {
             meta,
             ltime,
             status,
+            refreshed: false,
+            history: RecordHistory::empty(),
         }
     }
+
+    // Whether this record, for `mui`, is stale, i.e. it is Active, its mui
+    // appears in the stale muis index, and it was not refreshed since.
+    pub(crate) fn is_stale(
+        &self,
+        mui: u32,
+        stale_muis_bmin: &RoaringBitmap,
+    ) -> bool {
+        self.status == RouteStatus::Active
+            && !self.refreshed
+            && stale_muis_bmin.contains(mui)
+    }
 }
 
 impl<M: crate::prefix_record::Meta> std::fmt::Display for MultiMapValue<M> {
@@ -407,6 +696,67 @@ impl<M: Meta> From<PublicRecord<M>> for MultiMapValue<M> {
             meta: value.meta,
             ltime: value.ltime,
             status: value.status,
+            refreshed: false,
+            history: RecordHistory::empty(),
+        }
+    }
+}
+
+// ----------- RecordHistory ------------------------------------------------
+// The records that were replaced by a record, most recent first. The
+// record map clones a record on every update of it, so the history is
+// shared between the clones, instead of copied. An empty history doesn't
+// allocate.
+
+#[derive(Debug)]
+pub(crate) struct RecordHistory<M>(Option<Arc<[PublicRecord<M>]>>);
+
+impl<M> RecordHistory<M> {
+    pub(crate) fn empty() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.as_ref().map_or(0, |h| h.len())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn iter(&self) -> std::slice::Iter<'_, PublicRecord<M>> {
+        self.0.as_deref().unwrap_or(&[]).iter()
+    }
+}
+
+impl<M: Meta> RecordHistory<M> {
+    // The history for a record with `ltime`, that replaces `replaced`,
+    // with `history` as its history, as far as `retention` allows.
+    pub(crate) fn with_replaced(
+        replaced: PublicRecord<M>,
+        history: &RecordHistory<M>,
+        ltime: u64,
+        retention: HistoryRetention,
+    ) -> Self {
+        let mut new = std::iter::once(replaced)
+            .chain(history.iter().cloned())
+            .collect::<Vec<_>>();
+        retention.apply(ltime, &mut new);
+        Self::from(new)
+    }
+}
+
+impl<M> Clone for RecordHistory<M> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<M> From<Vec<PublicRecord<M>>> for RecordHistory<M> {
+    fn from(history: Vec<PublicRecord<M>>) -> Self {
+        match history.is_empty() {
+            true => Self(None),
+            false => Self(Some(history.into())),
         }
     }
 }
@@ -433,28 +783,161 @@ impl<M: Send + Sync + Debug + Display + Meta> MultiMap<M> {
         self.0.guard()
     }
 
+    // Returns the record for this mui as a PublicRecord, with the Stale
+    // status if it is stale according to `stale_muis_bmin`.
+    fn public_record(
+        mui: u32,
+        rec: &MultiMapValue<M>,
+        stale_muis_bmin: &RoaringBitmap,
+    ) -> PublicRecord<M> {
+        let mut pub_rec = PublicRecord::from((mui, rec));
+        if rec.is_stale(mui, stale_muis_bmin) {
+            pub_rec.status = RouteStatus::Stale;
+        }
+        pub_rec
+    }
+
     pub fn get_record_for_active_mui(
         &self,
         mui: u32,
     ) -> Option<PublicRecord<M>> {
         self.0.get(&mui, &self.0.guard()).and_then(|r| {
             if r.status == RouteStatus::Active {
-                Some(PublicRecord::from((mui, r.clone())))
+                Some(PublicRecord::from((mui, r)))
+            } else {
+                None
+            }
+        })
+    }
+
+    // Like `get_record_for_active_mui`, but the record is returned with the
+    // Stale status if it is stale according to `stale_muis_bmin`.
+    pub(crate) fn get_record_for_active_mui_with_stale_status(
+        &self,
+        mui: u32,
+        stale_muis_bmin: &RoaringBitmap,
+    ) -> Option<PublicRecord<M>> {
+        self.0.get(&mui, &self.0.guard()).and_then(|r| {
+            if r.status == RouteStatus::Active {
+                Some(Self::public_record(mui, r, stale_muis_bmin))
             } else {
                 None
             }
         })
     }
 
-    pub fn best_backup(&self, tbi: M::TBI) -> (Option<u32>, Option<u32>) {
+    // Returns the records that take part in the path selection, as
+    // candidates for `selector`. The status of the records for muis in
+    // `withdrawn_muis_bmin` is rewritten to Withdrawn. A record takes part
+    // if `selector` finds it eligible, and `eligible`, if given, returns
+    // true for its mui and meta-data.
+    fn path_candidates<'g>(
+        &'g self,
+        flurry_guard: &'g flurry::Guard<'_>,
+        withdrawn_muis_bmin: &RoaringBitmap,
+        selector: &dyn PathSelector<M>,
+        eligible: Option<EligibleFn<'_, M>>,
+    ) -> Vec<PathCandidate<'g, M>> {
+        self.0
+            .iter(flurry_guard)
+            .map(|(mui, rec)| PathCandidate {
+                mui: *mui,
+                ltime: rec.ltime,
+                status: if withdrawn_muis_bmin.contains(*mui) {
+                    RouteStatus::Withdrawn
+                } else {
+                    rec.status
+                },
+                meta: &rec.meta,
+            })
+            .filter(|c| {
+                selector.is_eligible(c)
+                    && eligible.map_or(true, |eligible| eligible(c.mui, c.meta))
+            })
+            .collect()
+    }
+
+    // Select the best and backup path from the eligible records with
+    // `selector`. A record is eligible if `selector` finds it eligible
+    // (for the default selector, if it has the Active status), with the
+    // status of muis in `withdrawn_muis_bmin` rewritten to Withdrawn, and
+    // `eligible`, if given, returns true for its mui and meta-data.
+    pub fn best_backup(
+        &self,
+        tbi: M::TBI,
+        withdrawn_muis_bmin: &RoaringBitmap,
+        selector: &dyn PathSelector<M>,
+        eligible: Option<EligibleFn<'_, M>>,
+    ) -> (Option<u32>, Option<u32>) {
+        let flurry_guard = self.guard();
+        let candidates = self.path_candidates(
+            &flurry_guard,
+            withdrawn_muis_bmin,
+            selector,
+            eligible,
+        );
+        selector.best_backup(&candidates, tbi)
+    }
+
+    // Rank the eligible records with `selector`, best first, and return
+    // their muis. The eligible records are the same as for `best_backup`,
+    // without an `eligible` predicate.
+    pub fn ranked_paths(
+        &self,
+        tbi: M::TBI,
+        withdrawn_muis_bmin: &RoaringBitmap,
+        selector: &dyn PathSelector<M>,
+    ) -> Vec<u32> {
         let flurry_guard = self.guard();
-        let ord_routes = self
-            .0
-            .iter(&flurry_guard)
-            .map(|r| (r.1.meta.as_orderable(tbi), r.0));
-        let (best, bckup) =
-            routecore::bgp::path_selection::best_backup_generic(ord_routes);
-        (best.map(|b| *b.1), bckup.map(|b| *b.1))
+        let mut candidates = self.path_candidates(
+            &flurry_guard,
+            withdrawn_muis_bmin,
+            selector,
+            None,
+        );
+        candidates.sort_by(|a, b| selector.compare(a, b, tbi));
+        candidates.into_iter().map(|c| c.mui).collect()
+    }
+
+    // Returns the muis from `ranked_muis`, a ranking of the paths like
+    // `ranked_paths` returns, that `selector` finds equivalent to the
+    // first, i.e. the best, path under `policy`, in the same order. The
+    // candidates get the same status as for the ranking. The best path
+    // itself is always included.
+    pub fn multipath(
+        &self,
+        ranked_muis: &[u32],
+        tbi: M::TBI,
+        withdrawn_muis_bmin: &RoaringBitmap,
+        selector: &dyn PathSelector<M>,
+        policy: MultipathPolicy,
+    ) -> Vec<u32> {
+        let record_map = self.0.pin();
+        let candidate = |mui: u32| {
+            record_map.get(&mui).map(|rec| PathCandidate {
+                mui,
+                ltime: rec.ltime,
+                status: if withdrawn_muis_bmin.contains(mui) {
+                    RouteStatus::Withdrawn
+                } else {
+                    rec.status
+                },
+                meta: &rec.meta,
+            })
+        };
+        let best = match ranked_muis.first().and_then(|m| candidate(*m)) {
+            Some(best) => best,
+            None => return vec![],
+        };
+        ranked_muis
+            .iter()
+            .filter(|mui| {
+                candidate(**mui).is_some_and(|c| {
+                    selector.is_multipath_equivalent(&c, &best, tbi, policy)
+                })
+            })
+            .copied()
+            .collect()
     }
 
     pub(crate) fn get_record_for_mui_with_rewritten_status(
@@ -462,27 +945,36 @@ impl<M: Send + Sync + Debug + Display + Meta> MultiMap<M> {
         mui: u32,
         bmin: &RoaringBitmap,
         rewrite_status: RouteStatus,
+        stale_muis_bmin: &RoaringBitmap,
     ) -> Option<PublicRecord<M>> {
         self.0.get(&mui, &self.0.guard()).map(|r| {
-            let mut r = r.clone();
+            let mut r = Self::public_record(mui, r, stale_muis_bmin);
             if bmin.contains(mui) {
                 r.status = rewrite_status;
             }
-            PublicRecord::from((mui, r))
+            r
         })
     }
 
     // Helper to filter out records that are not-active (Inactive or
-    // Withdrawn), or whose mui appears in the global withdrawn index.
+    // Withdrawn), or whose mui appears in the global withdrawn index. The
+    // records that are stale according to `stale_muis_bmin` are returned
+    // with the Stale status.
     pub fn get_filtered_records(
         &self,
         mui: Option<u32>,
         bmin: &RoaringBitmap,
+        stale_muis_bmin: &RoaringBitmap,
     ) -> Vec<PublicRecord<M>> {
         if let Some(mui) = mui {
-            self.get_record_for_active_mui(mui).into_iter().collect()
+            self.get_record_for_active_mui_with_stale_status(
+                mui,
+                stale_muis_bmin,
+            )
+            .into_iter()
+            .collect()
         } else {
-            self.as_active_records_not_in_bmin(bmin)
+            self.as_active_records_not_in_bmin(bmin, stale_muis_bmin)
         }
     }
 
@@ -492,27 +984,29 @@ impl<M: Send + Sync + Debug + Display + Meta> MultiMap<M> {
     ) -> impl Iterator<Item = PublicRecord<M>> + 'a {
         self.0
             .iter(guard)
-            .map(|r| PublicRecord::from((*r.0, r.1.clone())))
+            .map(|r| PublicRecord::from((*r.0, r.1)))
     }
 
     // return all records regardless of their local status, or any globally
     // set status for the mui of the record. However, the local status for a
     // record whose mui appears in the specified bitmap index, will be
-    // rewritten with the specified RouteStatus.
+    // rewritten with the specified RouteStatus, and otherwise to Stale for
+    // a record that is stale according to `stale_muis_bmin`.
     pub fn as_records_with_rewritten_status(
         &self,
         bmin: &RoaringBitmap,
         rewrite_status: RouteStatus,
+        stale_muis_bmin: &RoaringBitmap,
     ) -> Vec<PublicRecord<M>> {
         self.0
             .pin()
             .into_iter()
             .map(move |r| {
-                let mut rec = r.1.clone();
+                let mut rec = Self::public_record(*r.0, r.1, stale_muis_bmin);
                 if bmin.contains(*r.0) {
                     rec.status = rewrite_status;
                 }
-                PublicRecord::from((*r.0, rec))
+                rec
             })
             .collect::<Vec<_>>()
     }
@@ -521,23 +1015,25 @@ impl<M: Send + Sync + Debug + Display + Meta> MultiMap<M> {
         self.0
             .pin()
             .iter()
-            .map(|r| PublicRecord::from((*r.0, r.1.clone())))
+            .map(|r| PublicRecord::from((*r.0, r.1)))
             .collect::<Vec<_>>()
     }
 
     // Returns a vec of records whose keys are not in the supplied bitmap
     // index, and whose local Status is set to Active. Used to filter out
-    // withdrawn routes.
+    // withdrawn routes. The records that are stale according to
+    // `stale_muis_bmin` are returned with the Stale status.
     pub fn as_active_records_not_in_bmin(
         &self,
         bmin: &RoaringBitmap,
+        stale_muis_bmin: &RoaringBitmap,
     ) -> Vec<PublicRecord<M>> {
         self.0
             .pin()
             .iter()
             .filter_map(|r| {
                 if r.1.status == RouteStatus::Active && !bmin.contains(*r.0) {
-                    Some(PublicRecord::from((*r.0, r.1.clone())))
+                    Some(Self::public_record(*r.0, r.1, stale_muis_bmin))
                 } else {
                     None
                 }
@@ -554,6 +1050,37 @@ impl<M: Send + Sync + Debug + Display + Meta> MultiMap<M> {
         }
     }
 
+    // Change the local status of the record for this mui to Withdrawn, and
+    // its ltime to `ltime`, in one atomic operation. The record as it was
+    // before is kept in the history, like `upsert_record` does. Returns
+    // whether there was a record for this mui.
+    pub fn withdraw_for_mui(
+        &self,
+        mui: u32,
+        ltime: u64,
+        retention: HistoryRetention,
+    ) -> bool {
+        self.0
+            .pin()
+            .compute_if_present(&mui, |_, old| {
+                let mut rec = MultiMapValue {
+                    status: RouteStatus::Withdrawn,
+                    ltime,
+                    ..old.clone()
+                };
+                if retention != HistoryRetention::Off {
+                    rec.history = RecordHistory::with_replaced(
+                        PublicRecord::from((mui, old)),
+                        &old.history,
+                        rec.ltime,
+                        retention,
+                    );
+                }
+                Some(rec)
+            })
+            .is_some()
+    }
+
     // Change the local status of the record for this mui to Active.
     pub fn mark_as_active_for_mui(&self, mui: u32) {
         let record_map = self.0.pin();
@@ -563,15 +1090,312 @@ impl<M: Send + Sync + Debug + Display + Meta> MultiMap<M> {
         }
     }
 
-    // Insert or replace the PublicRecord in the HashMap for the key of
-    // record.multi_uniq_id. Returns the number of entries in the HashMap
-    // after updating it.
-    pub fn upsert_record(&self, record: PublicRecord<M>) -> Option<usize> {
+    // Remove the record for this mui, or change its local status to
+    // Withdrawn (if `remove` is false), if its ltime is older than the
+    // specified ltime. The check and the eviction happen in one atomic
+    // operation, so a record that was concurrently updated with a newer
+    // ltime survives. Returns whether the record was evicted.
+    pub fn evict_record_for_mui_older_than(
+        &self,
+        mui: u32,
+        ltime: u64,
+        remove: bool,
+    ) -> bool {
         let record_map = self.0.pin();
-        let mui_new = record_map
-            .insert(record.multi_uniq_id, MultiMapValue::from(record));
-        mui_new.map(|_| self.len())
+        match record_map.get(&mui) {
+            Some(rec) if rec.ltime < ltime => {}
+            _ => return false,
+        };
+
+        let mut evicted = false;
+        record_map.compute_if_present(&mui, |_, rec| {
+            if rec.ltime >= ltime
+                || (!remove && rec.status == RouteStatus::Withdrawn)
+            {
+                return Some(rec.clone());
+            }
+            evicted = true;
+            if remove {
+                None
+            } else {
+                Some(MultiMapValue {
+                    status: RouteStatus::Withdrawn,
+                    ..rec.clone()
+                })
+            }
+        });
+
+        evicted
+    }
+
+    // Remove the record for this mui from the HashMap. Returns the removed
+    // record, if there was any.
+    pub fn remove_record_for_mui(&self, mui: u32) -> Option<PublicRecord<M>> {
+        let record_map = self.0.pin();
+        record_map
+            .remove(&mui)
+            .map(|r| PublicRecord::from((mui, r.clone())))
+    }
+
+    // Remove all the records from the HashMap. Returns the removed records.
+    // Records that are inserted concurrently by other threads while this
+    // method runs, may or may not be part of the returned records.
+    pub fn remove_all_records(&self) -> Vec<PublicRecord<M>> {
+        let record_map = self.0.pin();
+        let muis = record_map.keys().copied().collect::<Vec<_>>();
+        muis.into_iter()
+            .filter_map(|mui| {
+                record_map
+                    .remove(&mui)
+                    .map(|r| PublicRecord::from((mui, r.clone())))
+            })
+            .collect::<Vec<_>>()
+    }
+
+    // Returns whether there is a record for this mui, whatever its status.
+    pub(crate) fn contains_mui(&self, mui: u32) -> bool {
+        self.0.pin().contains_key(&mui)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Remove the record for this mui, or change its local status to
+    // Withdrawn (if `remove` is false), if it was not refreshed while its mui
+    // was marked as stale. The refreshed flag of a surviving record is
+    // reset, so that it can go stale again. Returns whether the record was
+    // swept.
+    pub fn sweep_stale_record_for_mui(&self, mui: u32, remove: bool) -> bool {
+        let record_map = self.0.pin();
+        let mut swept = false;
+        record_map.compute_if_present(&mui, |_, rec| {
+            if rec.refreshed {
+                return Some(MultiMapValue {
+                    refreshed: false,
+                    ..rec.clone()
+                });
+            }
+            if !remove && rec.status == RouteStatus::Withdrawn {
+                return Some(rec.clone());
+            }
+            swept = true;
+            if remove {
+                None
+            } else {
+                Some(MultiMapValue {
+                    status: RouteStatus::Withdrawn,
+                    ..rec.clone()
+                })
+            }
+        });
+
+        swept
     }
+
+    // Returns the records as they were at `ltime`, i.e. for each mui the
+    // most recent version of its record with an ltime that is not greater
+    // than `ltime`, taken from the current record or its history. Muis that
+    // did not have a record (in the retained history) at `ltime` are left
+    // out, as are, unless `include_withdrawn` is set, records with a local
+    // status other than Active. The records can be restricted to a mui.
+    // Status changes that were made in place, without an ltime, show up in
+    // the current record for every `ltime` at or after its own ltime.
+    pub fn as_records_at(
+        &self,
+        ltime: u64,
+        mui: Option<u32>,
+        include_withdrawn: bool,
+    ) -> Vec<PublicRecord<M>> {
+        let record_map = self.0.pin();
+        record_map
+            .iter()
+            .filter(|r| mui.map_or(true, |mui| mui == *r.0))
+            .filter_map(|r| {
+                if r.1.ltime <= ltime {
+                    Some(PublicRecord::from((*r.0, r.1)))
+                } else {
+                    r.1.history.iter().find(|h| h.ltime <= ltime).cloned()
+                }
+            })
+            .filter(|r| include_withdrawn || r.status == RouteStatus::Active)
+            .collect::<Vec<_>>()
+    }
+
+    // Returns the record for this mui, followed by the records it replaced,
+    // most recent first.
+    pub fn get_history_for_mui(&self, mui: u32) -> Vec<PublicRecord<M>> {
+        self.0
+            .get(&mui, &self.0.guard())
+            .map(|r| {
+                std::iter::once(PublicRecord::from((mui, r)))
+                    .chain(r.history.iter().cloned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    }
+
+    // Insert or replace the record in the HashMap for the key of its mui.
+    // `refreshed` should be set if the mui of the record is currently
+    // marked as stale. A replaced record is kept in the history of the new
+    // record, as far as `retention` allows. With UpsertMode::IfNewer a
+    // record with an ltime older than the ltime of the record it would
+    // replace is dropped instead. The meta-data of a NewRecord::Merge is
+    // created from the meta-data of the record it replaces, or from None
+    // if there is none.
+    pub(crate) fn upsert_record(
+        &self,
+        record: NewRecord<M>,
+        refreshed: bool,
+        retention: HistoryRetention,
+        mode: UpsertMode,
+    ) -> RecordUpsert {
+        let record_map = self.0.pin();
+        let mui = record.mui();
+        let record = match record {
+            NewRecord::Record(record)
+                if retention == HistoryRetention::Off
+                    && mode == UpsertMode::Replace =>
+            {
+                let new_rec = MultiMapValue {
+                    refreshed,
+                    ..MultiMapValue::from(record)
+                };
+                return match record_map.insert(mui, new_rec) {
+                    None => RecordUpsert::Inserted,
+                    Some(_) => RecordUpsert::Replaced(self.len()),
+                };
+            }
+            record => record,
+        };
+        let ltime = record.ltime();
+        let new_rec = |old: Option<&M>| match &record {
+            NewRecord::Record(record) => MultiMapValue {
+                refreshed,
+                ..MultiMapValue::from(record.clone())
+            },
+            NewRecord::Merge { ltime, merge, .. } => MultiMapValue {
+                meta: merge(old),
+                ltime: *ltime,
+                status: RouteStatus::Active,
+                refreshed,
+                history: RecordHistory::empty(),
+            },
+        };
+
+        // The history is built from, and the ltime is compared with, the
+        // record that is actually replaced, so this has to happen in one
+        // atomic operation. If there's no record to replace, we try to
+        // insert our record, and if another thread beats us to it, we try
+        // to replace theirs.
+        loop {
+            let rejected = std::cell::Cell::new(false);
+            if record_map
+                .compute_if_present(&mui, |_, old| {
+                    if mode == UpsertMode::IfNewer && ltime < old.ltime {
+                        rejected.set(true);
+                        return Some(old.clone());
+                    }
+                    let mut rec = new_rec(Some(&old.meta));
+                    if retention != HistoryRetention::Off {
+                        rec.history = RecordHistory::with_replaced(
+                            PublicRecord::from((mui, old)),
+                            &old.history,
+                            rec.ltime,
+                            retention,
+                        );
+                    }
+                    Some(rec)
+                })
+                .is_some()
+            {
+                return match rejected.get() {
+                    true => RecordUpsert::Rejected(self.len()),
+                    false => RecordUpsert::Replaced(self.len()),
+                };
+            }
+            if record_map.try_insert(mui, new_rec(None)).is_ok() {
+                return RecordUpsert::Inserted;
+            }
+        }
+    }
+
+    // Returns the record for `mui`, whatever its status is.
+    pub(crate) fn get_record_for_mui(
+        &self,
+        mui: u32,
+    ) -> Option<PublicRecord<M>> {
+        self.0
+            .get(&mui, &self.0.guard())
+            .map(|r| PublicRecord::from((mui, r)))
+    }
+}
+
+// ----------- UpsertMode ---------------------------------------------------
+
+// How an upsert treats an existing record for the same (prefix, mui).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum UpsertMode {
+    // Always replace the existing record.
+    Replace,
+    // Only replace the existing record if the new record has the same or
+    // a newer ltime.
+    IfNewer,
+}
+
+// ----------- NewRecord ----------------------------------------------------
+
+// The record that an upsert stores for a (prefix, mui).
+pub(crate) enum NewRecord<'a, M> {
+    // A complete record.
+    Record(PublicRecord<M>),
+    // A record with the Active status, and the meta-data that the closure
+    // creates from the meta-data of the record it replaces, or from None
+    // if there is none. The closure is called inside the atomic operation
+    // that replaces the record, so it sees the record that is actually
+    // replaced.
+    Merge {
+        mui: u32,
+        ltime: u64,
+        merge: &'a dyn Fn(Option<&M>) -> M,
+    },
+}
+
+impl<'a, M> NewRecord<'a, M> {
+    pub(crate) fn mui(&self) -> u32 {
+        match self {
+            NewRecord::Record(record) => record.multi_uniq_id,
+            NewRecord::Merge { mui, .. } => *mui,
+        }
+    }
+
+    pub(crate) fn ltime(&self) -> u64 {
+        match self {
+            NewRecord::Record(record) => record.ltime,
+            NewRecord::Merge { ltime, .. } => *ltime,
+        }
+    }
+}
+
+impl<'a, M> From<PublicRecord<M>> for NewRecord<'a, M> {
+    fn from(record: PublicRecord<M>) -> Self {
+        NewRecord::Record(record)
+    }
+}
+
+// ----------- RecordUpsert -------------------------------------------------
+
+// What an upsert did with a record.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum RecordUpsert {
+    // The record was inserted for a mui that didn't have one.
+    Inserted,
+    // The record replaced the record for its mui. Holds the number of
+    // records after the upsert.
+    Replaced(usize),
+    // The record was dropped, because the record for its mui is newer.
+    // Holds the number of records.
+    Rejected(usize),
 }
 
 // ----------- AtomicStoredPrefix -------------------------------------------
@@ -608,26 +1432,41 @@ impl<AF: AddressFamily, Meta: crate::prefix_record::Meta>
         }
     }
 
-    pub(crate) fn _get_stored_prefix_with_tag<'a>(
-        &'a self,
+    // Register as a writer for `current`, the stored prefix that was loaded
+    // from this slot, before adding a record to it, or a prefix to its
+    // next_bucket. A registered prefix is not unlinked from its slot, see
+    // `CustomAllocStorage::remove_empty_prefix`.
+    //
+    // Returns None if the slot doesn't hold `current` anymore, or if it's
+    // marked (with tag 1) for removal. The caller should look up the slot
+    // again in that case. The remover marks the slot before it looks at the
+    // writers, and a writer registers before it looks at the slot, so
+    // either the remover sees the writer, or the writer sees the mark.
+    pub(crate) fn register_writer<'a>(
+        &self,
+        current: Shared<'a, StoredPrefix<AF, Meta>>,
         guard: &'a Guard,
-    ) -> Option<(&'a StoredPrefix<AF, Meta>, usize)> {
-        let pfx = self.0.load(Ordering::Acquire, guard);
-        match pfx.is_null() {
-            true => None,
-            false => Some((unsafe { pfx.deref() }, pfx.tag())),
+    ) -> Option<PrefixWriter<'a, AF, Meta>> {
+        if current.is_null() || current.tag() == 1 {
+            return None;
+        }
+        let stored_prefix = unsafe { current.deref() };
+        stored_prefix.writers.fetch_add(1, Ordering::SeqCst);
+        let writer = PrefixWriter(stored_prefix);
+        if self.0.load(Ordering::SeqCst, guard) != current {
+            return None;
         }
+        Some(writer)
     }
 
-    pub(crate) fn get_stored_prefix_mut<'a>(
+    pub(crate) fn _get_stored_prefix_with_tag<'a>(
         &'a self,
         guard: &'a Guard,
-    ) -> Option<&'a StoredPrefix<AF, Meta>> {
-        let mut pfx = self.0.load(Ordering::SeqCst, guard);
-
+    ) -> Option<(&'a StoredPrefix<AF, Meta>, usize)> {
+        let pfx = self.0.load(Ordering::Acquire, guard);
         match pfx.is_null() {
             true => None,
-            false => Some(unsafe { pfx.deref_mut() }),
+            false => Some((unsafe { pfx.deref() }, pfx.tag())),
         }
     }
 
@@ -654,7 +1493,7 @@ impl<AF: AddressFamily, Meta: crate::prefix_record::Meta>
     pub(crate) fn get_next_bucket<'a>(
         &'a self,
         guard: &'a Guard,
-    ) -> Option<&PrefixSet<AF, Meta>> {
+    ) -> Option<&'a PrefixSet<AF, Meta>> {
         // let guard = &epoch::pin();
         if let Some(stored_prefix) = self.get_stored_prefix(guard) {
             // if stored_prefix.super_agg_record.is_some() {
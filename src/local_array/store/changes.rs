@@ -0,0 +1,160 @@
+// ----------- Change log ----------------------------------------------------
+//
+// A change log is a bounded, in-memory log of the changes to the records in
+// a store. It is shared by the trees for both address families, so that
+// its sequence numbers are increasing over the whole store. Entries are
+// appended after the change was applied to the store, so a consumer that
+// reads an entry will find the change (or a later one) in the store. Once
+// the log is full, the oldest entries are dropped, and consumers with a
+// cursor that points to a dropped entry have to do a full resync.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use inetnum::addr::Prefix;
+
+use super::errors::PrefixStoreError;
+
+//------------ ChangeKind ----------------------------------------------------
+
+/// The kind of change to the store
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChangeKind {
+    /// A record was inserted for a (prefix, mui) that didn't have one
+    Inserted,
+    /// The record for a (prefix, mui) was replaced
+    Updated,
+    /// The local status of the record for a (prefix, mui) was set to
+    /// Withdrawn
+    Withdrawn,
+    /// The local status of the record for a (prefix, mui) was set to
+    /// Active
+    Activated,
+    /// The record for a (prefix, mui) was removed from the store
+    Removed,
+    /// The mui was marked as withdrawn globally, for an address family
+    MuiWithdrawn,
+    /// The mui was marked as active globally, for an address family
+    MuiActivated,
+    /// The mui was marked as stale, for an address family
+    MuiStale,
+}
+
+//------------ Change --------------------------------------------------------
+
+/// An entry in the change log of a store
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    /// The sequence number of the change
+    pub seq: u64,
+    /// The prefix that changed, or `None` for the changes to the global
+    /// status of a mui
+    pub prefix: Option<Prefix>,
+    /// The multi_uniq_id that changed
+    pub mui: u32,
+    /// What happened
+    pub kind: ChangeKind,
+}
+
+//------------ ChangeCursor --------------------------------------------------
+
+/// A position in the change log of a store
+///
+/// A cursor points to the next change that will be read with it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChangeCursor {
+    next_seq: u64,
+}
+
+impl ChangeCursor {
+    /// The sequence number of the next change that will be read with this
+    /// cursor.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+}
+
+//------------ ChangeLog -----------------------------------------------------
+
+#[derive(Debug)]
+pub(crate) struct ChangeLog {
+    capacity: usize,
+    inner: Mutex<ChangeLogInner>,
+}
+
+#[derive(Debug)]
+struct ChangeLogInner {
+    entries: VecDeque<Change>,
+    // The sequence number for the next change.
+    next_seq: u64,
+}
+
+impl ChangeLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            inner: Mutex::new(ChangeLogInner {
+                entries: VecDeque::with_capacity(capacity),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    // Append a change, dropping the oldest one if the log is full. This
+    // takes the lock on the log for every change to the store, so writers
+    // are serialized here. Handing out the sequence numbers and keeping the
+    // entries in their order is what the lock is for.
+    pub(crate) fn push(
+        &self,
+        prefix: Option<Prefix>,
+        mui: u32,
+        kind: ChangeKind,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() == self.capacity {
+            inner.entries.pop_front();
+        }
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.entries.push_back(Change {
+            seq,
+            prefix,
+            mui,
+            kind,
+        });
+    }
+
+    // A cursor that points to the next change that will be appended.
+    pub(crate) fn cursor(&self) -> ChangeCursor {
+        ChangeCursor {
+            next_seq: self.inner.lock().unwrap().next_seq,
+        }
+    }
+
+    // Read up to `max` changes from `cursor` onwards, and move the cursor
+    // past them. Returns a ChangeLogOverrun error, and leaves the cursor
+    // alone, if the change the cursor points to was dropped already.
+    pub(crate) fn read(
+        &self,
+        cursor: &mut ChangeCursor,
+        max: usize,
+    ) -> Result<Vec<Change>, PrefixStoreError> {
+        let inner = self.inner.lock().unwrap();
+        let oldest = inner.next_seq - inner.entries.len() as u64;
+        if cursor.next_seq < oldest {
+            return Err(PrefixStoreError::ChangeLogOverrun);
+        }
+        let changes = inner
+            .entries
+            .iter()
+            .skip((cursor.next_seq - oldest) as usize)
+            .take(max)
+            .cloned()
+            .collect::<Vec<_>>();
+        if let Some(last) = changes.last() {
+            cursor.next_seq = last.seq + 1;
+        }
+        Ok(changes)
+    }
+}
@@ -41,11 +41,12 @@
 // meta-data, through use of the `MergeUpdate` trait.
 //
 // The `upsert_prefix` methods retrieve only the most recent insert
-// for a prefix (for now).
+// for a prefix.
 //
-// Future work could have a user-configurable retention strategy that allows
-// the meta-data to be stored as a linked-list of references, where each
-// meta-data object has a reference to its predecessor.
+// There's a user-configurable retention strategy (`HistoryRetention`) that
+// allows the meta-data that was replaced to be kept around. Each meta-data
+// object then holds the list of its predecessors (most recent first), as
+// far as the strategy allows. By default no predecessors are kept.
 //
 // Prefix example
 //
@@ -186,6 +187,7 @@
 use std::{
     fmt::Debug,
     sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, OnceLock},
 };
 
 use log::{debug, info, log_enabled, trace};
@@ -211,7 +213,10 @@ use crate::{
 };
 
 use super::atomic_types::*;
-use crate::AddressFamily;
+use super::changes::{ChangeKind, ChangeLog};
+use super::selector::{OrderableSelector, PathSelector, SharedSelector};
+use super::watch::Watchlist;
+use crate::{AddressFamily, HistoryRetention, MultipathPolicy};
 
 //------------ Counters -----------------------------------------------------
 
@@ -241,6 +246,10 @@ impl Counters {
         self.prefixes[len as usize].fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn dec_prefixes_count(&self, len: u8) {
+        self.prefixes[len as usize].fetch_sub(1, Ordering::Relaxed);
+    }
+
     pub fn get_prefix_stats(&self) -> Vec<CreatedNodes> {
         self.prefixes
             .iter()
@@ -298,8 +307,90 @@ pub struct UpsertReport {
     pub mui_new: bool,
     // The number of mui records for this prefix after the upsert operation.
     pub mui_count: usize,
+    // Indicates whether the record was stored. False means that it was
+    // rejected, because the existing record for the mui has a newer ltime.
+    pub applied: bool,
+}
+
+//------------ PurgeReport ---------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct PurgeReport {
+    // The number of prefixes that held a record for the purged mui.
+    pub prefixes_touched: usize,
+    // The number of prefixes that were left without any records after the
+    // purge, and that were removed from the store.
+    pub prefixes_emptied: usize,
+}
+
+//------------ EvictReport ---------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct EvictReport {
+    // The number of records that were removed, or marked as Withdrawn.
+    pub records_evicted: usize,
+    // The number of prefixes that were left without any records after the
+    // eviction, and that were removed from the store.
+    pub prefixes_emptied: usize,
+}
+
+//------------ BgpUpdateReport -----------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct BgpUpdateReport {
+    // The number of announced prefixes that did not have a record for the
+    // mui yet.
+    pub prefixes_new: usize,
+    // The number of announced prefixes whose record for the mui was
+    // replaced.
+    pub prefixes_updated: usize,
+    // The number of withdrawn prefixes whose record for the mui was marked
+    // as Withdrawn.
+    pub prefixes_withdrawn: usize,
+    // The number of prefixes that were skipped: announcements for which no
+    // meta-data was built, withdrawals of prefixes without a record for
+    // the mui, and NLRI for other address families.
+    pub prefixes_skipped: usize,
+    // The total number of Compare-and-Swap operations for the inserts.
+    pub cas_count: usize,
+}
+
+//------------ BulkReport ----------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct BulkReport {
+    /// The number of records that were inserted for a (prefix, mui)
+    /// combination that did not have a record yet.
+    pub inserted: usize,
+    /// The number of records that replaced an existing record for their
+    /// (prefix, mui) combination.
+    pub updated: usize,
+    /// The number of prefixes that did not exist in the store before.
+    pub prefixes_new: usize,
+    /// The total number of Compare-and-Swap operations for the inserts.
+    pub cas_count: usize,
 }
 
+impl BulkReport {
+    pub(crate) fn add(&mut self, report: UpsertReport) {
+        if report.mui_new {
+            self.inserted += 1;
+        } else {
+            self.updated += 1;
+        }
+        if report.prefix_new {
+            self.prefixes_new += 1;
+        }
+        self.cas_count += report.cas_count;
+    }
+
+    pub(crate) fn merge(&mut self, other: BulkReport) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.prefixes_new += other.prefixes_new;
+        self.cas_count += other.cas_count;
+    }
+}
 
 // ----------- CustomAllocStorage -------------------------------------------
 //
@@ -317,6 +408,21 @@ pub struct CustomAllocStorage<
     pub default_route_prefix_serial: AtomicUsize,
     // Global Roaring Bitmap INdex that stores MUIs.
     pub withdrawn_muis_bmin: Atomic<RoaringBitmap>,
+    // Global Roaring Bitmap INdex that stores the MUIs that are marked as
+    // stale.
+    pub stale_muis_bmin: Atomic<RoaringBitmap>,
+    // The strategy for keeping the records that are replaced by newer
+    // records for the same (prefix, mui).
+    history_retention: Atomic<HistoryRetention>,
+    // The change log, if it is enabled. It is shared with the store for
+    // the other address family.
+    change_log: OnceLock<Arc<ChangeLog>>,
+    // The watched prefixes, if any were registered. It is shared with the
+    // store for the other address family.
+    watchlist: OnceLock<Arc<Watchlist<M>>>,
+    // The path selector, if one was given. It is shared with the store for
+    // the other address family. Without one, the OrderableSelector is used.
+    path_selector: OnceLock<SharedSelector<M>>,
     pub counters: Counters,
     _m: PhantomData<M>,
     _af: PhantomData<AF>,
@@ -349,6 +455,11 @@ impl<
             prefixes: PrefixBuckets::<AF, M>::init(),
             default_route_prefix_serial: AtomicUsize::new(0),
             withdrawn_muis_bmin: RoaringBitmap::new().into(),
+            stale_muis_bmin: RoaringBitmap::new().into(),
+            history_retention: HistoryRetention::default().into(),
+            change_log: OnceLock::new(),
+            watchlist: OnceLock::new(),
+            path_selector: OnceLock::new(),
             counters: Counters::default(),
             _af: PhantomData,
             _m: PhantomData,
@@ -659,155 +770,310 @@ impl<
     pub(crate) fn upsert_prefix(
         &self,
         prefix: PrefixId<AF>,
-        record: PublicRecord<M>,
+        record: NewRecord<M>,
         update_path_selections: Option<M::TBI>,
+        mode: UpsertMode,
         guard: &Guard,
     ) -> Result<UpsertReport, PrefixStoreError> {
         let mut retry_count = 0;
+        let mui = record.mui();
+        let refreshed = self.mui_is_stale(mui, guard);
+        let retention = self.history_retention(guard);
 
-        let (atomic_stored_prefix, level) = self
-            .non_recursive_retrieve_prefix_mut_with_guard(
-                // PrefixId::new(prefix.get_net(), prefix.get_len()),
-                prefix, guard,
-            )?;
+        let backoff = Backoff::new();
+        let (upserted, stored_prefix, prefix_new) = loop {
+            let (atomic_stored_prefix, parent, level) = self
+                .non_recursive_retrieve_prefix_mut_with_guard(
+                    // PrefixId::new(prefix.get_net(), prefix.get_len()),
+                    prefix, guard,
+                )?;
+
+            let inner_stored_prefix =
+                atomic_stored_prefix.0.load(Ordering::Acquire, guard);
+
+            match inner_stored_prefix.is_null() {
+                // There's no StoredPrefix at this location yet. Create a new
+                // PrefixRecord and try to store it in the empty slot.
+                true => {
+                    if log_enabled!(log::Level::Debug) {
+                        debug!(
+                            "{} store: Create new prefix record",
+                            std::thread::current().name().unwrap()
+                        );
+                    }
 
-        let inner_stored_prefix =
-            atomic_stored_prefix.0.load(Ordering::Acquire, guard);
-
-        let mui_new = match inner_stored_prefix.is_null() {
-            // There's no StoredPrefix at this location yet. Create a new
-            // PrefixRecord and try to store it in the empty slot.
-            true => {
-                if log_enabled!(log::Level::Debug) {
-                    debug!(
-                        "{} store: Create new prefix record",
-                        std::thread::current().name().unwrap()
+                    // The slot is in the next_bucket of another prefix, that
+                    // must not be removed before our prefix is in it.
+                    let parent_writer = match parent {
+                        Some((parent_slot, parent_prefix)) => {
+                            match parent_slot
+                                .register_writer(parent_prefix, guard)
+                            {
+                                Some(writer) => Some((writer, parent_prefix)),
+                                None => {
+                                    retry_count += 1;
+                                    backoff.snooze();
+                                    continue;
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+
+                    // We're creating a StoredPrefix without our record first,
+                    // to avoid having to clone it on retry.
+                    let new_stored_prefix = StoredPrefix::new::<PB>(
+                        PrefixId::new(prefix.get_net(), prefix.get_len()),
+                        level,
                     );
-                }
 
-                // We're creating a StoredPrefix without our record first,
-                // to avoid having to clone it on retry.
-                let new_stored_prefix = StoredPrefix::new::<PB>(
-                    PrefixId::new(prefix.get_net(), prefix.get_len()),
-                    level,
-                );
+                    // We're expecting an empty slot.
+                    match atomic_stored_prefix.0.compare_exchange(
+                        Shared::null(),
+                        Owned::new(new_stored_prefix),
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                        guard,
+                    ) {
+                        // ...and we got an empty slot, the newly created
+                        // StoredPrefix is stored into it.
+                        Ok(spfx) => {
+                            let stored_prefix = unsafe { spfx.deref() };
+                            // The new prefix was created with a registered
+                            // writer, so that it can't be removed before it
+                            // has our record.
+                            let _writer = PrefixWriter::adopt(stored_prefix);
+                            if let Some((_writer, parent_prefix)) =
+                                parent_writer
+                            {
+                                unsafe { parent_prefix.deref() }.add_child();
+                            }
 
-                // We're expecting an empty slot.
-                match atomic_stored_prefix.0.compare_exchange(
-                    Shared::null(),
-                    // tag with value 1, means the path selection is set to
-                    // outdated.
-                    Owned::new(new_stored_prefix).with_tag(1),
-                    Ordering::AcqRel,
-                    Ordering::Acquire,
-                    guard,
-                ) {
-                    // ...and we got an empty slot, the newly created
-                    // StoredPrefix is stored into it.
-                    Ok(spfx) => {
-                        if log_enabled!(log::Level::Info) {
-                            let StoredPrefix {
-                                prefix,
-                                record_map: stored_record,
-                                ..
-                            } = unsafe { spfx.deref() };
                             if log_enabled!(log::Level::Info) {
-                                info!(
-                                        "{} store: Inserted new prefix record {}/{} with {:?}",
+                                let StoredPrefix {
+                                    prefix,
+                                    record_map: stored_record,
+                                    ..
+                                } = stored_prefix;
+                                if log_enabled!(log::Level::Info) {
+                                    info!(
+                                            "{} store: Inserted new prefix record {}/{} with {:?}",
+                                            std::thread::current().name().unwrap(),
+                                            prefix.get_net().into_ipaddr(), prefix.get_len(),
+                                            stored_record
+                                        );
+                                }
+                            }
+
+                            self.counters.inc_prefixes_count(prefix.get_len());
+                            // A removal of an earlier StoredPrefix for this
+                            // prefix may have cleared the bit that the insert
+                            // set before it got here.
+                            self.update_prefix_bit(prefix, true, guard)?;
+
+                            // ..and update the record_map with the actual record
+                            // we got from the user.
+                            let upserted =
+                                stored_prefix.record_map.upsert_record(
+                                    record, refreshed, retention, mode,
+                                );
+                            // Other threads can rank the paths of the prefix
+                            // as soon as it is in its slot, i.e. before it
+                            // has our record.
+                            stored_prefix.set_ranking_outdated();
+                            break (upserted, stored_prefix, true);
+                        }
+                        // ...somebody beat us to it, the slot's not empty
+                        // anymore, we'll have to do it again. The winning
+                        // thread may have stored our prefix, or another
+                        // prefix that hashes to the same slot, in which case
+                        // our prefix goes into the next bucket of that one.
+                        Err(CompareExchangeError { current, new: _ }) => {
+                            if log_enabled!(log::Level::Debug) {
+                                debug!(
+                                        "{} store: Prefix can't be inserted as new {:?}",
                                         std::thread::current().name().unwrap(),
-                                        prefix.get_net().into_ipaddr(), prefix.get_len(),
-                                        stored_record
+                                        current
                                     );
                             }
+                            retry_count += 1;
+                            continue;
                         }
+                    }
+                }
+                // There already is a StoredPrefix with a record at this
+                // location.
+                false => {
+                    if log_enabled!(log::Level::Debug) {
+                        debug!(
+                            "{} store: Found existing prefix record for {}/{}",
+                            std::thread::current().name().unwrap(),
+                            prefix.get_net(),
+                            prefix.get_len()
+                        );
+                    }
 
-                        self.counters.inc_prefixes_count(prefix.get_len());
+                    // Update the already existing record_map with our caller's
+                    // record.
+                    let stored_prefix = unsafe { inner_stored_prefix.deref() };
 
-                        // ..and update the record_map with the actual record
-                        // we got from the user.
-                        unsafe { spfx.deref() }
-                            .record_map
-                            .upsert_record(record)
+                    // The slot may have been filled with another prefix
+                    // after we looked it up, see above.
+                    if stored_prefix.prefix != prefix {
+                        retry_count += 1;
+                        continue;
                     }
-                    // ...somebody beat us to it, the slot's not empty
-                    // anymore, we'll have to do it again.
-                    Err(CompareExchangeError { current, new: _ }) => {
-                        if log_enabled!(log::Level::Debug) {
-                            debug!(
-                                    "{} store: Prefix can't be inserted as new {:?}",
-                                    std::thread::current().name().unwrap(),
-                                    current
-                                );
+
+                    // The prefix may be being removed, see
+                    // `remove_empty_prefix`.
+                    let _writer = match atomic_stored_prefix
+                        .register_writer(inner_stored_prefix, guard)
+                    {
+                        Some(writer) => writer,
+                        None => {
+                            retry_count += 1;
+                            backoff.snooze();
+                            continue;
                         }
-                        retry_count += 1;
-                        let stored_prefix = unsafe { current.deref() };
+                    };
 
-                        // update the record_map from the winning thread
-                        // with our caller's record.
+                    let upserted = stored_prefix.record_map.upsert_record(
+                        record, refreshed, retention, mode,
+                    );
+                    // A prefix that was removed, but that stayed in its
+                    // slot, because there are prefixes in its next_bucket,
+                    // comes back to life here.
+                    let prefix_new = stored_prefix.revive();
+                    if prefix_new {
+                        self.counters.inc_prefixes_count(prefix.get_len());
+                        self.update_prefix_bit(prefix, true, guard)?;
+                    }
+                    // A rejected record leaves the path selections as they
+                    // are.
+                    if !matches!(upserted, RecordUpsert::Rejected(_)) {
                         stored_prefix.set_ps_outdated(guard)?;
-                        stored_prefix.record_map.upsert_record(record)
                     }
+                    break (upserted, stored_prefix, prefix_new);
                 }
             }
-            // There already is a StoredPrefix with a record at this
-            // location.
-            false => {
-                if log_enabled!(log::Level::Debug) {
-                    debug!(
-                        "{} store: Found existing prefix record for {}/{}",
-                        std::thread::current().name().unwrap(),
-                        prefix.get_net(),
-                        prefix.get_len()
-                    );
-                }
+        };
 
-                // Update the already existing record_map with our caller's
-                // record.
-                debug!("tag {}", inner_stored_prefix.tag());
-                let stored_prefix = unsafe { inner_stored_prefix.deref() };
-                stored_prefix.set_ps_outdated(guard)?;
-                stored_prefix
-                    .record_map
-                    .upsert_record(record)
+        let (mui_new, mui_count) = match upserted {
+            RecordUpsert::Inserted => (true, 1),
+            RecordUpsert::Replaced(count) => (false, count),
+            RecordUpsert::Rejected(count) => {
+                return Ok(UpsertReport {
+                    prefix_new,
+                    cas_count: retry_count,
+                    mui_new: false,
+                    mui_count: count,
+                    applied: false,
+                });
             }
         };
 
+        self.log_change(
+            Some(prefix),
+            mui,
+            if mui_new {
+                ChangeKind::Inserted
+            } else {
+                ChangeKind::Updated
+            },
+        );
+
+        // The watchers get the record as it was stored, so with the merged
+        // meta-data for a NewRecord::Merge. It is only looked up if the
+        // prefix is watched.
+        if let (Some(watchlist), true) = (self.watchlist.get(), mui_new) {
+            watchlist.notify(
+                prefix,
+                || stored_prefix.record_map.get_record_for_mui(mui),
+                prefix_new,
+            );
+        }
+
         if let Some(tbi) = update_path_selections {
-            unsafe { inner_stored_prefix.deref() }.calculate_and_store_best_backup(&tbi, guard)?;
+            stored_prefix.calculate_and_store_eligible_best_backup(
+                &tbi,
+                unsafe {
+                    self.withdrawn_muis_bmin
+                        .load(Ordering::Acquire, guard)
+                        .deref()
+                },
+                self.path_selector(),
+                None,
+                guard,
+            )?;
         }
 
         Ok(UpsertReport {
-            prefix_new: false,
+            prefix_new,
             cas_count: retry_count,
-            mui_new: mui_new.is_none(),
-            mui_count: mui_new.unwrap_or(1),
+            mui_new,
+            mui_count,
+            applied: true,
         })
     }
 
     // Change the status of the record for the specified (prefix, mui)
     // combination  to Withdrawn.
     pub fn mark_mui_as_withdrawn_for_prefix(&self, prefix: PrefixId<AF>, mui: u32, guard: &Guard) -> Result<(), PrefixStoreError> {
-        let (atomic_stored_prefix, _level) = self
+        let (atomic_stored_prefix, _parent, _level) = self
             .non_recursive_retrieve_prefix_mut_with_guard(
                 prefix, guard,
             )?;
         
-        let current = unsafe { atomic_stored_prefix.0.load(Ordering::Acquire, guard).as_ref() }.unwrap();
+        let current = unsafe { atomic_stored_prefix.0.load(Ordering::Acquire, guard).as_ref() }
+            .ok_or(PrefixStoreError::PrefixNotFound)?;
         current.record_map.mark_as_withdrawn_for_mui(mui);
+        current.set_ps_outdated(guard)?;
+        self.log_change(Some(prefix), mui, ChangeKind::Withdrawn);
 
         Ok(())
     }
 
+    // Change the status of the record for the specified (prefix, mui)
+    // combination to Withdrawn, and its ltime to `ltime`. Returns whether
+    // there was a record for the mui.
+    pub fn withdraw_record_for_mui(
+        &self,
+        prefix: PrefixId<AF>,
+        mui: u32,
+        ltime: u64,
+        guard: &Guard,
+    ) -> Result<bool, PrefixStoreError> {
+        let stored_prefix = self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::PrefixNotFound)?;
+
+        if !stored_prefix.record_map.withdraw_for_mui(
+            mui,
+            ltime,
+            self.history_retention(guard),
+        ) {
+            return Ok(false);
+        }
+        stored_prefix.set_ps_outdated(guard)?;
+        self.log_change(Some(prefix), mui, ChangeKind::Withdrawn);
+
+        Ok(true)
+    }
+
     // Change the status of the record for the specified (prefix, mui)
     // combination  to Active.
     pub fn mark_mui_as_active_for_prefix(&self, prefix: PrefixId<AF>, mui: u32, guard: &Guard) -> Result<(), PrefixStoreError> {
-        let (atomic_stored_prefix, _level) = self
+        let (atomic_stored_prefix, _parent, _level) = self
             .non_recursive_retrieve_prefix_mut_with_guard(
                 prefix, guard,
             )?;
         
-        let current = unsafe { atomic_stored_prefix.0.load(Ordering::Acquire, guard).as_ref() }.unwrap();
+        let current = unsafe { atomic_stored_prefix.0.load(Ordering::Acquire, guard).as_ref() }
+            .ok_or(PrefixStoreError::PrefixNotFound)?;
         current.record_map.mark_as_active_for_mui(mui);
+        current.set_ps_outdated(guard)?;
+        self.log_change(Some(prefix), mui, ChangeKind::Activated);
 
         Ok(())
     }
@@ -815,13 +1081,13 @@ impl<
     // Change the status of the mui globally to Withdrawn. Iterators and match
     // functions will by default not return any records for this mui.
     pub fn mark_mui_as_withdrawn(&self, mui: u32, guard: &Guard) -> Result<(), PrefixStoreError> {
-        let current = self.withdrawn_muis_bmin.load(Ordering::Acquire, guard);
-
-        let mut new = unsafe { current.as_ref() }.unwrap().clone();
-        new.insert(mui);
+        let mut current = self.withdrawn_muis_bmin.load(Ordering::Acquire, guard);
 
-        #[allow(clippy::assigning_clones)]
         loop {
+            let mut new = unsafe { current.as_ref() }.unwrap().clone();
+            if !new.insert(mui) {
+                return Ok(());
+            }
             match self.withdrawn_muis_bmin.compare_exchange(
                 current,
                 Owned::new(new),
@@ -829,9 +1095,12 @@ impl<
                 Ordering::Acquire,
                 guard
             ) {
-                Ok(_) => return Ok(()),
+                Ok(_) => {
+                    self.log_change(None, mui, ChangeKind::MuiWithdrawn);
+                    return self.set_ps_outdated_for_mui(mui, guard);
+                }
                 Err(updated) => {
-                    new = unsafe { updated.current.as_ref() }.unwrap().clone();
+                    current = updated.current;
                 }
             }
         }
@@ -840,26 +1109,420 @@ impl<
     // Change the status of the mui globally to Active. Iterators and match
     // functions will default to the status on the record itself.
     pub fn mark_mui_as_active(&self, mui: u32, guard: &Guard) -> Result<(), PrefixStoreError> {
-        let current = self.withdrawn_muis_bmin.load(Ordering::Acquire, guard);
-
-        let mut new = unsafe { current.as_ref() }.unwrap().clone();
-        new.remove(mui);
+        let mut current = self.withdrawn_muis_bmin.load(Ordering::Acquire, guard);
 
-        #[allow(clippy::assigning_clones)]
         loop {
+            let mut new = unsafe { current.as_ref() }.unwrap().clone();
+            if !new.remove(mui) {
+                return Ok(());
+            }
             match self.withdrawn_muis_bmin.compare_exchange(
                 current,
                 Owned::new(new),
                 Ordering::AcqRel,
                 Ordering::Acquire,
                 guard
+            ) {
+                Ok(_) => {
+                    self.log_change(None, mui, ChangeKind::MuiActivated);
+                    return self.set_ps_outdated_for_mui(mui, guard);
+                }
+                Err(updated) => {
+                    current = updated.current;
+                }
+            }
+        }
+    }
+
+    // Mark the path selections of the prefixes that have a record for this
+    // mui as outdated.
+    fn set_ps_outdated_for_mui(
+        &self,
+        mui: u32,
+        guard: &Guard,
+    ) -> Result<(), PrefixStoreError> {
+        let (prefix_ids, _) = self.prefix_and_node_ids_for_mui(mui, guard);
+        for prefix_id in prefix_ids {
+            if let Some(stored_prefix) = self
+                .non_recursive_retrieve_stored_prefix_with_guard(
+                    prefix_id,
+                    guard,
+                )
+                .0
+            {
+                if stored_prefix.record_map.contains_mui(mui) {
+                    stored_prefix.set_ps_outdated(guard)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Calculate the best and backup path for the prefix from its eligible
+    // records with `selector`, or the path selector of the store if it is
+    // None, and store them. Records the selector doesn't find eligible,
+    // with the status of muis that are withdrawn globally rewritten to
+    // Withdrawn, and records for which `eligible` returns false are not
+    // eligible.
+    pub(crate) fn calculate_and_store_best_backup(
+        &self,
+        prefix: PrefixId<AF>,
+        tbi: &M::TBI,
+        selector: Option<&dyn PathSelector<M>>,
+        eligible: Option<EligibleFn<'_, M>>,
+        guard: &Guard,
+    ) -> Result<(Option<u32>, Option<u32>), PrefixStoreError> {
+        self.non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::StoreNotReadyError)?
+            .calculate_and_store_eligible_best_backup(
+                tbi,
+                unsafe {
+                    self.withdrawn_muis_bmin
+                        .load(Ordering::Acquire, guard)
+                        .deref()
+                },
+                selector.unwrap_or_else(|| self.path_selector()),
+                eligible,
+                guard,
+            )
+    }
+
+    // Returns the muis of all the eligible paths for the prefix, ranked by
+    // the path selector of the store, best first, from the cached ranking
+    // if it is not outdated.
+    pub(crate) fn ranked_paths(
+        &self,
+        prefix: PrefixId<AF>,
+        tbi: &M::TBI,
+        guard: &Guard,
+    ) -> Result<Vec<u32>, PrefixStoreError>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        Ok(self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::StoreNotReadyError)?
+            .ranked_paths(
+                tbi,
+                unsafe {
+                    self.withdrawn_muis_bmin
+                        .load(Ordering::Acquire, guard)
+                        .deref()
+                },
+                self.path_selector(),
+                guard,
+            ))
+    }
+
+    // Returns the records of the `n` best paths from the cached ranking of
+    // the prefix, see `ranked_paths`, best first. The records of muis that
+    // are withdrawn globally get the Withdrawn status, like the path
+    // selector saw them.
+    pub(crate) fn top_paths(
+        &self,
+        prefix: PrefixId<AF>,
+        n: usize,
+        tbi: &M::TBI,
+        guard: &Guard,
+    ) -> Result<Vec<PublicRecord<M>>, PrefixStoreError>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        let stored_prefix = self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::StoreNotReadyError)?;
+        let withdrawn_muis_bmin = unsafe {
+            self.withdrawn_muis_bmin.load(Ordering::Acquire, guard).deref()
+        };
+        let stale_muis_bmin = unsafe {
+            self.stale_muis_bmin.load(Ordering::Acquire, guard).deref()
+        };
+        Ok(stored_prefix
+            .ranked_paths(
+                tbi,
+                withdrawn_muis_bmin,
+                self.path_selector(),
+                guard,
+            )
+            .into_iter()
+            .filter_map(|mui| {
+                stored_prefix
+                    .record_map
+                    .get_record_for_mui_with_rewritten_status(
+                        mui,
+                        withdrawn_muis_bmin,
+                        RouteStatus::Withdrawn,
+                        stale_muis_bmin,
+                    )
+            })
+            .take(n)
+            .collect())
+    }
+
+    // Returns the muis of all the paths for the prefix that `selector`
+    // finds eligible, ranked by `selector`, best first. This bypasses the
+    // cached ranking.
+    pub(crate) fn rank_paths(
+        &self,
+        prefix: PrefixId<AF>,
+        tbi: &M::TBI,
+        selector: &dyn PathSelector<M>,
+        guard: &Guard,
+    ) -> Result<Vec<u32>, PrefixStoreError> {
+        Ok(self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::StoreNotReadyError)?
+            .record_map
+            .ranked_paths(
+                *tbi,
+                unsafe {
+                    self.withdrawn_muis_bmin
+                        .load(Ordering::Acquire, guard)
+                        .deref()
+                },
+                selector,
+            ))
+    }
+
+    // Returns the muis of the eligible paths for the prefix that the path
+    // selector of the store finds equivalent to its best path under
+    // `policy`, best first.
+    pub(crate) fn multipath(
+        &self,
+        prefix: PrefixId<AF>,
+        tbi: &M::TBI,
+        policy: MultipathPolicy,
+        guard: &Guard,
+    ) -> Result<Vec<u32>, PrefixStoreError>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        let stored_prefix = self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::StoreNotReadyError)?;
+        let withdrawn_muis_bmin = unsafe {
+            self.withdrawn_muis_bmin.load(Ordering::Acquire, guard).deref()
+        };
+        let ranked = stored_prefix.ranked_paths(
+            tbi,
+            withdrawn_muis_bmin,
+            self.path_selector(),
+            guard,
+        );
+        Ok(stored_prefix.record_map.multipath(
+            &ranked,
+            *tbi,
+            withdrawn_muis_bmin,
+            self.path_selector(),
+            policy,
+        ))
+    }
+
+    // Mark all the records for this mui as stale. This only flags the mui in
+    // the global stale index, so it's cheap. Records for this mui that get
+    // inserted after this, are flagged as refreshed, and they survive the
+    // next sweep. Marking a mui that is already stale does not change
+    // anything.
+    pub fn mark_mui_as_stale(&self, mui: u32, guard: &Guard) -> Result<(), PrefixStoreError> {
+        let mut current = self.stale_muis_bmin.load(Ordering::Acquire, guard);
+
+        loop {
+            let mut new = unsafe { current.as_ref() }.unwrap().clone();
+            if !new.insert(mui) {
+                return Ok(());
+            }
+            match self.stale_muis_bmin.compare_exchange(
+                current,
+                Owned::new(new),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                guard
+            ) {
+                Ok(_) => {
+                    self.log_change(None, mui, ChangeKind::MuiStale);
+                    return Ok(());
+                }
+                Err(updated) => {
+                    current = updated.current;
+                }
+            }
+        }
+    }
+
+    // Remove this mui from the global stale index.
+    fn mark_mui_as_fresh(&self, mui: u32, guard: &Guard) -> Result<(), PrefixStoreError> {
+        let mut current = self.stale_muis_bmin.load(Ordering::Acquire, guard);
+
+        loop {
+            let mut new = unsafe { current.as_ref() }.unwrap().clone();
+            if !new.remove(mui) {
+                return Ok(());
+            }
+            match self.stale_muis_bmin.compare_exchange(
+                current,
+                Owned::new(new),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                guard
             ) {
                 Ok(_) => return Ok(()),
                 Err(updated) => {
-                    new = unsafe { updated.current.as_ref() }.unwrap().clone();
+                    current = updated.current;
+                }
+            }
+        }
+    }
+
+    // Whether this mui is marked as stale.
+    pub fn mui_is_stale(&self, mui: u32, guard: &Guard) -> bool {
+        unsafe { self.stale_muis_bmin.load(Ordering::Acquire, guard).as_ref() }.unwrap().contains(mui)
+    }
+
+    // Remove, or mark as Withdrawn, all the records for this stale mui that
+    // were not refreshed since the mui was marked as stale, and clear the
+    // stale mark for the mui. Returns the prefixes for which a record was
+    // swept. Sweeping a mui that is not stale is a no-op.
+    //
+    // Records for this mui that are inserted while the sweep is running may
+    // keep their refreshed flag, and thus survive the next sweep as well.
+    pub fn sweep_stale(
+        &self,
+        mui: u32,
+        remove: bool,
+        guard: &Guard,
+    ) -> Result<Vec<PrefixId<AF>>, PrefixStoreError> {
+        if !self.mui_is_stale(mui, guard) {
+            return Ok(vec![]);
+        }
+
+        let mut swept = vec![];
+        let (prefix_ids, _) = self.prefix_and_node_ids_for_mui(mui, guard);
+
+        for prefix_id in prefix_ids {
+            let stored_prefix = if let Some(stored_prefix) = self
+                .non_recursive_retrieve_stored_prefix_with_guard(
+                    prefix_id,
+                    guard,
+                )
+                .0
+            {
+                stored_prefix
+            } else {
+                continue;
+            };
+
+            if stored_prefix
+                .record_map
+                .sweep_stale_record_for_mui(mui, remove)
+            {
+                swept.push(prefix_id);
+                stored_prefix.set_ps_outdated(guard)?;
+                self.log_change(
+                    Some(prefix_id),
+                    mui,
+                    if remove {
+                        ChangeKind::Removed
+                    } else {
+                        ChangeKind::Withdrawn
+                    },
+                );
+                if remove && stored_prefix.record_map.is_empty() {
+                    self.remove_empty_prefix(prefix_id, guard)?;
                 }
             }
         }
+
+        self.mark_mui_as_fresh(mui, guard)?;
+
+        Ok(swept)
+    }
+
+    // Set the strategy for keeping the records that are replaced by newer
+    // records. Histories that are already in the store are brought in line
+    // with the new strategy on the next update of their record only.
+    pub fn set_history_retention(
+        &self,
+        retention: HistoryRetention,
+        guard: &Guard,
+    ) {
+        let old = self
+            .history_retention
+            .swap(Owned::new(retention), Ordering::AcqRel, guard);
+        unsafe { guard.defer_destroy(old) };
+    }
+
+    pub fn history_retention(&self, guard: &Guard) -> HistoryRetention {
+        *unsafe {
+            self.history_retention.load(Ordering::Acquire, guard).deref()
+        }
+    }
+
+    // Start appending changes to `change_log`. Returns false if there
+    // already is a change log, which is then kept.
+    pub(crate) fn set_change_log(&self, change_log: Arc<ChangeLog>) -> bool {
+        self.change_log.set(change_log).is_ok()
+    }
+
+    pub(crate) fn change_log(&self) -> Option<&ChangeLog> {
+        self.change_log.get().map(|log| log.as_ref())
+    }
+
+    // Start calling the callbacks in `watchlist` for new records. Returns
+    // false if there already is a watchlist, which is then kept.
+    pub(crate) fn set_watchlist(&self, watchlist: Arc<Watchlist<M>>) -> bool {
+        self.watchlist.set(watchlist).is_ok()
+    }
+
+    pub(crate) fn watchlist(&self) -> Option<&Arc<Watchlist<M>>> {
+        self.watchlist.get()
+    }
+
+    // Use `selector` for the path selection from now on. Returns false if
+    // there already is a path selector, which is then kept.
+    pub(crate) fn set_path_selector(
+        &self,
+        selector: Arc<dyn PathSelector<M>>,
+    ) -> bool {
+        self.path_selector.set(SharedSelector(selector)).is_ok()
+    }
+
+    pub(crate) fn path_selector(&self) -> &dyn PathSelector<M> {
+        match self.path_selector.get() {
+            Some(selector) => selector.0.as_ref(),
+            None => &OrderableSelector,
+        }
+    }
+
+    // Append a change to the change log, if there is one.
+    fn log_change(
+        &self,
+        prefix: Option<PrefixId<AF>>,
+        mui: u32,
+        kind: ChangeKind,
+    ) {
+        if let Some(change_log) = self.change_log.get() {
+            change_log.push(prefix.map(|p| p.into_pub()), mui, kind);
+        }
+    }
+
+    // Return the record for the (prefix, mui) combination, followed by the
+    // records it replaced (as far as they are retained), most recent first.
+    pub fn get_history_for_mui(
+        &self,
+        prefix: PrefixId<AF>,
+        mui: u32,
+        guard: &Guard,
+    ) -> Vec<PublicRecord<M>> {
+        self.non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .map(|sp| sp.record_map.get_history_for_mui(mui))
+            .unwrap_or_default()
     }
 
     // Whether this mui is globally withdrawn. Note that this overrules (by
@@ -875,6 +1538,477 @@ impl<
         !unsafe { self.withdrawn_muis_bmin.load(Ordering::Acquire, guard).as_ref() }.unwrap().contains(mui)
     }
 
+    // Remove the record for the specified (prefix, mui) combination from the
+    // store. If this was the last record for the prefix, the prefix itself
+    // is removed from the store as well. Returns the removed record, if
+    // there was any.
+    pub fn remove_record_for_mui(
+        &self,
+        prefix: PrefixId<AF>,
+        mui: u32,
+        guard: &Guard,
+    ) -> Result<Option<PublicRecord<M>>, PrefixStoreError> {
+        let stored_prefix = self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::PrefixNotFound)?;
+
+        // An empty StoredPrefix was removed earlier, or is being removed,
+        // see `remove_empty_prefix`.
+        if stored_prefix.record_map.is_empty() {
+            return Err(PrefixStoreError::PrefixNotFound);
+        }
+
+        let record = stored_prefix.record_map.remove_record_for_mui(mui);
+
+        if record.is_some() {
+            stored_prefix.set_ps_outdated(guard)?;
+            self.log_change(Some(prefix), mui, ChangeKind::Removed);
+            if stored_prefix.record_map.is_empty() {
+                self.remove_empty_prefix(prefix, guard)?;
+            }
+        }
+
+        Ok(record)
+    }
+
+    // Remove all the records for the specified prefix, and the prefix
+    // itself from the store. Returns the removed records.
+    pub fn remove_prefix(
+        &self,
+        prefix: PrefixId<AF>,
+        guard: &Guard,
+    ) -> Result<Vec<PublicRecord<M>>, PrefixStoreError> {
+        let stored_prefix = self
+            .non_recursive_retrieve_stored_prefix_with_guard(prefix, guard)
+            .0
+            .ok_or(PrefixStoreError::PrefixNotFound)?;
+
+        let records = stored_prefix.record_map.remove_all_records();
+        // Somebody else got here first, or the prefix was removed earlier.
+        if records.is_empty() {
+            return Err(PrefixStoreError::PrefixNotFound);
+        }
+        stored_prefix.set_ranking_outdated();
+        stored_prefix.set_path_selections(
+            PathSelections {
+                path_selection_muis: (None, None),
+            },
+            guard,
+        )?;
+        self.remove_empty_prefix(prefix, guard)?;
+        for record in records.iter() {
+            self.log_change(
+                Some(prefix),
+                record.multi_uniq_id,
+                ChangeKind::Removed,
+            );
+        }
+
+        Ok(records)
+    }
+
+    // Remove a prefix, whose record_map was emptied by the caller, from the
+    // store.
+    //
+    // The slot of the prefix is marked for removal (with tag 1) first, so
+    // that no new writers can register for the prefix, see
+    // `AtomicStoredPrefix::register_writer`, and the writers that did
+    // register are waited for. If the prefix got a record in the meantime,
+    // it stays. Otherwise its bit in the pfxbitarr of the node hosting it
+    // is cleared, so that tree traversals won't find it anymore, and the
+    // counters are decremented, by the thread that retires it.
+    //
+    // The StoredPrefix is then unlinked from its slot, and destroyed once no
+    // thread can see it anymore, unless there are prefixes in its
+    // next_bucket, which would get lost with it. In that case it stays in
+    // its slot, empty and retired, until its last child is unlinked, or it
+    // gets a record again. Lookups treat it as absent in the meantime.
+    fn remove_empty_prefix(
+        &self,
+        prefix: PrefixId<AF>,
+        guard: &Guard,
+    ) -> Result<(), PrefixStoreError> {
+        let backoff = Backoff::new();
+
+        let (slot, current, parent) = loop {
+            let (slot, parent, _level) = self
+                .non_recursive_retrieve_prefix_mut_with_guard(prefix, guard)?;
+            let current = slot.0.load(Ordering::SeqCst, guard);
+            match unsafe { current.as_ref() } {
+                Some(stored_prefix) if stored_prefix.prefix == prefix => {}
+                // Somebody else got here first.
+                _ => return Ok(()),
+            };
+            // Another thread is removing it, and it may find it has a
+            // record, so we have to wait for it.
+            if current.tag() == 1 {
+                backoff.snooze();
+                continue;
+            }
+            if slot
+                .0
+                .compare_exchange(
+                    current,
+                    current.with_tag(1),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                    guard,
+                )
+                .is_ok()
+            {
+                break (slot, current, parent);
+            }
+        };
+        let stored_prefix = unsafe { current.deref() };
+
+        while stored_prefix.has_writers() {
+            backoff.snooze();
+        }
+
+        // Nobody but us changes the slot while it is marked, so we can
+        // store the unmarked prefix back.
+        if !stored_prefix.record_map.is_empty() {
+            slot.0.store(current, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        if stored_prefix.retire() {
+            if let Err(err) = self.update_prefix_bit(prefix, false, guard) {
+                slot.0.store(current, Ordering::SeqCst);
+                return Err(err);
+            }
+            trace!("removed prefix {:?} from the store", prefix);
+            self.counters.dec_prefixes_count(prefix.get_len());
+        }
+
+        if stored_prefix.has_children() {
+            slot.0.store(current, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        slot.0.store(Shared::null(), Ordering::SeqCst);
+        unsafe { guard.defer_destroy(current) };
+        trace!("unlinked prefix {:?}", prefix);
+
+        // The prefix whose next_bucket held our prefix may have been
+        // waiting for its last child to go.
+        if let Some((_, parent)) = parent {
+            let parent = unsafe { parent.deref() };
+            parent.remove_child();
+            if parent.is_retired() && !parent.has_children() {
+                return self.remove_empty_prefix(parent.prefix, guard);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Set, or clear, the bit for `prefix` in the pfxbitarr of the node
+    // hosting it.
+    fn update_prefix_bit(
+        &self,
+        prefix: PrefixId<AF>,
+        set: bool,
+        guard: &Guard,
+    ) -> Result<(), PrefixStoreError> {
+        // The default route does not live in a pfxbitarr, see
+        // `TreeBitMap::update_default_route_prefix_meta`.
+        if prefix.get_len() == 0 {
+            return Ok(());
+        }
+
+        let (node_id, bit_span) = self.get_node_id_for_prefix(&prefix);
+        match self.retrieve_node_with_guard(node_id, guard) {
+            Some(SizedStrideRef::Stride3(n)) if set => {
+                n.insert_pfx_at(bit_span.bits, bit_span.len);
+            }
+            Some(SizedStrideRef::Stride3(n)) => {
+                n.remove_pfx_at(bit_span.bits, bit_span.len);
+            }
+            Some(SizedStrideRef::Stride4(n)) if set => {
+                n.insert_pfx_at(bit_span.bits, bit_span.len);
+            }
+            Some(SizedStrideRef::Stride4(n)) => {
+                n.remove_pfx_at(bit_span.bits, bit_span.len);
+            }
+            Some(SizedStrideRef::Stride5(n)) if set => {
+                n.insert_pfx_at(bit_span.bits, bit_span.len);
+            }
+            Some(SizedStrideRef::Stride5(n)) => {
+                n.remove_pfx_at(bit_span.bits, bit_span.len);
+            }
+            None => return Err(PrefixStoreError::NodeNotFound),
+        };
+
+        Ok(())
+    }
+
+    // Remove all the records for the specified mui from the store, and
+    // remove the mui from the global withdrawn bitmap index.
+    //
+    // Only the sub-trees that have the mui in their bitmap index are
+    // visited. The mui is removed from the bitmap indexes of all the nodes
+    // that were visited after the records have been removed. A record for
+    // this mui that gets inserted while the purge is running may be missed
+    // by the purge, and it may disappear from the bitmap index of the nodes
+    // it lives under, so that it won't show up in searches for this mui.
+    // Callers should stop inserting records for the mui before purging it.
+    pub fn purge_mui(
+        &self,
+        mui: u32,
+        guard: &Guard,
+    ) -> Result<PurgeReport, PrefixStoreError> {
+        let mut report = PurgeReport::default();
+
+        // Collect the nodes and prefixes in the sub-trees that have this mui
+        // first, so that we are not removing prefixes from under our feet.
+        let (prefix_ids, node_ids) = self.prefix_and_node_ids_for_mui(mui, guard);
+
+        for prefix_id in prefix_ids {
+            let stored_prefix = if let Some(stored_prefix) = self
+                .non_recursive_retrieve_stored_prefix_with_guard(
+                    prefix_id,
+                    guard,
+                )
+                .0
+            {
+                stored_prefix
+            } else {
+                continue;
+            };
+
+            if stored_prefix
+                .record_map
+                .remove_record_for_mui(mui)
+                .is_some()
+            {
+                report.prefixes_touched += 1;
+                stored_prefix.set_ps_outdated(guard)?;
+                self.log_change(Some(prefix_id), mui, ChangeKind::Removed);
+                if stored_prefix.record_map.is_empty() {
+                    self.remove_empty_prefix(prefix_id, guard)?;
+                    report.prefixes_emptied += 1;
+                }
+            }
+        }
+
+        for node_id in node_ids {
+            match self.get_stride_for_id(node_id) {
+                3 => self.remove_mui_from_node_index(
+                    node_id,
+                    mui,
+                    self.buckets.get_store3(node_id),
+                    0,
+                    guard,
+                )?,
+                4 => self.remove_mui_from_node_index(
+                    node_id,
+                    mui,
+                    self.buckets.get_store4(node_id),
+                    0,
+                    guard,
+                )?,
+                _ => self.remove_mui_from_node_index(
+                    node_id,
+                    mui,
+                    self.buckets.get_store5(node_id),
+                    0,
+                    guard,
+                )?,
+            };
+        }
+
+        self.mark_mui_as_active(mui, guard)?;
+        self.mark_mui_as_fresh(mui, guard)?;
+
+        Ok(report)
+    }
+
+    // Collect the ids of all the nodes that have this mui in their sub-trees,
+    // according to their mui indexes, and the ids of all the prefixes in
+    // those nodes. The prefixes are candidates, not all of them necessarily
+    // carry a record for the mui. The default route is always included.
+    fn prefix_and_node_ids_for_mui(
+        &self,
+        mui: u32,
+        guard: &Guard,
+    ) -> (Vec<PrefixId<AF>>, Vec<StrideNodeId<AF>>) {
+        let mut node_ids = vec![];
+        let mut prefix_ids = vec![PrefixId::new(AF::zero(), 0)];
+        let mut stack = vec![self.get_root_node_id()];
+
+        while let Some(node_id) = stack.pop() {
+            match self.retrieve_node_for_mui(node_id, mui, guard) {
+                Some(SizedStrideRef::Stride3(n)) => {
+                    prefix_ids.extend(n.more_specific_pfx_iter(
+                        node_id,
+                        BitSpan::new(0, 0),
+                        false,
+                    ));
+                    stack.extend(
+                        n.more_specific_ptr_iter(node_id, BitSpan::new(0, 0)),
+                    );
+                }
+                Some(SizedStrideRef::Stride4(n)) => {
+                    prefix_ids.extend(n.more_specific_pfx_iter(
+                        node_id,
+                        BitSpan::new(0, 0),
+                        false,
+                    ));
+                    stack.extend(
+                        n.more_specific_ptr_iter(node_id, BitSpan::new(0, 0)),
+                    );
+                }
+                Some(SizedStrideRef::Stride5(n)) => {
+                    prefix_ids.extend(n.more_specific_pfx_iter(
+                        node_id,
+                        BitSpan::new(0, 0),
+                        false,
+                    ));
+                    stack.extend(
+                        n.more_specific_ptr_iter(node_id, BitSpan::new(0, 0)),
+                    );
+                }
+                // The mui does not appear in this sub-tree.
+                None => continue,
+            }
+            node_ids.push(node_id);
+        }
+
+        (prefix_ids, node_ids)
+    }
+
+    // Remove, or mark as Withdrawn, all the records with an ltime older
+    // than the specified ltime. The records to evict can be restricted to
+    // the muis in the `muis` bitmap, and to the prefix `prefix` and its
+    // more-specifics.
+    //
+    // Records with the same mui that have their ltime updated while the
+    // sweep is running will not be evicted.
+    pub fn evict_older_than(
+        &self,
+        ltime: u64,
+        muis: Option<&RoaringBitmap>,
+        prefix: Option<PrefixId<AF>>,
+        remove: bool,
+        guard: &Guard,
+    ) -> Result<EvictReport, PrefixStoreError> {
+        let mut report = EvictReport::default();
+
+        let prefix_ids = match prefix {
+            Some(prefix) => std::iter::once(prefix)
+                .chain(
+                    self.more_specific_prefix_iter_from(
+                        prefix, None, true, guard,
+                    )
+                    .map(|p| p.0),
+                )
+                .collect::<Vec<_>>(),
+            None => self
+                .prefixes_iter(guard)
+                .map(|p| PrefixId::from(p.0))
+                .collect::<Vec<_>>(),
+        };
+
+        for prefix_id in prefix_ids {
+            let stored_prefix = if let Some(stored_prefix) = self
+                .non_recursive_retrieve_stored_prefix_with_guard(
+                    prefix_id,
+                    guard,
+                )
+                .0
+            {
+                stored_prefix
+            } else {
+                continue;
+            };
+
+            let mut evicted = 0;
+            for rec in stored_prefix.record_map.as_records() {
+                if rec.ltime >= ltime
+                    || muis.is_some_and(|m| !m.contains(rec.multi_uniq_id))
+                {
+                    continue;
+                }
+                if stored_prefix.record_map.evict_record_for_mui_older_than(
+                    rec.multi_uniq_id,
+                    ltime,
+                    remove,
+                ) {
+                    evicted += 1;
+                    self.log_change(
+                        Some(prefix_id),
+                        rec.multi_uniq_id,
+                        if remove {
+                            ChangeKind::Removed
+                        } else {
+                            ChangeKind::Withdrawn
+                        },
+                    );
+                }
+            }
+
+            if evicted > 0 {
+                report.records_evicted += evicted;
+                stored_prefix.set_ps_outdated(guard)?;
+                if remove && stored_prefix.record_map.is_empty() {
+                    self.remove_empty_prefix(prefix_id, guard)?;
+                    report.prefixes_emptied += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Remove the mui from the bitmap index of the node with the specified
+    // id. Walks down the levels of the NodeSets, just like the
+    // `retrieve_node_*` methods do.
+    fn remove_mui_from_node_index<S: Stride>(
+        &self,
+        id: StrideNodeId<AF>,
+        mui: u32,
+        nodes: &NodeSet<AF, S>,
+        level: u8,
+        guard: &Guard,
+    ) -> Result<u32, PrefixStoreError> {
+        let index = Self::hash_node_id(id, level);
+        let stored_nodes = nodes.0.load(Ordering::Acquire, guard);
+
+        if stored_nodes.is_null() {
+            return Err(PrefixStoreError::NodeNotFound);
+        }
+
+        let stored_node = unsafe { stored_nodes.deref()[index].assume_init_ref() }
+            .load(Ordering::Acquire, guard);
+
+        match unsafe { stored_node.as_ref() } {
+            None => Err(PrefixStoreError::NodeNotFound),
+            Some(StoredNode {
+                node_id, node_set, ..
+            }) => {
+                if id == *node_id {
+                    return node_set.remove_from_rbm_index(mui, guard);
+                }
+                match <NB as NodeBuckets<AF>>::len_to_store_bits(
+                    id.get_id().1,
+                    level + 1,
+                ) {
+                    next_bit_shift if next_bit_shift > 0 => self
+                        .remove_mui_from_node_index(
+                            id,
+                            mui,
+                            node_set,
+                            level + 1,
+                            guard,
+                        ),
+                    _ => Err(PrefixStoreError::NodeNotFound),
+                }
+            }
+        }
+    }
+
     // This function is used by the upsert_prefix function above.
     //
     // We're using a Chained Hash Table and this function returns one of:
@@ -882,6 +2016,10 @@ impl<
     // - the Last StoredPrefix in the chain.
     // - an error, if no StoredPrefix whatsoever can be found in the store.
     //
+    // Together with the slot, it returns the slot of the StoredPrefix whose
+    // next_bucket holds it, with the StoredPrefix that was loaded from it,
+    // if the slot is not in a root prefix set, and the level of the slot.
+    //
     // The error condition really shouldn't happen, because that basically
     // means the root node for that particular prefix length doesn't exist.
     #[allow(clippy::type_complexity)]
@@ -889,12 +2027,23 @@ impl<
         &'a self,
         search_prefix_id: PrefixId<AF>,
         guard: &'a Guard,
-    ) -> Result<(&'a AtomicStoredPrefix<AF, M>, u8), PrefixStoreError> {
+    ) -> Result<
+        (
+            &'a AtomicStoredPrefix<AF, M>,
+            Option<(
+                &'a AtomicStoredPrefix<AF, M>,
+                Shared<'a, StoredPrefix<AF, M>>,
+            )>,
+            u8,
+        ),
+        PrefixStoreError,
+    > {
         let mut prefix_set = self
             .prefixes
             .get_root_prefix_set(search_prefix_id.get_len());
         let mut level: u8 = 0;
         let mut stored_prefix = None;
+        let mut parent = None;
 
         loop {
             // HASHING FUNCTION
@@ -913,49 +2062,86 @@ impl<
                 // StoredPrefix, so the caller can attach a new one.
                 trace!("no prefix set.");
                 return stored_prefix
-                    .map(|sp| (sp, level))
+                    .map(|(sp, parent)| (sp, parent, level))
                     .ok_or(PrefixStoreError::StoreNotReadyError);
             };
 
-            stored_prefix = Some(unsafe { prefix_probe.assume_init_ref() });
+            let slot: &'a AtomicStoredPrefix<AF, M> =
+                unsafe { prefix_probe.assume_init_ref() };
+            let slot_parent = parent;
+            stored_prefix = Some((slot, slot_parent));
 
+            let current = slot.0.load(Ordering::SeqCst, guard);
             if let Some(StoredPrefix {
                 prefix,
                 next_bucket,
                 ..
-            }) = stored_prefix.unwrap().get_stored_prefix_mut(guard)
+            }) = unsafe { current.as_ref() }
             {
                 if search_prefix_id == *prefix {
                     // GOTCHA!
                     // Our search-prefix is stored here, so we're returning
                     // it, so its PrefixRecord can be updated by the caller.
                     trace!("found requested prefix {:?}", search_prefix_id);
-                    return stored_prefix
-                        .map(|sp| (sp, level))
-                        .ok_or(PrefixStoreError::StoreNotReadyError);
+                    return Ok((slot, slot_parent, level));
                 } else {
                     // A Collision. Follow the chain.
                     level += 1;
                     prefix_set = next_bucket;
+                    parent = Some((slot, current));
                     continue;
                 }
             }
 
             // No record at the deepest level, still we're returning a reference to it,
             // so the caller can insert a new record here.
-            return stored_prefix
-                .map(|sp| (sp, level))
-                .ok_or(PrefixStoreError::StoreNotReadyError);
+            return Ok((slot, slot_parent, level));
         }
     }
 
+    // Look up the stored prefix for `id`. This is
+    // `non_recursive_retrieve_stored_prefix_with_guard`, with the stored
+    // prefix wrapped in a StoredPrefixRef, so that path selections on it
+    // take the global status of the muis into account.
     #[allow(clippy::type_complexity)]
     pub fn non_recursive_retrieve_prefix_with_guard(
         &'a self,
         id: PrefixId<AF>,
         guard: &'a Guard,
     ) -> (
-        Option<&StoredPrefix<AF, M>>,
+        Option<StoredPrefixRef<'a, AF, M>>,
+        Option<(
+            PrefixId<AF>,
+            u8,
+            &'a PrefixSet<AF, M>,
+            [Option<(&'a PrefixSet<AF, M>, usize)>; 26],
+            usize,
+        )>,
+    ) {
+        let (stored_prefix, location) =
+            self.non_recursive_retrieve_stored_prefix_with_guard(id, guard);
+        let withdrawn_muis_bmin = unsafe {
+            self.withdrawn_muis_bmin.load(Ordering::Acquire, guard).deref()
+        };
+        (
+            stored_prefix.map(|sp| {
+                StoredPrefixRef::new(
+                    sp,
+                    withdrawn_muis_bmin,
+                    self.path_selector(),
+                )
+            }),
+            location,
+        )
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn non_recursive_retrieve_stored_prefix_with_guard(
+        &'a self,
+        id: PrefixId<AF>,
+        guard: &'a Guard,
+    ) -> (
+        Option<&'a StoredPrefix<AF, M>>,
         Option<(
             PrefixId<AF>,
             u8,
@@ -1012,7 +2198,7 @@ impl<
         &'a self,
         prefix_id: PrefixId<AF>,
         guard: &'a Guard,
-    ) -> Option<(&StoredPrefix<AF, M>, usize)> {
+    ) -> Option<(&'a StoredPrefix<AF, M>, usize)> {
         struct SearchLevel<
             's,
             AF: AddressFamily,
@@ -1069,14 +2255,6 @@ impl<
         )
     }
 
-    #[allow(dead_code)]
-    fn remove_prefix(&mut self, index: PrefixId<AF>) -> Option<M> {
-        match index.is_empty() {
-            false => self.prefixes.remove(index),
-            true => None,
-        }
-    }
-
     pub fn get_prefixes_count(&self) -> usize {
         self.counters.get_prefixes_count().iter().sum()
     }
@@ -1,6 +1,23 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
 use crate::prelude::*;
 use crate::prelude::multi::*;
+use log::trace;
+use routecore::bgp::message::UpdateMessage;
+use routecore::bgp::nlri::afisafi::{IsPrefix, Nlri};
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+use routecore::Octets;
+
+use super::atomic_types::{NewRecord, UpsertMode};
+use super::changes::ChangeLog;
+use super::watch::Watchlist;
+use super::{journal, persist};
+
+// The number of records a bulk insert inserts with the same guard, before
+// it repins it, so that the epoch can move on.
+const BULK_REPIN_INTERVAL: usize = 1024;
 
 // The default stride sizes for IPv4, IPv6, resp.
 #[create_store((
@@ -10,6 +27,1258 @@ use crate::prelude::multi::*;
 ))]
 struct DefaultStore;
 
+impl<M: Meta> DefaultStore<M> {
+    /// Create a new store that uses `selector` for selecting the best and
+    /// backup paths, and for ranking the paths of its prefixes.
+    ///
+    /// A store created with `new` uses the `OrderableSelector`, that ranks
+    /// the records by the orderables of their meta-data. The path selector
+    /// of a store cannot be changed, but most of the path selection methods
+    /// have a counterpart that takes a path selector for a single call.
+    pub fn with_path_selector(
+        selector: impl PathSelector<M> + 'static,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let store = Self::new()?;
+        let selector: std::sync::Arc<dyn PathSelector<M>> =
+            std::sync::Arc::new(selector);
+        store.v4.store.set_path_selector(selector.clone());
+        store.v6.store.set_path_selector(selector);
+        Ok(store)
+    }
+
+    /// Remove the record for the combination of (prefix, multi_uniq_id)
+    /// from the store. If this was the last record for the prefix, the
+    /// prefix itself is removed from the store as well. Returns the removed
+    /// record, or `None` if there was no record for this mui.
+    ///
+    /// Returns a `PrefixNotFound` error if the prefix does not exist in the
+    /// store.
+    pub fn remove(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+    ) -> Result<Option<Record<M>>, PrefixStoreError> {
+        let guard = &epoch::pin();
+        match prefix.addr() {
+            std::net::IpAddr::V4(_addr) => {
+                self.v4.store.remove_record_for_mui(
+                    PrefixId::<IPv4>::from(*prefix),
+                    mui,
+                    guard,
+                )
+            }
+            std::net::IpAddr::V6(_addr) => {
+                self.v6.store.remove_record_for_mui(
+                    PrefixId::<IPv6>::from(*prefix),
+                    mui,
+                    guard,
+                )
+            }
+        }
+    }
+
+    /// Change the local status of the record for the combination of
+    /// (prefix, multi_uniq_id) to Withdrawn, and set its ltime to `ltime`.
+    /// Unlike `mark_mui_as_withdrawn_for_prefix`, this keeps track of when
+    /// the record was withdrawn, and the record as it was before is kept
+    /// in the history, like for an insert, see `set_history_retention`. It
+    /// marks the path selection for the prefix as outdated. Returns whether
+    /// there was a record for this mui.
+    ///
+    /// Returns a `PrefixNotFound` error if the prefix does not exist in the
+    /// store.
+    pub fn withdraw(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+        ltime: u64,
+    ) -> Result<bool, PrefixStoreError> {
+        let guard = &epoch::pin();
+        match prefix.addr() {
+            std::net::IpAddr::V4(_addr) => {
+                self.v4.store.withdraw_record_for_mui(
+                    PrefixId::<IPv4>::from(*prefix),
+                    mui,
+                    ltime,
+                    guard,
+                )
+            }
+            std::net::IpAddr::V6(_addr) => {
+                self.v6.store.withdraw_record_for_mui(
+                    PrefixId::<IPv6>::from(*prefix),
+                    mui,
+                    ltime,
+                    guard,
+                )
+            }
+        }
+    }
+
+    /// Remove the prefix with all of its records from the store. Returns
+    /// the removed records.
+    ///
+    /// The memory of the prefix is freed once no thread can see it
+    /// anymore, unless other prefixes that share its hash bucket hang off
+    /// it. In that case it is kept until these are removed as well, and
+    /// re-used if the prefix is inserted again.
+    ///
+    /// Returns a `PrefixNotFound` error if the prefix does not exist in the
+    /// store.
+    pub fn remove_prefix(
+        &self,
+        prefix: &Prefix,
+    ) -> Result<Vec<Record<M>>, PrefixStoreError> {
+        let guard = &epoch::pin();
+        match prefix.addr() {
+            std::net::IpAddr::V4(_addr) => self
+                .v4
+                .store
+                .remove_prefix(PrefixId::<IPv4>::from(*prefix), guard),
+            std::net::IpAddr::V6(_addr) => self
+                .v6
+                .store
+                .remove_prefix(PrefixId::<IPv6>::from(*prefix), guard),
+        }
+    }
+
+    /// Remove all the records for a multi_uniq_id from the store, and remove
+    /// the multi_uniq_id from the global withdrawn index, so that it can be
+    /// re-used. Prefixes that are left without any records are removed from
+    /// the store.
+    ///
+    /// Only the parts of the tree that contain records for the
+    /// multi_uniq_id are visited. Records for this multi_uniq_id that are
+    /// inserted while the purge is running may be missed, so callers should
+    /// stop inserting for the multi_uniq_id first, e.g. by marking it
+    /// withdrawn.
+    ///
+    /// Returns a report with the number of prefixes that held a record for
+    /// the multi_uniq_id, and the number of those that were removed.
+    pub fn purge_mui(&self, mui: u32) -> Result<PurgeReport, PrefixStoreError> {
+        let guard = &epoch::pin();
+        let v4_report = self.v4.store.purge_mui(mui, guard)?;
+        let v6_report = self.v6.store.purge_mui(mui, guard)?;
+
+        Ok(PurgeReport {
+            prefixes_touched: v4_report.prefixes_touched
+                + v6_report.prefixes_touched,
+            prefixes_emptied: v4_report.prefixes_emptied
+                + v6_report.prefixes_emptied,
+        })
+    }
+
+    /// Set the strategy for keeping the records that are replaced by newer
+    /// records for the same (prefix, multi_uniq_id). The default is
+    /// `HistoryRetention::Off`, i.e. no history is kept at all.
+    ///
+    /// Existing histories are brought in line with a new strategy the next
+    /// time their record is replaced.
+    pub fn set_history_retention(&self, retention: HistoryRetention) {
+        let guard = &epoch::pin();
+        self.v4.store.set_history_retention(retention, guard);
+        self.v6.store.set_history_retention(retention, guard);
+    }
+
+    /// Returns the current strategy for keeping replaced records.
+    pub fn history_retention(&self) -> HistoryRetention {
+        self.v4.store.history_retention(&epoch::pin())
+    }
+
+    /// Start keeping a log of the changes to the store, that keeps the
+    /// `capacity` most recent changes. Enabling the change log when it is
+    /// enabled already does nothing.
+    ///
+    /// Every insert or replacement of a record, change of its local status,
+    /// removal of a record (including removals by `purge_mui`,
+    /// `sweep_stale` and `evict_older_than`), and change of the global
+    /// status of a multi_uniq_id is appended to the log, with a sequence
+    /// number. The global statuses are kept for each address family, so a
+    /// change that applies to both, like `mark_mui_as_stale`, is logged
+    /// twice. Changes to the path selections are not logged.
+    ///
+    /// Note that the change log is guarded by a single lock. Every change
+    /// takes it, after the change itself was applied, to append its entry.
+    /// With many threads writing to the store at the same time, they will
+    /// contend for this lock, so enabling the change log lowers the insert
+    /// throughput. A store without a change log doesn't pay for it.
+    pub fn enable_change_log(&self, capacity: usize) {
+        let change_log = std::sync::Arc::new(ChangeLog::new(capacity));
+        if self.v4.store.set_change_log(change_log.clone()) {
+            self.v6.store.set_change_log(change_log);
+        }
+    }
+
+    /// Returns a cursor that points to the next change that will be
+    /// appended to the change log.
+    ///
+    /// A consumer that needs all the changes should get a cursor before it
+    /// reads the whole store, and then read the changes from that cursor
+    /// onwards.
+    ///
+    /// Returns a `ChangeLogNotEnabled` error if the change log is not
+    /// enabled.
+    pub fn change_cursor(&self) -> Result<ChangeCursor, PrefixStoreError> {
+        self.v4
+            .store
+            .change_log()
+            .map(|change_log| change_log.cursor())
+            .ok_or(PrefixStoreError::ChangeLogNotEnabled)
+    }
+
+    /// Returns up to `max` changes from the change log, starting at the
+    /// change `cursor` points to, and moves the cursor past them. The
+    /// changes are in the order of their sequence numbers. An empty Vec
+    /// means that there are no new changes.
+    ///
+    /// The change log only keeps its most recent changes. If the change
+    /// the cursor points to was dropped already, a `ChangeLogOverrun`
+    /// error is returned, and the consumer has to resync with the whole
+    /// store, with a new cursor from `change_cursor`. Returns a
+    /// `ChangeLogNotEnabled` error if the change log is not enabled.
+    pub fn read_changes(
+        &self,
+        cursor: &mut ChangeCursor,
+        max: usize,
+    ) -> Result<Vec<Change>, PrefixStoreError> {
+        self.v4
+            .store
+            .change_log()
+            .ok_or(PrefixStoreError::ChangeLogNotEnabled)?
+            .read(cursor, max)
+    }
+
+    /// Watch `prefix` for new more-specifics, and call `callback` for them.
+    ///
+    /// The callback is called for every record that is inserted for a
+    /// (prefix, multi_uniq_id) that didn't have one, if the prefix of the
+    /// record is `prefix` itself, or one of its more-specifics, whatever
+    /// the multi_uniq_id. Replacing an existing record doesn't call it. It
+    /// is called on the thread that inserted the record, after the record
+    /// was stored, so it should be quick. The callback can call `watch`
+    /// and `unwatch` on this store, e.g. to remove its own watch.
+    ///
+    /// Returns the id of the watch, to remove it with `unwatch`.
+    pub fn watch(
+        &self,
+        prefix: &Prefix,
+        callback: impl Fn(&WatchEvent<M>) + Send + Sync + 'static,
+    ) -> WatchId {
+        self.watchlist().add(*prefix, std::sync::Arc::new(callback))
+    }
+
+    /// Watch `prefix` for new more-specifics, and send them to the returned
+    /// receiver.
+    ///
+    /// This works like `watch`. Events are dropped once the receiver is
+    /// dropped, but the watch stays in place until it is removed with
+    /// `unwatch`.
+    pub fn watch_channel(
+        &self,
+        prefix: &Prefix,
+    ) -> (WatchId, std::sync::mpsc::Receiver<WatchEvent<M>>)
+    where
+        M: 'static,
+    {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let id = self.watch(prefix, move |event| {
+            let _ = tx.lock().unwrap().send(event.clone());
+        });
+        (id, rx)
+    }
+
+    /// Remove the watch with `id`. Returns false if there was no such
+    /// watch.
+    ///
+    /// An insert that was checking the watchlist at the same time may still
+    /// call the callback of the watch once.
+    pub fn unwatch(&self, id: WatchId) -> bool {
+        self.v4
+            .store
+            .watchlist()
+            .is_some_and(|watchlist| watchlist.remove(id))
+    }
+
+    // The watchlist of the store, which is shared by both address families,
+    // and created when it is used for the first time.
+    fn watchlist(&self) -> std::sync::Arc<Watchlist<M>> {
+        let watchlist = match self.v4.store.watchlist() {
+            Some(watchlist) => watchlist.clone(),
+            None => {
+                self.v4
+                    .store
+                    .set_watchlist(std::sync::Arc::new(Watchlist::new()));
+                self.v4.store.watchlist().unwrap().clone()
+            }
+        };
+        self.v6.store.set_watchlist(watchlist.clone());
+        watchlist
+    }
+
+    /// Returns the current record for the combination of (prefix,
+    /// multi_uniq_id), followed by the records it replaced, most recent
+    /// first. Which replaced records are available depends on the
+    /// `HistoryRetention` of the store at the time they were replaced.
+    ///
+    /// Returns an empty Vec if there's no record for the combination.
+    pub fn record_history(&self, prefix: &Prefix, mui: u32) -> Vec<Record<M>> {
+        let guard = &epoch::pin();
+        match prefix.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.store.get_history_for_mui(
+                PrefixId::<IPv4>::from(*prefix),
+                mui,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.store.get_history_for_mui(
+                PrefixId::<IPv6>::from(*prefix),
+                mui,
+                guard,
+            ),
+        }
+    }
+
+    /// Return the result of `match_prefix` as it would have been at the
+    /// logical time `ltime`. For each multi_uniq_id of a prefix the most
+    /// recent record with an ltime not greater than `ltime` is returned,
+    /// with the local status it had.
+    ///
+    /// The records are reconstructed from the history the store keeps,
+    /// so this requires a `HistoryRetention` other than `Off` to be useful,
+    /// see `set_history_retention`. Only changes that come with an ltime,
+    /// i.e. inserts and `withdraw`, are part of the history. So this can
+    /// not answer queries about:
+    ///
+    /// * changes of the local status without an ltime, by
+    ///   `mark_mui_as_withdrawn_for_prefix`, `mark_mui_as_active_for_prefix`,
+    ///   and the withdrawals by `evict_older_than` and `sweep_stale`. These
+    ///   change the status of the record in place, so the record is
+    ///   returned with its current status, also for an ltime before the
+    ///   change.
+    /// * records that were removed from the store. These are not returned
+    ///   at all, also not for an ltime before they were removed.
+    /// * the global withdrawn status of multi_uniq_ids, which is ignored.
+    pub fn match_prefix_as_of<'a>(
+        &'a self,
+        search_pfx: &Prefix,
+        options: &MatchOptions,
+        ltime: u64,
+        guard: &'a Guard,
+    ) -> QueryResult<M> {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(addr) => self.v4.match_prefix_as_of(
+                PrefixId::<IPv4>::new(addr.into(), search_pfx.len()),
+                options,
+                ltime,
+                guard,
+            ),
+            std::net::IpAddr::V6(addr) => self.v6.match_prefix_as_of(
+                PrefixId::<IPv6>::new(addr.into(), search_pfx.len()),
+                options,
+                ltime,
+                guard,
+            ),
+        }
+    }
+
+    /// Returns an unordered iterator over all prefixes in the store, with
+    /// their records as they were at the logical time `ltime`, including
+    /// withdrawn records. Prefixes that had no records at `ltime` are left
+    /// out. The same limitations as for `match_prefix_as_of` apply.
+    pub fn prefixes_iter_as_of<'a>(
+        &'a self,
+        ltime: u64,
+        guard: &'a Guard,
+    ) -> impl Iterator<Item = PrefixRecord<M>> + 'a {
+        self.v4
+            .store
+            .prefixes_iter_as_of(ltime, guard)
+            .map(PrefixRecord::from)
+            .chain(
+                self.v6
+                    .store
+                    .prefixes_iter_as_of(ltime, guard)
+                    .map(PrefixRecord::from),
+            )
+    }
+
+    /// Calculate and store the best and backup path for `search_pfx`,
+    /// from its eligible records only.
+    ///
+    /// The best and backup path are selected with the path selector of the
+    /// store, see `with_path_selector`. Records that the path selector
+    /// does not find eligible are left out. For the default path selector
+    /// these are the records with a local status other than Active, and
+    /// the records for multi_uniq_ids that are withdrawn globally. If
+    /// `eligible` is given, a record is only eligible if it also returns
+    /// true for its multi_uniq_id and meta-data. Without `eligible`, this
+    /// works like `calculate_and_store_best_and_backup_path`.
+    ///
+    /// Returns a `StoreNotReadyError` if the prefix is not in the store.
+    pub fn calculate_and_store_eligible_best_and_backup_path(
+        &self,
+        search_pfx: &Prefix,
+        tbi: &M::TBI,
+        eligible: Option<EligibleFn<'_, M>>,
+        guard: &Guard,
+    ) -> Result<(Option<u32>, Option<u32>), PrefixStoreError> {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(_addr) => {
+                self.v4.store.calculate_and_store_best_backup(
+                    PrefixId::<IPv4>::from(*search_pfx),
+                    tbi,
+                    None,
+                    eligible,
+                    guard,
+                )
+            }
+            std::net::IpAddr::V6(_addr) => {
+                self.v6.store.calculate_and_store_best_backup(
+                    PrefixId::<IPv6>::from(*search_pfx),
+                    tbi,
+                    None,
+                    eligible,
+                    guard,
+                )
+            }
+        }
+    }
+
+    /// Returns the multi_uniq_ids of all the eligible paths for
+    /// `search_pfx`, ranked from best to worst, e.g. for advertising
+    /// multiple paths with ADD-PATH.
+    ///
+    /// The paths are ranked by the path selector of the store. The default
+    /// path selector ranks them by the orderables of their meta-data, and
+    /// it leaves out records with a local status other than Active, and
+    /// records for multi_uniq_ids that are withdrawn globally. There is no
+    /// `eligible` predicate, so the first two paths are only the stored
+    /// best and backup path if these were selected by the path selector of
+    /// the store, without a predicate.
+    ///
+    /// The ranking is cached with the prefix, together with `tbi`, until
+    /// its records change. Asking for the ranking with another `tbi`
+    /// calculates it again.
+    ///
+    /// Returns a `StoreNotReadyError` if the prefix is not in the store.
+    pub fn ranked_paths(
+        &self,
+        search_pfx: &Prefix,
+        tbi: &M::TBI,
+        guard: &Guard,
+    ) -> Result<Vec<u32>, PrefixStoreError>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.store.ranked_paths(
+                PrefixId::<IPv4>::from(*search_pfx),
+                tbi,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.store.ranked_paths(
+                PrefixId::<IPv6>::from(*search_pfx),
+                tbi,
+                guard,
+            ),
+        }
+    }
+
+    /// Returns the records of the `n` best eligible paths for
+    /// `search_pfx`, best first.
+    ///
+    /// See `ranked_paths` for how the paths are ranked. Fewer than `n`
+    /// records are returned if there are fewer eligible paths. The records
+    /// are returned for all the paths that the path selector of the store
+    /// finds eligible, whatever their status is. Records for
+    /// multi_uniq_ids that are withdrawn globally have the Withdrawn
+    /// status, like the path selector saw them, and stale records have the
+    /// Stale status.
+    ///
+    /// Returns a `StoreNotReadyError` if the prefix is not in the store.
+    pub fn top_paths(
+        &self,
+        search_pfx: &Prefix,
+        n: usize,
+        tbi: &M::TBI,
+        guard: &Guard,
+    ) -> Result<Vec<Record<M>>, PrefixStoreError>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.store.top_paths(
+                PrefixId::<IPv4>::from(*search_pfx),
+                n,
+                tbi,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.store.top_paths(
+                PrefixId::<IPv6>::from(*search_pfx),
+                n,
+                tbi,
+                guard,
+            ),
+        }
+    }
+
+    /// Returns the multi_uniq_ids of the eligible paths for `search_pfx`
+    /// that can be used together with its best path under `policy`, e.g.
+    /// to install them as ECMP next hops, best first.
+    ///
+    /// The paths are ranked like `ranked_paths` does, and a path is in the
+    /// set if `PathSelector::is_multipath_equivalent` of the path selector
+    /// of the store says it is equivalent to the best path. The default
+    /// implementation of that leaves it to `Meta::is_multipath_equivalent`,
+    /// so a custom path selector that ranks by other criteria than the
+    /// orderables should implement it as well. The best path itself is
+    /// always in the set, so the set is only empty if there are no eligible
+    /// paths.
+    ///
+    /// Returns a `StoreNotReadyError` if the prefix is not in the store.
+    pub fn multipath(
+        &self,
+        search_pfx: &Prefix,
+        tbi: &M::TBI,
+        policy: MultipathPolicy,
+        guard: &Guard,
+    ) -> Result<Vec<u32>, PrefixStoreError>
+    where
+        M::TBI: PartialEq + Send + Sync + 'static,
+    {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.store.multipath(
+                PrefixId::<IPv4>::from(*search_pfx),
+                tbi,
+                policy,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.store.multipath(
+                PrefixId::<IPv6>::from(*search_pfx),
+                tbi,
+                policy,
+                guard,
+            ),
+        }
+    }
+
+    /// Calculate and store the best and backup path for `search_pfx` with
+    /// `selector`, instead of the path selector of the store.
+    ///
+    /// This works like `calculate_and_store_eligible_best_and_backup_path`
+    /// without an `eligible` predicate otherwise. Note that the next insert
+    /// with path selection for the prefix uses the path selector of the
+    /// store again.
+    ///
+    /// Returns a `StoreNotReadyError` if the prefix is not in the store.
+    pub fn calculate_and_store_best_and_backup_path_with(
+        &self,
+        search_pfx: &Prefix,
+        tbi: &M::TBI,
+        selector: &dyn PathSelector<M>,
+        guard: &Guard,
+    ) -> Result<(Option<u32>, Option<u32>), PrefixStoreError> {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(_addr) => {
+                self.v4.store.calculate_and_store_best_backup(
+                    PrefixId::<IPv4>::from(*search_pfx),
+                    tbi,
+                    Some(selector),
+                    None,
+                    guard,
+                )
+            }
+            std::net::IpAddr::V6(_addr) => {
+                self.v6.store.calculate_and_store_best_backup(
+                    PrefixId::<IPv6>::from(*search_pfx),
+                    tbi,
+                    Some(selector),
+                    None,
+                    guard,
+                )
+            }
+        }
+    }
+
+    /// Returns the multi_uniq_ids of all the paths for `search_pfx` that
+    /// `selector` finds eligible, ranked from best to worst by `selector`.
+    ///
+    /// Unlike `ranked_paths`, this does not use the path selector of the
+    /// store, nor the cached ranking, so the ranking is calculated on every
+    /// call. The status of records for multi_uniq_ids that are withdrawn
+    /// globally is Withdrawn for `selector`.
+    ///
+    /// Returns a `StoreNotReadyError` if the prefix is not in the store.
+    pub fn rank_paths_with(
+        &self,
+        search_pfx: &Prefix,
+        tbi: &M::TBI,
+        selector: &dyn PathSelector<M>,
+        guard: &Guard,
+    ) -> Result<Vec<u32>, PrefixStoreError> {
+        match search_pfx.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.store.rank_paths(
+                PrefixId::<IPv4>::from(*search_pfx),
+                tbi,
+                selector,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.store.rank_paths(
+                PrefixId::<IPv6>::from(*search_pfx),
+                tbi,
+                selector,
+                guard,
+            ),
+        }
+    }
+
+    /// Change the status of all records for IPv6 prefixes for this
+    /// `multi_uniq_id` globally to Withdrawn. This is the IPv6 counterpart
+    /// of `mark_mui_as_withdrawn_v4`.
+    pub fn mark_mui_as_withdrawn_v6(
+        &self,
+        mui: u32,
+    ) -> Result<(), PrefixStoreError> {
+        let guard = &epoch::pin();
+        self.v6.store.mark_mui_as_withdrawn(mui, guard)
+    }
+
+    /// Mark all the records for a multi_uniq_id as stale, e.g. when the
+    /// BGP session for it goes down during a Graceful Restart. This only
+    /// sets a flag for the multi_uniq_id, it does not visit any records.
+    ///
+    /// Records for this multi_uniq_id that are inserted after this are no
+    /// longer stale. The records that are still stale can be removed, or
+    /// withdrawn, with `sweep_stale`. Stale records keep their local status
+    /// until they are swept, so they stay in use for path selection, but
+    /// `match_prefix` returns the Active ones with the Stale status (unless
+    /// their multi_uniq_id is withdrawn globally). Use `mui_is_stale` to
+    /// find out whether a multi_uniq_id is stale.
+    pub fn mark_mui_as_stale(&self, mui: u32) -> Result<(), PrefixStoreError> {
+        let guard = &epoch::pin();
+        self.v4.store.mark_mui_as_stale(mui, guard)?;
+        self.v6.store.mark_mui_as_stale(mui, guard)
+    }
+
+    /// Whether the multi_uniq_id is marked as stale.
+    pub fn mui_is_stale(&self, mui: u32) -> bool {
+        let guard = &epoch::pin();
+        self.v4.store.mui_is_stale(mui, guard)
+            || self.v6.store.mui_is_stale(mui, guard)
+    }
+
+    /// Sweep all the records for a stale multi_uniq_id that were not
+    /// inserted again since it was marked as stale. Depending on `remove`
+    /// the records are either removed, or their local status is set to
+    /// `Withdrawn`. Prefixes that are left without any records are removed
+    /// from the store. Afterwards the multi_uniq_id is no longer stale.
+    ///
+    /// Returns the prefixes that had a record swept. Sweeping a
+    /// multi_uniq_id that is not stale does nothing.
+    pub fn sweep_stale(
+        &self,
+        mui: u32,
+        remove: bool,
+    ) -> Result<Vec<Prefix>, PrefixStoreError> {
+        let guard = &epoch::pin();
+        let mut swept = self
+            .v4
+            .store
+            .sweep_stale(mui, remove, guard)?
+            .into_iter()
+            .map(|p| p.into_pub())
+            .collect::<Vec<_>>();
+        swept.extend(
+            self.v6
+                .store
+                .sweep_stale(mui, remove, guard)?
+                .into_iter()
+                .map(|p| p.into_pub()),
+        );
+
+        Ok(swept)
+    }
+
+    /// Sweep the records for IPv4 prefixes for a stale multi_uniq_id, like
+    /// `sweep_stale` does. The multi_uniq_id stays stale for IPv6
+    /// prefixes, e.g. until the routes for those are in as well.
+    pub fn sweep_stale_v4(
+        &self,
+        mui: u32,
+        remove: bool,
+    ) -> Result<Vec<Prefix>, PrefixStoreError> {
+        let guard = &epoch::pin();
+        Ok(self
+            .v4
+            .store
+            .sweep_stale(mui, remove, guard)?
+            .into_iter()
+            .map(|p| p.into_pub())
+            .collect())
+    }
+
+    /// Sweep the records for IPv6 prefixes for a stale multi_uniq_id. This
+    /// is the IPv6 counterpart of `sweep_stale_v4`.
+    pub fn sweep_stale_v6(
+        &self,
+        mui: u32,
+        remove: bool,
+    ) -> Result<Vec<Prefix>, PrefixStoreError> {
+        let guard = &epoch::pin();
+        Ok(self
+            .v6
+            .store
+            .sweep_stale(mui, remove, guard)?
+            .into_iter()
+            .map(|p| p.into_pub())
+            .collect())
+    }
+
+    /// Evict all the records with an `ltime` older than (i.e. smaller
+    /// than) the specified `ltime` from the store. Depending on the
+    /// `remove` field in `options` the records are either removed, or
+    /// their local status is set to `Withdrawn`. Prefixes that are left
+    /// without any records are removed from the store.
+    ///
+    /// The records to evict can be restricted to a set of multi_uniq_ids,
+    /// and to a prefix and its more-specifics, through `options`.
+    ///
+    /// Returns a report with the number of evicted records, and the number
+    /// of removed prefixes.
+    pub fn evict_older_than(
+        &self,
+        ltime: u64,
+        options: &EvictOptions,
+    ) -> Result<EvictReport, PrefixStoreError> {
+        let guard = &epoch::pin();
+        let muis = options.muis.as_ref();
+
+        match options.prefix {
+            Some(prefix) => match prefix.addr() {
+                std::net::IpAddr::V4(_addr) => self.v4.store.evict_older_than(
+                    ltime,
+                    muis,
+                    Some(PrefixId::<IPv4>::from(prefix)),
+                    options.remove,
+                    guard,
+                ),
+                std::net::IpAddr::V6(_addr) => self.v6.store.evict_older_than(
+                    ltime,
+                    muis,
+                    Some(PrefixId::<IPv6>::from(prefix)),
+                    options.remove,
+                    guard,
+                ),
+            },
+            None => {
+                let v4_report = self.v4.store.evict_older_than(
+                    ltime,
+                    muis,
+                    None,
+                    options.remove,
+                    guard,
+                )?;
+                let v6_report = self.v6.store.evict_older_than(
+                    ltime,
+                    muis,
+                    None,
+                    options.remove,
+                    guard,
+                )?;
+
+                Ok(EvictReport {
+                    records_evicted: v4_report.records_evicted
+                        + v6_report.records_evicted,
+                    prefixes_emptied: v4_report.prefixes_emptied
+                        + v6_report.prefixes_emptied,
+                })
+            }
+        }
+    }
+
+    /// Apply the announcements and withdrawals in a BGP UPDATE message for
+    /// the peer with `mui` to the store.
+    ///
+    /// The IPv4 unicast NLRI in the conventional fields, and the IPv4 and
+    /// IPv6 unicast NLRI in the MP_REACH_NLRI and MP_UNREACH_NLRI
+    /// attributes are applied, with `ltime` as the ltime of the records.
+    /// For every announced prefix, `meta_builder` is called with the prefix
+    /// and the path attributes of the message. The meta-data it returns is
+    /// inserted as a record with the Active status, or the prefix is
+    /// skipped if it returns `None`. Withdrawn prefixes are withdrawn as
+    /// with `withdraw`. NLRI of other address families, and NLRI with
+    /// ADD-PATH path ids are skipped. Best path selection is not run for
+    /// the updated prefixes.
+    ///
+    /// All the NLRI and the path attributes are parsed before anything is
+    /// applied, so a message that can't be parsed leaves the store
+    /// untouched, and returns an `InvalidBgpUpdate` error.
+    ///
+    /// Returns a report with the number of new, updated, withdrawn and
+    /// skipped prefixes.
+    pub fn apply_bgp_update<O, F>(
+        &self,
+        mui: u32,
+        ltime: u64,
+        update: &UpdateMessage<O>,
+        meta_builder: F,
+    ) -> Result<BgpUpdateReport, PrefixStoreError>
+    where
+        O: Octets,
+        F: FnMut(&Prefix, &OwnedPathAttributes) -> Option<M>,
+    {
+        let changes = Self::bgp_changes(update, meta_builder)?;
+        self.apply_bgp_changes(mui, ltime, changes)
+    }
+
+    // Parse the unicast NLRI in a BGP UPDATE message, and build the
+    // meta-data for the announced prefixes.
+    pub(crate) fn bgp_changes<O, F>(
+        update: &UpdateMessage<O>,
+        mut meta_builder: F,
+    ) -> Result<BgpChanges<M>, PrefixStoreError>
+    where
+        O: Octets,
+        F: FnMut(&Prefix, &OwnedPathAttributes) -> Option<M>,
+    {
+        let announcements = update
+            .announcements_vec()
+            .map_err(|_| PrefixStoreError::InvalidBgpUpdate)?;
+        let withdrawals = update
+            .withdrawals_vec()
+            .map_err(|_| PrefixStoreError::InvalidBgpUpdate)?;
+        let attributes = OwnedPathAttributes::from(
+            update
+                .path_attributes()
+                .map_err(|_| PrefixStoreError::InvalidBgpUpdate)?,
+        );
+
+        let mut changes = BgpChanges {
+            withdrawals: vec![],
+            announcements: vec![],
+            skipped: 0,
+        };
+        for nlri in withdrawals.iter() {
+            match unicast_prefix(nlri) {
+                Some(prefix) => changes.withdrawals.push(prefix),
+                None => changes.skipped += 1,
+            }
+        }
+        for nlri in announcements.iter() {
+            let meta = unicast_prefix(nlri).and_then(|prefix| {
+                meta_builder(&prefix, &attributes).map(|meta| (prefix, meta))
+            });
+            match meta {
+                Some(announcement) => {
+                    changes.announcements.push(announcement)
+                }
+                None => changes.skipped += 1,
+            }
+        }
+        Ok(changes)
+    }
+
+    pub(crate) fn apply_bgp_changes(
+        &self,
+        mui: u32,
+        ltime: u64,
+        changes: BgpChanges<M>,
+    ) -> Result<BgpUpdateReport, PrefixStoreError> {
+        let mut report = BgpUpdateReport {
+            prefixes_skipped: changes.skipped,
+            ..Default::default()
+        };
+        for prefix in changes.withdrawals {
+            match self.withdraw(&prefix, mui, ltime) {
+                Ok(true) => report.prefixes_withdrawn += 1,
+                Ok(false) | Err(PrefixStoreError::PrefixNotFound) => {
+                    report.prefixes_skipped += 1
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        for (prefix, meta) in changes.announcements {
+            let upsert = self.insert(
+                &prefix,
+                Record::new(mui, ltime, RouteStatus::Active, meta),
+                None,
+            )?;
+            report.cas_count += upsert.cas_count;
+            if upsert.mui_new {
+                report.prefixes_new += 1;
+            } else {
+                report.prefixes_updated += 1;
+            }
+        }
+
+        trace!("applied BGP UPDATE for mui {}: {:?}", mui, report);
+        Ok(report)
+    }
+
+    /// Insert `record` for `prefix`, unless the store has a record for the
+    /// same (prefix, multi_uniq_id) with a newer ltime.
+    ///
+    /// This works like `insert`, but the ltime of the record is compared
+    /// with the ltime of the record it would replace, in the same atomic
+    /// operation that replaces it, so that an older update that arrives
+    /// late on one thread cannot overwrite a newer update that was stored
+    /// by another thread. A record with the same ltime as the stored record
+    /// does replace it. The `applied` field of the returned report is false
+    /// if the record was rejected, in which case nothing else changed.
+    pub fn insert_if_newer(
+        &self,
+        prefix: &Prefix,
+        record: Record<M>,
+        update_path_selections: Option<M::TBI>,
+    ) -> Result<UpsertReport, PrefixStoreError> {
+        let guard = &epoch::pin();
+        match prefix.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.insert_with_guard(
+                PrefixId::<IPv4>::from(*prefix),
+                record.into(),
+                update_path_selections,
+                UpsertMode::IfNewer,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.insert_with_guard(
+                PrefixId::<IPv6>::from(*prefix),
+                record.into(),
+                update_path_selections,
+                UpsertMode::IfNewer,
+                guard,
+            ),
+        }
+    }
+
+    /// Insert a record for `prefix` and `mui`, with the meta-data that
+    /// `merge` creates from the meta-data of the current record for the
+    /// (prefix, mui), or from `None` if there is none.
+    ///
+    /// This is a read-copy-update: `merge` is called inside the atomic
+    /// operation that replaces the record, so it always sees the record
+    /// that it replaces, even if other threads update the same (prefix,
+    /// mui) at the same time, and no update gets lost. `merge` can be
+    /// called more than once if other threads update the same (prefix,
+    /// mui) at the same time, so it should not have side effects. It is
+    /// only called with `None` if there is no record yet, and the result of
+    /// that call is dropped if another thread inserts a record first. The
+    /// record gets `ltime` and the Active status. Otherwise this works like
+    /// `insert`.
+    pub fn insert_with(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+        ltime: u64,
+        merge: impl Fn(Option<&M>) -> M,
+        update_path_selections: Option<M::TBI>,
+    ) -> Result<UpsertReport, PrefixStoreError> {
+        let guard = &epoch::pin();
+        let record = NewRecord::Merge {
+            mui,
+            ltime,
+            merge: &merge,
+        };
+        match prefix.addr() {
+            std::net::IpAddr::V4(_addr) => self.v4.insert_with_guard(
+                PrefixId::<IPv4>::from(*prefix),
+                record,
+                update_path_selections,
+                UpsertMode::Replace,
+                guard,
+            ),
+            std::net::IpAddr::V6(_addr) => self.v6.insert_with_guard(
+                PrefixId::<IPv6>::from(*prefix),
+                record,
+                update_path_selections,
+                UpsertMode::Replace,
+                guard,
+            ),
+        }
+    }
+
+    /// Insert all the `records` into the store, on the calling thread.
+    ///
+    /// The records are sorted by address family, prefix length and address
+    /// first, so that the nodes for shorter prefixes are in place before
+    /// the more-specifics under them are inserted, and they are inserted
+    /// without pinning the epoch for every record. Best path selection is
+    /// not run for the inserted prefixes.
+    ///
+    /// Inserting stops at the first record that cannot be inserted, leaving
+    /// the records before it in the store. Returns a report with the number
+    /// of inserted and updated records, the number of new prefixes, and the
+    /// total number of Compare-and-Swap operations.
+    pub fn insert_bulk<I>(
+        &self,
+        records: I,
+    ) -> Result<BulkReport, PrefixStoreError>
+    where
+        I: IntoIterator<Item = (Prefix, Record<M>)>,
+    {
+        let mut records = records.into_iter().collect::<Vec<_>>();
+        records.sort_by_key(|(prefix, _)| bulk_order(prefix));
+        let report = self.insert_sorted(records)?;
+        trace!("bulk inserted records: {:?}", report);
+        Ok(report)
+    }
+
+    /// Insert all the `records` into the store, using `threads` threads.
+    /// Zero threads means one thread for every available CPU.
+    ///
+    /// The records are partitioned by the node they end up under at the end
+    /// of the first stride, and every partition is inserted by one thread,
+    /// like `insert_bulk` does, so that threads rarely contend for the same
+    /// nodes. The records for prefixes that are shorter than the first
+    /// stride all live in the root node, so these are inserted up front, by
+    /// the calling thread. Best path selection is not run for the inserted
+    /// prefixes.
+    ///
+    /// The records for the same (prefix, mui) combination are inserted in
+    /// the order they come in. If a record cannot be inserted, the thread
+    /// that inserts it stops, and its error is returned once all the other
+    /// threads are done.
+    pub fn par_insert_bulk<I>(
+        &self,
+        records: I,
+        threads: usize,
+    ) -> Result<BulkReport, PrefixStoreError>
+    where
+        I: IntoIterator<Item = (Prefix, Record<M>)>,
+    {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let first_stride_v4 = self.v4.store.get_stride_sizes()[0];
+        let first_stride_v6 = self.v6.store.get_stride_sizes()[0];
+
+        let mut short = vec![];
+        let mut partitions: HashMap<(bool, u32), Vec<_>> = HashMap::new();
+        for (prefix, record) in records {
+            let key = match prefix.addr() {
+                IpAddr::V4(addr) if prefix.len() >= first_stride_v4 => {
+                    (false, u32::from(addr) >> (32 - first_stride_v4))
+                }
+                IpAddr::V6(addr) if prefix.len() >= first_stride_v6 => {
+                    (true, (u128::from(addr) >> (128 - first_stride_v6)) as u32)
+                }
+                _ => {
+                    short.push((prefix, record));
+                    continue;
+                }
+            };
+            partitions.entry(key).or_default().push((prefix, record));
+        }
+
+        short.sort_by_key(|(prefix, _)| bulk_order(prefix));
+        let mut report = self.insert_sorted(short)?;
+
+        // The partitions are popped off the end, so the biggest ones go
+        // first.
+        let mut partitions = partitions.into_values().collect::<Vec<_>>();
+        partitions.sort_by_key(|partition| partition.len());
+        trace!("bulk inserting {} partitions", partitions.len());
+
+        let partitions = Mutex::new(partitions);
+        let reports = std::thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|i| {
+                    std::thread::Builder::new()
+                        .name(format!("bulk-insert-{}", i))
+                        .spawn_scoped(s, || {
+                            let mut report = BulkReport::default();
+                            loop {
+                                let partition =
+                                    partitions.lock().unwrap().pop();
+                                match partition {
+                                    Some(mut partition) => {
+                                        partition.sort_by_key(|(prefix, _)| {
+                                            bulk_order(prefix)
+                                        });
+                                        report.merge(
+                                            self.insert_sorted(partition)?,
+                                        );
+                                    }
+                                    None => return Ok(report),
+                                }
+                            }
+                        })
+                        .expect("cannot spawn bulk insert thread")
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<Result<_, PrefixStoreError>>>()
+        });
+        for thread_report in reports {
+            report.merge(thread_report?);
+        }
+
+        trace!("bulk inserted records: {:?}", report);
+        Ok(report)
+    }
+
+    // Insert the records, in the order they are in, with a guard that is
+    // repinned every BULK_REPIN_INTERVAL records.
+    fn insert_sorted(
+        &self,
+        records: Vec<(Prefix, Record<M>)>,
+    ) -> Result<BulkReport, PrefixStoreError> {
+        let mut report = BulkReport::default();
+        let mut guard = epoch::pin();
+        for (i, (prefix, record)) in records.into_iter().enumerate() {
+            if i > 0 && i % BULK_REPIN_INTERVAL == 0 {
+                guard.repin();
+            }
+            let upsert = match prefix.addr() {
+                IpAddr::V4(_addr) => self.v4.insert_with_guard(
+                    PrefixId::<IPv4>::from(prefix),
+                    record.into(),
+                    None,
+                    UpsertMode::Replace,
+                    &guard,
+                ),
+                IpAddr::V6(_addr) => self.v6.insert_with_guard(
+                    PrefixId::<IPv6>::from(prefix),
+                    record.into(),
+                    None,
+                    UpsertMode::Replace,
+                    &guard,
+                ),
+            }?;
+            report.add(upsert);
+        }
+        Ok(report)
+    }
+}
+
+impl<M: PersistMeta> DefaultStore<M> {
+    /// Write the whole store to `writer`, in a versioned binary format that
+    /// can be read back with `load_from`. This includes all the records
+    /// (with their history), the global withdrawn and stale statuses of the
+    /// multi_uniq_ids and the path selections for all the prefixes.
+    ///
+    /// Writing happens while the store stays available for reading and
+    /// writing. Prefixes that are inserted or removed while writing is in
+    /// progress may or may not end up in the written store. The writer is
+    /// written to in small pieces, so it is a good idea to wrap it in a
+    /// `BufWriter` if it isn't buffered already.
+    pub fn save_to<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), PersistError> {
+        let guard = &epoch::pin();
+        persist::write_header(writer, self.history_retention())?;
+        self.v4.write_section(writer, guard)?;
+        self.v6.write_section(writer, guard)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Create a new store from a store that was written with `save_to`.
+    ///
+    /// The prefixes are stored using multiple threads, and the path
+    /// selections are restored as they were written, instead of being
+    /// calculated again.
+    pub fn load_from<R: std::io::Read>(
+        reader: &mut R,
+    ) -> Result<Self, PersistError> {
+        let store = Self::new().map_err(|_| {
+            PersistError::Store(PrefixStoreError::StoreNotReadyError)
+        })?;
+        let guard = &epoch::pin();
+        let retention = persist::read_header(reader)?;
+        store.v4.read_section(reader, guard)?;
+        store.v6.read_section(reader, guard)?;
+        store.set_history_retention(retention);
+        Ok(store)
+    }
+
+    /// Rebuild a store after a crash, from the latest snapshot (written by
+    /// `save_to`, or `JournaledStore::snapshot`) and the journal that was
+    /// written by a `JournaledStore` since that snapshot was taken. Without
+    /// a snapshot the journal is replayed on an empty store.
+    ///
+    /// An incomplete entry at the end of the journal is ignored. Entries
+    /// that failed to apply to the store before the crash fail again when
+    /// they are replayed, which is logged as a warning. Path selections for
+    /// the prefixes that were inserted by the journal are outdated.
+    pub fn recover<S: std::io::Read, J: std::io::Read>(
+        snapshot: Option<&mut S>,
+        journal: &mut J,
+    ) -> Result<Self, PersistError> {
+        let store = match snapshot {
+            Some(snapshot) => Self::load_from(snapshot)?,
+            None => Self::new().map_err(|_| {
+                PersistError::Store(PrefixStoreError::StoreNotReadyError)
+            })?,
+        };
+        let count = journal::replay(&store, journal)?;
+        trace!("replayed {} journal entries", count);
+        Ok(store)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<M> DefaultStore<M>
+where
+    M: Meta + serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Write all the prefixes in the store to `writer` as NDJSON, i.e. one
+    /// JSON object per line, each of which is a serialized `PrefixRecord`
+    /// with all its records, with any status. Returns the number of
+    /// prefixes written.
+    ///
+    /// Unlike `save_to`, this does not write the history of the records,
+    /// the global statuses of the multi_uniq_ids, or the path selections.
+    /// Prefixes that are inserted or removed while writing is in progress
+    /// may or may not end up in the output.
+    pub fn dump_ndjson<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, NdjsonError> {
+        let guard = &epoch::pin();
+        let mut count = 0;
+        for record in self.prefixes_iter(guard) {
+            count += 1;
+            serde_json::to_writer(&mut *writer, &record)
+                .map_err(|err| NdjsonError::Json(count, err))?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Insert all the prefixes from NDJSON, as written by `dump_ndjson`,
+    /// into the store. Returns the number of prefixes read.
+    ///
+    /// The records are inserted as they are, i.e. with their ltime and
+    /// status, into whatever is in the store already. Empty lines are
+    /// skipped. Reading stops at the first line that cannot be parsed or
+    /// stored, leaving the prefixes of the lines before it in the store.
+    pub fn load_ndjson<R: std::io::BufRead>(
+        &self,
+        reader: R,
+    ) -> Result<usize, NdjsonError> {
+        let mut count = 0;
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: PrefixRecord<M> = serde_json::from_str(&line)
+                .map_err(|err| NdjsonError::Json(index + 1, err))?;
+            for rec in record.meta {
+                self.insert(&record.prefix, rec, None)
+                    .map_err(|err| NdjsonError::Store(index + 1, err))?;
+            }
+            count += 1;
+        }
+        trace!("loaded {} prefixes from NDJSON", count);
+        Ok(count)
+    }
+}
+
 impl<
         M: Meta,
         NB: NodeBuckets<IPv4>,
@@ -39,3 +1308,27 @@ impl<
         )
     }
 }
+
+// The prefixes withdrawn by a BGP UPDATE message, and the prefixes it
+// announces with their meta-data, as applied by `apply_bgp_update`.
+pub(crate) struct BgpChanges<M> {
+    pub(crate) withdrawals: Vec<Prefix>,
+    pub(crate) announcements: Vec<(Prefix, M)>,
+    // The number of NLRI that are skipped already.
+    pub(crate) skipped: usize,
+}
+
+// The prefix of IPv4 and IPv6 unicast NLRI.
+fn unicast_prefix<O>(nlri: &Nlri<O>) -> Option<Prefix> {
+    match nlri {
+        Nlri::Ipv4Unicast(nlri) => Some(nlri.prefix()),
+        Nlri::Ipv6Unicast(nlri) => Some(nlri.prefix()),
+        _ => None,
+    }
+}
+
+// The order in which a bulk insert inserts prefixes: shorter prefixes
+// before longer ones, for each address family.
+fn bulk_order(prefix: &Prefix) -> (bool, u8, IpAddr) {
+    (prefix.is_v6(), prefix.len(), prefix.addr())
+}
@@ -7,7 +7,10 @@ pub enum PrefixStoreError {
     StoreNotReadyError,
     PathSelectionOutdated,
     PrefixNotFound,
-    BestPathNotFound
+    BestPathNotFound,
+    InvalidBgpUpdate,
+    ChangeLogNotEnabled,
+    ChangeLogOverrun,
 }
 
 impl std::error::Error for PrefixStoreError {}
@@ -34,6 +37,103 @@ impl fmt::Display for PrefixStoreError {
             PrefixStoreError::BestPathNotFound => {
                 write!(f, "Error: The Prefix does not have a stored best path.")
             }
+            PrefixStoreError::InvalidBgpUpdate => {
+                write!(f, "Error: The BGP UPDATE message cannot be parsed.")
+            }
+            PrefixStoreError::ChangeLogNotEnabled => {
+                write!(f, "Error: The change log is not enabled.")
+            }
+            PrefixStoreError::ChangeLogOverrun => {
+                write!(f, "Error: The changes since the cursor were dropped from the change log, a full resync is needed.")
+            }
         }
     }
 }
+
+//------------ PersistError --------------------------------------------------
+
+#[derive(Debug)]
+pub enum PersistError {
+    Io(std::io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    InvalidData(&'static str),
+    Meta(Box<dyn std::error::Error + Send + Sync>),
+    Store(PrefixStoreError),
+}
+
+impl std::error::Error for PersistError {}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistError::Io(err) => write!(f, "Error: I/O error: {}", err),
+            PersistError::InvalidMagic => {
+                write!(f, "Error: Not a persisted store.")
+            }
+            PersistError::UnsupportedVersion(version) => write!(
+                f,
+                "Error: Unsupported persisted store version {}.",
+                version
+            ),
+            PersistError::InvalidData(msg) => {
+                write!(f, "Error: Invalid persisted store data: {}.", msg)
+            }
+            PersistError::Meta(err) => {
+                write!(f, "Error: Cannot read meta-data: {}", err)
+            }
+            PersistError::Store(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for PersistError {
+    fn from(value: std::io::Error) -> Self {
+        PersistError::Io(value)
+    }
+}
+
+impl From<PrefixStoreError> for PersistError {
+    fn from(value: PrefixStoreError) -> Self {
+        PersistError::Store(value)
+    }
+}
+
+//------------ NdjsonError ---------------------------------------------------
+
+/// An error while dumping a store to, or loading it from, NDJSON
+///
+/// The variants that are tied to a line of the NDJSON carry its line
+/// number, starting at 1.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum NdjsonError {
+    Io(std::io::Error),
+    Json(usize, serde_json::Error),
+    Store(usize, PrefixStoreError),
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for NdjsonError {}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for NdjsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NdjsonError::Io(err) => write!(f, "Error: I/O error: {}", err),
+            NdjsonError::Json(line, err) => {
+                write!(f, "Error: Invalid JSON on line {}: {}", line, err)
+            }
+            NdjsonError::Store(line, err) => {
+                write!(f, "Error: Cannot store line {}: {}", line, err)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<std::io::Error> for NdjsonError {
+    fn from(value: std::io::Error) -> Self {
+        NdjsonError::Io(value)
+    }
+}
@@ -8,7 +8,7 @@
 // individual nodes. The Node Iterators live in the node.rs file.
 use std::sync::atomic::Ordering;
 
-use super::atomic_types::{NodeBuckets, PrefixBuckets, PrefixSet};
+use super::atomic_types::{NodeBuckets, PrefixBuckets, PrefixSet, StoredPrefix};
 use super::custom_alloc::CustomAllocStorage;
 use crate::local_array::store::atomic_types::RouteStatus;
 use crate::prefix_record::PublicRecord;
@@ -49,9 +49,25 @@ pub(crate) struct PrefixIter<
     // which is the max number of of both IPv4 and IPv6.
     parents: [Option<(&'a PrefixSet<AF, M>, usize)>; 26],
     cursor: usize,
+    // Return the records as they were at this ltime, instead of the current
+    // records.
+    ltime: Option<u64>,
     guard: &'a Guard,
 }
 
+impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
+    PrefixIter<'a, AF, M, PB>
+{
+    fn records(&self, stored_prefix: &StoredPrefix<AF, M>) -> Vec<PublicRecord<M>> {
+        match self.ltime {
+            Some(ltime) => {
+                stored_prefix.record_map.as_records_at(ltime, None, true)
+            }
+            None => stored_prefix.record_map.as_records(),
+        }
+    }
+}
+
 impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
     Iterator for PrefixIter<'a, AF, M, PB>
 {
@@ -73,6 +89,24 @@ impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
             }
 
             if PB::get_bits_for_len(self.cur_len, self.cur_level) == 0 {
+                // The default route is the only prefix with length zero, it
+                // lives in the only slot of its PrefixSet. The cursor marks
+                // whether we've been there.
+                if self.cur_len == 0 && self.cursor == 0 {
+                    self.cursor = 1;
+                    if let Some(p) = self
+                        .cur_bucket
+                        .get_by_index(0, self.guard)
+                        .get_stored_prefix(self.guard)
+                    {
+                        let meta = self.records(p);
+                        if !meta.is_empty() {
+                            return Some((p.prefix.into_pub(), meta));
+                        }
+                    }
+                    continue;
+                }
+
                 // END OF THE LENGTH
 
                 // This length is done too, go to the next length
@@ -182,16 +216,21 @@ impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
                     self.cursor = 0;
 
                     // If there's a child here there MUST be a prefix here,
-                    // as well.
+                    // as well. It may have been removed though, leaving
+                    // only an empty StoredPrefix behind to hold on to the
+                    // child.
                     if let Some(meta) =
                         s_pfx.get_stored_prefix(self.guard).map(|p| {
                             if log_enabled!(log::Level::Trace) {
                                 // There's a prefix here, that's the next one
                                 trace!("D. found prefix {:?}", p.prefix);
                             }
-                            p.record_map.as_records()
+                            self.records(p)
                         })
                     {
+                        if meta.is_empty() {
+                            continue;
+                        }
                         return Some((
                             s_pfx.get_prefix_id().into_pub(),
                             meta,
@@ -209,10 +248,13 @@ impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
                             if log_enabled!(log::Level::Debug) {
                                 debug!("E. found prefix {:?}", p.prefix);
                             }
-                            p.record_map.as_records()
+                            self.records(p)
                         })
                     {
                         self.cursor += 1;
+                        if meta.is_empty() {
+                            continue;
+                        }
                         return Some((
                             s_pfx.get_prefix_id().into_pub(),
                             meta,
@@ -300,6 +342,9 @@ pub(crate) struct MoreSpecificPrefixIter<
     // This is the tree-wide index of withdrawn muis, used to rewrite the
     // statuses of these records, or filter them out.
     global_withdrawn_bmin: &'a RoaringBitmap,
+    // This is the tree-wide index of stale muis, used to report the stale
+    // records with the Stale status.
+    global_stale_bmin: &'a RoaringBitmap,
     // Whether we should filter out the withdrawn records in the search result
     include_withdrawn: bool,
     guard: &'a Guard,
@@ -332,7 +377,7 @@ impl<
                 if let Some(mui) = self.mui {
                     if let Some(p) = self
                         .store
-                        .non_recursive_retrieve_prefix_with_guard(
+                        .non_recursive_retrieve_stored_prefix_with_guard(
                             next_pfx.unwrap_or_else(|| {
                                 panic!(
                                 "BOOM! More-specific prefix {:?} disappeared \
@@ -355,54 +400,68 @@ impl<
                                     mui,
                                     self.global_withdrawn_bmin,
                                     RouteStatus::Withdrawn,
+                                    self.global_stale_bmin,
                                 )
                             {
                                 return Some((p.prefix, vec![rec]));
                             }
-                        } else if let Some(rec) =
-                            p.record_map.get_record_for_active_mui(mui)
+                        } else if let Some(rec) = p
+                            .record_map
+                            .get_record_for_active_mui_with_stale_status(
+                                mui,
+                                self.global_stale_bmin,
+                            )
                         {
                             return Some((p.prefix, vec![rec]));
                         }
                     };
-                } else {
-                    return self
-                        .store
-                        .non_recursive_retrieve_prefix_with_guard(
-                            next_pfx.unwrap_or_else(|| {
-                                panic!(
-                                "BOOM! More-specific prefix {:?} disappeared \
-                                from the store",
-                                next_pfx
-                            )
-                            }),
-                            self.guard,
+                    // No (qualifying) record for this mui here, move on to
+                    // the next prefix in this node.
+                    continue;
+                } else if let Some(pfx_rec) = self
+                    .store
+                    .non_recursive_retrieve_stored_prefix_with_guard(
+                        next_pfx.unwrap_or_else(|| {
+                            panic!(
+                            "BOOM! More-specific prefix {:?} disappeared \
+                            from the store",
+                            next_pfx
                         )
-                        .0
-                        .map(|p| {
-                            // Just like the mui specific records, we may have
-                            // to either rewrite the local status (if the user
-                            // wants the withdrawn records) or omit them.
-                            if self.include_withdrawn {
-                                (
-                                    p.prefix,
-                                    p.record_map
-                                        .as_records_with_rewritten_status(
-                                            self.global_withdrawn_bmin,
-                                            RouteStatus::Withdrawn,
-                                        ),
-                                )
-                            } else {
-                                (
-                                    p.prefix,
-                                    p.record_map
-                                        .as_active_records_not_in_bmin(
-                                            self.global_withdrawn_bmin,
-                                        ),
-                                )
-                            }
-                        });
+                        }),
+                        self.guard,
+                    )
+                    .0
+                    .map(|p| {
+                        // Just like the mui specific records, we may have
+                        // to either rewrite the local status (if the user
+                        // wants the withdrawn records) or omit them.
+                        if self.include_withdrawn {
+                            (
+                                p.prefix,
+                                p.record_map
+                                    .as_records_with_rewritten_status(
+                                        self.global_withdrawn_bmin,
+                                        RouteStatus::Withdrawn,
+                                        self.global_stale_bmin,
+                                    ),
+                            )
+                        } else {
+                            (
+                                p.prefix,
+                                p.record_map
+                                    .as_active_records_not_in_bmin(
+                                        self.global_withdrawn_bmin,
+                                        self.global_stale_bmin,
+                                    ),
+                            )
+                        }
+                    })
+                {
+                    return Some(pfx_rec);
                 }
+                // The prefix was removed after we read the pfxbitarr of this
+                // node, move on to the next prefix in this node.
+                continue;
             }
 
             // Our current prefix iterator for this node is done, look for
@@ -506,9 +565,12 @@ impl<
                             )
                             .wrap();
                     }
+                    // There's no node here, or the requested mui does not
+                    // appear in the sub-tree formed by it. Move on to the
+                    // next child node.
                     None => {
-                        println!("no node here.");
-                        return None;
+                        trace!("no node here.");
+                        continue;
                     }
                 };
             }
@@ -540,6 +602,9 @@ pub(crate) struct LessSpecificPrefixIter<
     // This is the tree-wide index of withdrawn muis, used to filter out the
     // records for those.
     global_withdrawn_bmin: &'a RoaringBitmap,
+    // This is the tree-wide index of stale muis, used to report the stale
+    // records with the Stale status.
+    global_stale_bmin: &'a RoaringBitmap,
     guard: &'a Guard,
 }
 
@@ -623,13 +688,17 @@ impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
                                 mui,
                                 self.global_withdrawn_bmin,
                                 RouteStatus::Withdrawn,
+                                self.global_stale_bmin,
                             )
                             .into_iter()
                             .collect()
                     } else {
                         stored_prefix
                             .record_map
-                            .get_record_for_active_mui(mui)
+                            .get_record_for_active_mui_with_stale_status(
+                                mui,
+                                self.global_stale_bmin,
+                            )
                             .into_iter()
                             .collect()
                     }
@@ -643,12 +712,14 @@ impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
                             .as_records_with_rewritten_status(
                                 self.global_withdrawn_bmin,
                                 RouteStatus::Withdrawn,
+                                self.global_stale_bmin,
                             )
                     } else {
                         stored_prefix
                             .record_map
                             .as_active_records_not_in_bmin(
                                 self.global_withdrawn_bmin,
+                                self.global_stale_bmin,
                             )
                     }
                 };
@@ -660,11 +731,13 @@ impl<'a, AF: AddressFamily + 'a, M: Meta + 'a, PB: PrefixBuckets<AF, M>>
                     self.cur_level = 0;
                     self.cur_bucket =
                         self.prefixes.get_root_prefix_set(self.cur_len);
-                    return if !pfx_rec.is_empty() {
-                        Some((stored_prefix.prefix, pfx_rec))
-                    } else {
-                        None
-                    };
+                    // An empty record set means the prefix was removed, or
+                    // all its records were filtered out, keep on looking for
+                    // shorter prefixes.
+                    if !pfx_rec.is_empty() {
+                        return Some((stored_prefix.prefix, pfx_rec));
+                    }
+                    continue;
                 };
                 // Advance to the next level or the next len.
                 match stored_prefix
@@ -719,7 +792,7 @@ impl<
         mui: Option<u32>,
         include_withdrawn: bool,
         guard: &'a Guard,
-    ) -> impl Iterator<Item = (PrefixId<AF>, Vec<PublicRecord<M>>)> + '_ {
+    ) -> impl Iterator<Item = (PrefixId<AF>, Vec<PublicRecord<M>>)> + 'a {
         trace!("more specifics for {:?}", start_prefix_id);
 
         // A v4 /32 or a v4 /128 doesn't have more specific prefixes 🤓.
@@ -810,6 +883,9 @@ impl<
                         .load(Ordering::Acquire, guard)
                         .deref()
                 };
+                let global_stale_bmin = unsafe {
+                    self.stale_muis_bmin.load(Ordering::Acquire, guard).deref()
+                };
 
                 Some(MoreSpecificPrefixIter {
                     store: self,
@@ -819,6 +895,7 @@ impl<
                     start_bit_span,
                     parent_and_position: vec![],
                     global_withdrawn_bmin,
+                    global_stale_bmin,
                     include_withdrawn,
                     mui,
                 })
@@ -840,7 +917,7 @@ impl<
         mui: Option<u32>,
         include_withdrawn: bool,
         guard: &'a Guard,
-    ) -> impl Iterator<Item = (PrefixId<AF>, Vec<PublicRecord<M>>)> + '_ {
+    ) -> impl Iterator<Item = (PrefixId<AF>, Vec<PublicRecord<M>>)> + 'a {
         trace!("less specifics for {:?}", start_prefix_id);
         trace!("level {}, len {}", 0, start_prefix_id.get_len());
 
@@ -858,6 +935,9 @@ impl<
                     .load(Ordering::Acquire, guard)
                     .deref()
             };
+            let global_stale_bmin = unsafe {
+                self.stale_muis_bmin.load(Ordering::Acquire, guard).deref()
+            };
 
             Some(LessSpecificPrefixIter {
                 prefixes: &self.prefixes,
@@ -867,6 +947,7 @@ impl<
                 cur_prefix_id: start_prefix_id,
                 mui,
                 global_withdrawn_bmin,
+                global_stale_bmin,
                 include_withdrawn,
                 guard,
             })
@@ -887,6 +968,27 @@ impl<
             cur_level: 0,
             cursor: 0,
             parents: [None; 26],
+            ltime: None,
+            guard,
+        }
+    }
+
+    // Iterate over all the prefixes in the store, with their records as
+    // they were at `ltime`, as far as the history of the records goes.
+    // Prefixes that did not have any records at `ltime` are skipped.
+    pub fn prefixes_iter_as_of(
+        &'a self,
+        ltime: u64,
+        guard: &'a Guard,
+    ) -> impl Iterator<Item = (Prefix, Vec<PublicRecord<M>>)> + 'a {
+        PrefixIter {
+            prefixes: &self.prefixes,
+            cur_bucket: self.prefixes.get_root_prefix_set(0),
+            cur_len: 0,
+            cur_level: 0,
+            cursor: 0,
+            parents: [None; 26],
+            ltime: Some(ltime),
             guard,
         }
     }
@@ -0,0 +1,877 @@
+// ----------- Journal -------------------------------------------------------
+//
+// A journal is an append-only file with the updates to a store. Every update
+// that goes through a `JournaledStore` is written to the journal before it
+// is applied to the store, so that after a crash the store can be rebuilt by
+// loading the latest snapshot (written by `save_to`), and replaying the
+// journal on top of it.
+//
+// All integers are written in network byte order. The layout is:
+//
+// header:  magic (4 bytes "RTSJ") | version (u16)
+// entries: a sequence of entries, each of which is a length (u32) followed
+//          by that number of bytes.
+//
+// An entry starts with its kind (u8, see the ENTRY_* constants), followed by
+// the arguments for the update. Prefixes are written as an address family
+// (u8, 4 or 6) | address (4 or 16 bytes) | length (u8), records are written
+// as mui (u32) | ltime (u64) | status (u8) | meta, where meta is written in
+// the same way as it is in the snapshot format. A batch entry has a count
+// (u32), followed by that number of entries, without their lengths. Updates
+// that are made up of many smaller updates, like bulk inserts, are written
+// as a batch, so that they are in the journal either completely or not at
+// all.
+//
+// Updates that take a closure, like `insert_with` and `apply_bgp_update`,
+// are written as the inserts and withdrawals that they resulted in, since
+// the closure can't be replayed.
+//
+// A crash may leave an incomplete entry at the end of the journal. Replaying
+// stops at such an entry, since it was never applied to the store.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+use inetnum::addr::Prefix;
+use log::{trace, warn};
+use routecore::bgp::message::UpdateMessage;
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+use routecore::Octets;
+
+use crate::prefix_record::PublicRecord;
+use crate::{EvictOptions, HistoryRetention, MatchOptions, MatchType};
+
+use super::atomic_types::RouteStatus;
+use super::custom_alloc::{
+    BgpUpdateReport, BulkReport, EvictReport, PurgeReport, UpsertReport,
+};
+use super::default_store::DefaultStore;
+use super::errors::PersistError;
+use super::persist::{
+    read_bitmap, read_meta, read_retention, read_u32, read_u64, read_u8,
+    status_from_u8, status_to_u8, write_bitmap, write_meta, write_retention,
+    write_u32, write_u64, write_u8, PersistMeta,
+};
+
+pub(crate) const MAGIC: &[u8; 4] = b"RTSJ";
+pub(crate) const VERSION: u16 = 1;
+
+const ENTRY_INSERT: u8 = 1;
+const ENTRY_MARK_WITHDRAWN_FOR_PREFIX: u8 = 2;
+const ENTRY_MARK_ACTIVE_FOR_PREFIX: u8 = 3;
+const ENTRY_MARK_WITHDRAWN_V4: u8 = 4;
+const ENTRY_MARK_WITHDRAWN_V6: u8 = 5;
+const ENTRY_MARK_ACTIVE_V4: u8 = 6;
+const ENTRY_MARK_ACTIVE_V6: u8 = 7;
+const ENTRY_MARK_STALE: u8 = 8;
+const ENTRY_SWEEP_STALE: u8 = 9;
+const ENTRY_REMOVE: u8 = 10;
+const ENTRY_REMOVE_PREFIX: u8 = 11;
+const ENTRY_PURGE_MUI: u8 = 12;
+const ENTRY_EVICT_OLDER_THAN: u8 = 13;
+const ENTRY_SET_HISTORY_RETENTION: u8 = 14;
+const ENTRY_WITHDRAW: u8 = 15;
+const ENTRY_INSERT_IF_NEWER: u8 = 16;
+const ENTRY_BATCH: u8 = 17;
+const ENTRY_SWEEP_STALE_V4: u8 = 18;
+const ENTRY_SWEEP_STALE_V6: u8 = 19;
+
+//------------ Journal -------------------------------------------------------
+
+/// An append-only journal file with updates to a store
+///
+/// A `Journal` is used by a [JournaledStore] to record the updates to its
+/// store, before they are applied. After a crash the store can be rebuilt
+/// from the latest snapshot and the journal with
+/// [MultiThreadedStore::recover](crate::MultiThreadedStore::recover).
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal file at `path`, creating it if it doesn't exist.
+    /// New entries are appended to the entries already in the journal. An
+    /// incomplete entry at the end of the journal, left behind by a crash,
+    /// is removed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, PersistError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let file_len = file.metadata()?.len();
+        if file_len == 0 {
+            write_header(&mut file)?;
+            return Ok(Self { file });
+        }
+
+        file.seek(SeekFrom::Start(0))?;
+        read_header(&mut file)?;
+
+        // Skip over the complete entries, to find where the last one ends.
+        let mut end = (MAGIC.len() + 2) as u64;
+        while end + 4 <= file_len {
+            file.seek(SeekFrom::Start(end))?;
+            let len = read_u32(&mut file)? as u64;
+            if end + 4 + len > file_len {
+                break;
+            }
+            end += 4 + len;
+        }
+        if end < file_len {
+            trace!("removing incomplete entry at the end of the journal");
+            file.set_len(end)?;
+        }
+
+        Ok(Self { file })
+    }
+
+    /// Flush the journal to disk. Entries are handed to the operating
+    /// system as soon as they are written, but only after a sync are they
+    /// guaranteed to survive a power failure.
+    pub fn sync(&self) -> Result<(), PersistError> {
+        Ok(self.file.sync_data()?)
+    }
+
+    /// Remove all the entries from the journal. This should only be done
+    /// right after a snapshot of the store was taken.
+    pub fn truncate(&mut self) -> Result<(), PersistError> {
+        self.file.set_len(0)?;
+        write_header(&mut self.file)?;
+        self.sync()
+    }
+
+    // Write an entry in one go, so that a crash can only leave an
+    // incomplete entry at the very end of the journal.
+    fn append(&mut self, entry: &[u8]) -> Result<(), PersistError> {
+        let len = u32::try_from(entry.len()).map_err(|_| {
+            PersistError::InvalidData("journal entry too large")
+        })?;
+        let mut buf = Vec::with_capacity(entry.len() + 4);
+        write_u32(&mut buf, len)?;
+        buf.extend_from_slice(entry);
+        Ok(self.file.write_all(&buf)?)
+    }
+}
+
+fn write_header<W: Write>(w: &mut W) -> Result<(), PersistError> {
+    w.write_all(MAGIC)?;
+    Ok(w.write_all(&VERSION.to_be_bytes())?)
+}
+
+fn read_header<R: Read>(r: &mut R) -> Result<(), PersistError> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(PersistError::InvalidMagic);
+    }
+    let mut version = [0; 2];
+    r.read_exact(&mut version)?;
+    let version = u16::from_be_bytes(version);
+    if version != VERSION {
+        return Err(PersistError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+//------------ Replay --------------------------------------------------------
+
+// Apply all the complete entries in the journal `r` to `store`. Returns the
+// number of entries that were applied.
+pub(crate) fn replay<M: PersistMeta, R: Read>(
+    store: &DefaultStore<M>,
+    r: &mut R,
+) -> Result<usize, PersistError> {
+    read_header(r)?;
+
+    let mut count = 0;
+    let mut buf = vec![];
+    loop {
+        let len = match read_u32(r) {
+            Ok(len) => len,
+            Err(PersistError::Io(err))
+                if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        // The length comes from the file, so only allocate for what is
+        // really there.
+        buf.clear();
+        r.take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len as usize {
+            trace!("incomplete entry at the end of the journal");
+            break;
+        }
+
+        apply_logged(store, &mut buf.as_slice(), count)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Apply an entry, logging it if it fails on the store. The update was
+// already tried on the store before the crash, so an update that failed
+// then, fails again now. That's not a reason to stop the replay.
+fn apply_logged<M: PersistMeta>(
+    store: &DefaultStore<M>,
+    r: &mut &[u8],
+    count: usize,
+) -> Result<(), PersistError> {
+    match apply_entry(store, r, count) {
+        Ok(_) => Ok(()),
+        Err(PersistError::Store(err)) => {
+            warn!("journal entry {} not applied: {}", count, err);
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn apply_entry<M: PersistMeta>(
+    store: &DefaultStore<M>,
+    r: &mut &[u8],
+    count: usize,
+) -> Result<(), PersistError> {
+    match read_u8(r)? {
+        ENTRY_INSERT => {
+            let prefix = read_prefix(r)?;
+            let record = read_record(r)?;
+            store.insert(&prefix, record, None)?;
+        }
+        ENTRY_INSERT_IF_NEWER => {
+            let prefix = read_prefix(r)?;
+            let record = read_record(r)?;
+            store.insert_if_newer(&prefix, record, None)?;
+        }
+        ENTRY_WITHDRAW => {
+            let prefix = read_prefix(r)?;
+            let mui = read_u32(r)?;
+            store.withdraw(&prefix, mui, read_u64(r)?)?;
+        }
+        ENTRY_BATCH => {
+            for _ in 0..read_u32(r)? {
+                apply_logged(store, r, count)?;
+            }
+        }
+        ENTRY_MARK_WITHDRAWN_FOR_PREFIX => {
+            let prefix = read_prefix(r)?;
+            store.mark_mui_as_withdrawn_for_prefix(&prefix, read_u32(r)?)?;
+        }
+        ENTRY_MARK_ACTIVE_FOR_PREFIX => {
+            let prefix = read_prefix(r)?;
+            store.mark_mui_as_active_for_prefix(&prefix, read_u32(r)?)?;
+        }
+        ENTRY_MARK_WITHDRAWN_V4 => {
+            store.mark_mui_as_withdrawn_v4(read_u32(r)?)?
+        }
+        ENTRY_MARK_WITHDRAWN_V6 => {
+            store.mark_mui_as_withdrawn_v6(read_u32(r)?)?
+        }
+        ENTRY_MARK_ACTIVE_V4 => store.mark_mui_as_active_v4(read_u32(r)?)?,
+        ENTRY_MARK_ACTIVE_V6 => store.mark_mui_as_active_v6(read_u32(r)?)?,
+        ENTRY_MARK_STALE => store.mark_mui_as_stale(read_u32(r)?)?,
+        ENTRY_SWEEP_STALE => {
+            let mui = read_u32(r)?;
+            store.sweep_stale(mui, read_u8(r)? != 0)?;
+        }
+        ENTRY_SWEEP_STALE_V4 => {
+            let mui = read_u32(r)?;
+            store.sweep_stale_v4(mui, read_u8(r)? != 0)?;
+        }
+        ENTRY_SWEEP_STALE_V6 => {
+            let mui = read_u32(r)?;
+            store.sweep_stale_v6(mui, read_u8(r)? != 0)?;
+        }
+        ENTRY_REMOVE => {
+            let prefix = read_prefix(r)?;
+            store.remove(&prefix, read_u32(r)?)?;
+        }
+        ENTRY_REMOVE_PREFIX => {
+            store.remove_prefix(&read_prefix(r)?)?;
+        }
+        ENTRY_PURGE_MUI => {
+            store.purge_mui(read_u32(r)?)?;
+        }
+        ENTRY_EVICT_OLDER_THAN => {
+            let ltime = read_u64(r)?;
+            let options = read_evict_options(r)?;
+            store.evict_older_than(ltime, &options)?;
+        }
+        ENTRY_SET_HISTORY_RETENTION => {
+            store.set_history_retention(read_retention(r)?)
+        }
+        _ => {
+            return Err(PersistError::InvalidData("unknown journal entry"));
+        }
+    }
+    Ok(())
+}
+
+//------------ JournaledStore ------------------------------------------------
+
+/// A store that records all of its updates in a [Journal]
+///
+/// A `JournaledStore` wraps a [MultiThreadedStore](crate::MultiThreadedStore)
+/// and has the same methods for updating it, except that every update is
+/// written to the journal before it is applied to the store. Reading from
+/// the store is done through the reference returned by `store`.
+///
+/// Writing an entry to the journal and applying it to the store happens
+/// while holding a lock on the journal, so that the order of the entries in
+/// the journal is the order in which the updates were applied. This means
+/// that updates through a `JournaledStore` are serialized.
+///
+/// Path selections are not journaled. The inserts that take
+/// `update_path_selections` run best path selection on the store, but when
+/// the journal is replayed, the path selections of the updated prefixes are
+/// marked as outdated instead. After recovering a store, the path
+/// selections can be calculated again through `store`.
+pub struct JournaledStore<M: PersistMeta> {
+    store: DefaultStore<M>,
+    journal: Mutex<Journal>,
+}
+
+impl<M: PersistMeta> JournaledStore<M> {
+    /// Create a journaled store from a store and a journal. The journal
+    /// should be empty, or the journal that, together with the latest
+    /// snapshot, the store was recovered from.
+    pub fn new(store: DefaultStore<M>, journal: Journal) -> Self {
+        Self {
+            store,
+            journal: Mutex::new(journal),
+        }
+    }
+
+    /// Returns a reference to the store, for reading from it, and for
+    /// selecting paths. Note that updates to the records of the store
+    /// through this reference are NOT recorded in the journal, and are lost
+    /// when the store is recovered.
+    pub fn store(&self) -> &DefaultStore<M> {
+        &self.store
+    }
+
+    /// Take the store and the journal out of the journaled store.
+    pub fn into_parts(self) -> (DefaultStore<M>, Journal) {
+        (
+            self.store,
+            self.journal.into_inner().unwrap_or_else(|e| e.into_inner()),
+        )
+    }
+
+    /// Write a snapshot of the store to `writer` with `save_to`, and, if
+    /// that succeeds, truncate the journal. No updates are accepted while
+    /// the snapshot is being written.
+    ///
+    /// The snapshot is synced to disk (if `writer` is a file) before the
+    /// journal is truncated. If the snapshot is written to a file that
+    /// replaces the previous snapshot, that previous snapshot should only
+    /// be removed after this method returns successfully.
+    pub fn snapshot(&self, writer: &mut File) -> Result<(), PersistError> {
+        let mut journal = self.lock_journal();
+        self.store.save_to(writer)?;
+        writer.sync_all()?;
+        journal.truncate()
+    }
+
+    /// Flush the journal to disk, see [Journal::sync].
+    pub fn sync(&self) -> Result<(), PersistError> {
+        self.lock_journal().sync()
+    }
+
+    /// Insert or update a record, see
+    /// [MultiThreadedStore::insert](crate::MultiThreadedStore::insert).
+    pub fn insert(
+        &self,
+        prefix: &Prefix,
+        record: PublicRecord<M>,
+        update_path_selections: Option<M::TBI>,
+    ) -> Result<UpsertReport, PersistError> {
+        let mut buf = vec![];
+        write_insert(&mut buf, ENTRY_INSERT, prefix, &record)?;
+        self.journaled(&buf, |store| {
+            store.insert(prefix, record, update_path_selections)
+        })
+    }
+
+    /// Insert a record, unless the store has a newer one, see
+    /// [MultiThreadedStore::insert_if_newer](crate::MultiThreadedStore::insert_if_newer).
+    pub fn insert_if_newer(
+        &self,
+        prefix: &Prefix,
+        record: PublicRecord<M>,
+        update_path_selections: Option<M::TBI>,
+    ) -> Result<UpsertReport, PersistError> {
+        let mut buf = vec![];
+        write_insert(&mut buf, ENTRY_INSERT_IF_NEWER, prefix, &record)?;
+        self.journaled(&buf, |store| {
+            store.insert_if_newer(prefix, record, update_path_selections)
+        })
+    }
+
+    /// Insert a record with meta-data created from the meta-data of the
+    /// current record, see
+    /// [MultiThreadedStore::insert_with](crate::MultiThreadedStore::insert_with).
+    ///
+    /// Since all updates through a `JournaledStore` are serialized, `merge`
+    /// is called exactly once, and the resulting record is journaled as an
+    /// insert.
+    pub fn insert_with(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+        ltime: u64,
+        merge: impl Fn(Option<&M>) -> M,
+        update_path_selections: Option<M::TBI>,
+    ) -> Result<UpsertReport, PersistError> {
+        let mut journal = self.lock_journal();
+        let options = MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: true,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: Some(mui),
+        };
+        let current = self
+            .store
+            .match_prefix(prefix, &options, &crossbeam_epoch::pin())
+            .prefix_meta
+            .into_iter()
+            .find(|r| r.multi_uniq_id == mui);
+        let record = PublicRecord::new(
+            mui,
+            ltime,
+            RouteStatus::Active,
+            merge(current.as_ref().map(|r| &r.meta)),
+        );
+
+        let mut buf = vec![];
+        write_insert(&mut buf, ENTRY_INSERT, prefix, &record)?;
+        journal.append(&buf)?;
+        Ok(self.store.insert(prefix, record, update_path_selections)?)
+    }
+
+    /// Mark the record for a (prefix, mui) as withdrawn with a new ltime,
+    /// see
+    /// [MultiThreadedStore::withdraw](crate::MultiThreadedStore::withdraw).
+    pub fn withdraw(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+        ltime: u64,
+    ) -> Result<bool, PersistError> {
+        let mut buf = vec![];
+        write_withdraw(&mut buf, prefix, mui, ltime)?;
+        self.journaled(&buf, |store| store.withdraw(prefix, mui, ltime))
+    }
+
+    /// Apply a BGP UPDATE message for a mui, see
+    /// [MultiThreadedStore::apply_bgp_update](crate::MultiThreadedStore::apply_bgp_update).
+    ///
+    /// The message is journaled as the withdrawals and inserts it results
+    /// in, in one batch.
+    pub fn apply_bgp_update<O, F>(
+        &self,
+        mui: u32,
+        ltime: u64,
+        update: &UpdateMessage<O>,
+        meta_builder: F,
+    ) -> Result<BgpUpdateReport, PersistError>
+    where
+        O: Octets,
+        F: FnMut(&Prefix, &OwnedPathAttributes) -> Option<M>,
+    {
+        let changes = DefaultStore::bgp_changes(update, meta_builder)?;
+
+        let mut buf = vec![ENTRY_BATCH];
+        let count = changes.withdrawals.len() + changes.announcements.len();
+        write_u32(&mut buf, count as u32)?;
+        for prefix in changes.withdrawals.iter() {
+            write_withdraw(&mut buf, prefix, mui, ltime)?;
+        }
+        for (prefix, meta) in changes.announcements.iter() {
+            write_u8(&mut buf, ENTRY_INSERT)?;
+            write_prefix(&mut buf, prefix)?;
+            write_u32(&mut buf, mui)?;
+            write_u64(&mut buf, ltime)?;
+            write_u8(&mut buf, status_to_u8(RouteStatus::Active))?;
+            write_meta(&mut buf, meta);
+        }
+        self.journaled(&buf, |store| {
+            store.apply_bgp_changes(mui, ltime, changes)
+        })
+    }
+
+    /// Insert a large set of records on the calling thread, see
+    /// [MultiThreadedStore::insert_bulk](crate::MultiThreadedStore::insert_bulk).
+    ///
+    /// The records are journaled as inserts, in one batch.
+    pub fn insert_bulk<I>(
+        &self,
+        records: I,
+    ) -> Result<BulkReport, PersistError>
+    where
+        I: IntoIterator<Item = (Prefix, PublicRecord<M>)>,
+    {
+        let records = records.into_iter().collect::<Vec<_>>();
+        let buf = bulk_entry(&records)?;
+        self.journaled(&buf, |store| store.insert_bulk(records))
+    }
+
+    /// Insert a large set of records on multiple threads, see
+    /// [MultiThreadedStore::par_insert_bulk](crate::MultiThreadedStore::par_insert_bulk).
+    ///
+    /// The records are journaled as inserts, in one batch.
+    pub fn par_insert_bulk<I>(
+        &self,
+        records: I,
+        threads: usize,
+    ) -> Result<BulkReport, PersistError>
+    where
+        I: IntoIterator<Item = (Prefix, PublicRecord<M>)>,
+    {
+        let records = records.into_iter().collect::<Vec<_>>();
+        let buf = bulk_entry(&records)?;
+        self.journaled(&buf, |store| store.par_insert_bulk(records, threads))
+    }
+
+    /// Change the local status of a record to Withdrawn, see
+    /// [MultiThreadedStore::mark_mui_as_withdrawn_for_prefix](crate::MultiThreadedStore::mark_mui_as_withdrawn_for_prefix).
+    pub fn mark_mui_as_withdrawn_for_prefix(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_WITHDRAWN_FOR_PREFIX];
+        write_prefix(&mut buf, prefix)?;
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| {
+            store.mark_mui_as_withdrawn_for_prefix(prefix, mui)
+        })
+    }
+
+    /// Change the local status of a record to Active, see
+    /// [MultiThreadedStore::mark_mui_as_active_for_prefix](crate::MultiThreadedStore::mark_mui_as_active_for_prefix).
+    pub fn mark_mui_as_active_for_prefix(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_ACTIVE_FOR_PREFIX];
+        write_prefix(&mut buf, prefix)?;
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| {
+            store.mark_mui_as_active_for_prefix(prefix, mui)
+        })
+    }
+
+    /// Change the global status of a mui for IPv4 prefixes to Withdrawn.
+    pub fn mark_mui_as_withdrawn_v4(
+        &self,
+        mui: u32,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_WITHDRAWN_V4];
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.mark_mui_as_withdrawn_v4(mui))
+    }
+
+    /// Change the global status of a mui for IPv6 prefixes to Withdrawn.
+    pub fn mark_mui_as_withdrawn_v6(
+        &self,
+        mui: u32,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_WITHDRAWN_V6];
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.mark_mui_as_withdrawn_v6(mui))
+    }
+
+    /// Change the global status of a mui for IPv4 prefixes to Active.
+    pub fn mark_mui_as_active_v4(
+        &self,
+        mui: u32,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_ACTIVE_V4];
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.mark_mui_as_active_v4(mui))
+    }
+
+    /// Change the global status of a mui for IPv6 prefixes to Active.
+    pub fn mark_mui_as_active_v6(
+        &self,
+        mui: u32,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_ACTIVE_V6];
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.mark_mui_as_active_v6(mui))
+    }
+
+    /// Mark all the records for a mui as stale, see
+    /// [MultiThreadedStore::mark_mui_as_stale](crate::MultiThreadedStore::mark_mui_as_stale).
+    pub fn mark_mui_as_stale(&self, mui: u32) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_MARK_STALE];
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.mark_mui_as_stale(mui))
+    }
+
+    /// Withdraw or remove the stale records for a mui, see
+    /// [MultiThreadedStore::sweep_stale](crate::MultiThreadedStore::sweep_stale).
+    pub fn sweep_stale(
+        &self,
+        mui: u32,
+        remove: bool,
+    ) -> Result<Vec<Prefix>, PersistError> {
+        let mut buf = vec![ENTRY_SWEEP_STALE];
+        write_u32(&mut buf, mui)?;
+        write_u8(&mut buf, remove as u8)?;
+        self.journaled(&buf, |store| store.sweep_stale(mui, remove))
+    }
+
+    /// Withdraw or remove the stale records for IPv4 prefixes for a mui,
+    /// see
+    /// [MultiThreadedStore::sweep_stale_v4](crate::MultiThreadedStore::sweep_stale_v4).
+    pub fn sweep_stale_v4(
+        &self,
+        mui: u32,
+        remove: bool,
+    ) -> Result<Vec<Prefix>, PersistError> {
+        let mut buf = vec![ENTRY_SWEEP_STALE_V4];
+        write_u32(&mut buf, mui)?;
+        write_u8(&mut buf, remove as u8)?;
+        self.journaled(&buf, |store| store.sweep_stale_v4(mui, remove))
+    }
+
+    /// Withdraw or remove the stale records for IPv6 prefixes for a mui,
+    /// see
+    /// [MultiThreadedStore::sweep_stale_v6](crate::MultiThreadedStore::sweep_stale_v6).
+    pub fn sweep_stale_v6(
+        &self,
+        mui: u32,
+        remove: bool,
+    ) -> Result<Vec<Prefix>, PersistError> {
+        let mut buf = vec![ENTRY_SWEEP_STALE_V6];
+        write_u32(&mut buf, mui)?;
+        write_u8(&mut buf, remove as u8)?;
+        self.journaled(&buf, |store| store.sweep_stale_v6(mui, remove))
+    }
+
+    /// Remove the record for a (prefix, mui), see
+    /// [MultiThreadedStore::remove](crate::MultiThreadedStore::remove).
+    pub fn remove(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+    ) -> Result<Option<PublicRecord<M>>, PersistError> {
+        let mut buf = vec![ENTRY_REMOVE];
+        write_prefix(&mut buf, prefix)?;
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.remove(prefix, mui))
+    }
+
+    /// Remove a prefix with all of its records, see
+    /// [MultiThreadedStore::remove_prefix](crate::MultiThreadedStore::remove_prefix).
+    pub fn remove_prefix(
+        &self,
+        prefix: &Prefix,
+    ) -> Result<Vec<PublicRecord<M>>, PersistError> {
+        let mut buf = vec![ENTRY_REMOVE_PREFIX];
+        write_prefix(&mut buf, prefix)?;
+        self.journaled(&buf, |store| store.remove_prefix(prefix))
+    }
+
+    /// Remove all the records for a mui, see
+    /// [MultiThreadedStore::purge_mui](crate::MultiThreadedStore::purge_mui).
+    pub fn purge_mui(&self, mui: u32) -> Result<PurgeReport, PersistError> {
+        let mut buf = vec![ENTRY_PURGE_MUI];
+        write_u32(&mut buf, mui)?;
+        self.journaled(&buf, |store| store.purge_mui(mui))
+    }
+
+    /// Evict the records older than `ltime`, see
+    /// [MultiThreadedStore::evict_older_than](crate::MultiThreadedStore::evict_older_than).
+    pub fn evict_older_than(
+        &self,
+        ltime: u64,
+        options: &EvictOptions,
+    ) -> Result<EvictReport, PersistError> {
+        let mut buf = vec![ENTRY_EVICT_OLDER_THAN];
+        write_u64(&mut buf, ltime)?;
+        write_evict_options(&mut buf, options)?;
+        self.journaled(&buf, |store| store.evict_older_than(ltime, options))
+    }
+
+    /// Set the history retention strategy, see
+    /// [MultiThreadedStore::set_history_retention](crate::MultiThreadedStore::set_history_retention).
+    pub fn set_history_retention(
+        &self,
+        retention: HistoryRetention,
+    ) -> Result<(), PersistError> {
+        let mut buf = vec![ENTRY_SET_HISTORY_RETENTION];
+        write_retention(&mut buf, retention)?;
+        self.journaled(&buf, |store| {
+            store.set_history_retention(retention);
+            Ok(())
+        })
+    }
+
+    fn lock_journal(&self) -> std::sync::MutexGuard<'_, Journal> {
+        // A panic while holding the lock can only have happened after the
+        // entry was written, so the journal itself is fine.
+        self.journal.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn journaled<T>(
+        &self,
+        entry: &[u8],
+        update: impl FnOnce(
+            &DefaultStore<M>,
+        ) -> Result<T, super::errors::PrefixStoreError>,
+    ) -> Result<T, PersistError> {
+        let mut journal = self.lock_journal();
+        journal.append(entry)?;
+        Ok(update(&self.store)?)
+    }
+}
+
+//------------ Primitives ----------------------------------------------------
+
+fn write_prefix(
+    buf: &mut Vec<u8>,
+    prefix: &Prefix,
+) -> Result<(), PersistError> {
+    match prefix.addr() {
+        IpAddr::V4(addr) => {
+            write_u8(buf, 4)?;
+            buf.extend_from_slice(&addr.octets());
+        }
+        IpAddr::V6(addr) => {
+            write_u8(buf, 6)?;
+            buf.extend_from_slice(&addr.octets());
+        }
+    }
+    write_u8(buf, prefix.len())
+}
+
+fn write_insert<M: PersistMeta>(
+    buf: &mut Vec<u8>,
+    kind: u8,
+    prefix: &Prefix,
+    record: &PublicRecord<M>,
+) -> Result<(), PersistError> {
+    write_u8(buf, kind)?;
+    write_prefix(buf, prefix)?;
+    write_record(buf, record)
+}
+
+fn write_withdraw(
+    buf: &mut Vec<u8>,
+    prefix: &Prefix,
+    mui: u32,
+    ltime: u64,
+) -> Result<(), PersistError> {
+    write_u8(buf, ENTRY_WITHDRAW)?;
+    write_prefix(buf, prefix)?;
+    write_u32(buf, mui)?;
+    write_u64(buf, ltime)
+}
+
+// A batch entry with an insert for every record.
+fn bulk_entry<M: PersistMeta>(
+    records: &[(Prefix, PublicRecord<M>)],
+) -> Result<Vec<u8>, PersistError> {
+    let mut buf = vec![ENTRY_BATCH];
+    let count = u32::try_from(records.len())
+        .map_err(|_| PersistError::InvalidData("journal entry too large"))?;
+    write_u32(&mut buf, count)?;
+    for (prefix, record) in records {
+        write_insert(&mut buf, ENTRY_INSERT, prefix, record)?;
+    }
+    Ok(buf)
+}
+
+fn read_prefix<R: Read>(r: &mut R) -> Result<Prefix, PersistError> {
+    let addr = match read_u8(r)? {
+        4 => {
+            let mut octets = [0; 4];
+            r.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        6 => {
+            let mut octets = [0; 16];
+            r.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        _ => return Err(PersistError::InvalidData("unknown address family")),
+    };
+    Prefix::new(addr, read_u8(r)?)
+        .map_err(|_| PersistError::InvalidData("invalid prefix"))
+}
+
+fn write_record<M: PersistMeta>(
+    buf: &mut Vec<u8>,
+    record: &PublicRecord<M>,
+) -> Result<(), PersistError> {
+    write_u32(buf, record.multi_uniq_id)?;
+    write_u64(buf, record.ltime)?;
+    write_u8(buf, status_to_u8(record.status))?;
+    write_meta(buf, &record.meta);
+    Ok(())
+}
+
+fn read_record<M: PersistMeta, R: Read>(
+    r: &mut R,
+) -> Result<PublicRecord<M>, PersistError> {
+    let mui = read_u32(r)?;
+    let ltime = read_u64(r)?;
+    let status = status_from_u8(read_u8(r)?)?;
+    let meta = read_meta(r)?;
+    Ok(PublicRecord::new(mui, ltime, status, meta))
+}
+
+fn write_evict_options(
+    buf: &mut Vec<u8>,
+    options: &EvictOptions,
+) -> Result<(), PersistError> {
+    write_u8(buf, options.remove as u8)?;
+    match &options.muis {
+        Some(muis) => {
+            write_u8(buf, 1)?;
+            write_bitmap(buf, muis)?;
+        }
+        None => write_u8(buf, 0)?,
+    }
+    match &options.prefix {
+        Some(prefix) => {
+            write_u8(buf, 1)?;
+            write_prefix(buf, prefix)
+        }
+        None => write_u8(buf, 0),
+    }
+}
+
+fn read_evict_options<R: Read>(
+    r: &mut R,
+) -> Result<EvictOptions, PersistError> {
+    let remove = read_u8(r)? != 0;
+    let muis = if read_u8(r)? != 0 {
+        Some(read_bitmap(r)?)
+    } else {
+        None
+    };
+    let prefix = if read_u8(r)? != 0 {
+        Some(read_prefix(r)?)
+    } else {
+        None
+    };
+    Ok(EvictOptions {
+        remove,
+        muis,
+        prefix,
+    })
+}
@@ -88,19 +88,23 @@ macro_rules! impl_search_level_for_mui {
                                 this_node.deref() 
                             };
 
-                            // early return if the mui is not in the index
-                            // stored in this node, meaning the mui does not
-                            // appear anywhere in the sub-tree formed from
-                            // this node.
-                            let bmin: &RoaringBitmap = unsafe { 
-                                node_set.1.load(Ordering::Acquire, guard).deref()
-                            };
-                            if !bmin.contains($mui) {
-                                return None;
-                            }
-                            
                             if $id == *node_id {
                                 // YES, It's the one we're looking for!
+
+                                // early return if the mui is not in the
+                                // index stored in this node, meaning the mui
+                                // does not appear anywhere in the sub-tree
+                                // formed from this node. Note that the index
+                                // of a node that we collided with on the way
+                                // here says nothing about our node, so we're
+                                // only checking it here.
+                                let bmin: &RoaringBitmap = unsafe { 
+                                    node_set.1.load(Ordering::Acquire, guard).deref()
+                                };
+                                if !bmin.contains($mui) {
+                                    return None;
+                                }
+
                                 return Some(SizedStrideRef::$stride(&node));
                             };
                             // Meh, it's not, but we can a go to the next
@@ -4,6 +4,11 @@ pub mod errors;
 
 pub(crate) mod default_store;
 pub(crate) mod atomic_types;
+pub(crate) mod persist;
+pub(crate) mod journal;
+pub(crate) mod changes;
+pub(crate) mod watch;
+pub(crate) mod selector;
 
 pub use default_store::DefaultStore;
 #[macro_use]
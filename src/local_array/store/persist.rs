@@ -0,0 +1,577 @@
+// ----------- Persistence ---------------------------------------------------
+//
+// A store can be written to, and read back from, a versioned binary format.
+// All integers are written in network byte order. The layout is:
+//
+// header:   magic (4 bytes "RTSS") | version (u16) | history retention
+// sections: one for IPv4 and one for IPv6, in that order
+//
+// A history retention is written as a kind (u8, 0 = off, 1 = versions,
+// 2 = max age) followed by its value (u64).
+//
+// Each section has:
+//
+// withdrawn muis bitmap:  length (u32) | serialized RoaringBitmap
+// stale muis bitmap:      length (u32) | serialized RoaringBitmap
+// prefixes:               a sequence of prefix entries, each preceded by a
+//                         1 (u8), and terminated by a 0 (u8).
+//
+// A prefix entry is:
+//
+// prefix:          address (4 or 16 bytes) | length (u8)
+// path selections: flags (u8, see the PS_* constants) | best (u32, if
+//                  present) | backup (u32, if present)
+// records:         count (u32) | records
+//
+// A record is:
+//
+// mui (u32) | ltime (u64) | status (u8) | refreshed (u8) | meta | history
+//
+// The history is a count (u32), followed by that number of replaced records,
+// most recent first, each of which is: ltime (u64) | status (u8) | meta.
+// Meta-data is written as a length (u32), followed by the bytes that the
+// `PersistMeta` implementation for the meta-data type produced.
+//
+// Reading the prefix entries is done sequentially, but storing them is done
+// by multiple threads, since the store allows for that. The entries are
+// handed to these threads in chunks while reading, so a section is never
+// read into memory as a whole. Path selections are restored as they were
+// written, instead of being calculated again.
+
+use std::io::{Read, Write};
+use std::net::IpAddr;
+
+use crossbeam_epoch::Guard;
+use log::trace;
+use roaring::RoaringBitmap;
+
+use crate::af::AddressFamily;
+use crate::local_array::node::PrefixId;
+use crate::local_array::tree::TreeBitMap;
+use crate::prefix_record::{Meta, PublicRecord};
+use crate::HistoryRetention;
+
+use super::atomic_types::{
+    MultiMapValue, NodeBuckets, PathSelections, PrefixBuckets, RouteStatus,
+};
+use super::errors::{PersistError, PrefixStoreError};
+
+pub(crate) const MAGIC: &[u8; 4] = b"RTSS";
+pub(crate) const VERSION: u16 = 1;
+
+const PS_OUTDATED: u8 = 0x01;
+const PS_BEST: u8 = 0x02;
+const PS_BACKUP: u8 = 0x04;
+
+// The maximum number of prefix entries a loading thread gets handed at a
+// time.
+const LOAD_CHUNK_SIZE: usize = 4096;
+
+// The maximum number of records or history entries that room is reserved for
+// up front. The counts come from the file, so they can't be trusted to
+// allocate for.
+const MAX_PREALLOC: u32 = 1024;
+
+//------------ PersistMeta ---------------------------------------------------
+
+/// Serialization of meta-data for persisting a store
+///
+/// Meta-data types that implement this trait can be written to, and read
+/// back from, the binary format used by `save_to` and `load_from`. The store
+/// takes care of delimiting the serialized meta-data, so `read_meta` gets
+/// exactly the bytes that `write_meta` produced.
+pub trait PersistMeta: Meta {
+    /// Append the serialized form of the meta-data to `buf`.
+    fn write_meta(&self, buf: &mut Vec<u8>);
+
+    /// Create the meta-data from its serialized form.
+    fn read_meta(
+        buf: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+impl PersistMeta for inetnum::asn::Asn {
+    fn write_meta(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.into_u32().to_be_bytes());
+    }
+
+    fn read_meta(
+        buf: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes: [u8; 4] = buf.try_into()?;
+        Ok(inetnum::asn::Asn::from(u32::from_be_bytes(bytes)))
+    }
+}
+
+//------------ Header --------------------------------------------------------
+
+pub(crate) fn write_header<W: Write>(
+    w: &mut W,
+    retention: HistoryRetention,
+) -> Result<(), PersistError> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_be_bytes())?;
+    write_retention(w, retention)
+}
+
+pub(crate) fn read_header<R: Read>(
+    r: &mut R,
+) -> Result<HistoryRetention, PersistError> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(PersistError::InvalidMagic);
+    }
+    let mut version = [0; 2];
+    r.read_exact(&mut version)?;
+    let version = u16::from_be_bytes(version);
+    if version != VERSION {
+        return Err(PersistError::UnsupportedVersion(version));
+    }
+    read_retention(r)
+}
+
+//------------ PrefixEntry ---------------------------------------------------
+
+// A prefix with all of its records, as read from the binary format.
+struct PrefixEntry<AF: AddressFamily, M> {
+    prefix: PrefixId<AF>,
+    ps_flags: u8,
+    path_selections: PathSelections,
+    records: Vec<(u32, MultiMapValue<M>)>,
+}
+
+//------------ Sections ------------------------------------------------------
+
+impl<AF, M, NB, PB> TreeBitMap<AF, M, NB, PB>
+where
+    AF: AddressFamily,
+    M: PersistMeta,
+    NB: NodeBuckets<AF>,
+    PB: PrefixBuckets<AF, M>,
+{
+    // Write the section for this address family. Prefixes that are
+    // inserted or removed while this runs may or may not be written.
+    pub(crate) fn write_section<W: Write>(
+        &self,
+        w: &mut W,
+        guard: &Guard,
+    ) -> Result<(), PersistError> {
+        let bmin = unsafe {
+            self.store
+                .withdrawn_muis_bmin
+                .load(std::sync::atomic::Ordering::Acquire, guard)
+                .deref()
+        };
+        write_bitmap(w, bmin)?;
+        let bmin = unsafe {
+            self.store
+                .stale_muis_bmin
+                .load(std::sync::atomic::Ordering::Acquire, guard)
+                .deref()
+        };
+        write_bitmap(w, bmin)?;
+
+        let mut buf = vec![];
+        for (prefix, _) in self.store.prefixes_iter(guard) {
+            let prefix_id =
+                PrefixId::new(AF::from_ipaddr(prefix.addr()), prefix.len());
+            let stored_prefix = if let Some(stored_prefix) = self
+                .store
+                .non_recursive_retrieve_stored_prefix_with_guard(
+                    prefix_id,
+                    guard,
+                )
+                .0
+            {
+                stored_prefix
+            } else {
+                continue;
+            };
+
+            let record_map = stored_prefix.record_map.0.pin();
+            if record_map.is_empty() {
+                continue;
+            }
+
+            buf.clear();
+            write_u8(&mut buf, 1)?;
+            match prefix.addr() {
+                IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()),
+                IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()),
+            }
+            write_u8(&mut buf, prefix.len())?;
+
+            let ps = stored_prefix.get_path_selections(guard);
+            let mut flags = 0;
+            if stored_prefix.is_ps_outdated(guard) {
+                flags |= PS_OUTDATED;
+            }
+            if ps.best().is_some() {
+                flags |= PS_BEST;
+            }
+            if ps.backup().is_some() {
+                flags |= PS_BACKUP;
+            }
+            write_u8(&mut buf, flags)?;
+            if let Some(best) = ps.best() {
+                write_u32(&mut buf, best)?;
+            }
+            if let Some(backup) = ps.backup() {
+                write_u32(&mut buf, backup)?;
+            }
+
+            write_u32(&mut buf, record_map.len() as u32)?;
+            for (mui, rec) in record_map.iter() {
+                write_u32(&mut buf, *mui)?;
+                write_u64(&mut buf, rec.ltime)?;
+                write_u8(&mut buf, status_to_u8(rec.status))?;
+                write_u8(&mut buf, rec.refreshed as u8)?;
+                write_meta(&mut buf, &rec.meta);
+                write_u32(&mut buf, rec.history.len() as u32)?;
+                for h in rec.history.iter() {
+                    write_u64(&mut buf, h.ltime)?;
+                    write_u8(&mut buf, status_to_u8(h.status))?;
+                    write_meta(&mut buf, &h.meta);
+                }
+            }
+            w.write_all(&buf)?;
+        }
+
+        write_u8(w, 0)
+    }
+
+    // Read the section for this address family into this (empty) tree.
+    pub(crate) fn read_section<R: Read>(
+        &self,
+        r: &mut R,
+        guard: &Guard,
+    ) -> Result<(), PersistError>
+    where
+        Self: Sync,
+        AF: Send,
+    {
+        let withdrawn_muis = read_bitmap(r)?;
+        let stale_muis = read_bitmap(r)?;
+        // Withdrawing a mui marks the path selections of its prefixes as
+        // outdated, so this has to happen before the path selections are
+        // restored. Marking a mui as stale has to wait until its records
+        // are restored, though, or they would be flagged as refreshed.
+        for mui in withdrawn_muis.iter() {
+            self.store.mark_mui_as_withdrawn(mui, guard)?;
+        }
+
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get());
+
+        // The prefix entries are handed to the loading threads in chunks,
+        // through a channel that holds at most one chunk per thread, so
+        // only a bounded part of the section is in memory at a time. The
+        // loading threads share the receiving end, and it goes away when
+        // they're all done, so if they all bail out with an error, sending
+        // a chunk fails instead of blocking forever.
+        let (tx, rx) = std::sync::mpsc::sync_channel(threads);
+        let rx = std::sync::Arc::new(std::sync::Mutex::new(rx));
+        std::thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|i| {
+                    let rx = rx.clone();
+                    std::thread::Builder::new()
+                        .name(format!("load-{}", i))
+                        .spawn_scoped(s, move || loop {
+                            let chunk: Result<Vec<PrefixEntry<AF, M>>, _> =
+                                rx.lock().unwrap().recv();
+                            match chunk {
+                                Ok(chunk) => {
+                                    for entry in chunk {
+                                        self.restore_prefix_entry(entry)?;
+                                    }
+                                }
+                                Err(_) => return Ok::<_, PersistError>(()),
+                            }
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            drop(rx);
+
+            let read = (|| {
+                let mut chunk = Vec::with_capacity(LOAD_CHUNK_SIZE);
+                let mut count = 0;
+                while read_u8(r)? == 1 {
+                    chunk.push(read_prefix_entry::<AF, M, R>(r)?);
+                    if chunk.len() == LOAD_CHUNK_SIZE {
+                        count += 1;
+                        let full = std::mem::replace(
+                            &mut chunk,
+                            Vec::with_capacity(LOAD_CHUNK_SIZE),
+                        );
+                        if tx.send(full).is_err() {
+                            // All the loading threads are gone, their
+                            // error is returned below.
+                            return Ok(());
+                        }
+                    }
+                }
+                trace!("loaded {} chunks of prefixes", count + 1);
+                let _ = tx.send(chunk);
+                Ok::<_, PersistError>(())
+            })();
+            drop(tx);
+
+            handles.into_iter().try_for_each(|h| h.join().unwrap())?;
+            read
+        })?;
+
+        for mui in stale_muis.iter() {
+            self.store.mark_mui_as_stale(mui, guard)?;
+        }
+
+        Ok(())
+    }
+
+    fn restore_prefix_entry(
+        &self,
+        entry: PrefixEntry<AF, M>,
+    ) -> Result<(), PrefixStoreError> {
+        let guard = &crossbeam_epoch::pin();
+
+        // Inserting the records one by one makes sure all the nodes, and
+        // their mui indexes, are in place. The parts of the records that
+        // an insert does not take are set afterwards.
+        let mut rest = vec![];
+        for (mui, rec) in entry.records {
+            self.insert(
+                entry.prefix,
+                PublicRecord::new(mui, rec.ltime, rec.status, rec.meta),
+                None,
+            )?;
+            if rec.refreshed || !rec.history.is_empty() {
+                rest.push((mui, rec.refreshed, rec.history));
+            }
+        }
+
+        let stored_prefix = self
+            .store
+            .non_recursive_retrieve_stored_prefix_with_guard(
+                entry.prefix,
+                guard,
+            )
+            .0
+            .ok_or(PrefixStoreError::PrefixNotFound)?;
+
+        let record_map = stored_prefix.record_map.0.pin();
+        for (mui, refreshed, history) in rest {
+            record_map.compute_if_present(&mui, |_, rec| {
+                Some(MultiMapValue {
+                    refreshed,
+                    history: history.clone(),
+                    ..rec.clone()
+                })
+            });
+        }
+
+        stored_prefix.set_path_selections(entry.path_selections, guard)?;
+        if entry.ps_flags & PS_OUTDATED != 0 {
+            stored_prefix.set_ps_outdated(guard)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_prefix_entry<AF: AddressFamily, M: PersistMeta, R: Read>(
+    r: &mut R,
+) -> Result<PrefixEntry<AF, M>, PersistError> {
+    let addr = if AF::BITS == 32 {
+        let mut octets = [0; 4];
+        r.read_exact(&mut octets)?;
+        IpAddr::from(octets)
+    } else {
+        let mut octets = [0; 16];
+        r.read_exact(&mut octets)?;
+        IpAddr::from(octets)
+    };
+    let len = read_u8(r)?;
+    if len > AF::BITS {
+        return Err(PersistError::InvalidData("invalid prefix length"));
+    }
+    let prefix = PrefixId::new(AF::from_ipaddr(addr), len);
+
+    let ps_flags = read_u8(r)?;
+    let best = if ps_flags & PS_BEST != 0 {
+        Some(read_u32(r)?)
+    } else {
+        None
+    };
+    let backup = if ps_flags & PS_BACKUP != 0 {
+        Some(read_u32(r)?)
+    } else {
+        None
+    };
+
+    let count = read_u32(r)?;
+    let mut records = Vec::with_capacity(count.min(MAX_PREALLOC) as usize);
+    for _ in 0..count {
+        let mui = read_u32(r)?;
+        let ltime = read_u64(r)?;
+        let status = status_from_u8(read_u8(r)?)?;
+        let refreshed = read_u8(r)? != 0;
+        let meta = read_meta::<M, R>(r)?;
+        let history_count = read_u32(r)?;
+        let mut history =
+            Vec::with_capacity(history_count.min(MAX_PREALLOC) as usize);
+        for _ in 0..history_count {
+            let ltime = read_u64(r)?;
+            let status = status_from_u8(read_u8(r)?)?;
+            let meta = read_meta::<M, R>(r)?;
+            history.push(PublicRecord::new(mui, ltime, status, meta));
+        }
+        records.push((
+            mui,
+            MultiMapValue {
+                meta,
+                ltime,
+                status,
+                refreshed,
+                history: history.into(),
+            },
+        ));
+    }
+
+    Ok(PrefixEntry {
+        prefix,
+        ps_flags,
+        path_selections: PathSelections {
+            path_selection_muis: (best, backup),
+        },
+        records,
+    })
+}
+
+//------------ Primitives ----------------------------------------------------
+
+pub(crate) fn write_retention<W: Write>(
+    w: &mut W,
+    retention: HistoryRetention,
+) -> Result<(), PersistError> {
+    let (kind, value) = match retention {
+        HistoryRetention::Off => (0, 0),
+        HistoryRetention::Versions(n) => (1, n as u64),
+        HistoryRetention::MaxAge(age) => (2, age),
+    };
+    write_u8(w, kind)?;
+    write_u64(w, value)
+}
+
+pub(crate) fn read_retention<R: Read>(
+    r: &mut R,
+) -> Result<HistoryRetention, PersistError> {
+    let kind = read_u8(r)?;
+    let value = read_u64(r)?;
+    match kind {
+        0 => Ok(HistoryRetention::Off),
+        1 => Ok(HistoryRetention::Versions(value as usize)),
+        2 => Ok(HistoryRetention::MaxAge(value)),
+        _ => Err(PersistError::InvalidData("unknown history retention")),
+    }
+}
+
+pub(crate) fn status_to_u8(status: RouteStatus) -> u8 {
+    match status {
+        RouteStatus::Active => 0,
+        RouteStatus::InActive => 1,
+        RouteStatus::Withdrawn => 2,
+        RouteStatus::Stale => 3,
+    }
+}
+
+pub(crate) fn status_from_u8(
+    status: u8,
+) -> Result<RouteStatus, PersistError> {
+    match status {
+        0 => Ok(RouteStatus::Active),
+        1 => Ok(RouteStatus::InActive),
+        2 => Ok(RouteStatus::Withdrawn),
+        3 => Ok(RouteStatus::Stale),
+        _ => Err(PersistError::InvalidData("unknown route status")),
+    }
+}
+
+pub(crate) fn write_meta<M: PersistMeta>(buf: &mut Vec<u8>, meta: &M) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    meta.write_meta(buf);
+    let len = (buf.len() - start - 4) as u32;
+    buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+pub(crate) fn read_meta<M: PersistMeta, R: Read>(
+    r: &mut R,
+) -> Result<M, PersistError> {
+    let len = read_u32(r)?;
+    // The length comes from the file, so only allocate for what is really
+    // there.
+    let mut buf = vec![];
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len as usize {
+        return Err(
+            std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+        );
+    }
+    M::read_meta(&buf).map_err(PersistError::Meta)
+}
+
+pub(crate) fn write_bitmap<W: Write>(
+    w: &mut W,
+    bitmap: &RoaringBitmap,
+) -> Result<(), PersistError> {
+    write_u32(w, bitmap.serialized_size() as u32)?;
+    bitmap.serialize_into(w)?;
+    Ok(())
+}
+
+pub(crate) fn read_bitmap<R: Read>(
+    r: &mut R,
+) -> Result<RoaringBitmap, PersistError> {
+    let len = read_u32(r)? as u64;
+    Ok(RoaringBitmap::deserialize_from(r.take(len))?)
+}
+
+pub(crate) fn write_u8<W: Write>(
+    w: &mut W,
+    v: u8,
+) -> Result<(), PersistError> {
+    Ok(w.write_all(&[v])?)
+}
+
+pub(crate) fn write_u32<W: Write>(
+    w: &mut W,
+    v: u32,
+) -> Result<(), PersistError> {
+    Ok(w.write_all(&v.to_be_bytes())?)
+}
+
+pub(crate) fn write_u64<W: Write>(
+    w: &mut W,
+    v: u64,
+) -> Result<(), PersistError> {
+    Ok(w.write_all(&v.to_be_bytes())?)
+}
+
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> Result<u8, PersistError> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> Result<u32, PersistError> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> Result<u64, PersistError> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
@@ -0,0 +1,189 @@
+// ----------- Path selection ------------------------------------------------
+//
+// A path selector decides which records for a prefix take part in the path
+// selection, and how they are ranked. The store has one path selector,
+// which is used for the best and backup paths that are selected on insert,
+// and for the cached ranking of the paths of a prefix. The default path
+// selector ranks the records by the orderables of their meta-data, like the
+// path selection of routecore does, so that stores with BGP routes don't
+// have to do anything. Stores with other kinds of routes, e.g. static
+// routes with an administrative distance, can bring their own.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use super::atomic_types::RouteStatus;
+use crate::prefix_record::{Meta, MultipathPolicy};
+
+//------------ PathCandidate -------------------------------------------------
+
+/// A record that takes part in the path selection for a prefix
+pub struct PathCandidate<'a, M> {
+    /// The multi_uniq_id of the record
+    pub mui: u32,
+    /// The logical time of the record
+    pub ltime: u64,
+    /// The status of the record. This is Withdrawn if the multi_uniq_id is
+    /// withdrawn globally, whatever the local status of the record is.
+    pub status: RouteStatus,
+    /// The meta-data of the record
+    pub meta: &'a M,
+}
+
+// Deriving these would require M to be Clone and Debug.
+impl<'a, M> Clone for PathCandidate<'a, M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, M> Copy for PathCandidate<'a, M> {}
+
+impl<'a, M: fmt::Debug> fmt::Debug for PathCandidate<'a, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PathCandidate")
+            .field("mui", &self.mui)
+            .field("ltime", &self.ltime)
+            .field("status", &self.status)
+            .field("meta", self.meta)
+            .finish()
+    }
+}
+
+//------------ PathSelector --------------------------------------------------
+
+/// A strategy for selecting and ranking the paths for a prefix
+///
+/// A path selector can be given to a store with
+/// [`with_path_selector`](crate::MultiThreadedStore::with_path_selector),
+/// or to a single call of one of the path selection methods of the store.
+/// [OrderableSelector] is the path selector that is used if none is given.
+pub trait PathSelector<M: Meta>: Send + Sync {
+    /// Returns whether the candidate can be selected at all.
+    ///
+    /// The default implementation only takes candidates with the Active
+    /// status.
+    fn is_eligible(&self, candidate: &PathCandidate<M>) -> bool {
+        candidate.status == RouteStatus::Active
+    }
+
+    /// Compares two eligible candidates. `Ordering::Less` means that `a`
+    /// is preferred over `b`.
+    ///
+    /// This should be a total order, so that the ranking of the paths is
+    /// the same every time. Only candidates with the same multi_uniq_id
+    /// should be equal.
+    fn compare(
+        &self,
+        a: &PathCandidate<M>,
+        b: &PathCandidate<M>,
+        tbi: M::TBI,
+    ) -> Ordering;
+
+    /// Returns the multi_uniq_ids of the best and backup path from the
+    /// eligible `candidates`.
+    ///
+    /// The default implementation takes the two most preferred candidates
+    /// according to `compare`.
+    fn best_backup(
+        &self,
+        candidates: &[PathCandidate<M>],
+        tbi: M::TBI,
+    ) -> (Option<u32>, Option<u32>) {
+        let mut best: Option<&PathCandidate<M>> = None;
+        let mut backup: Option<&PathCandidate<M>> = None;
+        for c in candidates {
+            match best {
+                Some(b) if self.compare(c, b, tbi) != Ordering::Less => {
+                    if backup.map_or(true, |bu| {
+                        self.compare(c, bu, tbi) == Ordering::Less
+                    }) {
+                        backup = Some(c);
+                    }
+                }
+                _ => {
+                    backup = best;
+                    best = Some(c);
+                }
+            }
+        }
+        (best.map(|c| c.mui), backup.map(|c| c.mui))
+    }
+
+    /// Returns whether the eligible `candidate` can be used together with
+    /// the `best` path in a multipath set under `policy`.
+    ///
+    /// The default implementation leaves this to
+    /// [Meta::is_multipath_equivalent], which knows nothing about the
+    /// ranking of this path selector. Path selectors that rank by other
+    /// criteria than the orderables should implement this, so that the
+    /// multipath set agrees with their ranking.
+    fn is_multipath_equivalent(
+        &self,
+        candidate: &PathCandidate<M>,
+        best: &PathCandidate<M>,
+        tbi: M::TBI,
+        policy: MultipathPolicy,
+    ) -> bool {
+        candidate.meta.is_multipath_equivalent(best.meta, tbi, policy)
+    }
+}
+
+//------------ OrderableSelector ---------------------------------------------
+
+/// The default path selector
+///
+/// This ranks the eligible paths by the orderables of their meta-data, see
+/// [Meta::as_orderable], and, for paths with equal orderables, by their
+/// multi_uniq_id. The best and backup path are selected with the path
+/// selection of routecore, with the same tie-break, so they are always the
+/// first two paths of the ranking.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrderableSelector;
+
+impl OrderableSelector {
+    // The key both the ranking and the best and backup path selection
+    // order the candidates by.
+    fn key<'a, M: Meta>(
+        candidate: &PathCandidate<'a, M>,
+        tbi: M::TBI,
+    ) -> (M::Orderable<'a>, u32) {
+        (candidate.meta.as_orderable(tbi), candidate.mui)
+    }
+}
+
+impl<M: Meta> PathSelector<M> for OrderableSelector {
+    fn compare(
+        &self,
+        a: &PathCandidate<M>,
+        b: &PathCandidate<M>,
+        tbi: M::TBI,
+    ) -> Ordering {
+        Self::key(a, tbi).cmp(&Self::key(b, tbi))
+    }
+
+    fn best_backup(
+        &self,
+        candidates: &[PathCandidate<M>],
+        tbi: M::TBI,
+    ) -> (Option<u32>, Option<u32>) {
+        let (best, backup) =
+            routecore::bgp::path_selection::best_backup_generic(
+                candidates.iter().map(|c| Self::key(c, tbi)),
+            );
+        (best.map(|b| b.1), backup.map(|b| b.1))
+    }
+}
+
+//------------ SharedSelector ------------------------------------------------
+
+// The path selector of a store, shared by the trees for both address
+// families.
+pub(crate) struct SharedSelector<M>(pub(crate) Arc<dyn PathSelector<M>>);
+
+impl<M> fmt::Debug for SharedSelector<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SharedSelector")
+    }
+}
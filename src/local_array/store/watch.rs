@@ -0,0 +1,218 @@
+// ----------- Watchlist -----------------------------------------------------
+//
+// A watchlist holds the prefixes that users want to be told about, when a
+// record for them, or for one of their more-specifics, is inserted. It is
+// shared by the trees for both address families. Every insert that creates
+// a new record for a (prefix, mui) checks the watchlist, after the record
+// was stored, and calls the callbacks for all the watched prefixes that
+// cover the inserted prefix.
+//
+// The watched prefixes are indexed in a single-threaded tree bitmap, so an
+// insert finds the watched prefixes that cover it with a longest match
+// that includes the less-specifics, like any other lookup in a tree. That
+// tree doesn't hold the default route, so watches on the default route are
+// looked up separately. The callbacks are called after the lock on the
+// watchlist is released, so that they can add and remove watches
+// themselves. Inserts skip the check altogether if there are no watched
+// prefixes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use inetnum::addr::Prefix;
+
+use crate::af::AddressFamily;
+use crate::local_array::node::PrefixId;
+use crate::local_vec::store::Store;
+use crate::meta_examples::NoMeta;
+use crate::prefix_record::PublicRecord;
+use crate::{MatchOptions, MatchType};
+
+//------------ WatchId -------------------------------------------------------
+
+/// The identifier of a watch on a prefix, used to remove it again
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WatchId(u64);
+
+//------------ WatchEvent ----------------------------------------------------
+
+/// A record that was inserted for a watched prefix, or a more-specific of it
+#[derive(Clone, Debug)]
+pub struct WatchEvent<M> {
+    /// The watched prefix
+    pub watched: Prefix,
+    /// The prefix the record was inserted for, which is either the watched
+    /// prefix, or a more-specific of it
+    pub prefix: Prefix,
+    /// The inserted record
+    pub record: PublicRecord<M>,
+    /// Whether the prefix did not have any records before this one
+    pub prefix_new: bool,
+}
+
+//------------ Watchlist -----------------------------------------------------
+
+type Callback<M> = Arc<dyn Fn(&WatchEvent<M>) + Send + Sync>;
+
+pub(crate) struct Watchlist<M> {
+    next_id: AtomicU64,
+    // The number of watches, so that inserts can skip the lock if there
+    // aren't any.
+    count: AtomicUsize,
+    inner: RwLock<WatchlistInner<M>>,
+}
+
+struct WatchlistInner<M> {
+    watches: HashMap<Prefix, Vec<(WatchId, Callback<M>)>>,
+    // The watched prefixes, except for the default routes.
+    index: Store<NoMeta>,
+}
+
+impl<M> Watchlist<M> {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            count: AtomicUsize::new(0),
+            inner: RwLock::new(WatchlistInner {
+                watches: HashMap::new(),
+                index: WatchlistInner::<M>::new_index(),
+            }),
+        }
+    }
+
+    pub(crate) fn add(
+        &self,
+        prefix: Prefix,
+        callback: Callback<M>,
+    ) -> WatchId {
+        let id = WatchId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut inner = self.inner.write().unwrap();
+        let watches = inner.watches.entry(prefix).or_default();
+        watches.push((id, callback));
+        if watches.len() == 1 && prefix.len() > 0 {
+            // The index only holds prefixes that are not in it yet.
+            let _ = inner.index.insert(&prefix, NoMeta::Empty);
+        }
+        self.count.fetch_add(1, Ordering::Release);
+        id
+    }
+
+    // Remove the watch with this id. Returns whether there was one.
+    pub(crate) fn remove(&self, id: WatchId) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let prefix = match inner.watches.iter().find_map(|(prefix, watches)| {
+            watches.iter().any(|w| w.0 == id).then_some(*prefix)
+        }) {
+            Some(prefix) => prefix,
+            None => return false,
+        };
+        if let Some(watches) = inner.watches.get_mut(&prefix) {
+            watches.retain(|w| w.0 != id);
+            if watches.is_empty() {
+                inner.watches.remove(&prefix);
+                // Prefixes cannot be removed from the index, so it is
+                // built again from the prefixes that are still watched.
+                inner.rebuild_index();
+            }
+        }
+        self.count.fetch_sub(1, Ordering::Release);
+        true
+    }
+
+    // Call the callbacks for all the watched prefixes that cover `prefix`,
+    // including `prefix` itself, with the record that `record` returns.
+    // `record` is only called if there are any.
+    pub(crate) fn notify<AF: AddressFamily>(
+        &self,
+        prefix: PrefixId<AF>,
+        record: impl FnOnce() -> Option<PublicRecord<M>>,
+        prefix_new: bool,
+    ) where
+        M: Clone,
+    {
+        if !self.is_active() {
+            return;
+        }
+        let prefix = prefix.into_pub();
+        let callbacks = self.inner.read().unwrap().covering(&prefix);
+        if callbacks.is_empty() {
+            return;
+        }
+        let record = match record() {
+            Some(record) => record,
+            None => return,
+        };
+        for (watched, callback) in callbacks {
+            callback(&WatchEvent {
+                watched,
+                prefix,
+                record: record.clone(),
+                prefix_new,
+            });
+        }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.count.load(Ordering::Acquire) > 0
+    }
+}
+
+impl<M> WatchlistInner<M> {
+    fn new_index() -> Store<NoMeta> {
+        Store::new(vec![4], vec![4])
+    }
+
+    fn rebuild_index(&mut self) {
+        let mut index = Self::new_index();
+        for prefix in self.watches.keys().filter(|p| p.len() > 0) {
+            let _ = index.insert(prefix, NoMeta::Empty);
+        }
+        self.index = index;
+    }
+
+    // Returns the callbacks for the watched prefixes that cover `prefix`,
+    // together with the watched prefix.
+    fn covering(&self, prefix: &Prefix) -> Vec<(Prefix, Callback<M>)> {
+        let res = self.index.match_prefix(
+            prefix,
+            &MatchOptions {
+                match_type: MatchType::LongestMatch,
+                include_withdrawn: true,
+                include_less_specifics: true,
+                include_more_specifics: false,
+                mui: None,
+            },
+        );
+        // The default route of the address family of `prefix`.
+        let default_route = match prefix.is_v6() {
+            false => Prefix::new_v4(Ipv4Addr::UNSPECIFIED, 0),
+            true => Prefix::new_v6(Ipv6Addr::UNSPECIFIED, 0),
+        };
+        default_route
+            .into_iter()
+            .chain(
+                res.less_specifics
+                    .iter()
+                    .flat_map(|ls| ls.iter().map(|r| r.prefix)),
+            )
+            .chain(res.prefix)
+            .filter_map(|watched| {
+                self.watches.get(&watched).map(|watches| (watched, watches))
+            })
+            .flat_map(|(watched, watches)| {
+                watches.iter().map(move |(_, cb)| (watched, cb.clone()))
+            })
+            .collect()
+    }
+}
+
+impl<M> fmt::Debug for Watchlist<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchlist")
+            .field("count", &self.count.load(Ordering::Relaxed))
+            .finish()
+    }
+}
@@ -11,7 +11,9 @@ use std::{fmt::Debug, marker::PhantomData};
 use crate::af::AddressFamily;
 use crate::custom_alloc::{CustomAllocStorage, UpsertReport};
 use crate::insert_match;
-use crate::local_array::store::atomic_types::{NodeBuckets, PrefixBuckets};
+use crate::local_array::store::atomic_types::{
+    NewRecord, NodeBuckets, PrefixBuckets, UpsertMode,
+};
 
 pub(crate) use super::atomic_stride::*;
 use super::store::errors::PrefixStoreError;
@@ -451,11 +453,31 @@ impl<
         // user_data: Option<&<M as MergeUpdate>::UserDataIn>,
     ) -> Result<UpsertReport, PrefixStoreError> {
         let guard = &epoch::pin();
+        self.insert_with_guard(
+            pfx,
+            record.into(),
+            update_path_selections,
+            UpsertMode::Replace,
+            guard,
+        )
+    }
+
+    // The same as `insert`, but with a guard of the caller, so that callers
+    // that insert many prefixes in a row don't have to pin for each one,
+    // and with the mode for the upsert of the record.
+    pub(crate) fn insert_with_guard(
+        &self,
+        pfx: PrefixId<AF>,
+        record: NewRecord<M>,
+        update_path_selections: Option<M::TBI>,
+        mode: UpsertMode,
+        guard: &epoch::Guard,
+    ) -> Result<UpsertReport, PrefixStoreError> {
         // let record = MultiMapValue::new(meta, ltime, status);
 
         if pfx.get_len() == 0 {
             let res = self.update_default_route_prefix_meta(
-                record, guard)?;
+                record, mode, guard)?;
             return Ok(res);
         }
 
@@ -494,6 +516,7 @@ impl<
                 pfx;
                 record;
                 update_path_selections; // perform an update for the paths in this record
+                mode; // how to treat an existing record for the same mui
                 stride_start; // the length at the start of the stride a.k.a. start_bit
                 stride;
                 cur_i;
@@ -557,22 +580,23 @@ impl<
     //   those specialized methods we're good to go.
     fn update_default_route_prefix_meta(
         &self,
-        record: PublicRecord<M>,
+        record: NewRecord<M>,
+        mode: UpsertMode,
         guard: &epoch::Guard,
         // user_data: Option<&<M as MergeUpdate>::UserDataIn>,
     ) -> Result<UpsertReport, PrefixStoreError> {
         trace!("Updating the default route...");
 
-        if let Some(root_node) = self.store.retrieve_node_mut_with_guard(self.store.get_root_node_id(), record.multi_uniq_id, guard) {
+        if let Some(root_node) = self.store.retrieve_node_mut_with_guard(self.store.get_root_node_id(), record.mui(), guard) {
             match root_node {
                 SizedStrideRefMut::Stride3(_) => { 
-                    self.store.buckets.get_store3(self.store.get_root_node_id()).update_rbm_index(record.multi_uniq_id, guard)?;
+                    self.store.buckets.get_store3(self.store.get_root_node_id()).update_rbm_index(record.mui(), guard)?;
                 },
                 SizedStrideRefMut::Stride4(_) => {
-                    self.store.buckets.get_store4(self.store.get_root_node_id()).update_rbm_index(record.multi_uniq_id, guard)?;
+                    self.store.buckets.get_store4(self.store.get_root_node_id()).update_rbm_index(record.mui(), guard)?;
                 },
                 SizedStrideRefMut::Stride5(_) => {
-                    self.store.buckets.get_store5(self.store.get_root_node_id()).update_rbm_index(record.multi_uniq_id, guard)?;
+                    self.store.buckets.get_store5(self.store.get_root_node_id()).update_rbm_index(record.mui(), guard)?;
                  },
             };
         };
@@ -582,6 +606,7 @@ impl<
             record,
             // Do not update the path selection for the default route.
             None,
+            mode,
             guard,
             // user_data,
         )
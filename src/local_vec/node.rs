@@ -233,12 +233,11 @@ where
 
                 // Receiving a less_specifics_vec means that the user wants to have
                 // all the last-specific prefixes returned, so add the found prefix.
+                // The longest matching prefix ends up in there as well, if it is
+                // shorter than the search prefix, so that is taken out again
+                // after the last stride.
                 if let Some(ls_vec) = less_specifics_vec {
-                    if !(search_pfx.get_len() <= start_bit + nibble_len
-                        || (S::into_stride_size(self.ptrbitarr)
-                            & S::get_bit_pos(nibble, nibble_len))
-                            == S::zero())
-                    {
+                    if search_pfx.get_len() > start_bit + n_l {
                         ls_vec.push(f_pfx);
                     }
                 }
@@ -753,6 +753,15 @@ where
         // Now we will look up more-specifics for longest-matching prefixes that were found in the last stride only,
         // Note that still any of the match_types (as specified by the user, not the return type) may end up here.
 
+        // The longest matching prefix is not a less-specific of itself.
+        if let (Some(ls_vec), Some(pfx_idx)) =
+            (less_specifics_vec.as_mut(), match_prefix_idx)
+        {
+            if ls_vec.last().map(|p| p.get_part()) == Some(pfx_idx) {
+                ls_vec.pop();
+            }
+        }
+
         let mut match_type: MatchType = MatchType::EmptyMatch;
         let mut prefix = None;
         if let Some(pfx_idx) = match_prefix_idx {
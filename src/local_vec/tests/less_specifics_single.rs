@@ -0,0 +1,68 @@
+#![cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        meta_examples::PrefixAs,
+        SingleThreadedStore
+    };
+
+    use std::error::Error;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_longest_match_with_less_specifics() -> Result<(), Box<dyn Error>>
+    {
+        let mut tree_bitmap =
+            SingleThreadedStore::<PrefixAs>::new(vec![4], vec![4]);
+        for pfx in ["10.0.0.0/8", "10.0.0.0/9", "10.0.0.0/10", "10.0.0.0/16"]
+        {
+            tree_bitmap.insert(&Prefix::from_str(pfx)?, PrefixAs(666))?;
+        }
+
+        for (search_pfx, match_type, longest, less_specifics) in [
+            // The less-specifics in the last stride are included...
+            (
+                "10.0.0.0/10",
+                MatchType::ExactMatch,
+                "10.0.0.0/10",
+                vec!["10.0.0.0/8", "10.0.0.0/9"],
+            ),
+            // ...and the longest match is not one of them.
+            (
+                "10.128.0.0/9",
+                MatchType::LongestMatch,
+                "10.0.0.0/8",
+                vec![],
+            ),
+            (
+                "10.0.0.0/24",
+                MatchType::LongestMatch,
+                "10.0.0.0/16",
+                vec!["10.0.0.0/8", "10.0.0.0/9", "10.0.0.0/10"],
+            ),
+        ] {
+            let found_result = tree_bitmap.match_prefix(
+                &Prefix::from_str(search_pfx)?,
+                &MatchOptions {
+                    match_type: MatchType::LongestMatch,
+                    include_withdrawn: false,
+                    include_less_specifics: true,
+                    include_more_specifics: false,
+                    mui: None,
+                },
+            );
+            assert_eq!(found_result.match_type, match_type);
+            assert_eq!(found_result.prefix, Some(Prefix::from_str(longest)?));
+            assert_eq!(
+                found_result
+                    .less_specifics
+                    .unwrap()
+                    .iter()
+                    .map(|r| r.prefix.to_string())
+                    .collect::<Vec<_>>(),
+                less_specifics
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,3 @@
 mod full_table_single;
+mod less_specifics_single;
 mod more_specifics_single;
\ No newline at end of file
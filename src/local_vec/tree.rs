@@ -340,7 +340,7 @@ where
         match self.store.retrieve_prefix_mut(update_node_idx) {
             Some(update_pfx) => {
                 update_pfx.meta = meta;
-                Ok(UpsertReport { cas_count: 0, prefix_new: false, mui_new: false, mui_count: 0 })
+                Ok(UpsertReport { cas_count: 0, prefix_new: false, mui_new: false, mui_count: 0, applied: true })
                 // <Store::Meta>::merge_update(&mut update_pfx.meta, meta)
             }
             // TODO
@@ -5,6 +5,7 @@ use inetnum::asn::Asn;
 use crate::Meta;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrefixAs(pub u32);
 
 // impl MergeUpdate for PrefixAs {
@@ -60,6 +61,7 @@ impl std::fmt::Display for PrefixAs {
 /// storing the prefixes. Note that this is different from a tree with
 /// optional meta-data.
 #[derive(Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NoMeta {
     Empty,
 }
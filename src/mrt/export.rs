@@ -0,0 +1,260 @@
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use inetnum::addr::Prefix;
+use log::trace;
+use roaring::RoaringBitmap;
+
+use crate::bgp::BgpMeta;
+use crate::prelude::multi::{epoch, RouteStatus};
+use crate::MultiThreadedStore;
+
+use super::{
+    MrtError, MrtPeer, MrtRecord, PEER_INDEX_TABLE, RIB_IPV4_UNICAST,
+    RIB_IPV6_UNICAST, TABLE_DUMP_V2,
+};
+
+// The type code for the MP_REACH_NLRI path attribute.
+const MP_REACH_NLRI: u8 = 14;
+
+//------------ ExportOptions -------------------------------------------------
+
+/// Options for exporting a store to an MRT TABLE_DUMP_V2 file
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    /// The BGP identifier of the collector, for the PEER_INDEX_TABLE
+    pub collector_bgp_id: [u8; 4],
+    /// The name of the view, for the PEER_INDEX_TABLE
+    pub view_name: Option<String>,
+    /// The timestamp for the MRT records, in seconds since the Unix epoch
+    pub timestamp: u32,
+    /// The peers for the multi_uniq_ids in the store, e.g. the peers from
+    /// an [ImportReport](super::ImportReport). Multi_uniq_ids without a
+    /// peer in here end up in the PEER_INDEX_TABLE with the unspecified
+    /// address and AS0.
+    pub peers: Vec<MrtPeer>,
+    /// Whether to export withdrawn records as well
+    pub include_withdrawn: bool,
+}
+
+//------------ ExportReport --------------------------------------------------
+
+/// The result of exporting a store to an MRT TABLE_DUMP_V2 file
+#[derive(Clone, Debug, Default)]
+pub struct ExportReport {
+    /// The peers in the PEER_INDEX_TABLE, indexed by their peer index
+    pub peers: Vec<MrtPeer>,
+    /// The number of RIB_IPV4_UNICAST and RIB_IPV6_UNICAST records
+    pub rib_records: usize,
+    /// The number of routes in the RIB records
+    pub routes_exported: usize,
+}
+
+//------------ Exporting -----------------------------------------------------
+
+/// Write the contents of `store` to `writer` as an MRT TABLE_DUMP_V2 file.
+///
+/// The file starts with a PEER_INDEX_TABLE with a peer for every
+/// multi_uniq_id that has records in the store, ordered by multi_uniq_id.
+/// After that, there's a RIB_IPV4_UNICAST or RIB_IPV6_UNICAST record for
+/// every prefix, with a RIB entry for every record of that prefix. The
+/// path attributes for the entries come from the [BgpMeta] implementation
+/// of the meta-data, and the `ltime` of a record is used as the originated
+/// time of the entry.
+///
+/// The store is walked twice, once to find the multi_uniq_ids, and once to
+/// write the RIB records. Records for a multi_uniq_id that was inserted
+/// in between are left out. The writer is written to in small pieces, so
+/// it is a good idea to wrap it in a `BufWriter` if it isn't buffered
+/// already.
+///
+/// Returns an `InvalidData` error, before anything is written, if the view
+/// name is longer than 65535 octets, or if there are more than 65535 peers.
+pub fn export_table_dump<M: BgpMeta, W: Write>(
+    store: &MultiThreadedStore<M>,
+    writer: &mut W,
+    options: &ExportOptions,
+) -> Result<ExportReport, MrtError> {
+    let guard = &epoch::pin();
+
+    let is_exported = |prefix: &Prefix, mui: u32, status: RouteStatus| {
+        options.include_withdrawn
+            || (status != RouteStatus::Withdrawn
+                && !match prefix.addr() {
+                    IpAddr::V4(_) => store.mui_is_withdrawn_v4(mui),
+                    IpAddr::V6(_) => store.mui_is_withdrawn_v6(mui),
+                })
+    };
+
+    let mut muis = RoaringBitmap::new();
+    for pfx in store.prefixes_iter(guard) {
+        for rec in pfx.meta.iter() {
+            if is_exported(&pfx.prefix, rec.multi_uniq_id, rec.status) {
+                muis.insert(rec.multi_uniq_id);
+            }
+        }
+    }
+    if muis.len() > u16::MAX as u64 {
+        return Err(MrtError::InvalidData("too many peers"));
+    }
+
+    let peers = muis
+        .iter()
+        .map(|mui| {
+            options
+                .peers
+                .iter()
+                .find(|peer| peer.mui == mui)
+                .copied()
+                .unwrap_or(MrtPeer {
+                    mui,
+                    bgp_id: [0; 4],
+                    addr: Ipv4Addr::UNSPECIFIED.into(),
+                    asn: 0.into(),
+                })
+        })
+        .collect::<Vec<_>>();
+    trace!("{} peers in the PEER_INDEX_TABLE", peers.len());
+
+    peer_index_table(options, &peers)?.write(writer)?;
+
+    let mut report = ExportReport {
+        peers,
+        ..Default::default()
+    };
+    let mut attributes = vec![];
+    for pfx in store.prefixes_iter(guard) {
+        let mut body = vec![];
+        body.extend_from_slice(&(report.rib_records as u32).to_be_bytes());
+        write_prefix(&mut body, &pfx.prefix);
+        let count_pos = body.len();
+        body.extend_from_slice(&[0, 0]);
+
+        let mut count = 0_u16;
+        for rec in pfx.meta.iter() {
+            if !is_exported(&pfx.prefix, rec.multi_uniq_id, rec.status)
+                || !muis.contains(rec.multi_uniq_id)
+            {
+                continue;
+            }
+            attributes.clear();
+            write_attributes(&mut attributes, &pfx.prefix, &rec.meta);
+            let attributes_len = u16::try_from(attributes.len())
+                .map_err(|_| MrtError::InvalidData("attributes too long"))?;
+
+            // The rank of a mui is one more than its index, since it
+            // includes the mui itself.
+            let peer_index = (muis.rank(rec.multi_uniq_id) - 1) as u16;
+            body.extend_from_slice(&peer_index.to_be_bytes());
+            body.extend_from_slice(
+                &u32::try_from(rec.ltime).unwrap_or(u32::MAX).to_be_bytes(),
+            );
+            body.extend_from_slice(&attributes_len.to_be_bytes());
+            body.extend_from_slice(&attributes);
+            count += 1;
+        }
+        if count == 0 {
+            continue;
+        }
+        body[count_pos..count_pos + 2].copy_from_slice(&count.to_be_bytes());
+
+        let subtype = match pfx.prefix.addr() {
+            IpAddr::V4(_) => RIB_IPV4_UNICAST,
+            IpAddr::V6(_) => RIB_IPV6_UNICAST,
+        };
+        MrtRecord {
+            timestamp: options.timestamp,
+            msg_type: TABLE_DUMP_V2,
+            subtype,
+            body,
+        }
+        .write(writer)?;
+        report.rib_records += 1;
+        report.routes_exported += count as usize;
+    }
+
+    writer.flush()?;
+    Ok(report)
+}
+
+fn peer_index_table(
+    options: &ExportOptions,
+    peers: &[MrtPeer],
+) -> Result<MrtRecord, MrtError> {
+    let mut body = vec![];
+    body.extend_from_slice(&options.collector_bgp_id);
+    let view_name = options.view_name.as_deref().unwrap_or("").as_bytes();
+    let view_name_len = u16::try_from(view_name.len())
+        .map_err(|_| MrtError::InvalidData("view name too long"))?;
+    body.extend_from_slice(&view_name_len.to_be_bytes());
+    body.extend_from_slice(view_name);
+    body.extend_from_slice(&(peers.len() as u16).to_be_bytes());
+
+    // All the AS numbers are written as four octet AS numbers.
+    for peer in peers {
+        match peer.addr {
+            IpAddr::V4(addr) => {
+                body.push(0x02);
+                body.extend_from_slice(&peer.bgp_id);
+                body.extend_from_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                body.push(0x03);
+                body.extend_from_slice(&peer.bgp_id);
+                body.extend_from_slice(&addr.octets());
+            }
+        }
+        body.extend_from_slice(&peer.asn.into_u32().to_be_bytes());
+    }
+
+    Ok(MrtRecord {
+        timestamp: options.timestamp,
+        msg_type: TABLE_DUMP_V2,
+        subtype: PEER_INDEX_TABLE,
+        body,
+    })
+}
+
+fn write_prefix(buf: &mut Vec<u8>, prefix: &Prefix) {
+    let n = (prefix.len() as usize + 7) / 8;
+    buf.push(prefix.len());
+    match prefix.addr() {
+        IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()[..n]),
+        IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()[..n]),
+    }
+}
+
+// Write the path attributes for a route, ordered by type code. Routes for
+// IPv6 prefixes get an MP_REACH_NLRI attribute with only the next hop, as
+// RFC 6396 prescribes.
+fn write_attributes<M: BgpMeta>(
+    buf: &mut Vec<u8>,
+    prefix: &Prefix,
+    meta: &M,
+) {
+    let mut mp_reach = match (prefix.addr(), meta.next_hop()) {
+        (IpAddr::V6(_), Some(IpAddr::V6(next_hop))) => Some(next_hop),
+        _ => None,
+    };
+
+    for (type_code, pa) in meta.path_attributes().attributes().iter() {
+        if *type_code == MP_REACH_NLRI {
+            continue;
+        }
+        if *type_code > MP_REACH_NLRI {
+            if let Some(next_hop) = mp_reach.take() {
+                write_mp_reach(buf, next_hop);
+            }
+        }
+        pa.compose(buf).unwrap_or_else(|e| match e {});
+    }
+    if let Some(next_hop) = mp_reach {
+        write_mp_reach(buf, next_hop);
+    }
+}
+
+fn write_mp_reach(buf: &mut Vec<u8>, next_hop: Ipv6Addr) {
+    // Optional, non-transitive.
+    buf.extend_from_slice(&[0x80, MP_REACH_NLRI, 17, 16]);
+    buf.extend_from_slice(&next_hop.octets());
+}
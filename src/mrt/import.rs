@@ -0,0 +1,309 @@
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+
+use inetnum::addr::Prefix;
+use log::trace;
+use routecore::bgp::message::PduParseInfo;
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+
+use crate::prelude::multi::{Record, RouteStatus};
+use crate::prelude::Meta;
+use crate::MultiThreadedStore;
+
+use super::{
+    BodyParser, MrtError, MrtPeer, MrtRecord, PEER_INDEX_TABLE,
+    RIB_IPV4_UNICAST, RIB_IPV6_UNICAST, TABLE_DUMP_V2,
+};
+
+// The number of RIB records that are handed to an importing thread at a
+// time.
+const BATCH_SIZE: usize = 256;
+
+//------------ ImportOptions -------------------------------------------------
+
+/// Options for importing an MRT TABLE_DUMP_V2 file
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    /// The multi_uniq_id for the first peer in the PEER_INDEX_TABLE. The
+    /// peer with index `n` gets the multi_uniq_id `mui_base + n`.
+    pub mui_base: u32,
+    /// The number of threads that insert the routes into the store. Zero
+    /// means one thread for every available CPU.
+    pub threads: usize,
+}
+
+//------------ ImportReport --------------------------------------------------
+
+/// The result of importing an MRT TABLE_DUMP_V2 file
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    /// The peers from the PEER_INDEX_TABLE, indexed by their peer index
+    pub peers: Vec<MrtPeer>,
+    /// The number of RIB_IPV4_UNICAST and RIB_IPV6_UNICAST records
+    pub rib_records: usize,
+    /// The number of routes that were inserted into the store
+    pub routes_inserted: usize,
+    /// The number of routes for which the meta-data builder returned `None`
+    pub routes_skipped: usize,
+    /// The number of records of other types and subtypes, which were
+    /// skipped
+    pub records_skipped: usize,
+}
+
+//------------ Importing -----------------------------------------------------
+
+/// Import the MRT TABLE_DUMP_V2 file at `path` into `store`.
+///
+/// See [import_table_dump] for details.
+pub fn import_table_dump_file<M, P, F>(
+    store: &MultiThreadedStore<M>,
+    path: P,
+    options: &ImportOptions,
+    meta_builder: F,
+) -> Result<ImportReport, MrtError>
+where
+    M: Meta,
+    P: AsRef<Path>,
+    F: Fn(&MrtPeer, &Prefix, &OwnedPathAttributes) -> Option<M> + Sync,
+{
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    import_table_dump(store, &mut reader, options, meta_builder)
+}
+
+/// Import an MRT TABLE_DUMP_V2 file from `reader` into `store`.
+///
+/// The file should start with a PEER_INDEX_TABLE record. Every peer in that
+/// table gets its own multi_uniq_id (see [ImportOptions]). For every route
+/// in the RIB_IPV4_UNICAST and RIB_IPV6_UNICAST records that follow,
+/// `meta_builder` is called with the peer, the prefix and the path
+/// attributes of the route. The meta-data it returns is inserted into the
+/// store, as a record with the Active status and the originated time of
+/// the route as its `ltime`. If it returns `None` the route is skipped.
+/// Records of any other type or subtype are skipped as well.
+///
+/// The file is read by the calling thread, while the records are parsed
+/// and inserted into the store by multiple threads, so routes are not
+/// inserted in the order they appear in the file. Best path selection is
+/// not run for the inserted prefixes.
+pub fn import_table_dump<M, R, F>(
+    store: &MultiThreadedStore<M>,
+    reader: &mut R,
+    options: &ImportOptions,
+    meta_builder: F,
+) -> Result<ImportReport, MrtError>
+where
+    M: Meta,
+    R: Read,
+    F: Fn(&MrtPeer, &Prefix, &OwnedPathAttributes) -> Option<M> + Sync,
+{
+    let peers = match MrtRecord::read(reader)? {
+        Some(record)
+            if record.msg_type == TABLE_DUMP_V2
+                && record.subtype == PEER_INDEX_TABLE =>
+        {
+            parse_peer_index_table(&record.body, options.mui_base)?
+        }
+        _ => {
+            return Err(MrtError::InvalidData(
+                "file does not start with a PEER_INDEX_TABLE",
+            ));
+        }
+    };
+    trace!("{} peers in the PEER_INDEX_TABLE", peers.len());
+
+    let threads = match options.threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+
+    let routes_inserted = AtomicUsize::new(0);
+    let routes_skipped = AtomicUsize::new(0);
+    let mut rib_records = 0;
+    let mut records_skipped = 0;
+
+    // The first error that occurred in one of the importing threads. Once
+    // it is set, the remaining batches are not imported anymore.
+    let error: Mutex<Option<MrtError>> = Mutex::new(None);
+
+    let (tx, rx) = mpsc::sync_channel::<Vec<MrtRecord>>(threads * 2);
+    let rx = Mutex::new(rx);
+
+    std::thread::scope(|s| {
+        // The importing threads stop when the sender is dropped, which
+        // happens when this closure returns, also when it returns early
+        // with an error.
+        let tx = tx;
+
+        for i in 0..threads {
+            let rx = &rx;
+            let error = &error;
+            let peers = &peers;
+            let meta_builder = &meta_builder;
+            let routes_inserted = &routes_inserted;
+            let routes_skipped = &routes_skipped;
+            std::thread::Builder::new()
+                .name(format!("mrt-import-{}", i))
+                .spawn_scoped(s, move || loop {
+                    let batch = match rx.lock().unwrap().recv() {
+                        Ok(batch) => batch,
+                        Err(_) => return,
+                    };
+                    if error.lock().unwrap().is_some() {
+                        continue;
+                    }
+                    for record in batch {
+                        match insert_rib_record(
+                            store,
+                            &record,
+                            peers,
+                            meta_builder,
+                        ) {
+                            Ok((inserted, skipped)) => {
+                                routes_inserted
+                                    .fetch_add(inserted, Ordering::Relaxed);
+                                routes_skipped
+                                    .fetch_add(skipped, Ordering::Relaxed);
+                            }
+                            Err(err) => {
+                                error.lock().unwrap().get_or_insert(err);
+                                break;
+                            }
+                        }
+                    }
+                })?;
+        }
+
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while let Some(record) = MrtRecord::read(reader)? {
+            if record.msg_type != TABLE_DUMP_V2
+                || (record.subtype != RIB_IPV4_UNICAST
+                    && record.subtype != RIB_IPV6_UNICAST)
+            {
+                records_skipped += 1;
+                continue;
+            }
+            rib_records += 1;
+            batch.push(record);
+            if batch.len() == BATCH_SIZE {
+                if error.lock().unwrap().is_some() {
+                    break;
+                }
+                let batch = std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(BATCH_SIZE),
+                );
+                // The importing threads only stop after the sender is
+                // dropped, so this can't fail.
+                let _ = tx.send(batch);
+            }
+        }
+        let _ = tx.send(batch);
+        Ok::<_, MrtError>(())
+    })?;
+
+    if let Some(err) = error.into_inner().unwrap() {
+        return Err(err);
+    }
+
+    Ok(ImportReport {
+        peers,
+        rib_records,
+        routes_inserted: routes_inserted.into_inner(),
+        routes_skipped: routes_skipped.into_inner(),
+        records_skipped,
+    })
+}
+
+fn parse_peer_index_table(
+    body: &[u8],
+    mui_base: u32,
+) -> Result<Vec<MrtPeer>, MrtError> {
+    let mut parser = BodyParser::new(body);
+    let _collector_bgp_id = parser.u32()?;
+    let view_name_len = parser.u16()?;
+    parser.take(view_name_len as usize)?;
+    let count = parser.u16()?;
+
+    let mut peers = Vec::with_capacity(count as usize);
+    for i in 0..count as u32 {
+        let peer_type = parser.u8()?;
+        let bgp_id = parser.take(4)?.try_into().unwrap();
+        let addr = if peer_type & 0x01 == 0 {
+            parser.ipv4()?
+        } else {
+            parser.ipv6()?
+        };
+        let asn = if peer_type & 0x02 == 0 {
+            u32::from(parser.u16()?)
+        } else {
+            parser.u32()?
+        };
+        let mui = mui_base
+            .checked_add(i)
+            .ok_or(MrtError::InvalidData("multi_uniq_id out of range"))?;
+        peers.push(MrtPeer {
+            mui,
+            bgp_id,
+            addr,
+            asn: asn.into(),
+        });
+    }
+
+    Ok(peers)
+}
+
+// Insert the routes in a RIB_IPV4_UNICAST or RIB_IPV6_UNICAST record.
+// Returns the number of inserted and skipped routes.
+fn insert_rib_record<M: Meta, F>(
+    store: &MultiThreadedStore<M>,
+    record: &MrtRecord,
+    peers: &[MrtPeer],
+    meta_builder: &F,
+) -> Result<(usize, usize), MrtError>
+where
+    F: Fn(&MrtPeer, &Prefix, &OwnedPathAttributes) -> Option<M>,
+{
+    let mut parser = BodyParser::new(&record.body);
+    let _seq_number = parser.u32()?;
+    let prefix = parser.prefix(record.subtype == RIB_IPV6_UNICAST)?;
+    let count = parser.u16()?;
+
+    let (mut inserted, mut skipped) = (0, 0);
+    for _ in 0..count {
+        let peer_index = parser.u16()?;
+        let originated_time = parser.u32()?;
+        let attributes_len = parser.u16()?;
+        let attributes = parser.take(attributes_len as usize)?;
+
+        let peer = peers
+            .get(peer_index as usize)
+            .ok_or(MrtError::InvalidData("unknown peer index"))?;
+
+        // Path attributes in TABLE_DUMP_V2 always have four octet AS
+        // numbers, and never have ADD-PATH path ids.
+        let attributes = OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            attributes.to_vec(),
+        );
+        match meta_builder(peer, &prefix, &attributes) {
+            Some(meta) => {
+                store.insert(
+                    &prefix,
+                    Record::new(
+                        peer.mui,
+                        originated_time as u64,
+                        RouteStatus::Active,
+                        meta,
+                    ),
+                    None,
+                )?;
+                inserted += 1;
+            }
+            None => skipped += 1,
+        }
+    }
+
+    Ok((inserted, skipped))
+}
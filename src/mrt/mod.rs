@@ -0,0 +1,218 @@
+//! Reading and writing MRT files
+//!
+//! This module has facilities to move routing information between a
+//! [MultiThreadedStore](crate::MultiThreadedStore) and files in the MRT
+//! format, as described in RFC 6396, that are produced by route collectors.
+//! The path attributes in these files are parsed with `routecore`.
+//!
+//! This module is only available with the `mrt` feature.
+
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::net::IpAddr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+
+use crate::prelude::multi::PrefixStoreError;
+
+mod export;
+mod import;
+mod replay;
+
+pub use export::{export_table_dump, ExportOptions, ExportReport};
+pub use import::{
+    import_table_dump, import_table_dump_file, ImportOptions, ImportReport,
+};
+pub use replay::{
+    replay_updates, replay_updates_file, ReplayOptions, ReplayReport,
+};
+
+// MRT types and subtypes, RFC 6396 and RFC 8050.
+pub(crate) const TABLE_DUMP_V2: u16 = 13;
+
+pub(crate) const PEER_INDEX_TABLE: u16 = 1;
+pub(crate) const RIB_IPV4_UNICAST: u16 = 2;
+pub(crate) const RIB_IPV6_UNICAST: u16 = 4;
+
+//------------ MrtPeer -------------------------------------------------------
+
+/// A peer from the PEER_INDEX_TABLE of an MRT file
+///
+/// The routes of every peer in an MRT file are stored in the store under
+/// their own multi_uniq_id, the `mui` of the peer.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MrtPeer {
+    /// The multi_uniq_id the routes of this peer are stored under
+    pub mui: u32,
+    /// The BGP identifier of the peer
+    pub bgp_id: [u8; 4],
+    /// The address of the peer
+    pub addr: IpAddr,
+    /// The AS number of the peer
+    pub asn: Asn,
+}
+
+//------------ MrtError ------------------------------------------------------
+
+/// An error that occurred while reading or writing an MRT file
+#[derive(Debug)]
+pub enum MrtError {
+    Io(std::io::Error),
+    InvalidData(&'static str),
+    Store(PrefixStoreError),
+}
+
+impl std::error::Error for MrtError {}
+
+impl fmt::Display for MrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MrtError::Io(err) => write!(f, "Error: I/O error: {}", err),
+            MrtError::InvalidData(msg) => {
+                write!(f, "Error: Invalid MRT data: {}.", msg)
+            }
+            MrtError::Store(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for MrtError {
+    fn from(value: std::io::Error) -> Self {
+        MrtError::Io(value)
+    }
+}
+
+impl From<PrefixStoreError> for MrtError {
+    fn from(value: PrefixStoreError) -> Self {
+        MrtError::Store(value)
+    }
+}
+
+//------------ MrtRecord -----------------------------------------------------
+
+// An MRT record, with the common header taken apart.
+pub(crate) struct MrtRecord {
+    pub timestamp: u32,
+    pub msg_type: u16,
+    pub subtype: u16,
+    pub body: Vec<u8>,
+}
+
+impl MrtRecord {
+    // Read the next record from `r`. Returns `None` at the end of the file,
+    // and an error if the file ends halfway a record.
+    pub fn read<R: Read>(r: &mut R) -> Result<Option<Self>, MrtError> {
+        let mut header = [0; 12];
+        match r.read_exact(&mut header[..1]) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        }
+        r.read_exact(&mut header[1..])?;
+
+        let timestamp = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let msg_type = u16::from_be_bytes([header[4], header[5]]);
+        let subtype = u16::from_be_bytes([header[6], header[7]]);
+        let len = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+        // The length comes from the file, so the body is only allocated as
+        // it is read.
+        let mut body = vec![];
+        r.take(u64::from(len)).read_to_end(&mut body)?;
+        if body.len() != len as usize {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(Some(Self {
+            timestamp,
+            msg_type,
+            subtype,
+            body,
+        }))
+    }
+
+    // Write the record to `w`.
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), MrtError> {
+        let len = u32::try_from(self.body.len())
+            .map_err(|_| MrtError::InvalidData("record too long"))?;
+        let mut header = [0; 12];
+        header[0..4].copy_from_slice(&self.timestamp.to_be_bytes());
+        header[4..6].copy_from_slice(&self.msg_type.to_be_bytes());
+        header[6..8].copy_from_slice(&self.subtype.to_be_bytes());
+        header[8..12].copy_from_slice(&len.to_be_bytes());
+        w.write_all(&header)?;
+        Ok(w.write_all(&self.body)?)
+    }
+}
+
+//------------ BodyParser ----------------------------------------------------
+
+// A parser for the body of an MRT record.
+pub(crate) struct BodyParser<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> BodyParser<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], MrtError> {
+        if self.buf.len() < len {
+            return Err(MrtError::InvalidData("truncated record"));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    // Take everything that is left.
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.buf)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, MrtError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, MrtError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, MrtError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn ipv4(&mut self) -> Result<IpAddr, MrtError> {
+        let octets: [u8; 4] = self.take(4)?.try_into().unwrap();
+        Ok(IpAddr::from(octets))
+    }
+
+    pub fn ipv6(&mut self) -> Result<IpAddr, MrtError> {
+        let octets: [u8; 16] = self.take(16)?.try_into().unwrap();
+        Ok(IpAddr::from(octets))
+    }
+
+    // A prefix in the NLRI encoding: the length in bits, followed by the
+    // minimal number of octets to hold that number of bits.
+    pub fn prefix(&mut self, v6: bool) -> Result<Prefix, MrtError> {
+        let len = self.u8()?;
+        let mut octets = [0; 16];
+        let max_len = if v6 { 128 } else { 32 };
+        if len > max_len {
+            return Err(MrtError::InvalidData("invalid prefix length"));
+        }
+        let n = (len as usize + 7) / 8;
+        octets[..n].copy_from_slice(self.take(n)?);
+        let addr = if v6 {
+            IpAddr::from(octets)
+        } else {
+            IpAddr::from(<[u8; 4]>::try_from(&octets[..4]).unwrap())
+        };
+        Prefix::new_relaxed(addr, len)
+            .map_err(|_| MrtError::InvalidData("invalid prefix"))
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use inetnum::addr::Prefix;
+use log::trace;
+use routecore::bgp::message::{SessionConfig, UpdateMessage};
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+
+use crate::prelude::multi::PrefixStoreError;
+use crate::prelude::Meta;
+use crate::MultiThreadedStore;
+
+use super::{BodyParser, MrtError, MrtPeer, MrtRecord};
+
+// MRT types and subtypes for BGP4MP, RFC 6396 and RFC 8050.
+const BGP4MP: u16 = 16;
+const BGP4MP_ET: u16 = 17;
+
+const BGP4MP_MESSAGE: u16 = 1;
+const BGP4MP_MESSAGE_AS4: u16 = 4;
+
+// The BGP message type of an UPDATE.
+const BGP_UPDATE: u8 = 2;
+
+//------------ ReplayOptions -------------------------------------------------
+
+/// Options for replaying an MRT BGP4MP file
+#[derive(Clone, Debug, Default)]
+pub struct ReplayOptions {
+    /// The peers with a multi_uniq_id that is known up front, e.g. the
+    /// peers from an [ImportReport](super::ImportReport) of the RIB dump
+    /// the updates apply to. Peers are matched on their address and AS
+    /// number.
+    pub peers: Vec<MrtPeer>,
+    /// The multi_uniq_id for the first peer that is not in `peers`. Every
+    /// next unknown peer gets the next multi_uniq_id.
+    pub mui_base: u32,
+    /// Stop at the first message with a timestamp later than this, in
+    /// seconds since the Unix epoch.
+    pub until: Option<u32>,
+    /// The speed of the replay relative to the timestamps of the messages:
+    /// 1.0 replays in real time, 60.0 replays a minute of messages every
+    /// second. `None` replays as fast as possible. The speed has to be a
+    /// positive number.
+    pub speed: Option<f64>,
+}
+
+//------------ ReplayReport --------------------------------------------------
+
+/// The result of replaying an MRT BGP4MP file
+#[derive(Clone, Debug, Default)]
+pub struct ReplayReport {
+    /// All the peers the updates came from, including the ones from the
+    /// options
+    pub peers: Vec<MrtPeer>,
+    /// The number of BGP UPDATE messages that were applied
+    pub updates: usize,
+    /// The number of BGP UPDATE messages that could not be parsed, which
+    /// were skipped
+    pub invalid_updates: usize,
+    /// The number of announced routes that were inserted into the store
+    pub announcements: usize,
+    /// The number of withdrawn routes that were marked as withdrawn in the
+    /// store
+    pub withdrawals: usize,
+    /// The number of routes that were skipped: announcements for which the
+    /// meta-data builder returned `None`, withdrawals of routes that are
+    /// not in the store, and routes for address families other than
+    /// IPv4 and IPv6 unicast
+    pub routes_skipped: usize,
+    /// The number of records of other types and subtypes, and of other BGP
+    /// messages than UPDATEs, which were skipped
+    pub records_skipped: usize,
+    /// The timestamp of the last applied message
+    pub last_timestamp: Option<u32>,
+}
+
+//------------ Replaying -----------------------------------------------------
+
+/// Replay the MRT BGP4MP file at `path` into `store`.
+///
+/// See [replay_updates] for details.
+pub fn replay_updates_file<M, P, F>(
+    store: &MultiThreadedStore<M>,
+    path: P,
+    options: &ReplayOptions,
+    meta_builder: F,
+) -> Result<ReplayReport, MrtError>
+where
+    M: Meta,
+    P: AsRef<Path>,
+    F: Fn(&MrtPeer, &Prefix, &OwnedPathAttributes) -> Option<M>,
+{
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    replay_updates(store, &mut reader, options, meta_builder)
+}
+
+/// Replay an MRT BGP4MP file with BGP UPDATE messages from `reader` into
+/// `store`.
+///
+/// Every peer gets its own multi_uniq_id (see [ReplayOptions]). For every
+/// route announced in the BGP4MP_MESSAGE and BGP4MP_MESSAGE_AS4 records,
+/// `meta_builder` is called with the peer, the prefix and the path
+/// attributes of the UPDATE. The meta-data it returns is inserted into the
+/// store, as a record with the Active status and the timestamp of the MRT
+/// record as its `ltime`. If it returns `None` the route is skipped. Every
+/// withdrawn route is withdrawn in the store, with the timestamp of the MRT
+/// record as its `ltime`. Records of any other type or subtype are skipped,
+/// most notably the state changes of the BGP sessions, and the messages
+/// with ADD-PATH path ids.
+///
+/// The messages are applied in the order they appear in the file, by the
+/// calling thread, with
+/// [apply_bgp_update](crate::MultiThreadedStore::apply_bgp_update). Best
+/// path selection is not run for the updated prefixes.
+pub fn replay_updates<M, R, F>(
+    store: &MultiThreadedStore<M>,
+    reader: &mut R,
+    options: &ReplayOptions,
+    meta_builder: F,
+) -> Result<ReplayReport, MrtError>
+where
+    M: Meta,
+    R: Read,
+    F: Fn(&MrtPeer, &Prefix, &OwnedPathAttributes) -> Option<M>,
+{
+    if options
+        .speed
+        .is_some_and(|speed| !(speed.is_finite() && speed > 0.0))
+    {
+        return Err(MrtError::InvalidData("replay speed is not positive"));
+    }
+
+    let mut report = ReplayReport {
+        peers: options.peers.clone(),
+        ..Default::default()
+    };
+    let mut next_mui = options.mui_base;
+
+    // The timestamp of the first message, and the moment it was applied.
+    let mut start: Option<(u32, Instant)> = None;
+
+    while let Some(record) = MrtRecord::read(reader)? {
+        if record.msg_type != BGP4MP && record.msg_type != BGP4MP_ET {
+            report.records_skipped += 1;
+            continue;
+        }
+        let session_config = match record.subtype {
+            BGP4MP_MESSAGE => SessionConfig::legacy(),
+            BGP4MP_MESSAGE_AS4 => SessionConfig::modern(),
+            _ => {
+                report.records_skipped += 1;
+                continue;
+            }
+        };
+
+        if options.until.is_some_and(|until| record.timestamp > until) {
+            trace!("stopping at timestamp {}", record.timestamp);
+            break;
+        }
+        if let Some(speed) = options.speed {
+            let (first, started) =
+                *start.get_or_insert((record.timestamp, Instant::now()));
+            let offset = Duration::try_from_secs_f64(
+                record.timestamp.saturating_sub(first) as f64 / speed,
+            )
+            .map_err(|_| MrtError::InvalidData("replay speed too low"))?;
+            if let Some(wait) =
+                (started + offset).checked_duration_since(Instant::now())
+            {
+                std::thread::sleep(wait);
+            }
+        }
+
+        let mut parser = BodyParser::new(&record.body);
+        if record.msg_type == BGP4MP_ET {
+            // The microseconds of the extended timestamp.
+            parser.u32()?;
+        }
+        let (peer_asn, _local_asn) = if record.subtype == BGP4MP_MESSAGE {
+            (u32::from(parser.u16()?), u32::from(parser.u16()?))
+        } else {
+            (parser.u32()?, parser.u32()?)
+        };
+        let _if_index = parser.u16()?;
+        let (peer_addr, _local_addr) = match parser.u16()? {
+            1 => (parser.ipv4()?, parser.ipv4()?),
+            2 => (parser.ipv6()?, parser.ipv6()?),
+            _ => return Err(MrtError::InvalidData("unknown address family")),
+        };
+        let message = parser.rest();
+        if message.get(18) != Some(&BGP_UPDATE) {
+            report.records_skipped += 1;
+            continue;
+        }
+
+        let update =
+            match UpdateMessage::from_octets(message, &session_config) {
+                Ok(update) => update,
+                Err(err) => {
+                    trace!("invalid UPDATE at {}: {}", record.timestamp, err);
+                    report.invalid_updates += 1;
+                    continue;
+                }
+            };
+
+        let peer =
+            peer_for(&mut report.peers, &mut next_mui, peer_addr, peer_asn);
+        match store.apply_bgp_update(
+            peer.mui,
+            record.timestamp as u64,
+            &update,
+            |prefix, attributes| meta_builder(&peer, prefix, attributes),
+        ) {
+            Ok(applied) => {
+                report.updates += 1;
+                report.announcements +=
+                    applied.prefixes_new + applied.prefixes_updated;
+                report.withdrawals += applied.prefixes_withdrawn;
+                report.routes_skipped += applied.prefixes_skipped;
+            }
+            Err(PrefixStoreError::InvalidBgpUpdate) => {
+                report.invalid_updates += 1
+            }
+            Err(err) => return Err(err.into()),
+        }
+        report.last_timestamp = Some(record.timestamp);
+    }
+
+    Ok(report)
+}
+
+// Look up the peer with `addr` and `asn`, or add it with the next mui.
+fn peer_for(
+    peers: &mut Vec<MrtPeer>,
+    next_mui: &mut u32,
+    addr: IpAddr,
+    asn: u32,
+) -> MrtPeer {
+    if let Some(peer) = peers
+        .iter()
+        .find(|peer| peer.addr == addr && peer.asn.into_u32() == asn)
+    {
+        return *peer;
+    }
+    let peer = MrtPeer {
+        mui: *next_mui,
+        bgp_id: [0; 4],
+        addr,
+        asn: asn.into(),
+    };
+    trace!("new peer {} AS{} with mui {}", addr, asn, peer.mui);
+    *next_mui += 1;
+    peers.push(peer);
+    peer
+}
@@ -229,6 +229,7 @@ impl<M: Meta> From<(Prefix, M)> for PublicPrefixSingleRecord<M> {
 //------------ PublicRecord -------------------------------------------
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublicRecord<M> {
     pub multi_uniq_id: u32,
     pub ltime: u64,
@@ -264,10 +265,22 @@ impl<M: Clone> From<(u32, MultiMapValue<M>)> for PublicRecord<M> {
     }
 }
 
+impl<M: Clone> From<(u32, &MultiMapValue<M>)> for PublicRecord<M> {
+    fn from(value: (u32, &MultiMapValue<M>)) -> Self {
+        Self {
+            multi_uniq_id: value.0,
+            meta: value.1.meta.clone(),
+            ltime: value.1.ltime,
+            status: value.1.status,
+        }
+    }
+}
+
 
 //------------ PublicPrefixRecord -------------------------------------------
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublicPrefixRecord<M: Meta> {
     pub prefix: Prefix,
     pub meta: Vec<PublicRecord<M>>,
@@ -481,6 +494,7 @@ impl<M: Meta> std::ops::Index<usize> for RecordSingleSet<M> {
 //------------ RecordSet ----------------------------------------------------
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordSet<M: Meta> {
     pub v4: Vec<PublicPrefixRecord<M>>,
     pub v6: Vec<PublicPrefixRecord<M>>,
@@ -745,8 +759,50 @@ where
         type TBI: Copy;
 
         fn as_orderable(&self, tbi: Self::TBI) -> Self::Orderable<'_>;
+
+        /// Returns whether the path with this meta-data can be used
+        /// together with the best path, with the meta-data `best`, in a
+        /// multipath set under `policy`.
+        ///
+        /// The default implementation ignores `policy`, and only takes
+        /// paths with an orderable that is equal to the orderable of the
+        /// best path. Implementations that know the steps of their path
+        /// selection should implement the relaxations of the policy. For
+        /// meta-data with BGP path attributes this can be left to
+        /// [crate::bgp::is_multipath_equivalent].
+        fn is_multipath_equivalent(
+            &self,
+            best: &Self,
+            tbi: Self::TBI,
+            _policy: MultipathPolicy,
+        ) -> bool {
+            self.as_orderable(tbi) == best.as_orderable(tbi)
+        }
     }
 
+//------------ MultipathPolicy -----------------------------------------------
+
+/// The paths that can be used together with the best path for a prefix,
+/// e.g. for ECMP
+///
+/// See [Meta::is_multipath_equivalent]. Its default implementation treats
+/// every policy like `Equal`, so the relaxed policies only work for
+/// meta-data types that implement it themselves, e.g. by calling
+/// [crate::bgp::is_multipath_equivalent].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MultipathPolicy {
+    /// Only paths that are equal to the best path in all the steps of the
+    /// path selection
+    #[default]
+    Equal,
+    /// Paths that are equal to the best path in all the steps of the path
+    /// selection up to, but not including, the IGP cost
+    UpToIgpCost,
+    /// Like `UpToIgpCost`, but the AS paths only have to be of the same
+    /// length, instead of being the same ("multipath relax")
+    AsPathRelax,
+}
+
 impl Meta for inetnum::asn::Asn {
     type Orderable<'a> = inetnum::asn::Asn;
     type TBI = ();
@@ -5,9 +5,11 @@ pub use crate::{AddressFamily, IPv4, IPv6};
 
 pub use crate::prefix_record::{
     PublicPrefixRecord as PrefixRecord,
-    Meta
+    Meta, MultipathPolicy
+};
+pub use crate::{
+    EvictOptions, HistoryRetention, MatchOptions, MatchType, QueryResult,
 };
-pub use crate::{MatchOptions, MatchType, QueryResult};
 pub use crate::stride::{Stride3, Stride4, Stride5};
 
 pub mod multi {
@@ -23,11 +25,29 @@ pub mod multi {
         NodeBuckets, NodeSet, PrefixBuckets, PrefixSet,
     };
     pub use crate::local_array::tree::{PrefixId, StrideNodeId, TreeBitMap};
-    pub use crate::local_array::store::errors::PrefixStoreError;
+    pub use crate::local_array::store::errors::{
+        PersistError, PrefixStoreError,
+    };
+    #[cfg(feature = "serde")]
+    pub use crate::local_array::store::errors::NdjsonError;
+    pub use crate::local_array::store::persist::PersistMeta;
+    pub use crate::local_array::store::journal::{Journal, JournaledStore};
+    pub use crate::local_array::store::changes::{
+        Change, ChangeCursor, ChangeKind,
+    };
+    pub use crate::local_array::store::watch::{WatchEvent, WatchId};
+    pub use crate::local_array::store::selector::{
+        OrderableSelector, PathCandidate, PathSelector,
+    };
     pub use crate::prefix_record::PublicRecord as Record;
-    pub use crate::local_array::store::atomic_types::RouteStatus;
+    pub use crate::local_array::store::atomic_types::{
+        EligibleFn, RouteStatus,
+    };
 
-    pub use crate::custom_alloc::{Upsert, Counters, StoreStats, UpsertReport};
+    pub use crate::custom_alloc::{
+        Upsert, BgpUpdateReport, BulkReport, Counters, EvictReport, PurgeReport, StoreStats,
+        UpsertReport
+    };
     pub use crate::custom_alloc::CustomAllocStorage;
 
     pub use routecore::bgp::path_selection::TiebreakerInfo;
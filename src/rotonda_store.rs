@@ -1,10 +1,13 @@
 use std::{fmt, slice};
 
 use crate::prefix_record::{PublicRecord, RecordSet};
-pub use crate::prefix_record::{PublicPrefixSingleRecord, Meta, RecordSingleSet};
+pub use crate::prefix_record::{
+    PublicPrefixSingleRecord, Meta, MultipathPolicy, RecordSingleSet,
+};
 use crate::{prefix_record::InternalPrefixRecord, stats::StrideStats};
 
 use inetnum::addr::Prefix;
+use roaring::RoaringBitmap;
 
 pub use crate::af::{AddressFamily, IPv4, IPv6};
 
@@ -86,6 +89,7 @@ pub struct MatchOptions {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MatchType {
     ExactMatch,
     LongestMatch,
@@ -108,6 +112,71 @@ impl std::fmt::Display for MatchType {
     }
 }
 
+//------------ EvictOptions -------------------------------------------------
+
+/// Options for the `evict_older_than` method
+///
+/// The `EvictOptions` struct is used to specify which records are evicted
+/// from the store by the `evict_older_than` method, and what happens to
+/// them.
+#[derive(Debug, Clone, Default)]
+pub struct EvictOptions {
+    /// Whether to remove the evicted records from the store. If false, the
+    /// local status of the evicted records is set to `Withdrawn`.
+    pub remove: bool,
+    /// Only evict records for the multi_uniq_ids in this bitmap, None
+    /// indicates all multi_uniq_ids.
+    pub muis: Option<RoaringBitmap>,
+    /// Only evict records for this prefix and its more-specifics, None
+    /// indicates all prefixes.
+    pub prefix: Option<Prefix>,
+}
+
+//------------ HistoryRetention ---------------------------------------------
+
+/// The retention strategy for the history of records
+///
+/// By default the store only keeps the most recent record for each
+/// combination of (prefix, multi_uniq_id), a new record simply replaces the
+/// previous one. With a `HistoryRetention` other than `Off`, the store
+/// keeps the records that were replaced as well, so that they can be
+/// retrieved with `record_history`.
+///
+/// Only records that are replaced by an insert become part of the history,
+/// changes of the status of a record, e.g. by `mark_mui_as_withdrawn_for_prefix`,
+/// are not. Removing a record also removes its history.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HistoryRetention {
+    /// Do not keep any history.
+    #[default]
+    Off,
+    /// Keep at most this number of replaced records per (prefix,
+    /// multi_uniq_id).
+    Versions(usize),
+    /// Keep the replaced records whose ltime is at most this much older
+    /// than the ltime of the current record.
+    MaxAge(u64),
+}
+
+impl HistoryRetention {
+    // Drop the records from `history` (most recent first) that are not
+    // retained for a current record with `ltime`.
+    pub(crate) fn apply<M: Meta>(
+        &self,
+        ltime: u64,
+        history: &mut Vec<PublicRecord<M>>,
+    ) {
+        match self {
+            HistoryRetention::Off => history.clear(),
+            HistoryRetention::Versions(n) => history.truncate(*n),
+            HistoryRetention::MaxAge(age) => {
+                let oldest = ltime.saturating_sub(*age);
+                history.retain(|r| r.ltime >= oldest);
+            }
+        }
+    }
+}
+
 
 //------------ PrefixRecordIter ---------------------------------------------
 
@@ -158,6 +227,7 @@ impl<'a, M: Meta> Iterator
 
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct QueryResult<M: crate::prefix_record::Meta> {
     /// The match type of the resulting prefix
     pub match_type: MatchType,
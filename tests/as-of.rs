@@ -0,0 +1,220 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn options(match_type: MatchType, include_withdrawn: bool) -> MatchOptions {
+    MatchOptions {
+        match_type,
+        include_withdrawn,
+        include_less_specifics: true,
+        include_more_specifics: true,
+        mui: None,
+    }
+}
+
+// The history of the store:
+//
+// ltime 10: mui 1 announces the /16 and the /24
+// ltime 20: mui 2 announces the /16 and the v6 /32
+// ltime 30: mui 1 announces the /16 with a new AS
+// ltime 40: mui 1 withdraws the /24
+fn create_store(
+) -> Result<(MultiThreadedStore<Asn>, Vec<Prefix>), Box<dyn std::error::Error>>
+{
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.set_history_retention(HistoryRetention::Versions(8));
+
+    let pfxs = vec![
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    let updates = [
+        (10, 1, pfxs[0], RouteStatus::Active, 65401),
+        (10, 1, pfxs[1], RouteStatus::Active, 65401),
+        (20, 2, pfxs[0], RouteStatus::Active, 65402),
+        (20, 2, pfxs[2], RouteStatus::Active, 65402),
+        (30, 1, pfxs[0], RouteStatus::Active, 65411),
+        (40, 1, pfxs[1], RouteStatus::Withdrawn, 65401),
+    ];
+    for (ltime, mui, pfx, status, asn) in updates {
+        store.insert(
+            &pfx,
+            Record::new(mui, ltime, status, Asn::from(asn)),
+            None,
+        )?;
+    }
+
+    Ok((store, pfxs))
+}
+
+#[test]
+fn test_match_prefix_as_of() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+    let guard = &epoch::pin();
+
+    // Before anything was announced.
+    let res = store.match_prefix_as_of(
+        &pfxs[1],
+        &options(MatchType::LongestMatch, true),
+        5,
+        guard,
+    );
+    assert_eq!(res.match_type, MatchType::EmptyMatch);
+    assert!(res.prefix_meta.is_empty());
+
+    // Only mui 1 was there at ltime 15.
+    let res = store.match_prefix_as_of(
+        &pfxs[0],
+        &options(MatchType::ExactMatch, true),
+        15,
+        guard,
+    );
+    assert_eq!(res.match_type, MatchType::ExactMatch);
+    assert_eq!(res.prefix_meta.len(), 1);
+    assert_eq!(res.prefix_meta[0].meta, Asn::from(65401));
+    assert_eq!(res.more_specifics.unwrap().len(), 1);
+
+    // At ltime 25 mui 2 was there as well, and mui 1 still had the old AS.
+    let res = store.match_prefix_as_of(
+        &pfxs[0],
+        &options(MatchType::ExactMatch, true),
+        25,
+        guard,
+    );
+    let mut recs = res.prefix_meta;
+    recs.sort_by_key(|r| r.multi_uniq_id);
+    assert_eq!(
+        recs.iter().map(|r| r.meta).collect::<Vec<_>>(),
+        vec![Asn::from(65401), Asn::from(65402)]
+    );
+
+    // At ltime 35 the /24 was still active, at ltime 45 it was withdrawn.
+    let res = store.match_prefix_as_of(
+        &pfxs[1],
+        &options(MatchType::LongestMatch, false),
+        35,
+        guard,
+    );
+    assert_eq!(res.match_type, MatchType::ExactMatch);
+    assert_eq!(res.prefix_meta[0].status, RouteStatus::Active);
+    assert_eq!(res.less_specifics.unwrap().len(), 1);
+
+    let res = store.match_prefix_as_of(
+        &pfxs[1],
+        &options(MatchType::LongestMatch, false),
+        45,
+        guard,
+    );
+    assert_eq!(res.match_type, MatchType::LongestMatch);
+    assert_eq!(res.prefix, Some(pfxs[0]));
+    assert_eq!(res.prefix_meta.len(), 2);
+
+    let res = store.match_prefix_as_of(
+        &pfxs[1],
+        &options(MatchType::ExactMatch, true),
+        45,
+        guard,
+    );
+    assert_eq!(res.prefix_meta[0].status, RouteStatus::Withdrawn);
+
+    Ok(())
+}
+
+#[test]
+fn test_prefixes_iter_as_of() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+    let guard = &epoch::pin();
+
+    assert_eq!(store.prefixes_iter_as_of(5, guard).count(), 0);
+
+    let mut at_15 = store
+        .prefixes_iter_as_of(15, guard)
+        .map(|p| p.prefix)
+        .collect::<Vec<_>>();
+    at_15.sort();
+    let mut expected = vec![pfxs[0], pfxs[1]];
+    expected.sort();
+    assert_eq!(at_15, expected);
+
+    assert_eq!(store.prefixes_iter_as_of(25, guard).count(), 3);
+    for p in store.prefixes_iter_as_of(25, guard) {
+        assert!(p.meta.iter().all(|r| r.ltime <= 25));
+    }
+
+    // The most recent ltime gives the current state of the store.
+    let mut now = store
+        .prefixes_iter_as_of(u64::MAX, guard)
+        .map(|p| (p.prefix, p.meta.len()))
+        .collect::<Vec<_>>();
+    now.sort();
+    let mut current = store
+        .prefixes_iter(guard)
+        .map(|p| (p.prefix, p.meta.len()))
+        .collect::<Vec<_>>();
+    current.sort();
+    assert_eq!(now, current);
+
+    Ok(())
+}
+
+#[test]
+fn test_as_of_limitations() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+    let guard = &epoch::pin();
+    let status_at = |pfx: &Prefix, mui: u32, ltime: u64| {
+        store
+            .match_prefix_as_of(
+                pfx,
+                &MatchOptions {
+                    mui: Some(mui),
+                    ..options(MatchType::ExactMatch, true)
+                },
+                ltime,
+                guard,
+            )
+            .prefix_meta
+            .iter()
+            .map(|r| r.status)
+            .collect::<Vec<_>>()
+    };
+
+    // A status change without an ltime changes the record in place, so it
+    // shows up at all the ltimes the record was current for.
+    assert_eq!(status_at(&pfxs[2], 2, 25), vec![RouteStatus::Active]);
+    store.mark_mui_as_withdrawn_for_prefix(&pfxs[2], 2)?;
+    assert_eq!(status_at(&pfxs[2], 2, 25), vec![RouteStatus::Withdrawn]);
+    store.mark_mui_as_active_for_prefix(&pfxs[2], 2)?;
+    assert_eq!(status_at(&pfxs[2], 2, 25), vec![RouteStatus::Active]);
+
+    // The global status of a mui is ignored.
+    store.mark_mui_as_withdrawn_v4(2)?;
+    assert_eq!(status_at(&pfxs[0], 2, 25), vec![RouteStatus::Active]);
+
+    // Removed records are gone, for any ltime.
+    store.remove(&pfxs[0], 2)?;
+    assert_eq!(status_at(&pfxs[0], 2, 25), vec![]);
+
+    Ok(())
+}
@@ -127,4 +127,226 @@ fn test_best_path_1() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(best_path.unwrap().unwrap().multi_uniq_id, 1);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_best_path_eligible() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    // For Asn meta-data, the lowest ASN is the best path.
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    let other_pfx = Prefix::from_str("185.35.0.0/16")?;
+    for mui in 1..=4 {
+        let rec =
+            Record::new(mui, 0, RouteStatus::Active, Asn::from(65400 + mui));
+        store.insert(&pfx, rec.clone(), None)?;
+        store.insert(&other_pfx, rec, None)?;
+    }
+
+    let guard = &rotonda_store::epoch::pin();
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(1), Some(2))
+    );
+
+    // Withdrawn records are not eligible.
+    store.mark_mui_as_withdrawn_for_prefix(&pfx, 1)?;
+    assert!(store.is_ps_outdated(&pfx, guard)?);
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(2), Some(3))
+    );
+    assert!(!store.is_ps_outdated(&pfx, guard)?);
+
+    // Withdrawing a mui globally outdates the prefixes it has records for...
+    store.calculate_and_store_best_and_backup_path(&other_pfx, &(), guard)?;
+    store.mark_mui_as_withdrawn_v4(3)?;
+    assert!(store.is_ps_outdated(&pfx, guard)?);
+    assert!(store.is_ps_outdated(&other_pfx, guard)?);
+    // ...and it is left out of the selection.
+    assert_eq!(
+        store.calculate_and_store_eligible_best_and_backup_path(
+            &pfx,
+            &(),
+            None,
+            guard
+        )?,
+        (Some(2), Some(4))
+    );
+    store.mark_mui_as_withdrawn_v4(2)?;
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(4), None)
+    );
+    store.mark_mui_as_active_v4(2)?;
+
+    // With a predicate.
+    let not_two = |mui: u32, _: &Asn| mui != 2;
+    assert_eq!(
+        store.calculate_and_store_eligible_best_and_backup_path(
+            &pfx,
+            &(),
+            Some(&not_two),
+            guard
+        )?,
+        (Some(4), None)
+    );
+
+    // Activating the mui again outdates all the prefixes it has records for.
+    store.mark_mui_as_active_v4(3)?;
+    assert!(store.is_ps_outdated(&other_pfx, guard)?);
+    assert_eq!(
+        store.calculate_and_store_eligible_best_and_backup_path(
+            &pfx,
+            &(),
+            None,
+            guard
+        )?,
+        (Some(2), Some(3))
+    );
+
+    // Inserting with a path selection update leaves globally withdrawn muis
+    // out as well.
+    store.mark_mui_as_withdrawn_v4(2)?;
+    store.insert(
+        &pfx,
+        Record::new(4, 1, RouteStatus::Active, Asn::from(65404)),
+        Some(()),
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn test_ranked_paths() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    // For Asn meta-data, the lowest ASN is the best path.
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("2001:db8::/32")?;
+    for (mui, asn) in [(1, 65403), (2, 65401), (3, 65404), (4, 65402)] {
+        store.insert(
+            &pfx,
+            Record::new(mui, 0, RouteStatus::Active, Asn::from(asn)),
+            None,
+        )?;
+    }
+
+    let guard = &rotonda_store::epoch::pin();
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![2, 4, 1, 3]);
+    assert_eq!(
+        store
+            .top_paths(&pfx, 2, &(), guard)?
+            .iter()
+            .map(|r| (r.multi_uniq_id, r.meta))
+            .collect::<Vec<_>>(),
+        vec![(2, Asn::from(65401)), (4, Asn::from(65402))]
+    );
+    assert_eq!(store.top_paths(&pfx, 10, &(), guard)?.len(), 4);
+
+    // The first two are the best and backup path.
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(2), Some(4))
+    );
+
+    // Changes to the records outdate the ranking.
+    store.insert(
+        &pfx,
+        Record::new(3, 1, RouteStatus::Active, Asn::from(65400)),
+        None,
+    )?;
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![3, 2, 4, 1]);
+    store.mark_mui_as_withdrawn_for_prefix(&pfx, 2)?;
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![3, 4, 1]);
+    store.mark_mui_as_withdrawn_v6(3)?;
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![4, 1]);
+    store.mark_mui_as_active_v6(3)?;
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![3, 4, 1]);
+
+    assert_eq!(
+        store.ranked_paths(&Prefix::from_str("2001:db9::/32")?, &(), guard),
+        Err(PrefixStoreError::StoreNotReadyError)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ranked_paths_equal_orderables(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    // Paths with equal orderables are ranked by their mui, both for the
+    // ranking and for the best and backup path.
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("2001:db8::/32")?;
+    for (mui, asn) in [(7, 65401), (5, 65402), (3, 65401), (6, 65401)] {
+        store.insert(
+            &pfx,
+            Record::new(mui, 0, RouteStatus::Active, Asn::from(asn)),
+            None,
+        )?;
+    }
+
+    let guard = &rotonda_store::epoch::pin();
+    let ranked = store.ranked_paths(&pfx, &(), guard)?;
+    assert_eq!(ranked, vec![3, 6, 7, 5]);
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(ranked[0]), Some(ranked[1]))
+    );
+
+    // With only equal orderables left.
+    store.mark_mui_as_withdrawn_for_prefix(&pfx, 3)?;
+    let ranked = store.ranked_paths(&pfx, &(), guard)?;
+    assert_eq!(ranked, vec![6, 7, 5]);
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(6), Some(7))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ranked_paths_concurrent_changes(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    // A ranking that is calculated while the records change must not stay
+    // cached after the change.
+    let store = std::sync::Arc::new(MultiThreadedStore::<Asn>::new()?);
+    let pfx = Prefix::from_str("2001:db8::/32")?;
+    store.insert(
+        &pfx,
+        Record::new(0, 0, RouteStatus::Active, Asn::from(65535)),
+        None,
+    )?;
+
+    let ranker = {
+        let store = std::sync::Arc::clone(&store);
+        std::thread::spawn(move || {
+            let guard = &rotonda_store::epoch::pin();
+            for _ in 0..2000 {
+                store.ranked_paths(&pfx, &(), guard).unwrap();
+            }
+        })
+    };
+    for mui in 1..500 {
+        store.insert(
+            &pfx,
+            Record::new(mui, 0, RouteStatus::Active, Asn::from(65535 - mui)),
+            None,
+        )?;
+    }
+    ranker.join().unwrap();
+
+    let guard = &rotonda_store::epoch::pin();
+    let ranked = store.ranked_paths(&pfx, &(), guard)?;
+    assert_eq!(ranked.len(), 500);
+    assert_eq!(ranked[0], 499);
+
+    Ok(())
+}
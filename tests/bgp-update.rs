@@ -0,0 +1,329 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::bgp::{
+    adj_rib_updates, loc_rib_updates, BgpMeta, UpdateError, UpdateOptions,
+};
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+use routecore::bgp::aspath::HopPath;
+use routecore::bgp::message::{SessionConfig, UpdateMessage};
+use routecore::bgp::path_attributes::{
+    OwnedPathAttributes, PaMap, PathAttribute,
+};
+
+mod common;
+
+use common::bgp::{origin_as, update};
+
+// Meta-data with all the path attributes of a route, that prefers routes
+// with shorter AS paths.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Route {
+    pa_map: PaMap,
+    next_hop: Option<IpAddr>,
+    hops: usize,
+}
+
+impl Route {
+    fn new(attributes: &OwnedPathAttributes) -> Option<Self> {
+        let mut route = Route {
+            pa_map: PaMap::empty(),
+            next_hop: None,
+            hops: attributes.get::<HopPath>()?.hop_count(),
+        };
+        for pa in attributes.iter() {
+            match pa.ok()?.to_owned().ok()? {
+                PathAttribute::Unimplemented(pa) if pa.type_code() == 14 => {
+                    let octets: [u8; 16] =
+                        pa.value().get(4..20)?.try_into().ok()?;
+                    route.next_hop = Some(octets.into());
+                }
+                PathAttribute::Unimplemented(pa) if pa.type_code() == 15 => {}
+                pa => {
+                    route.pa_map.attributes_mut().insert(pa.type_code(), pa);
+                }
+            }
+        }
+        Some(route)
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Meta for Route {
+    type Orderable<'a> = usize;
+    type TBI = ();
+
+    fn as_orderable(&self, _tbi: Self::TBI) -> usize {
+        self.hops
+    }
+}
+
+impl BgpMeta for Route {
+    fn path_attributes(&self) -> PaMap {
+        self.pa_map.clone()
+    }
+
+    fn next_hop(&self) -> Option<IpAddr> {
+        self.next_hop
+    }
+}
+
+fn parse(msg: &[u8]) -> UpdateMessage<&[u8]> {
+    UpdateMessage::from_octets(msg, &SessionConfig::modern()).unwrap()
+}
+
+fn records_for(
+    store: &MultiThreadedStore<Asn>,
+    prefix: &Prefix,
+) -> Vec<(u32, u64, RouteStatus, Asn)> {
+    let guard = &epoch::pin();
+    let mut recs = store
+        .match_prefix(
+            prefix,
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: true,
+                include_less_specifics: false,
+                include_more_specifics: false,
+                mui: None,
+            },
+            guard,
+        )
+        .prefix_meta
+        .into_iter()
+        .map(|r| (r.multi_uniq_id, r.ltime, r.status, r.meta))
+        .collect::<Vec<_>>();
+    recs.sort_by_key(|r| (r.0, r.1));
+    recs
+}
+
+#[test]
+fn test_apply_bgp_update() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfxs = [
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("2001:db8:1::/48")?,
+        Prefix::from_str("192.0.2.0/24")?,
+    ];
+
+    let msg = update(&[], &pfxs[..3], &[65000, 64500], true);
+    let upd = parse(&msg);
+    let report = store.apply_bgp_update(1, 10, &upd, |_, attributes| {
+        origin_as(attributes)
+    })?;
+    assert_eq!(report.prefixes_new, 3);
+    assert_eq!(report.prefixes_updated, 0);
+    assert_eq!(report.prefixes_withdrawn, 0);
+    assert_eq!(report.prefixes_skipped, 0);
+
+    // Another peer announces the same prefixes, but only gets the IPv4
+    // ones in.
+    let report =
+        store.apply_bgp_update(2, 11, &upd, |prefix, attributes| {
+            if prefix.is_v4() {
+                origin_as(attributes)
+            } else {
+                None
+            }
+        })?;
+    assert_eq!(report.prefixes_new, 2);
+    assert_eq!(report.prefixes_skipped, 1);
+
+    // Re-announce the /16, withdraw the /24 and the IPv6 prefix, and a
+    // prefix that was never announced.
+    let msg = update(
+        &[pfxs[1], pfxs[2], pfxs[3]],
+        &pfxs[..1],
+        &[65000, 64501],
+        true,
+    );
+    let upd = parse(&msg);
+    let report = store.apply_bgp_update(1, 12, &upd, |_, attributes| {
+        origin_as(attributes)
+    })?;
+    assert_eq!(report.prefixes_new, 0);
+    assert_eq!(report.prefixes_updated, 1);
+    assert_eq!(report.prefixes_withdrawn, 2);
+    assert_eq!(report.prefixes_skipped, 1);
+
+    assert_eq!(
+        records_for(&store, &pfxs[0]),
+        vec![
+            (1, 12, RouteStatus::Active, Asn::from(64501)),
+            (2, 11, RouteStatus::Active, Asn::from(64500)),
+        ]
+    );
+    assert_eq!(
+        records_for(&store, &pfxs[1]),
+        vec![
+            (1, 12, RouteStatus::Withdrawn, Asn::from(64500)),
+            (2, 11, RouteStatus::Active, Asn::from(64500)),
+        ]
+    );
+    assert_eq!(
+        records_for(&store, &pfxs[2]),
+        vec![(1, 12, RouteStatus::Withdrawn, Asn::from(64500))]
+    );
+    assert_eq!(store.prefixes_count(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_invalid_bgp_update() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+
+    // An MP_UNREACH_NLRI attribute with a /129 for IPv6.
+    let mut msg = update(&[], &[pfx], &[65000, 64500], true);
+    let attrs_len = u16::from_be_bytes([msg[21], msg[22]]);
+    msg.truncate(23 + attrs_len as usize);
+    msg.extend_from_slice(&[0x80, 15, 4, 0, 2, 1, 129]);
+    msg.extend_from_slice(&[16, 185, 34]);
+    let len = msg.len() as u16;
+    msg[16..18].copy_from_slice(&len.to_be_bytes());
+    msg[21..23].copy_from_slice(&(attrs_len + 7).to_be_bytes());
+
+    let upd = parse(&msg);
+    assert_eq!(
+        store
+            .apply_bgp_update(1, 10, &upd, |_, attributes| origin_as(
+                attributes
+            ))
+            .err(),
+        Some(PrefixStoreError::InvalidBgpUpdate)
+    );
+    assert_eq!(store.prefixes_count(), 0);
+
+    Ok(())
+}
+
+fn routes_for(
+    store: &MultiThreadedStore<Route>,
+    mui: u32,
+) -> Vec<(Prefix, Route)> {
+    let guard = &epoch::pin();
+    let mut routes = store
+        .prefixes_iter(guard)
+        .flat_map(|p| {
+            p.meta
+                .into_iter()
+                .filter(|r| r.multi_uniq_id == mui)
+                .map(move |r| (p.prefix, r.meta))
+        })
+        .collect::<Vec<_>>();
+    routes.sort_by_key(|r| r.0);
+    routes
+}
+
+// Apply the messages to a new store with mui 1.
+fn store_from(
+    msgs: &[UpdateMessage<Vec<u8>>],
+) -> Result<MultiThreadedStore<Route>, Box<dyn std::error::Error>> {
+    let store = MultiThreadedStore::<Route>::new()?;
+    for msg in msgs {
+        let msg = parse(msg.as_ref());
+        store.apply_bgp_update(1, 1, &msg, |_, attributes| {
+            Route::new(attributes)
+        })?;
+    }
+    Ok(store)
+}
+
+#[test]
+fn test_rib_updates() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Route>::new()?;
+    let pfxs = [
+        Prefix::from_str("10.0.0.0/24")?,
+        Prefix::from_str("10.0.1.0/24")?,
+        Prefix::from_str("10.1.0.0/16")?,
+        Prefix::from_str("10.2.0.0/16")?,
+        Prefix::from_str("2001:db8:1::/48")?,
+        Prefix::from_str("2001:db8:2::/48")?,
+    ];
+    let apply = |mui, msg: Vec<u8>| {
+        store.apply_bgp_update(mui, 1, &parse(&msg), |_, attributes| {
+            Route::new(attributes)
+        })
+    };
+    apply(1, update(&[], &pfxs[..3], &[65000, 64500], true))?;
+    apply(1, update(&[], &pfxs[3..4], &[65000, 64501], true))?;
+    apply(1, update(&[], &pfxs[4..], &[65000, 64500], true))?;
+    apply(2, update(&[], &pfxs[..1], &[64502], true))?;
+
+    // One message for each set of path attributes, and address family.
+    let msgs = adj_rib_updates(&store, 1, &UpdateOptions::default())?;
+    assert_eq!(msgs.len(), 3);
+    assert_eq!(routes_for(&store_from(&msgs)?, 1), routes_for(&store, 1));
+
+    // Small messages, that fit only one of the IPv6 prefixes.
+    let options = UpdateOptions { max_pdu_size: 71 };
+    let msgs = adj_rib_updates(&store, 1, &options)?;
+    assert_eq!(msgs.len(), 4);
+    assert!(msgs.iter().all(|msg| msg.as_ref().len() <= 71));
+    assert_eq!(routes_for(&store_from(&msgs)?, 1), routes_for(&store, 1));
+
+    // Messages that can't fit an IPv6 prefix at all.
+    assert!(matches!(
+        adj_rib_updates(&store, 1, &UpdateOptions { max_pdu_size: 70 }),
+        Err(UpdateError::PduTooLarge(pfx)) if pfx.is_v6()
+    ));
+
+    // The route from mui 2 is the best path for 10.0.0.0/24.
+    let msgs = loc_rib_updates(&store, &(), &UpdateOptions::default())?;
+    assert_eq!(msgs.len(), 4);
+    let mut expected = routes_for(&store, 1);
+    expected[0] = routes_for(&store, 2).remove(0);
+    assert_eq!(routes_for(&store_from(&msgs)?, 1), expected);
+
+    // Withdrawn routes are not announced.
+    store.mark_mui_as_withdrawn_v4(2)?;
+    assert!(adj_rib_updates(&store, 2, &UpdateOptions::default())?.is_empty());
+    store.withdraw(&pfxs[3], 1, 2)?;
+    let msgs = adj_rib_updates(&store, 1, &UpdateOptions::default())?;
+    assert_eq!(msgs.len(), 2);
+
+    // An IPv4 route without a next hop can't be announced.
+    let mut route = store
+        .match_prefix(
+            &pfxs[2],
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: false,
+                include_less_specifics: false,
+                include_more_specifics: false,
+                mui: Some(1),
+            },
+            &epoch::pin(),
+        )
+        .prefix_meta
+        .remove(0)
+        .meta;
+    route.pa_map.attributes_mut().remove(&3);
+    store.insert(
+        &pfxs[2],
+        Record::new(3, 2, RouteStatus::Active, route),
+        None,
+    )?;
+    assert!(matches!(
+        adj_rib_updates(&store, 3, &UpdateOptions::default()),
+        Err(UpdateError::MissingNextHop(pfx)) if pfx == pfxs[2]
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::bmp::{BmpAdaptor, BmpPeer};
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+use routecore::bmp::message::Message;
+
+mod common;
+
+use common::bgp::{bgp_message, end_of_rib, nlri, origin_as, update};
+
+fn records_for(
+    store: &MultiThreadedStore<Asn>,
+    prefix: &Prefix,
+) -> Vec<(u32, u64, RouteStatus, Asn)> {
+    let guard = &epoch::pin();
+    let mut recs = store
+        .match_prefix(
+            prefix,
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: true,
+                include_less_specifics: false,
+                include_more_specifics: false,
+                mui: None,
+            },
+            guard,
+        )
+        .prefix_meta
+        .into_iter()
+        .map(|r| (r.multi_uniq_id, r.ltime, r.status, r.meta))
+        .collect::<Vec<_>>();
+    recs.sort_by_key(|r| (r.0, r.1));
+    recs
+}
+
+fn bmp_message(msg_type: u8, body: &[u8]) -> Message<Vec<u8>> {
+    let mut msg = vec![3];
+    msg.extend_from_slice(&(6 + body.len() as u32).to_be_bytes());
+    msg.push(msg_type);
+    msg.extend_from_slice(body);
+    Message::from_octets(msg).unwrap()
+}
+
+fn per_peer_header(peer: &BmpPeer, timestamp: u32) -> Vec<u8> {
+    let mut flags = 0;
+    if peer.addr.is_ipv6() {
+        flags |= 0x80;
+    }
+    if peer.post_policy {
+        flags |= 0x40;
+    }
+    let mut pph = vec![0, flags];
+    pph.extend_from_slice(&peer.distinguisher);
+    match peer.addr {
+        IpAddr::V4(addr) => {
+            pph.extend_from_slice(&[0; 12]);
+            pph.extend_from_slice(&addr.octets());
+        }
+        IpAddr::V6(addr) => pph.extend_from_slice(&addr.octets()),
+    }
+    pph.extend_from_slice(&peer.asn.into_u32().to_be_bytes());
+    pph.extend_from_slice(&[192, 0, 2, 1]);
+    pph.extend_from_slice(&timestamp.to_be_bytes());
+    pph.extend_from_slice(&0_u32.to_be_bytes());
+    pph
+}
+
+// A BGP OPEN message with the four octet AS number capability, and, if
+// `addpath` is true, the ADD-PATH capability to send and receive IPv4
+// unicast.
+fn open(asn: u32, addpath: bool) -> Vec<u8> {
+    let mut opt_params = vec![2, 6, 65, 4];
+    opt_params.extend_from_slice(&asn.to_be_bytes());
+    if addpath {
+        opt_params.extend_from_slice(&[2, 6, 69, 4, 0, 1, 1, 3]);
+    }
+    let mut msg = vec![0xff; 16];
+    msg.extend_from_slice(&(29 + opt_params.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&[1, 4]);
+    msg.extend_from_slice(&23456_u16.to_be_bytes());
+    msg.extend_from_slice(&180_u16.to_be_bytes());
+    msg.extend_from_slice(&[192, 0, 2, 254]);
+    msg.push(opt_params.len() as u8);
+    msg.extend_from_slice(&opt_params);
+    msg
+}
+
+// A BGP UPDATE message that announces IPv4 prefixes with an ADD-PATH path
+// id.
+fn addpath_update(announced: &[Prefix], path_id: u32) -> Vec<u8> {
+    let mut attrs = vec![0x40, 1, 1, 0, 0x40, 2, 6, 2, 1];
+    attrs.extend_from_slice(&65002_u32.to_be_bytes());
+    attrs.extend_from_slice(&[0x40, 3, 4, 192, 0, 2, 2]);
+    let mut body = vec![0, 0];
+    body.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+    body.extend_from_slice(&attrs);
+    for pfx in announced {
+        body.extend_from_slice(&path_id.to_be_bytes());
+        nlri(&mut body, pfx);
+    }
+    bgp_message(2, &body)
+}
+
+fn peer_up(
+    peer: &BmpPeer,
+    timestamp: u32,
+    addpath: bool,
+) -> Message<Vec<u8>> {
+    let mut body = per_peer_header(peer, timestamp);
+    body.extend_from_slice(&[0; 16]);
+    body.extend_from_slice(&179_u16.to_be_bytes());
+    body.extend_from_slice(&50000_u16.to_be_bytes());
+    body.extend_from_slice(&open(65000, addpath));
+    body.extend_from_slice(&open(peer.asn.into_u32(), addpath));
+    bmp_message(3, &body)
+}
+
+fn route_monitoring(
+    peer: &BmpPeer,
+    timestamp: u32,
+    update: &[u8],
+) -> Message<Vec<u8>> {
+    let mut body = per_peer_header(peer, timestamp);
+    body.extend_from_slice(update);
+    bmp_message(0, &body)
+}
+
+fn peer_down(peer: &BmpPeer, timestamp: u32) -> Message<Vec<u8>> {
+    // The remote system closed the session without a NOTIFICATION.
+    let mut body = per_peer_header(peer, timestamp);
+    body.push(4);
+    bmp_message(2, &body)
+}
+
+#[test]
+fn test_bmp_adaptor() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let mut adaptor = BmpAdaptor::new(100);
+    let pfxs = vec![
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("2001:db8:1::/48")?,
+    ];
+    let pre_policy = BmpPeer {
+        addr: "192.0.2.1".parse()?,
+        asn: Asn::from(65001),
+        distinguisher: [0; 8],
+        post_policy: false,
+    };
+    let post_policy = BmpPeer {
+        post_policy: true,
+        ..pre_policy
+    };
+    let process = |adaptor: &mut BmpAdaptor, msg: &Message<Vec<u8>>| {
+        adaptor.process(&store, msg, |peer, _, attributes| {
+            assert_eq!(peer.asn, Asn::from(65001));
+            origin_as(attributes)
+        })
+    };
+
+    process(&mut adaptor, &peer_up(&pre_policy, 1000, false))?;
+    assert_eq!(adaptor.mui_for(&pre_policy), Some(100));
+    assert!(adaptor.is_up(&pre_policy));
+
+    let report = process(
+        &mut adaptor,
+        &route_monitoring(
+            &pre_policy,
+            1001,
+            &update(&[], &pfxs, &[65000, 64500], true),
+        ),
+    )?;
+    assert_eq!(report.prefixes_new, 2);
+
+    // The post-policy routes of the same peer, without a Peer Up.
+    let report = process(
+        &mut adaptor,
+        &route_monitoring(
+            &post_policy,
+            1002,
+            &update(&[], &pfxs, &[65000, 64501], true),
+        ),
+    )?;
+    assert_eq!(report.prefixes_new, 2);
+    assert_eq!(adaptor.mui_for(&post_policy), Some(101));
+    assert_eq!(adaptor.peer_for(101), Some(&post_policy));
+    assert_eq!(adaptor.peers().count(), 2);
+
+    let report = process(
+        &mut adaptor,
+        &route_monitoring(
+            &pre_policy,
+            1003,
+            &update(&pfxs[..1], &[], &[], true),
+        ),
+    )?;
+    assert_eq!(report.prefixes_withdrawn, 1);
+    assert_eq!(
+        records_for(&store, &pfxs[0]),
+        vec![
+            (100, 1003, RouteStatus::Withdrawn, Asn::from(64500)),
+            (101, 1002, RouteStatus::Active, Asn::from(64501)),
+        ]
+    );
+
+    // The peer goes down, which withdraws all of its routes.
+    process(&mut adaptor, &peer_down(&pre_policy, 1004))?;
+    assert!(!adaptor.is_up(&pre_policy));
+    assert!(store.mui_is_withdrawn_v4(100));
+    assert!(store.mui_is_withdrawn_v6(100));
+    assert!(!store.mui_is_withdrawn_v4(101));
+
+    // And comes back up with the same mui, re-announcing only the IPv6
+    // prefix. The old routes are not used until the End-of-RIB.
+    process(&mut adaptor, &peer_up(&pre_policy, 1010, false))?;
+    assert_eq!(adaptor.mui_for(&pre_policy), Some(100));
+    assert!(adaptor.is_up(&pre_policy));
+    assert!(store.mui_is_withdrawn_v4(100));
+    assert!(store.mui_is_withdrawn_v6(100));
+    assert!(store.mui_is_stale(100));
+    process(
+        &mut adaptor,
+        &route_monitoring(
+            &pre_policy,
+            1011,
+            &update(&[], &pfxs[1..], &[65000, 64502], true),
+        ),
+    )?;
+    assert!(store.mui_is_withdrawn_v6(100));
+
+    process(
+        &mut adaptor,
+        &route_monitoring(&pre_policy, 1012, &end_of_rib(true)),
+    )?;
+    assert!(store.mui_is_withdrawn_v4(100));
+    assert!(!store.mui_is_withdrawn_v6(100));
+    assert_eq!(
+        records_for(&store, &pfxs[1]),
+        vec![
+            (100, 1011, RouteStatus::Active, Asn::from(64502)),
+            (101, 1002, RouteStatus::Active, Asn::from(64501)),
+        ]
+    );
+
+    // The IPv4 prefix was not announced again, so it is swept.
+    process(
+        &mut adaptor,
+        &route_monitoring(&pre_policy, 1013, &end_of_rib(false)),
+    )?;
+    assert!(!store.mui_is_withdrawn_v4(100));
+    assert!(!store.mui_is_stale(100));
+    assert_eq!(
+        records_for(&store, &pfxs[0]),
+        vec![(101, 1002, RouteStatus::Active, Asn::from(64501))]
+    );
+
+    // The end of the BMP session takes down all the peers.
+    process(&mut adaptor, &bmp_message(5, &[]))?;
+    assert!(!adaptor.is_up(&pre_policy));
+    assert!(!adaptor.is_up(&post_policy));
+    assert!(store.mui_is_withdrawn_v6(100));
+    assert!(store.mui_is_withdrawn_v6(101));
+
+    Ok(())
+}
+
+#[test]
+fn test_bmp_peer_back_without_peer_up(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let mut adaptor = BmpAdaptor::new(1);
+    let pfxs = [
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.35.0.0/16")?,
+    ];
+    // A peer with ADD-PATH for IPv4 unicast.
+    let peer = BmpPeer {
+        addr: "192.0.2.2".parse()?,
+        asn: Asn::from(65002),
+        distinguisher: [0; 8],
+        post_policy: false,
+    };
+    let mut process = |msg: &Message<Vec<u8>>| {
+        adaptor.process(&store, msg, |_, _, _| Some(Asn::from(65002)))
+    };
+
+    process(&peer_up(&peer, 1000, true))?;
+    process(&peer_down(&peer, 1001))?;
+    assert!(store.mui_is_withdrawn_v4(1));
+
+    // The peer is back without a Peer Up. Its updates are parsed with the
+    // session parameters it had, so the NLRI are recognized as ADD-PATH
+    // NLRI, which are skipped.
+    let report =
+        process(&route_monitoring(&peer, 1002, &addpath_update(&pfxs, 1)))?;
+    assert_eq!(report.prefixes_skipped, 2);
+    assert_eq!(store.prefixes_count(), 0);
+    assert!(adaptor.is_up(&peer));
+    assert!(store.mui_is_withdrawn_v4(1));
+    assert!(store.mui_is_stale(1));
+
+    Ok(())
+}
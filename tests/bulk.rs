@@ -0,0 +1,87 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use inetnum::addr::{Prefix, PrefixError};
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+
+mod common;
+
+use common::store::sorted_contents;
+
+// A couple of thousand records for both address families, including the
+// default routes and prefixes shorter than the first strides, with two
+// records for the same (prefix, mui) for some of them.
+fn records() -> Result<Vec<(Prefix, Record<Asn>)>, PrefixError> {
+    let mut records = vec![];
+    for len in 0..=24 {
+        for i in 0..100_u32 {
+            let addr = i.wrapping_mul(0x9e37_79b9) & !(u32::MAX >> len);
+            let prefix = Prefix::new(Ipv4Addr::from(addr).into(), len)?;
+            let mui = i % 3;
+            records.push((
+                prefix,
+                Record::new(mui, 1, RouteStatus::Active, Asn::from(i)),
+            ));
+            if i % 10 == 0 {
+                records.push((
+                    prefix,
+                    Record::new(mui, 2, RouteStatus::Active, Asn::from(i)),
+                ));
+            }
+        }
+    }
+    for len in [0, 3, 32, 48] {
+        for i in 0..50_u128 {
+            let addr = (0x2001_0db8_u128 << 96) | (i << 80);
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            let prefix =
+                Prefix::new(Ipv6Addr::from(addr & mask).into(), len as u8)?;
+            records.push((
+                prefix,
+                Record::new(1, 1, RouteStatus::Active, Asn::from(65400)),
+            ));
+        }
+    }
+    Ok(records)
+}
+
+#[test]
+fn test_insert_bulk() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let records = records()?;
+
+    let expected = MultiThreadedStore::<Asn>::new()?;
+    for (prefix, record) in records.iter() {
+        expected.insert(prefix, record.clone(), None)?;
+    }
+    let expected_records = sorted_contents(&expected)
+        .iter()
+        .map(|c| c.1.len())
+        .sum::<usize>();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let report = store.insert_bulk(records.clone())?;
+    assert_eq!(sorted_contents(&store), sorted_contents(&expected));
+    assert_eq!(report.inserted, expected_records);
+    assert_eq!(report.inserted + report.updated, records.len());
+    assert_eq!(report.prefixes_new, expected.prefixes_count());
+
+    for threads in [0, 1, 4] {
+        let store = MultiThreadedStore::<Asn>::new()?;
+        let report = store.par_insert_bulk(records.clone(), threads)?;
+        assert_eq!(sorted_contents(&store), sorted_contents(&expected));
+        assert_eq!(store.prefixes_count(), expected.prefixes_count());
+        assert_eq!(report.inserted, expected_records);
+        assert_eq!(report.inserted + report.updated, records.len());
+        assert_eq!(report.prefixes_new, expected.prefixes_count());
+    }
+
+    // Inserting everything again only updates.
+    let report = store.insert_bulk(records.clone())?;
+    assert_eq!(report.inserted, 0);
+    assert_eq!(report.updated, records.len());
+    assert_eq!(report.prefixes_new, 0);
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn kinds(changes: &[Change]) -> Vec<(Option<Prefix>, u32, ChangeKind)> {
+    changes.iter().map(|c| (c.prefix, c.mui, c.kind)).collect()
+}
+
+#[test]
+fn test_change_log() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    assert_eq!(
+        store.change_cursor(),
+        Err(PrefixStoreError::ChangeLogNotEnabled)
+    );
+
+    let pfx4 = Prefix::from_str("185.34.0.0/16")?;
+    let pfx6 = Prefix::from_str("2001:db8::/32")?;
+    let rec = |mui, ltime| {
+        Record::new(mui, ltime, RouteStatus::Active, Asn::from(65400 + mui))
+    };
+
+    // Changes before the log is enabled are not logged.
+    store.insert(&pfx4, rec(1, 1), None)?;
+
+    store.enable_change_log(100);
+    let mut cursor = store.change_cursor()?;
+    assert_eq!(store.read_changes(&mut cursor, 10)?, vec![]);
+
+    store.insert(&pfx4, rec(1, 2), None)?;
+    store.insert(&pfx6, rec(2, 2), None)?;
+    store.withdraw(&pfx4, 1, 3)?;
+    store.mark_mui_as_active_for_prefix(&pfx4, 1)?;
+    store.mark_mui_as_withdrawn_v6(2)?;
+    // Marking a mui that is withdrawn already doesn't change anything.
+    store.mark_mui_as_withdrawn_v6(2)?;
+    store.mark_mui_as_stale(1)?;
+    store.remove(&pfx6, 2)?;
+
+    let changes = store.read_changes(&mut cursor, 3)?;
+    assert_eq!(
+        kinds(&changes),
+        vec![
+            (Some(pfx4), 1, ChangeKind::Updated),
+            (Some(pfx6), 2, ChangeKind::Inserted),
+            (Some(pfx4), 1, ChangeKind::Withdrawn),
+        ]
+    );
+    let changes = [changes, store.read_changes(&mut cursor, 10)?].concat();
+    assert_eq!(
+        kinds(&changes[3..]),
+        vec![
+            (Some(pfx4), 1, ChangeKind::Activated),
+            (None, 2, ChangeKind::MuiWithdrawn),
+            (None, 1, ChangeKind::MuiStale),
+            (None, 1, ChangeKind::MuiStale),
+            (Some(pfx6), 2, ChangeKind::Removed),
+        ]
+    );
+    assert!(changes.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+    assert_eq!(cursor.next_seq(), changes[7].seq + 1);
+    assert_eq!(store.read_changes(&mut cursor, 10)?, vec![]);
+
+    // Sweeping the stale mui logs the swept records.
+    assert_eq!(store.sweep_stale(1, true)?, vec![pfx4]);
+    assert_eq!(
+        kinds(&store.read_changes(&mut cursor, 10)?),
+        vec![(Some(pfx4), 1, ChangeKind::Removed)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_change_log_overrun() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.enable_change_log(4);
+    let mut cursor = store.change_cursor()?;
+    let mut late_cursor = store.change_cursor()?;
+
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    for ltime in 0..3 {
+        store.insert(
+            &pfx,
+            Record::new(1, ltime, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+    }
+    assert_eq!(store.read_changes(&mut cursor, 10)?.len(), 3);
+
+    for ltime in 3..6 {
+        store.insert(
+            &pfx,
+            Record::new(1, ltime, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+    }
+
+    // The first two changes were dropped, the cursor that read the first
+    // three changes can go on, the other one has to resync.
+    assert_eq!(store.read_changes(&mut cursor, 10)?.len(), 3);
+    assert_eq!(
+        store.read_changes(&mut late_cursor, 10),
+        Err(PrefixStoreError::ChangeLogOverrun)
+    );
+    assert_eq!(late_cursor.next_seq(), 0);
+
+    let mut cursor = store.change_cursor()?;
+    assert_eq!(cursor.next_seq(), 6);
+    store.remove_prefix(&pfx)?;
+    assert_eq!(
+        kinds(&store.read_changes(&mut cursor, 10)?),
+        vec![(Some(pfx), 1, ChangeKind::Removed)]
+    );
+
+    Ok(())
+}
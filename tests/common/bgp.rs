@@ -0,0 +1,122 @@
+// BGP messages for the tests that feed the store from BGP.
+
+use std::net::IpAddr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use routecore::bgp::aspath::HopPath;
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+
+// The origin AS of the AS path in `attributes`.
+pub fn origin_as(attributes: &OwnedPathAttributes) -> Option<Asn> {
+    let as_path = attributes.get::<HopPath>()?;
+    Asn::try_from(as_path.origin()?.clone()).ok()
+}
+
+pub fn nlri(buf: &mut Vec<u8>, prefix: &Prefix) {
+    let n = (prefix.len() as usize + 7) / 8;
+    buf.push(prefix.len());
+    match prefix.addr() {
+        IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()[..n]),
+        IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()[..n]),
+    }
+}
+
+// A BGP UPDATE message, with four octet AS numbers if `four_octet` is
+// true. IPv4 prefixes go in the conventional fields, IPv6 prefixes in the
+// MP_REACH_NLRI and MP_UNREACH_NLRI attributes.
+pub fn update(
+    withdrawn: &[Prefix],
+    announced: &[Prefix],
+    as_path: &[u32],
+    four_octet: bool,
+) -> Vec<u8> {
+    let mut withdrawn_v4 = vec![];
+    let mut withdrawn_v6 = vec![];
+    for pfx in withdrawn {
+        nlri(
+            if pfx.is_v4() {
+                &mut withdrawn_v4
+            } else {
+                &mut withdrawn_v6
+            },
+            pfx,
+        );
+    }
+    let mut announced_v4 = vec![];
+    let mut announced_v6 = vec![];
+    for pfx in announced {
+        nlri(
+            if pfx.is_v4() {
+                &mut announced_v4
+            } else {
+                &mut announced_v6
+            },
+            pfx,
+        );
+    }
+
+    let mut attrs = vec![];
+    if !announced.is_empty() {
+        let asn_len = if four_octet { 4 } else { 2 };
+        attrs.extend_from_slice(&[0x40, 1, 1, 0]);
+        attrs.extend_from_slice(&[
+            0x40,
+            2,
+            (2 + asn_len * as_path.len()) as u8,
+            2,
+            as_path.len() as u8,
+        ]);
+        for asn in as_path {
+            attrs.extend_from_slice(&asn.to_be_bytes()[4 - asn_len..]);
+        }
+    }
+    if !announced_v4.is_empty() {
+        attrs.extend_from_slice(&[0x40, 3, 4, 192, 0, 2, 1]);
+    }
+    if !announced_v6.is_empty() {
+        attrs.extend_from_slice(&[0x80, 14, (21 + announced_v6.len()) as u8]);
+        attrs.extend_from_slice(&[0, 2, 1, 16]);
+        attrs.extend_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        attrs.push(0);
+        attrs.extend_from_slice(&announced_v6);
+    }
+    if !withdrawn_v6.is_empty() {
+        attrs.extend_from_slice(&[0x80, 15, (3 + withdrawn_v6.len()) as u8]);
+        attrs.extend_from_slice(&[0, 2, 1]);
+        attrs.extend_from_slice(&withdrawn_v6);
+    }
+
+    let mut body = vec![];
+    body.extend_from_slice(&(withdrawn_v4.len() as u16).to_be_bytes());
+    body.extend_from_slice(&withdrawn_v4);
+    body.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+    body.extend_from_slice(&attrs);
+    body.extend_from_slice(&announced_v4);
+    bgp_message(2, &body)
+}
+
+pub fn bgp_message(msg_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut msg = vec![0xff; 16];
+    msg.extend_from_slice(&(19 + body.len() as u16).to_be_bytes());
+    msg.push(msg_type);
+    msg.extend_from_slice(body);
+    msg
+}
+
+// The End-of-RIB marker for IPv6 unicast if `v6` is true, or else for
+// IPv4 unicast.
+pub fn end_of_rib(v6: bool) -> Vec<u8> {
+    let mut body = vec![0, 0];
+    if v6 {
+        body.extend_from_slice(&[0, 6, 0x80, 15, 3, 0, 2, 1]);
+    } else {
+        body.extend_from_slice(&[0, 0]);
+    }
+    bgp_message(2, &body)
+}
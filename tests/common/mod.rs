@@ -0,0 +1,15 @@
+// Helpers that are shared by the integration tests. Not every test uses
+// all of them.
+#![allow(dead_code)]
+
+use std::io::Write;
+
+pub mod bgp;
+pub mod store;
+
+pub fn init() {
+    let _ = env_logger::builder()
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .is_test(true)
+        .try_init();
+}
@@ -0,0 +1,30 @@
+// Helpers for comparing the contents of stores.
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+
+// The (multi_uniq_id, ltime, status, meta) of a record.
+pub type RecordContents = (u32, u64, RouteStatus, Asn);
+
+// All the prefixes in `store`, with their records, sorted by prefix and
+// multi_uniq_id, so that the contents of stores can be compared.
+pub fn sorted_contents(
+    store: &MultiThreadedStore<Asn>,
+) -> Vec<(Prefix, Vec<RecordContents>)> {
+    let guard = &epoch::pin();
+    let mut contents = store
+        .prefixes_iter(guard)
+        .map(|p| {
+            let mut recs = p
+                .meta
+                .iter()
+                .map(|r| (r.multi_uniq_id, r.ltime, r.status, r.meta))
+                .collect::<Vec<_>>();
+            recs.sort_by_key(|r| r.0);
+            (p.prefix, recs)
+        })
+        .collect::<Vec<_>>();
+    contents.sort_by_key(|c| c.0);
+    contents
+}
@@ -365,4 +365,126 @@ fn test_concurrent_updates_2() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(active_len, len_2);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_concurrent_colliding_inserts(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let tree_bitmap = std::sync::Arc::new(MultiThreadedStore::<Asn>::new()?);
+
+    // Many threads insert different prefixes of the same lengths at the
+    // same time, so that they race for the same prefix slots and create
+    // the same nodes. Every prefix gets the address as its meta-data, so
+    // that a record that ends up under another prefix is noticed.
+    let pfxs = (0..=255_u8)
+        .flat_map(|a| {
+            (0..8_u8).flat_map(move |b| {
+                [20, 24].map(|len| {
+                    Prefix::new(
+                        std::net::Ipv4Addr::new(a, b * 32, 0, 0).into(),
+                        len,
+                    )
+                })
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let asn = |pfx: &Prefix| match pfx.addr() {
+        std::net::IpAddr::V4(addr) => Asn::from(u32::from(addr)),
+        std::net::IpAddr::V6(_) => unreachable!(),
+    };
+
+    let threads = (0..8)
+        .map(|n| {
+            let tree_bitmap = tree_bitmap.clone();
+            let pfxs =
+                pfxs.iter().skip(n).step_by(8).copied().collect::<Vec<_>>();
+            std::thread::Builder::new()
+                .name(n.to_string())
+                .spawn(move || {
+                    for pfx in pfxs.iter() {
+                        tree_bitmap
+                            .insert(
+                                pfx,
+                                Record::new(
+                                    1,
+                                    0,
+                                    RouteStatus::Active,
+                                    asn(pfx),
+                                ),
+                                None,
+                            )
+                            .unwrap();
+                    }
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(tree_bitmap.prefixes_count(), pfxs.len());
+    let match_options = MatchOptions {
+        match_type: rotonda_store::MatchType::ExactMatch,
+        include_withdrawn: true,
+        include_less_specifics: false,
+        include_more_specifics: false,
+        mui: None,
+    };
+    let guard = rotonda_store::epoch::pin();
+    for pfx in pfxs.iter() {
+        let res = tree_bitmap.match_prefix(pfx, &match_options, &guard);
+        assert_eq!(res.prefix, Some(*pfx));
+        assert_eq!(res.prefix_meta.len(), 1);
+        assert_eq!(res.prefix_meta[0].meta, asn(pfx));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_mui_status_changes(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let tree_bitmap = std::sync::Arc::new(MultiThreadedStore::<Asn>::new()?);
+    tree_bitmap.insert(
+        &Prefix::from_str("185.34.0.0/16")?,
+        Record::new(1, 0, RouteStatus::Active, Asn::from(65501)),
+        None,
+    )?;
+
+    // Every thread flips the global status of its own mui, while the other
+    // threads replace the bitmap with the global statuses, so that most
+    // Compare-and-Swaps have to be retried. A retry that doesn't pick up
+    // the current bitmap loops forever, so the threads report back over a
+    // channel, and the test gives up after a while.
+    let (tx, rx) = std::sync::mpsc::channel();
+    for mui in 0..8_u32 {
+        let tree_bitmap = tree_bitmap.clone();
+        let tx = tx.clone();
+        std::thread::Builder::new().name(mui.to_string()).spawn(
+            move || {
+                for _ in 0..20_000 {
+                    tree_bitmap.mark_mui_as_withdrawn_v4(mui).unwrap();
+                    tree_bitmap.mark_mui_as_active_v4(mui).unwrap();
+                }
+                if mui % 2 == 0 {
+                    tree_bitmap.mark_mui_as_withdrawn_v4(mui).unwrap();
+                }
+                tx.send(mui).unwrap();
+            },
+        )?;
+    }
+    drop(tx);
+    for _ in 0..8 {
+        rx.recv_timeout(std::time::Duration::from_secs(60))?;
+    }
+
+    for mui in 0..8_u32 {
+        assert_eq!(tree_bitmap.mui_is_withdrawn_v4(mui), mui % 2 == 0);
+    }
+
+    Ok(())
+}
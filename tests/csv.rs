@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::csv::{
+    load, load_single, CsvError, CsvOptions, PrefixColumns,
+};
+use rotonda_store::meta_examples::PrefixAs;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::SingleThreadedStore;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+// Tells whether an error is the one that was expected.
+type ErrorCheck = fn(&CsvError) -> bool;
+
+fn records_for(
+    store: &MultiThreadedStore<Asn>,
+    prefix: &str,
+) -> Vec<(u32, u64, RouteStatus, Asn)> {
+    let guard = &epoch::pin();
+    let mut recs = store
+        .prefixes_iter(guard)
+        .find(|p| p.prefix == Prefix::from_str(prefix).unwrap())
+        .map(|p| p.meta)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|r| (r.multi_uniq_id, r.ltime, r.status, r.meta))
+        .collect::<Vec<_>>();
+    recs.sort_by_key(|r| r.0);
+    recs
+}
+
+#[test]
+fn test_load_default_columns() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let input = "ip,len,asn\n\
+        185.34.0.0,16,65401\n\
+        185.34.10.0, 24 ,65402\n\
+        2001:db8::,32,65403\n";
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let count =
+        load(&store, input.as_bytes(), &CsvOptions::default(), |fields| {
+            Ok(Asn::from(fields[2].parse::<u32>()?))
+        })?;
+    assert_eq!(count, 3);
+    assert_eq!(store.prefixes_count(), 3);
+    assert_eq!(
+        records_for(&store, "185.34.10.0/24"),
+        vec![(0, 0, RouteStatus::Active, Asn::from(65402))]
+    );
+    assert_eq!(
+        records_for(&store, "2001:db8::/32"),
+        vec![(0, 0, RouteStatus::Active, Asn::from(65403))]
+    );
+
+    let mut single = SingleThreadedStore::<PrefixAs>::new(vec![4], vec![4]);
+    let count = load_single(
+        &mut single,
+        input.as_bytes(),
+        &CsvOptions::default(),
+        |fields| Ok(PrefixAs(fields[2].parse()?)),
+    )?;
+    assert_eq!(count, 3);
+    assert_eq!(single.prefixes_iter().count(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_load_configured_columns() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let input = "65401;1;10;185.34.0.0/16;Active\n\
+        65402;2;20;185.34.0.0/16;withdrawn\n\
+        65403;3;30;2001:db8::/32;INACTIVE\n\
+        65404;4;40;185.34.0.0/16;stale\n";
+    let options = CsvOptions {
+        prefix: PrefixColumns::Cidr(3),
+        mui: Some(1),
+        ltime: Some(2),
+        status: Some(4),
+        has_headers: false,
+        delimiter: b';',
+        ..Default::default()
+    };
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let count = load(&store, input.as_bytes(), &options, |fields| {
+        Ok(Asn::from(fields[0].parse::<u32>()?))
+    })?;
+    assert_eq!(count, 4);
+    assert_eq!(
+        records_for(&store, "185.34.0.0/16"),
+        vec![
+            (1, 10, RouteStatus::Active, Asn::from(65401)),
+            (2, 20, RouteStatus::Withdrawn, Asn::from(65402)),
+            (4, 40, RouteStatus::Stale, Asn::from(65404)),
+        ]
+    );
+    assert_eq!(
+        records_for(&store, "2001:db8::/32"),
+        vec![(3, 30, RouteStatus::InActive, Asn::from(65403))]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_load_errors() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let meta = |fields: &csv::StringRecord| {
+        Ok(Asn::from(fields.get(2).unwrap_or_default().parse::<u32>()?))
+    };
+
+    let cases: [(&[u8], ErrorCheck); 4] = [
+        (b"ip,len,asn\n10.0.0.0,8,1\n10.0.0.1,8,1\n", |err| {
+            matches!(err, CsvError::InvalidField(3, 1))
+        }),
+        (b"ip,len,asn\n10.0.0.0,8,1\n10.0.0.0,8,\xff\n", |err| {
+            matches!(err, CsvError::Csv(_))
+        }),
+        (b"ip,len,asn\n10.0.0.0,8,1\n10.0.0.0,8,AS1\n", |err| {
+            matches!(err, CsvError::Meta(3, _))
+        }),
+        (b"ip,len,asn\n10.0.0.0,8,1\nten.0.0.0,8,1\n", |err| {
+            matches!(err, CsvError::InvalidField(3, 0))
+        }),
+    ];
+
+    for (input, expected) in cases {
+        let store = MultiThreadedStore::<Asn>::new()?;
+        match load(&store, input, &CsvOptions::default(), meta) {
+            Err(err) => assert!(expected(&err), "unexpected error {}", err),
+            Ok(count) => panic!("loaded {} routes from {:?}", count, input),
+        }
+        assert_eq!(store.prefixes_count(), 1);
+    }
+
+    // A missing column, with the iterator continuing after the error.
+    let options = CsvOptions {
+        mui: Some(3),
+        has_headers: false,
+        ..Default::default()
+    };
+    let input = "10.0.0.0,8,1,1\n10.0.0.0,8,1\n10.0.0.0,8,1,2\n";
+    let routes = rotonda_store::csv::routes(input.as_bytes(), &options, meta)
+        .collect::<Vec<_>>();
+    assert_eq!(routes.len(), 3);
+    assert!(matches!(routes[1], Err(CsvError::MissingColumn(2, 3))));
+    assert_eq!(routes[2].as_ref().map(|r| r.1.multi_uniq_id).ok(), Some(2));
+
+    Ok(())
+}
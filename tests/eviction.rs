@@ -0,0 +1,185 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use roaring::RoaringBitmap;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn records_for(
+    store: &MultiThreadedStore<Asn>,
+    prefix: &Prefix,
+) -> Vec<Record<Asn>> {
+    let guard = &epoch::pin();
+    let mut recs = store
+        .match_prefix(
+            prefix,
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: true,
+                include_less_specifics: false,
+                include_more_specifics: false,
+                mui: None,
+            },
+            guard,
+        )
+        .prefix_meta;
+    recs.sort_by_key(|r| r.multi_uniq_id);
+    recs
+}
+
+// Inserts three prefixes with records for muis 1, 2 and 3 that have ltime
+// 10, 20 and 30 resp.
+fn create_store(
+) -> Result<(MultiThreadedStore<Asn>, Vec<Prefix>), Box<dyn std::error::Error>>
+{
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfxs = vec![
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    for pfx in pfxs.iter() {
+        for mui in 1..=3 {
+            store.insert(
+                pfx,
+                Record::new(
+                    mui,
+                    mui as u64 * 10,
+                    RouteStatus::Active,
+                    Asn::from(65400 + mui),
+                ),
+                None,
+            )?;
+        }
+    }
+
+    Ok((store, pfxs))
+}
+
+#[test]
+fn test_evict_withdraw() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+
+    let report = store.evict_older_than(25, &EvictOptions::default())?;
+    assert_eq!(report.records_evicted, 6);
+    assert_eq!(report.prefixes_emptied, 0);
+    assert_eq!(store.prefixes_count(), 3);
+
+    for pfx in pfxs.iter() {
+        let statuses = records_for(&store, pfx)
+            .iter()
+            .map(|r| r.status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                RouteStatus::Withdrawn,
+                RouteStatus::Withdrawn,
+                RouteStatus::Active
+            ]
+        );
+    }
+
+    // Records that are already withdrawn are not evicted again.
+    let report = store.evict_older_than(25, &EvictOptions::default())?;
+    assert_eq!(report.records_evicted, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_evict_remove() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+
+    let report = store.evict_older_than(
+        15,
+        &EvictOptions {
+            remove: true,
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(report.records_evicted, 3);
+    assert_eq!(report.prefixes_emptied, 0);
+
+    for pfx in pfxs.iter() {
+        let muis = records_for(&store, pfx)
+            .iter()
+            .map(|r| r.multi_uniq_id)
+            .collect::<Vec<_>>();
+        assert_eq!(muis, vec![2, 3]);
+    }
+
+    let report = store.evict_older_than(
+        u64::MAX,
+        &EvictOptions {
+            remove: true,
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(report.records_evicted, 6);
+    assert_eq!(report.prefixes_emptied, 3);
+    assert_eq!(store.prefixes_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_evict_filtered() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+
+    // Only mui 2, and only in a range that covers the /24, but not the /16.
+    let mut muis = RoaringBitmap::new();
+    muis.insert(2);
+    let report = store.evict_older_than(
+        100,
+        &EvictOptions {
+            remove: true,
+            muis: Some(muis),
+            prefix: Some(Prefix::from_str("185.34.10.0/23")?),
+        },
+    )?;
+    assert_eq!(report.records_evicted, 1);
+    assert_eq!(
+        records_for(&store, &pfxs[1])
+            .iter()
+            .map(|r| r.multi_uniq_id)
+            .collect::<Vec<_>>(),
+        vec![1, 3]
+    );
+    assert_eq!(records_for(&store, &pfxs[0]).len(), 3);
+    assert_eq!(records_for(&store, &pfxs[2]).len(), 3);
+
+    let report = store.evict_older_than(
+        100,
+        &EvictOptions {
+            remove: false,
+            muis: None,
+            prefix: Some(pfxs[0]),
+        },
+    )?;
+    assert_eq!(report.records_evicted, 5);
+    assert!(records_for(&store, &pfxs[2])
+        .iter()
+        .all(|r| r.status == RouteStatus::Active));
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+// Inserts records for mui 1 with ltime 1 up to and including 10 for the
+// prefix, and the ASN 65400 + ltime as meta-data.
+fn insert_versions(
+    store: &MultiThreadedStore<Asn>,
+    pfx: &Prefix,
+) -> Result<(), PrefixStoreError> {
+    for ltime in 1..=10 {
+        store.insert(
+            pfx,
+            Record::new(
+                1,
+                ltime,
+                RouteStatus::Active,
+                Asn::from(65400 + ltime as u32),
+            ),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_history_off() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    assert_eq!(store.history_retention(), HistoryRetention::Off);
+
+    insert_versions(&store, &pfx)?;
+
+    let history = store.record_history(&pfx, 1);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].ltime, 10);
+    assert!(store.record_history(&pfx, 2).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_history_versions() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.set_history_retention(HistoryRetention::Versions(3));
+
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+    for pfx in pfxs.iter() {
+        insert_versions(&store, pfx)?;
+
+        let history = store.record_history(pfx, 1);
+        assert_eq!(
+            history.iter().map(|r| r.ltime).collect::<Vec<_>>(),
+            vec![10, 9, 8, 7]
+        );
+        assert_eq!(history[1].meta, Asn::from(65409));
+    }
+
+    // Queries only return the current record.
+    let guard = &epoch::pin();
+    let res = store.match_prefix(
+        &pfxs[1],
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: true,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: None,
+        },
+        guard,
+    );
+    assert_eq!(res.prefix_meta.len(), 1);
+    assert_eq!(res.prefix_meta[0].ltime, 10);
+
+    // Removing the record removes its history.
+    store.remove(&pfxs[1], 1)?;
+    assert!(store.record_history(&pfxs[1], 1).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_history_max_age() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.set_history_retention(HistoryRetention::MaxAge(4));
+
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    insert_versions(&store, &pfx)?;
+    assert_eq!(
+        store
+            .record_history(&pfx, 1)
+            .iter()
+            .map(|r| r.ltime)
+            .collect::<Vec<_>>(),
+        vec![10, 9, 8, 7, 6]
+    );
+
+    // Switching history off drops the history on the next update.
+    store.set_history_retention(HistoryRetention::Off);
+    assert_eq!(store.record_history(&pfx, 1).len(), 5);
+    store.insert(
+        &pfx,
+        Record::new(1, 11, RouteStatus::Active, Asn::from(65411)),
+        None,
+    )?;
+    assert_eq!(store.record_history(&pfx, 1).len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn rec(mui: u32, ltime: u64) -> Record<Asn> {
+    Record::new(mui, ltime, RouteStatus::Active, Asn::from(ltime as u32))
+}
+
+#[test]
+fn test_insert_if_newer() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    let current = |mui| store.record_history(&pfx, mui).first().cloned();
+
+    let report = store.insert_if_newer(&pfx, rec(1, 10), None)?;
+    assert!(report.applied);
+    assert!(report.mui_new);
+    assert!(report.prefix_new);
+
+    // An older record is rejected...
+    let report = store.insert_if_newer(&pfx, rec(1, 5), None)?;
+    assert!(!report.applied);
+    assert!(!report.mui_new);
+    assert_eq!(report.mui_count, 1);
+    assert_eq!(current(1).unwrap().ltime, 10);
+
+    // ...but not for another mui...
+    let report = store.insert_if_newer(&pfx, rec(2, 5), None)?;
+    assert!(report.applied);
+    assert!(report.mui_new);
+    assert_eq!(report.mui_count, 1);
+
+    // ...and a record with the same or a newer ltime is applied.
+    let report = store.insert_if_newer(&pfx, rec(1, 10), None)?;
+    assert!(report.applied);
+    assert_eq!(report.mui_count, 2);
+    let report = store.insert_if_newer(&pfx, rec(1, 11), None)?;
+    assert!(report.applied);
+    assert_eq!(current(1).unwrap().ltime, 11);
+
+    // A rejected record leaves the path selections as they are.
+    let guard = &rotonda_store::epoch::pin();
+    store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?;
+    assert!(!store.insert_if_newer(&pfx, rec(1, 10), None)?.applied);
+    assert!(!store.is_ps_outdated(&pfx, guard)?);
+    assert!(store.insert_if_newer(&pfx, rec(2, 5), None)?.applied);
+    assert!(store.is_ps_outdated(&pfx, guard)?);
+
+    // A plain insert still overwrites a newer record.
+    let report = store.insert(&pfx, rec(1, 1), None)?;
+    assert!(report.applied);
+    assert_eq!(current(1).unwrap().ltime, 1);
+
+    // With a history, rejected records don't end up in it.
+    store.set_history_retention(HistoryRetention::Versions(10));
+    store.insert_if_newer(&pfx, rec(2, 6), None)?;
+    store.insert_if_newer(&pfx, rec(2, 4), None)?;
+    assert_eq!(
+        store
+            .record_history(&pfx, 2)
+            .iter()
+            .map(|r| r.ltime)
+            .collect::<Vec<_>>(),
+        vec![6, 5]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_insert_if_newer() -> Result<(), Box<dyn std::error::Error>>
+{
+    crate::common::init();
+
+    let store = Arc::new(MultiThreadedStore::<Asn>::new()?);
+    let pfxs = ["185.34.0.0/16", "185.34.10.0/24", "2001:db8::/32"]
+        .iter()
+        .map(|p| Prefix::from_str(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Every thread inserts the ltimes in a different order, with the
+    // newest one somewhere in the middle.
+    let threads = (0..4_u64)
+        .map(|n| {
+            let store = store.clone();
+            let pfxs = pfxs.clone();
+            std::thread::Builder::new()
+                .name(format!("insert-{}", n))
+                .spawn(move || {
+                    for i in 0..1000_u64 {
+                        let ltime = (i * 7 + n * 251) % 1000;
+                        for pfx in &pfxs {
+                            for mui in 1..=2 {
+                                store
+                                    .insert_if_newer(
+                                        pfx,
+                                        rec(mui, ltime),
+                                        None,
+                                    )
+                                    .unwrap();
+                            }
+                        }
+                    }
+                })
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    for pfx in &pfxs {
+        for mui in 1..=2 {
+            let record = store.record_history(pfx, mui)[0].clone();
+            assert_eq!(record.ltime, 999);
+            assert_eq!(record.meta, Asn::from(999));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+// Use the meta-data as a counter of the announcements.
+fn count(meta: Option<&Asn>) -> Asn {
+    Asn::from(meta.map_or(0, |asn| asn.into_u32()) + 1)
+}
+
+#[test]
+fn test_insert_with() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+
+    let report = store.insert_with(&pfx, 1, 1, count, None)?;
+    assert!(report.mui_new);
+    assert!(report.prefix_new);
+    store.insert_with(&pfx, 1, 2, count, None)?;
+    let report = store.insert_with(&pfx, 1, 3, count, None)?;
+    assert!(!report.mui_new);
+    assert!(report.applied);
+    store.insert_with(&pfx, 2, 3, count, None)?;
+
+    let current = store.record_history(&pfx, 1)[0].clone();
+    assert_eq!(current.meta, Asn::from(3));
+    assert_eq!(current.ltime, 3);
+    assert_eq!(current.status, RouteStatus::Active);
+    assert_eq!(store.record_history(&pfx, 2)[0].meta, Asn::from(1));
+
+    // The closure is only called with None if there is no record yet.
+    let calls = std::cell::RefCell::new(vec![]);
+    store.insert_with(
+        &pfx,
+        2,
+        4,
+        |meta| {
+            calls.borrow_mut().push(meta.copied());
+            count(meta)
+        },
+        None,
+    )?;
+    assert_eq!(calls.into_inner(), vec![Some(Asn::from(1))]);
+
+    // The merged records end up in the history, like replaced records.
+    store.set_history_retention(HistoryRetention::Versions(10));
+    store.insert_with(&pfx, 1, 4, count, None)?;
+    assert_eq!(
+        store
+            .record_history(&pfx, 1)
+            .iter()
+            .map(|r| (r.ltime, r.meta))
+            .collect::<Vec<_>>(),
+        vec![(4, Asn::from(4)), (3, Asn::from(3))]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_insert_with() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = Arc::new(MultiThreadedStore::<Asn>::new()?);
+    let pfxs = ["0.0.0.0/0", "185.34.10.0/24", "2001:db8::/32"]
+        .iter()
+        .map(|p| Prefix::from_str(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let threads = (0..4_u64)
+        .map(|n| {
+            let store = store.clone();
+            let pfxs = pfxs.clone();
+            std::thread::Builder::new()
+                .name(format!("insert-with-{}", n))
+                .spawn(move || {
+                    for ltime in 0..1000 {
+                        for pfx in &pfxs {
+                            store
+                                .insert_with(pfx, 1, ltime, count, None)
+                                .unwrap();
+                        }
+                    }
+                })
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // No announcement got lost.
+    for pfx in &pfxs {
+        assert_eq!(store.record_history(pfx, 1)[0].meta, Asn::from(4000));
+    }
+
+    Ok(())
+}
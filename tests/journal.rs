@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common;
+
+use common::store::sorted_contents;
+
+fn tmp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "rotonda-store-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_recover_from_journal() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let journal_path = tmp_path("recover.journal");
+    let snapshot_path = tmp_path("recover.snapshot");
+
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    let store = JournaledStore::new(
+        MultiThreadedStore::<Asn>::new()?,
+        Journal::open(&journal_path)?,
+    );
+    for pfx in pfxs.iter() {
+        for mui in 1..=3 {
+            store.insert(
+                pfx,
+                Record::new(mui, 1, RouteStatus::Active, Asn::from(65400)),
+                None,
+            )?;
+        }
+    }
+    store.set_history_retention(HistoryRetention::Versions(4))?;
+
+    // Take a snapshot half way.
+    store.snapshot(&mut File::create(&snapshot_path)?)?;
+
+    store.insert(
+        &pfxs[1],
+        Record::new(1, 2, RouteStatus::Active, Asn::from(65401)),
+        None,
+    )?;
+    store.mark_mui_as_withdrawn_for_prefix(&pfxs[2], 2)?;
+    store.mark_mui_as_withdrawn_v6(3)?;
+    store.remove(&pfxs[0], 3)?;
+    store.remove_prefix(&pfxs[3])?;
+    store.purge_mui(2)?;
+    store.mark_mui_as_stale(1)?;
+    store.insert(
+        &pfxs[1],
+        Record::new(1, 3, RouteStatus::Active, Asn::from(65402)),
+        None,
+    )?;
+    assert_eq!(store.sweep_stale_v4(1, true)?, vec![pfxs[0], pfxs[2]]);
+    // Fails, and fails again on replay.
+    assert!(store.remove(&Prefix::from_str("10.0.0.0/8")?, 1).is_err());
+    store.sync()?;
+
+    let (store, journal) = store.into_parts();
+    drop(journal);
+
+    // Recover from the snapshot and the journal.
+    let recovered = MultiThreadedStore::<Asn>::recover(
+        Some(&mut File::open(&snapshot_path)?),
+        &mut File::open(&journal_path)?,
+    )?;
+    assert_eq!(sorted_contents(&store), sorted_contents(&recovered));
+    assert_eq!(recovered.prefixes_count(), store.prefixes_count());
+    assert!(recovered.mui_is_withdrawn_v6(3));
+    assert!(!recovered.mui_is_withdrawn_v4(3));
+    assert_eq!(recovered.history_retention(), HistoryRetention::Versions(4));
+    assert_eq!(recovered.record_history(&pfxs[1], 1).len(), 3);
+
+    // The journal alone only has the updates since the snapshot.
+    let from_journal = MultiThreadedStore::<Asn>::recover(
+        None::<&mut File>,
+        &mut File::open(&journal_path)?,
+    )?;
+    assert_eq!(
+        sorted_contents(&from_journal)
+            .iter()
+            .map(|c| c.0)
+            .collect::<Vec<_>>(),
+        vec![pfxs[1]]
+    );
+
+    std::fs::remove_file(&journal_path)?;
+    std::fs::remove_file(&snapshot_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_journal_all_inserts() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let journal_path = tmp_path("inserts.journal");
+    let pfxs = (0..64_u8)
+        .map(|i| {
+            Prefix::new(std::net::Ipv4Addr::new(185, 34, i, 0).into(), 24)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let store = JournaledStore::new(
+        MultiThreadedStore::<Asn>::new()?,
+        Journal::open(&journal_path)?,
+    );
+    store.insert_bulk(pfxs.iter().map(|pfx| {
+        (
+            *pfx,
+            Record::new(1, 1, RouteStatus::Active, Asn::from(65401)),
+        )
+    }))?;
+    store.par_insert_bulk(
+        pfxs.iter().map(|pfx| {
+            (
+                *pfx,
+                Record::new(2, 1, RouteStatus::Active, Asn::from(65402)),
+            )
+        }),
+        4,
+    )?;
+    store.insert(
+        &pfxs[0],
+        Record::new(3, 2, RouteStatus::Active, Asn::from(65403)),
+        Some(()),
+    )?;
+    // Rejected, and rejected again on replay.
+    let report = store.insert_if_newer(
+        &pfxs[0],
+        Record::new(3, 1, RouteStatus::Active, Asn::from(65400)),
+        None,
+    )?;
+    assert!(!report.applied);
+    store.insert_if_newer(
+        &pfxs[1],
+        Record::new(1, 3, RouteStatus::Active, Asn::from(65404)),
+        None,
+    )?;
+    store.insert_with(
+        &pfxs[2],
+        1,
+        4,
+        |meta| Asn::from(meta.map_or(0, |asn| asn.into_u32() + 100)),
+        None,
+    )?;
+    store.withdraw(&pfxs[3], 2, 5)?;
+    let (store, _) = store.into_parts();
+
+    let recovered = MultiThreadedStore::<Asn>::recover(
+        None::<&mut File>,
+        &mut File::open(&journal_path)?,
+    )?;
+    assert_eq!(sorted_contents(&store), sorted_contents(&recovered));
+    assert_eq!(
+        sorted_contents(&recovered)[2].1[0],
+        (1, 4, RouteStatus::Active, Asn::from(65501))
+    );
+
+    std::fs::remove_file(&journal_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_incomplete_journal() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let journal_path = tmp_path("incomplete.journal");
+    let pfxs = [
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+    ];
+
+    let store = JournaledStore::new(
+        MultiThreadedStore::<Asn>::new()?,
+        Journal::open(&journal_path)?,
+    );
+    for pfx in pfxs.iter() {
+        store.insert(
+            pfx,
+            Record::new(1, 1, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+    }
+    drop(store);
+
+    // Cut the last entry in half, as a crash might do.
+    let len = std::fs::metadata(&journal_path)?.len();
+    File::options()
+        .write(true)
+        .open(&journal_path)?
+        .set_len(len - 10)?;
+
+    let recovered = MultiThreadedStore::<Asn>::recover(
+        None::<&mut File>,
+        &mut File::open(&journal_path)?,
+    )?;
+    assert_eq!(recovered.prefixes_count(), 1);
+
+    // Opening the journal again removes the incomplete entry, so that new
+    // entries can be appended.
+    let store = JournaledStore::new(recovered, Journal::open(&journal_path)?);
+    store.insert(
+        &pfxs[1],
+        Record::new(2, 2, RouteStatus::Active, Asn::from(65402)),
+        None,
+    )?;
+    let (store, _) = store.into_parts();
+
+    let recovered = MultiThreadedStore::<Asn>::recover(
+        None::<&mut File>,
+        &mut File::open(&journal_path)?,
+    )?;
+    assert_eq!(sorted_contents(&store), sorted_contents(&recovered));
+
+    // An entry with a length that is way longer than the journal is
+    // incomplete too.
+    let mut buf = std::fs::read(&journal_path)?;
+    buf.extend_from_slice(&u32::MAX.to_be_bytes());
+    buf.extend_from_slice(&[0; 16]);
+    let recovered =
+        MultiThreadedStore::<Asn>::recover(None::<&mut File>, &mut &buf[..])?;
+    assert_eq!(sorted_contents(&store), sorted_contents(&recovered));
+
+    // Truncating leaves an empty journal.
+    let mut journal = Journal::open(&journal_path)?;
+    journal.truncate()?;
+    let recovered = MultiThreadedStore::<Asn>::recover(
+        None::<&mut File>,
+        &mut File::open(&journal_path)?,
+    )?;
+    assert_eq!(recovered.prefixes_count(), 0);
+
+    std::fs::remove_file(&journal_path)?;
+
+    Ok(())
+}
@@ -6,6 +6,8 @@ mod tests {
     };
 
     use std::error::Error;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
 
     #[test]
     fn test_more_specifics() -> Result<(), Box<dyn Error>> {
@@ -211,4 +213,136 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_more_specifics_not_stride_aligned() -> Result<(), Box<dyn Error>>
+    {
+        let tree_bitmap = MultiThreadedStore::<PrefixAs>::new()?;
+
+        // A /16 does not align with a stride boundary in the default store,
+        // so the more-specifics for it live in only some of the child nodes
+        // of the node that hosts it. Its siblings live in the others.
+        let pfxs = [
+            Prefix::from_str("130.55.0.0/16")?, // 0
+            Prefix::from_str("130.55.0.0/20")?, // 1
+            Prefix::from_str("130.55.64.0/18")?, // 2
+            Prefix::from_str("130.55.128.0/24")?, // 3
+            Prefix::from_str("130.55.240.0/24")?, // 4
+            Prefix::from_str("130.55.255.255/32")?, // 5
+            Prefix::from_str("130.54.0.0/16")?, // 6
+            Prefix::from_str("130.54.255.0/24")?, // 7
+            Prefix::from_str("130.56.0.0/24")?, // 8
+            Prefix::from_str("130.48.0.0/12")?, // 9
+            Prefix::from_str("130.63.240.0/20")?, // 10
+        ];
+        for pfx in pfxs.iter() {
+            tree_bitmap.insert(
+                pfx,
+                Record::new(0, 0, RouteStatus::Active, PrefixAs(666)),
+                None,
+            )?;
+        }
+
+        let guard = &epoch::pin();
+        for (search_pfx, expected) in [
+            ("130.55.0.0/16", vec![1, 2, 3, 4, 5]),
+            ("130.55.0.0/17", vec![1, 2]),
+            ("130.55.128.0/17", vec![3, 4, 5]),
+            ("130.54.0.0/16", vec![7]),
+            ("130.56.0.0/16", vec![8]),
+            ("130.48.0.0/12", vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 10]),
+        ] {
+            let found_result = tree_bitmap.match_prefix(
+                &Prefix::from_str(search_pfx)?,
+                &MatchOptions {
+                    match_type: MatchType::ExactMatch,
+                    include_withdrawn: false,
+                    include_less_specifics: false,
+                    include_more_specifics: true,
+                    mui: None,
+                },
+                guard,
+            );
+
+            let mut found = found_result
+                .more_specifics
+                .unwrap()
+                .iter()
+                .map(|rec| rec.prefix)
+                .collect::<Vec<_>>();
+            found.sort();
+            let mut expected =
+                expected.into_iter().map(|i| pfxs[i]).collect::<Vec<_>>();
+            expected.sort();
+            assert_eq!(found, expected, "more-specifics for {}", search_pfx);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_more_specifics_for_mui() -> Result<(), Box<dyn Error>> {
+        let tree_bitmap = MultiThreadedStore::<PrefixAs>::new()?;
+
+        // Mui 1 only shows up in the upper half of 130.55.0.0/16, and not
+        // for every prefix there, so that the search has to skip over
+        // prefixes in a node, and over child nodes, without it.
+        let mut expected = vec![];
+        for i in 0..=255_u8 {
+            for (pfx, muis) in [
+                (
+                    Prefix::new(Ipv4Addr::new(130, 55, i, 0).into(), 24)?,
+                    if i >= 128 && i % 2 == 0 { vec![1, 2] } else { vec![2] },
+                ),
+                (
+                    Prefix::new(Ipv4Addr::new(130, 55, i, 0).into(), 25)?,
+                    if i >= 128 && i % 3 == 0 { vec![1] } else { vec![2] },
+                ),
+                (
+                    Prefix::new(Ipv4Addr::new(130, 55, i, i).into(), 32)?,
+                    if i >= 128 { vec![1] } else { vec![2] },
+                ),
+            ] {
+                for mui in muis {
+                    tree_bitmap.insert(
+                        &pfx,
+                        Record::new(mui, 0, RouteStatus::Active, PrefixAs(mui)),
+                        None,
+                    )?;
+                    if mui == 1 {
+                        expected.push(pfx);
+                    }
+                }
+            }
+        }
+
+        let guard = &epoch::pin();
+        let found_result = tree_bitmap.match_prefix(
+            &Prefix::from_str("130.55.0.0/16")?,
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: false,
+                include_less_specifics: false,
+                include_more_specifics: true,
+                mui: Some(1),
+            },
+            guard,
+        );
+
+        let more_specifics = found_result.more_specifics.unwrap();
+        let mut found = more_specifics
+            .iter()
+            .map(|rec| {
+                assert_eq!(rec.meta.len(), 1);
+                assert_eq!(rec.meta[0].multi_uniq_id, 1);
+                rec.prefix
+            })
+            .collect::<Vec<_>>();
+        found.sort();
+        expected.sort();
+        assert_eq!(found.len(), expected.len());
+        assert_eq!(found, expected);
+
+        Ok(())
+    }
 }
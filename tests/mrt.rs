@@ -0,0 +1,658 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::bgp::BgpMeta;
+use rotonda_store::mrt::{
+    export_table_dump, import_table_dump, replay_updates, ExportOptions,
+    ImportOptions, MrtError, MrtPeer, ReplayOptions,
+};
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+use routecore::bgp::message::PduParseInfo;
+use routecore::bgp::path_attributes::{
+    OwnedPathAttributes, PaMap, PathAttribute,
+};
+
+mod common;
+
+use common::bgp::{bgp_message, origin_as, update};
+
+fn mrt_record(msg_type: u16, subtype: u16, body: &[u8]) -> Vec<u8> {
+    mrt_record_at(1_700_000_000, msg_type, subtype, body)
+}
+
+fn mrt_record_at(
+    timestamp: u32,
+    msg_type: u16,
+    subtype: u16,
+    body: &[u8],
+) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.extend_from_slice(&timestamp.to_be_bytes());
+    buf.extend_from_slice(&msg_type.to_be_bytes());
+    buf.extend_from_slice(&subtype.to_be_bytes());
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    buf.extend_from_slice(body);
+    buf
+}
+
+// Three peers: 192.0.2.1 in AS65001 (two octet AS), 2001:db8::1 in
+// AS4200000002 and 192.0.2.3 in AS65003.
+fn peer_index_table() -> Vec<u8> {
+    let mut body = vec![];
+    body.extend_from_slice(&[10, 0, 0, 1]);
+    body.extend_from_slice(&4_u16.to_be_bytes());
+    body.extend_from_slice(b"test");
+    body.extend_from_slice(&3_u16.to_be_bytes());
+
+    body.push(0x00);
+    body.extend_from_slice(&[192, 0, 2, 1]);
+    body.extend_from_slice(&[192, 0, 2, 1]);
+    body.extend_from_slice(&65001_u16.to_be_bytes());
+
+    body.push(0x03);
+    body.extend_from_slice(&[192, 0, 2, 2]);
+    body.extend_from_slice(
+        &"2001:db8::1"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    body.extend_from_slice(&4_200_000_002_u32.to_be_bytes());
+
+    body.push(0x02);
+    body.extend_from_slice(&[192, 0, 2, 3]);
+    body.extend_from_slice(&[192, 0, 2, 3]);
+    body.extend_from_slice(&65003_u32.to_be_bytes());
+
+    mrt_record(13, 1, &body)
+}
+
+// ORIGIN, AS_PATH and NEXT_HOP attributes.
+fn attributes(as_path: &[u32]) -> Vec<u8> {
+    let mut attrs = vec![0x40, 1, 1, 0];
+    attrs.extend_from_slice(&[0x40, 2, (2 + 4 * as_path.len()) as u8]);
+    attrs.extend_from_slice(&[2, as_path.len() as u8]);
+    for asn in as_path {
+        attrs.extend_from_slice(&asn.to_be_bytes());
+    }
+    attrs.extend_from_slice(&[0x40, 3, 4, 192, 0, 2, 1]);
+    attrs
+}
+
+fn rib_record(
+    seq: u32,
+    prefix: &Prefix,
+    entries: &[(u16, &[u32])],
+) -> Vec<u8> {
+    let mut body = vec![];
+    body.extend_from_slice(&seq.to_be_bytes());
+    body.push(prefix.len());
+    let n = (prefix.len() as usize + 7) / 8;
+    match prefix.addr() {
+        IpAddr::V4(addr) => body.extend_from_slice(&addr.octets()[..n]),
+        IpAddr::V6(addr) => body.extend_from_slice(&addr.octets()[..n]),
+    }
+    body.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for (peer_index, as_path) in entries {
+        let attrs = attributes(as_path);
+        body.extend_from_slice(&peer_index.to_be_bytes());
+        body.extend_from_slice(&(1_600_000_000 + seq).to_be_bytes());
+        body.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+        body.extend_from_slice(&attrs);
+    }
+    let subtype = if prefix.is_v4() { 2 } else { 4 };
+    mrt_record(13, subtype, &body)
+}
+
+// A BGP4MP_MESSAGE_AS4 record, or a BGP4MP_MESSAGE record if `four_octet`
+// is false, from `peer` to 192.0.2.254 or 2001:db8::254 in AS65000.
+fn bgp4mp(
+    timestamp: u32,
+    peer: (IpAddr, u32),
+    four_octet: bool,
+    message: &[u8],
+) -> Vec<u8> {
+    let mut body = vec![];
+    if four_octet {
+        body.extend_from_slice(&peer.1.to_be_bytes());
+        body.extend_from_slice(&65000_u32.to_be_bytes());
+    } else {
+        body.extend_from_slice(&(peer.1 as u16).to_be_bytes());
+        body.extend_from_slice(&65000_u16.to_be_bytes());
+    }
+    body.extend_from_slice(&0_u16.to_be_bytes());
+    match peer.0 {
+        IpAddr::V4(addr) => {
+            body.extend_from_slice(&1_u16.to_be_bytes());
+            body.extend_from_slice(&addr.octets());
+            body.extend_from_slice(&[192, 0, 2, 254]);
+        }
+        IpAddr::V6(addr) => {
+            body.extend_from_slice(&2_u16.to_be_bytes());
+            body.extend_from_slice(&addr.octets());
+            body.extend_from_slice(
+                &"2001:db8::254"
+                    .parse::<std::net::Ipv6Addr>()
+                    .unwrap()
+                    .octets(),
+            );
+        }
+    }
+    body.extend_from_slice(message);
+    mrt_record_at(timestamp, 16, if four_octet { 4 } else { 1 }, &body)
+}
+
+// Meta-data with all the path attributes of a route.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Route {
+    pa_map: PaMap,
+    next_hop: Option<IpAddr>,
+}
+
+impl Route {
+    fn new(attributes: &OwnedPathAttributes) -> Option<Self> {
+        let mut route = Route {
+            pa_map: PaMap::empty(),
+            next_hop: None,
+        };
+        for pa in attributes.iter() {
+            match pa.ok()?.to_owned().ok()? {
+                // The abbreviated MP_REACH_NLRI of TABLE_DUMP_V2 entries.
+                PathAttribute::Unimplemented(pa) if pa.type_code() == 14 => {
+                    let octets: [u8; 16] =
+                        pa.value().get(1..17)?.try_into().ok()?;
+                    route.next_hop = Some(octets.into());
+                }
+                pa => {
+                    route.pa_map.attributes_mut().insert(pa.type_code(), pa);
+                }
+            }
+        }
+        Some(route)
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Meta for Route {
+    type Orderable<'a> = ();
+    type TBI = ();
+
+    fn as_orderable(&self, _tbi: Self::TBI) {}
+}
+
+impl BgpMeta for Route {
+    fn path_attributes(&self) -> PaMap {
+        self.pa_map.clone()
+    }
+
+    fn next_hop(&self) -> Option<IpAddr> {
+        self.next_hop
+    }
+}
+
+#[test]
+fn test_import_table_dump() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    let mut file = peer_index_table();
+    file.extend(rib_record(0, &pfxs[0], &[(0, &[65001, 65100])]));
+    file.extend(rib_record(
+        1,
+        &pfxs[1],
+        &[
+            (0, &[65001, 211321]),
+            (1, &[4_200_000_002, 211321]),
+            (2, &[65003]),
+        ],
+    ));
+    // A RIB_IPV4_MULTICAST record, which is skipped.
+    file.extend(mrt_record(13, 3, &[0; 8]));
+    file.extend(rib_record(2, &pfxs[2], &[(1, &[4_200_000_002])]));
+    // Enough records to keep all the threads busy.
+    for i in 0..4096_u32 {
+        let pfx =
+            Prefix::new(Ipv4Addr::from(0x0a00_0000 + (i << 8)).into(), 24)?;
+        file.extend(rib_record(3 + i, &pfx, &[(0, &[65001, 64512 + i])]));
+    }
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let report = import_table_dump(
+        &store,
+        &mut file.as_slice(),
+        &ImportOptions {
+            mui_base: 10,
+            threads: 4,
+        },
+        |peer, _prefix, attributes| {
+            // Skip everything from the third peer.
+            if peer.mui == 12 {
+                return None;
+            }
+            origin_as(attributes)
+        },
+    )?;
+
+    assert_eq!(
+        report.peers.iter().map(|p| p.mui).collect::<Vec<_>>(),
+        vec![10, 11, 12]
+    );
+    assert_eq!(report.peers[0].asn, Asn::from(65001));
+    assert_eq!(report.peers[1].addr, "2001:db8::1".parse::<IpAddr>()?);
+    assert_eq!(report.peers[1].asn, Asn::from(4_200_000_002));
+    assert_eq!(report.peers[2].asn, Asn::from(65003));
+    assert_eq!(report.rib_records, 3 + 4096);
+    assert_eq!(report.routes_inserted, 4 + 4096);
+    assert_eq!(report.routes_skipped, 1);
+    assert_eq!(report.records_skipped, 1);
+    assert_eq!(store.prefixes_count(), 3 + 4096);
+
+    let guard = &epoch::pin();
+    let res = store.match_prefix(
+        &pfxs[1],
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: None,
+        },
+        guard,
+    );
+    let mut recs = res.prefix_meta;
+    recs.sort_by_key(|r| r.multi_uniq_id);
+    assert_eq!(
+        recs.iter()
+            .map(|r| (r.multi_uniq_id, r.ltime, r.meta))
+            .collect::<Vec<_>>(),
+        vec![
+            (10, 1_600_000_001, Asn::from(211321)),
+            (11, 1_600_000_001, Asn::from(211321))
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_import_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+
+    // No PEER_INDEX_TABLE.
+    let file = rib_record(0, &pfx, &[(0, &[65001])]);
+    let res = import_table_dump(
+        &store,
+        &mut file.as_slice(),
+        &ImportOptions::default(),
+        |_, _, attributes| origin_as(attributes),
+    );
+    assert!(matches!(res, Err(MrtError::InvalidData(_))));
+
+    // A peer index that is not in the PEER_INDEX_TABLE.
+    let mut file = peer_index_table();
+    file.extend(rib_record(0, &pfx, &[(3, &[65001])]));
+    let res = import_table_dump(
+        &store,
+        &mut file.as_slice(),
+        &ImportOptions::default(),
+        |_, _, attributes| origin_as(attributes),
+    );
+    assert!(matches!(res, Err(MrtError::InvalidData(_))));
+
+    // A truncated file.
+    let mut file = peer_index_table();
+    file.extend(rib_record(0, &pfx, &[(0, &[65001])]));
+    file.truncate(file.len() - 4);
+    let res = import_table_dump(
+        &store,
+        &mut file.as_slice(),
+        &ImportOptions::default(),
+        |_, _, attributes| origin_as(attributes),
+    );
+    assert!(matches!(res, Err(MrtError::Io(_))));
+
+    // A record that claims to be 4 GiB long, in a file that is not.
+    let mut file = peer_index_table();
+    let mut record = rib_record(0, &pfx, &[(0, &[65001])]);
+    record[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+    file.extend(record);
+    let res = import_table_dump(
+        &store,
+        &mut file.as_slice(),
+        &ImportOptions::default(),
+        |_, _, attributes| origin_as(attributes),
+    );
+    assert!(matches!(res, Err(MrtError::Io(_))));
+
+    // Multi_uniq_ids for the peers beyond u32::MAX.
+    let res = import_table_dump(
+        &store,
+        &mut peer_index_table().as_slice(),
+        &ImportOptions {
+            mui_base: u32::MAX - 1,
+            ..Default::default()
+        },
+        |_, _, attributes| origin_as(attributes),
+    );
+    assert!(matches!(res, Err(MrtError::InvalidData(_))));
+
+    Ok(())
+}
+
+#[test]
+fn test_export_table_dump() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    let route = |as_path: &[u32], next_hop: Option<&str>| {
+        let attrs = OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            attributes(as_path),
+        );
+        let mut route = Route::new(&attrs).unwrap();
+        route.next_hop = next_hop.map(|nh| nh.parse().unwrap());
+        route
+    };
+
+    let store = MultiThreadedStore::<Route>::new()?;
+    for (i, pfx) in pfxs.iter().enumerate() {
+        let next_hop = if pfx.is_v6() {
+            Some("2001:db8::1")
+        } else {
+            None
+        };
+        for mui in 10..13 {
+            store.insert(
+                pfx,
+                Record::new(
+                    mui,
+                    1_600_000_000 + i as u64,
+                    RouteStatus::Active,
+                    route(&[65001, 64512 + mui], next_hop),
+                ),
+                None,
+            )?;
+        }
+    }
+    // Withdrawn records are not exported, so mui 13 doesn't end up in the
+    // PEER_INDEX_TABLE.
+    store.insert(
+        &pfxs[1],
+        Record::new(13, 1, RouteStatus::Withdrawn, route(&[65013], None)),
+        None,
+    )?;
+
+    let peer = MrtPeer {
+        mui: 11,
+        bgp_id: [192, 0, 2, 11],
+        addr: "2001:db8::11".parse()?,
+        asn: Asn::from(4_200_000_011),
+    };
+    let mut file = vec![];
+    let report = export_table_dump(
+        &store,
+        &mut file,
+        &ExportOptions {
+            collector_bgp_id: [10, 0, 0, 1],
+            view_name: Some("test".to_string()),
+            timestamp: 1_700_000_000,
+            peers: vec![peer],
+            include_withdrawn: false,
+        },
+    )?;
+    assert_eq!(
+        report.peers.iter().map(|p| p.mui).collect::<Vec<_>>(),
+        vec![10, 11, 12]
+    );
+    assert_eq!(report.rib_records, pfxs.len());
+    assert_eq!(report.routes_exported, 3 * pfxs.len());
+
+    let imported = MultiThreadedStore::<Route>::new()?;
+    let import_report = import_table_dump(
+        &imported,
+        &mut file.as_slice(),
+        &ImportOptions {
+            mui_base: 10,
+            threads: 2,
+        },
+        |_, _, attributes| Route::new(attributes),
+    )?;
+    assert_eq!(import_report.peers, report.peers);
+    assert_eq!(import_report.peers[1], peer);
+    assert_eq!(import_report.peers[0].asn, Asn::from(0));
+    assert_eq!(import_report.routes_inserted, report.routes_exported);
+
+    let guard = &epoch::pin();
+    let contents = |store: &MultiThreadedStore<Route>| {
+        let mut contents = store
+            .prefixes_iter(guard)
+            .flat_map(|p| {
+                p.meta
+                    .into_iter()
+                    .filter(|r| r.status == RouteStatus::Active)
+                    .map(move |r| {
+                        (p.prefix, r.multi_uniq_id, r.ltime, r.meta)
+                    })
+            })
+            .collect::<Vec<_>>();
+        contents.sort_by_key(|c| (c.0, c.1));
+        contents
+    };
+    assert_eq!(contents(&imported), contents(&store));
+    assert!(contents(&imported)
+        .iter()
+        .filter(|c| c.0.is_v6())
+        .all(|c| c.3.next_hop == Some("2001:db8::1".parse().unwrap())));
+
+    // The length of the view name has to fit in two octets.
+    let res = export_table_dump(
+        &store,
+        &mut vec![],
+        &ExportOptions {
+            collector_bgp_id: [10, 0, 0, 1],
+            view_name: Some("x".repeat(65536)),
+            timestamp: 1_700_000_000,
+            peers: vec![],
+            include_withdrawn: false,
+        },
+    );
+    assert!(matches!(res, Err(MrtError::InvalidData(_))));
+
+    Ok(())
+}
+
+#[test]
+fn test_replay_updates() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let pfxs = [
+        Prefix::from_str("10.0.0.0/24")?,
+        Prefix::from_str("10.0.1.0/24")?,
+        Prefix::from_str("2001:db8:1::/48")?,
+        Prefix::from_str("10.9.9.0/24")?,
+        Prefix::from_str("10.0.2.0/24")?,
+    ];
+    let peer_a = ("192.0.2.1".parse()?, 65001);
+    let peer_b = ("2001:db8::2".parse()?, 65002);
+
+    let mut file = vec![];
+    file.extend(bgp4mp(
+        1000,
+        peer_a,
+        true,
+        &update(&[], &pfxs[..2], &[65001, 64500], true),
+    ));
+    // A BGP4MP_MESSAGE with two octet AS numbers in the AS_PATH.
+    file.extend(bgp4mp(
+        1001,
+        peer_b,
+        false,
+        &update(&[], &[pfxs[0], pfxs[2]], &[65002, 64501], false),
+    ));
+    // A KEEPALIVE, which is skipped.
+    file.extend(bgp4mp(1002, peer_a, true, &bgp_message(4, &[])));
+    // The withdrawal for 10.9.9.0/24 is skipped, it's not in the store.
+    file.extend(bgp4mp(
+        1003,
+        peer_a,
+        true,
+        &update(&[pfxs[1], pfxs[3]], &pfxs[..1], &[65001, 64502], true),
+    ));
+    file.extend(bgp4mp(
+        1004,
+        peer_b,
+        false,
+        &update(&pfxs[2..3], &[], &[], false),
+    ));
+    // After the `until` timestamp.
+    file.extend(bgp4mp(
+        2000,
+        peer_a,
+        true,
+        &update(&[], &pfxs[4..], &[65001, 64503], true),
+    ));
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.set_history_retention(HistoryRetention::Versions(4));
+    let report = replay_updates(
+        &store,
+        &mut file.as_slice(),
+        &ReplayOptions {
+            peers: vec![MrtPeer {
+                mui: 5,
+                bgp_id: [192, 0, 2, 1],
+                addr: peer_a.0,
+                asn: Asn::from(peer_a.1),
+            }],
+            mui_base: 10,
+            until: Some(1500),
+            speed: Some(1000.0),
+        },
+        |_, _, attributes| origin_as(attributes),
+    )?;
+
+    assert_eq!(
+        report.peers.iter().map(|p| p.mui).collect::<Vec<_>>(),
+        vec![5, 10]
+    );
+    assert_eq!(report.peers[1].addr, peer_b.0);
+    assert_eq!(report.peers[1].asn, Asn::from(peer_b.1));
+    assert_eq!(report.updates, 4);
+    assert_eq!(report.invalid_updates, 0);
+    assert_eq!(report.announcements, 5);
+    assert_eq!(report.withdrawals, 2);
+    assert_eq!(report.routes_skipped, 1);
+    assert_eq!(report.records_skipped, 1);
+    assert_eq!(report.last_timestamp, Some(1004));
+
+    let guard = &epoch::pin();
+    let mut contents = store
+        .prefixes_iter(guard)
+        .flat_map(|p| {
+            p.meta.into_iter().map(move |r| {
+                (p.prefix, r.multi_uniq_id, r.ltime, r.status, r.meta)
+            })
+        })
+        .collect::<Vec<_>>();
+    contents.sort_by_key(|c| (c.0, c.1));
+    assert_eq!(
+        contents,
+        vec![
+            (pfxs[0], 5, 1003, RouteStatus::Active, Asn::from(64502)),
+            (pfxs[0], 10, 1001, RouteStatus::Active, Asn::from(64501)),
+            (pfxs[1], 5, 1003, RouteStatus::Withdrawn, Asn::from(64500)),
+            (pfxs[2], 10, 1004, RouteStatus::Withdrawn, Asn::from(64501)),
+        ]
+    );
+
+    // The withdrawals are in the history, so the routes were still active
+    // before them.
+    let status_at = |pfx: &Prefix, mui: u32, ltime: u64| {
+        store
+            .match_prefix_as_of(
+                pfx,
+                &MatchOptions {
+                    match_type: MatchType::ExactMatch,
+                    include_withdrawn: true,
+                    include_less_specifics: false,
+                    include_more_specifics: false,
+                    mui: Some(mui),
+                },
+                ltime,
+                guard,
+            )
+            .prefix_meta
+            .iter()
+            .map(|r| r.status)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(status_at(&pfxs[1], 5, 1002), vec![RouteStatus::Active]);
+    assert_eq!(status_at(&pfxs[1], 5, 1003), vec![RouteStatus::Withdrawn]);
+    assert_eq!(status_at(&pfxs[2], 10, 1003), vec![RouteStatus::Active]);
+
+    // The speed has to be positive.
+    for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let res = replay_updates(
+            &store,
+            &mut file.as_slice(),
+            &ReplayOptions {
+                speed: Some(speed),
+                ..Default::default()
+            },
+            |_, _, attributes| origin_as(attributes),
+        );
+        assert!(matches!(res, Err(MrtError::InvalidData(_))));
+    }
+
+    // Replays of different files can run concurrently.
+    let store = MultiThreadedStore::<Asn>::new()?;
+    std::thread::scope(|s| {
+        for i in 0..4_u32 {
+            let store = &store;
+            let file = &file;
+            std::thread::Builder::new()
+                .name(format!("replay-{}", i))
+                .spawn_scoped(s, move || {
+                    replay_updates(
+                        store,
+                        &mut file.as_slice(),
+                        &ReplayOptions {
+                            mui_base: 10 * i,
+                            ..Default::default()
+                        },
+                        |_, _, attributes| origin_as(attributes),
+                    )
+                    .unwrap();
+                })
+                .unwrap();
+        }
+    });
+    assert_eq!(store.prefixes_count(), 4);
+    for mui in [0, 1, 10, 11, 20, 21, 30, 31] {
+        assert!(store.iter_records_for_mui_v4(mui, true, guard).count() > 0);
+    }
+
+    Ok(())
+}
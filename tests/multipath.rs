@@ -0,0 +1,254 @@
+use std::cmp::Reverse;
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::bgp::{is_multipath_equivalent, BgpMeta};
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+use routecore::bgp::aspath::HopPath;
+use routecore::bgp::path_attributes::PaMap;
+use routecore::bgp::types::{LocalPref, MultiExitDisc, Origin, OriginType};
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+// A path with a simplified BGP decision process: the highest local pref,
+// the shortest AS path, the lowest IGP cost, and the lowest router id wins.
+#[derive(Clone, Debug)]
+struct Path {
+    local_pref: u32,
+    as_path: Vec<u32>,
+    igp_cost: u32,
+    router_id: u32,
+}
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Meta for Path {
+    type Orderable<'a> = (Reverse<u32>, usize, &'a [u32], u32, u32);
+    type TBI = ();
+
+    fn as_orderable(&self, _tbi: Self::TBI) -> Self::Orderable<'_> {
+        (
+            Reverse(self.local_pref),
+            self.as_path.len(),
+            &self.as_path,
+            self.igp_cost,
+            self.router_id,
+        )
+    }
+
+    fn is_multipath_equivalent(
+        &self,
+        best: &Self,
+        tbi: Self::TBI,
+        policy: MultipathPolicy,
+    ) -> bool {
+        match policy {
+            MultipathPolicy::Equal => {
+                self.as_orderable(tbi) == best.as_orderable(tbi)
+            }
+            MultipathPolicy::UpToIgpCost => {
+                self.local_pref == best.local_pref
+                    && self.as_path == best.as_path
+            }
+            MultipathPolicy::AsPathRelax => {
+                self.local_pref == best.local_pref
+                    && self.as_path.len() == best.as_path.len()
+            }
+        }
+    }
+}
+
+// A BGP route, that leaves the multipath relaxations to the path
+// attributes. The highest local pref, the shortest AS path and then the
+// lowest IGP cost wins.
+#[derive(Clone, Debug)]
+struct BgpPath {
+    pa_map: PaMap,
+    igp_cost: u32,
+}
+
+impl std::fmt::Display for BgpPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Meta for BgpPath {
+    type Orderable<'a> = (Reverse<Option<u32>>, usize, u32);
+    type TBI = ();
+
+    fn as_orderable(&self, _tbi: Self::TBI) -> Self::Orderable<'_> {
+        (
+            Reverse(self.pa_map.get::<LocalPref>().map(|lp| lp.0)),
+            self.pa_map
+                .get::<HopPath>()
+                .map_or(0, |p| p.hop_count_path_selection()),
+            self.igp_cost,
+        )
+    }
+
+    fn is_multipath_equivalent(
+        &self,
+        best: &Self,
+        tbi: Self::TBI,
+        policy: MultipathPolicy,
+    ) -> bool {
+        is_multipath_equivalent(self, best, tbi, policy)
+    }
+}
+
+impl BgpMeta for BgpPath {
+    fn path_attributes(&self) -> PaMap {
+        self.pa_map.clone()
+    }
+}
+
+#[test]
+fn test_multipath() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Path>::new()?;
+    let guard = &rotonda_store::epoch::pin();
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    let path = |local_pref, as_path: &[u32], igp_cost, router_id| Path {
+        local_pref,
+        as_path: as_path.to_vec(),
+        igp_cost,
+        router_id,
+    };
+    for (mui, path, status) in [
+        (1, path(100, &[65001, 65010], 10, 1), RouteStatus::Active),
+        (2, path(100, &[65001, 65010], 20, 2), RouteStatus::Active),
+        (3, path(100, &[65002, 65010], 5, 3), RouteStatus::Active),
+        (4, path(90, &[65001, 65010], 10, 4), RouteStatus::Active),
+        (5, path(100, &[65001, 65010], 10, 5), RouteStatus::Withdrawn),
+    ] {
+        store.insert(&pfx, Record::new(mui, 0, status, path), None)?;
+    }
+
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![1, 2, 3, 4]);
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::Equal, guard)?,
+        vec![1]
+    );
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::UpToIgpCost, guard)?,
+        vec![1, 2]
+    );
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::AsPathRelax, guard)?,
+        vec![1, 2, 3]
+    );
+
+    // The set follows the best path.
+    store.mark_mui_as_withdrawn_v4(1)?;
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::UpToIgpCost, guard)?,
+        vec![2]
+    );
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::AsPathRelax, guard)?,
+        vec![2, 3]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_multipath_default() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    // Asn doesn't implement the relaxations, so paths are only equivalent
+    // if their orderables are equal.
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let guard = &rotonda_store::epoch::pin();
+    let pfx = Prefix::from_str("2001:db8::/32")?;
+    for (mui, asn) in [(1, 65402), (2, 65401), (3, 65401)] {
+        let rec = Record::new(mui, 0, RouteStatus::Active, Asn::from(asn));
+        store.insert(&pfx, rec, None)?;
+    }
+
+    for policy in [
+        MultipathPolicy::Equal,
+        MultipathPolicy::UpToIgpCost,
+        MultipathPolicy::AsPathRelax,
+    ] {
+        assert_eq!(store.multipath(&pfx, &(), policy, guard)?, vec![2, 3]);
+    }
+    assert_eq!(
+        store.multipath(
+            &Prefix::from_str("2001:db9::/32")?,
+            &(),
+            MultipathPolicy::Equal,
+            guard
+        ),
+        Err(PrefixStoreError::StoreNotReadyError)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_multipath_bgp() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<BgpPath>::new()?;
+    let guard = &rotonda_store::epoch::pin();
+    let pfx = Prefix::from_str("192.0.2.0/24")?;
+    let path = |local_pref, as_path: &[u32], med, igp_cost| {
+        let mut pa_map = PaMap::empty();
+        pa_map.set(LocalPref(local_pref));
+        pa_map.set(HopPath::from(
+            as_path
+                .iter()
+                .map(|asn| Asn::from(*asn))
+                .collect::<Vec<_>>(),
+        ));
+        pa_map.set(Origin(OriginType::Igp));
+        pa_map.set(MultiExitDisc(med));
+        BgpPath { pa_map, igp_cost }
+    };
+    for (mui, path) in [
+        (1, path(100, &[65001, 65010], 10, 5)),
+        (2, path(100, &[65001, 65010], 10, 10)),
+        // Another neighbouring AS, so the MED is not compared.
+        (3, path(100, &[65002, 65020], 50, 20)),
+        // The same neighbouring AS, with a higher MED.
+        (4, path(100, &[65001, 65030], 20, 30)),
+        (5, path(90, &[65001, 65010], 10, 1)),
+    ] {
+        let rec = Record::new(mui, 0, RouteStatus::Active, path);
+        store.insert(&pfx, rec, None)?;
+    }
+
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![1, 2, 3, 4, 5]);
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::Equal, guard)?,
+        vec![1]
+    );
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::UpToIgpCost, guard)?,
+        vec![1, 2]
+    );
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::AsPathRelax, guard)?,
+        vec![1, 2, 3]
+    );
+
+    Ok(())
+}
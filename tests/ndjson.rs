@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common;
+
+use common::store::sorted_contents;
+
+#[test]
+fn test_dump_and_load_ndjson() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("::/0")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    for pfx in pfxs.iter() {
+        for mui in 1..=3 {
+            store.insert(
+                pfx,
+                Record::new(
+                    mui,
+                    mui as u64,
+                    if mui == 3 {
+                        RouteStatus::Withdrawn
+                    } else {
+                        RouteStatus::Active
+                    },
+                    Asn::from(65400 + mui),
+                ),
+                None,
+            )?;
+        }
+    }
+
+    let mut buf = vec![];
+    assert_eq!(store.dump_ndjson(&mut buf)?, 5);
+
+    let dump = String::from_utf8(buf.clone())?;
+    assert_eq!(dump.lines().count(), 5);
+    for line in dump.lines() {
+        let value: serde_json::Value = serde_json::from_str(line)?;
+        assert!(value["prefix"].is_string());
+        assert_eq!(value["meta"].as_array().map(|m| m.len()), Some(3));
+    }
+
+    let loaded = MultiThreadedStore::<Asn>::new()?;
+    assert_eq!(loaded.load_ndjson(&buf[..])?, 5);
+    assert_eq!(sorted_contents(&store), sorted_contents(&loaded));
+
+    // Loading into a store merges with what is in there already.
+    let extra = format!(
+        "\n{}\n",
+        serde_json::to_string(&PrefixRecord::new(
+            pfxs[2],
+            vec![Record::new(4, 4, RouteStatus::Active, Asn::from(65404))],
+        ))?
+    );
+    assert_eq!(loaded.load_ndjson(extra.as_bytes())?, 1);
+    let contents = sorted_contents(&loaded);
+    assert_eq!(contents.len(), 5);
+    assert_eq!(
+        contents.iter().find(|c| c.0 == pfxs[2]).map(|c| c.1.len()),
+        Some(4)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_load_invalid_ndjson() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let input = concat!(
+        r#"{"prefix":"10.0.0.0/8","meta":[{"multi_uniq_id":1,"ltime":1,"#,
+        r#""status":"Active","meta":65401}]}"#,
+        "\n\n",
+        r#"{"prefix":"10.0.0.0/33","meta":[]}"#,
+        "\n"
+    );
+
+    match store.load_ndjson(input.as_bytes()) {
+        Err(NdjsonError::Json(line, _)) => assert_eq!(line, 3),
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert_eq!(store.prefixes_count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_query_result() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+    let more_specific = Prefix::from_str("185.34.10.0/24")?;
+    for p in [pfx, more_specific] {
+        store.insert(
+            &p,
+            Record::new(1, 1, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+    }
+
+    let guard = &epoch::pin();
+    let res = store.match_prefix(
+        &pfx,
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: true,
+            mui: None,
+        },
+        guard,
+    );
+
+    let value = serde_json::to_value(&res)?;
+    assert_eq!(value["match_type"], "ExactMatch");
+    assert_eq!(value["prefix"], "185.34.0.0/16");
+    assert_eq!(value["prefix_meta"][0]["status"], "Active");
+    assert_eq!(value["more_specifics"]["v4"][0]["prefix"], "185.34.10.0/24");
+    assert!(value["more_specifics"]["v6"].as_array().unwrap().is_empty());
+
+    let back: QueryResult<Asn> = serde_json::from_value(value)?;
+    assert_eq!(back.prefix, Some(pfx));
+    assert_eq!(back.more_specifics.map(|r| r.len()), Some(1));
+
+    Ok(())
+}
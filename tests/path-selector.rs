@@ -0,0 +1,266 @@
+use std::cmp::{Ordering, Reverse};
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+// A static route, not a BGP route. By its orderables, the lowest
+// administrative distance, and then the lowest metric, wins.
+#[derive(Clone, Debug)]
+struct StaticRoute {
+    distance: u8,
+    metric: u32,
+}
+
+impl std::fmt::Display for StaticRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Meta for StaticRoute {
+    type Orderable<'a> = (u8, u32);
+    type TBI = ();
+
+    fn as_orderable(&self, _tbi: Self::TBI) -> Self::Orderable<'_> {
+        (self.distance, self.metric)
+    }
+}
+
+// Prefers Active routes over InActive ones, that are still eligible as a
+// last resort, and then the lowest distance and the most recent route,
+// ignoring the metric. Withdrawn routes are not eligible. Routes with the
+// same status and distance as the best route can be used together with it.
+struct NewestSelector;
+
+impl PathSelector<StaticRoute> for NewestSelector {
+    fn is_eligible(&self, candidate: &PathCandidate<StaticRoute>) -> bool {
+        candidate.status != RouteStatus::Withdrawn
+    }
+
+    fn compare(
+        &self,
+        a: &PathCandidate<StaticRoute>,
+        b: &PathCandidate<StaticRoute>,
+        _tbi: (),
+    ) -> Ordering {
+        let key = |c: &PathCandidate<StaticRoute>| {
+            (
+                c.status != RouteStatus::Active,
+                c.meta.distance,
+                Reverse(c.ltime),
+                c.mui,
+            )
+        };
+        key(a).cmp(&key(b))
+    }
+
+    fn is_multipath_equivalent(
+        &self,
+        candidate: &PathCandidate<StaticRoute>,
+        best: &PathCandidate<StaticRoute>,
+        _tbi: (),
+        _policy: MultipathPolicy,
+    ) -> bool {
+        candidate.status == best.status
+            && candidate.meta.distance == best.meta.distance
+    }
+}
+
+// A route with two metrics. The tiebreaker info tells which of the two
+// counts, the lowest wins.
+#[derive(Clone, Debug)]
+struct TwoMetrics(u32, u32);
+
+impl std::fmt::Display for TwoMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Meta for TwoMetrics {
+    type Orderable<'a> = u32;
+    type TBI = bool;
+
+    fn as_orderable(&self, second: bool) -> u32 {
+        if second {
+            self.1
+        } else {
+            self.0
+        }
+    }
+}
+
+fn insert_routes(
+    store: &MultiThreadedStore<StaticRoute>,
+    pfx: &Prefix,
+    update_path_selections: Option<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (mui, ltime, status, distance, metric) in [
+        (1, 5, RouteStatus::Active, 1, 10),
+        (2, 7, RouteStatus::Active, 1, 20),
+        (3, 9, RouteStatus::InActive, 200, 0),
+        (4, 3, RouteStatus::Withdrawn, 1, 5),
+    ] {
+        let route = StaticRoute { distance, metric };
+        store.insert(
+            pfx,
+            Record::new(mui, ltime, status, route),
+            update_path_selections,
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_path_selector_store() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::with_path_selector(NewestSelector)?;
+    let pfx = Prefix::from_str("192.0.2.0/24")?;
+    insert_routes(&store, &pfx, Some(()))?;
+
+    let guard = &rotonda_store::epoch::pin();
+    assert_eq!(store.best_path(&pfx, guard).unwrap()?.multi_uniq_id, 2);
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![2, 1, 3]);
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?,
+        (Some(2), Some(1))
+    );
+    // The InActive record is eligible for the path selector, so it is
+    // returned as well.
+    assert_eq!(
+        store
+            .top_paths(&pfx, 4, &(), guard)?
+            .iter()
+            .map(|r| (r.multi_uniq_id, r.status))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, RouteStatus::Active),
+            (1, RouteStatus::Active),
+            (3, RouteStatus::InActive)
+        ]
+    );
+    // The path selector decides which paths are equivalent, not the
+    // orderables, that differ in the metric.
+    assert_eq!(
+        store.multipath(&pfx, &(), MultipathPolicy::Equal, guard)?,
+        vec![2, 1]
+    );
+
+    // Globally withdrawn muis have the Withdrawn status for the selector.
+    store.mark_mui_as_withdrawn_v4(2)?;
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![1, 3]);
+    assert_eq!(
+        store.calculate_and_store_eligible_best_and_backup_path(
+            &pfx,
+            &(),
+            None,
+            guard
+        )?,
+        (Some(1), Some(3))
+    );
+    assert_eq!(
+        store
+            .top_paths(&pfx, 1, &(), guard)?
+            .iter()
+            .map(|r| (r.multi_uniq_id, r.status))
+            .collect::<Vec<_>>(),
+        vec![(1, RouteStatus::Active)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_path_selector_per_call() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<StaticRoute>::new()?;
+    let pfx = Prefix::from_str("2001:db8::/32")?;
+    insert_routes(&store, &pfx, None)?;
+
+    // The default path selector ranks the Active records by orderables.
+    let guard = &rotonda_store::epoch::pin();
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![1, 2]);
+    assert_eq!(
+        store.rank_paths_with(&pfx, &(), &OrderableSelector, guard)?,
+        vec![1, 2]
+    );
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path_with(
+            &pfx,
+            &(),
+            &OrderableSelector,
+            guard
+        )?,
+        store.calculate_and_store_best_and_backup_path(&pfx, &(), guard)?
+    );
+
+    assert_eq!(
+        store.rank_paths_with(&pfx, &(), &NewestSelector, guard)?,
+        vec![2, 1, 3]
+    );
+    assert_eq!(
+        store.calculate_and_store_best_and_backup_path_with(
+            &pfx,
+            &(),
+            &NewestSelector,
+            guard
+        )?,
+        (Some(2), Some(1))
+    );
+    assert_eq!(store.best_path(&pfx, guard).unwrap()?.multi_uniq_id, 2);
+    // The cached ranking is still the one of the store's path selector.
+    assert_eq!(store.ranked_paths(&pfx, &(), guard)?, vec![1, 2]);
+
+    assert_eq!(
+        store.rank_paths_with(
+            &Prefix::from_str("2001:db9::/32")?,
+            &(),
+            &NewestSelector,
+            guard
+        ),
+        Err(PrefixStoreError::StoreNotReadyError)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ranked_paths_tbi() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<TwoMetrics>::new()?;
+    let pfx = Prefix::from_str("198.51.100.0/24")?;
+    for (mui, metrics) in [(1, TwoMetrics(10, 30)), (2, TwoMetrics(20, 10))]
+    {
+        store.insert(
+            &pfx,
+            Record::new(mui, 0, RouteStatus::Active, metrics),
+            None,
+        )?;
+    }
+
+    // The cached ranking is only used for the tiebreaker info it was
+    // calculated with.
+    let guard = &rotonda_store::epoch::pin();
+    assert_eq!(store.ranked_paths(&pfx, &false, guard)?, vec![1, 2]);
+    assert_eq!(store.ranked_paths(&pfx, &true, guard)?, vec![2, 1]);
+    assert_eq!(store.ranked_paths(&pfx, &true, guard)?, vec![2, 1]);
+    assert_eq!(store.ranked_paths(&pfx, &false, guard)?, vec![1, 2]);
+
+    Ok(())
+}
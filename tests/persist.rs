@@ -0,0 +1,209 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common;
+
+use common::store::sorted_contents;
+
+#[test]
+fn test_save_and_load() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.set_history_retention(HistoryRetention::Versions(2));
+
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("::/0")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    for pfx in pfxs.iter() {
+        for mui in 1..=3 {
+            store.insert(
+                pfx,
+                Record::new(mui, 1, RouteStatus::Active, Asn::from(65400)),
+                None,
+            )?;
+            store.insert(
+                pfx,
+                Record::new(
+                    mui,
+                    2,
+                    if mui == 3 {
+                        RouteStatus::InActive
+                    } else {
+                        RouteStatus::Active
+                    },
+                    Asn::from(65400 + mui),
+                ),
+                Some(()),
+            )?;
+        }
+    }
+    // A record that was stored with the Stale status, e.g. from a dump.
+    store.insert(
+        &pfxs[2],
+        Record::new(4, 1, RouteStatus::Stale, Asn::from(65404)),
+        None,
+    )?;
+    store.mark_mui_as_withdrawn_v4(2)?;
+    store.mark_mui_as_stale(3)?;
+    store.insert(
+        &pfxs[1],
+        Record::new(3, 3, RouteStatus::Active, Asn::from(65403)),
+        Some(()),
+    )?;
+
+    let mut buf = vec![];
+    store.save_to(&mut buf)?;
+    let loaded = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice())?;
+
+    assert_eq!(sorted_contents(&store), sorted_contents(&loaded));
+    assert_eq!(loaded.prefixes_count(), store.prefixes_count());
+    // The default routes are stored outside of the tree, make sure they
+    // made it.
+    assert_eq!(sorted_contents(&loaded).len(), pfxs.len());
+    assert_eq!(loaded.history_retention(), HistoryRetention::Versions(2));
+    assert!(loaded.mui_is_withdrawn_v4(2));
+    assert!(!loaded.mui_is_withdrawn_v6(2));
+    assert!(loaded.mui_is_stale(3));
+
+    let guard = &epoch::pin();
+    for pfx in pfxs.iter() {
+        assert_eq!(
+            loaded.is_ps_outdated(pfx, guard)?,
+            store.is_ps_outdated(pfx, guard)?
+        );
+        assert_eq!(
+            loaded
+                .best_path(pfx, guard)
+                .map(|r| r.map(|r| r.multi_uniq_id)),
+            store
+                .best_path(pfx, guard)
+                .map(|r| r.map(|r| r.multi_uniq_id)),
+        );
+        for mui in 1..=3 {
+            assert_eq!(
+                loaded
+                    .record_history(pfx, mui)
+                    .iter()
+                    .map(|r| (r.ltime, r.meta))
+                    .collect::<Vec<_>>(),
+                store
+                    .record_history(pfx, mui)
+                    .iter()
+                    .map(|r| (r.ltime, r.meta))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    // The refreshed record survives a sweep in the loaded store as well.
+    let swept = loaded.sweep_stale(3, true)?;
+    assert_eq!(swept.len(), pfxs.len() - 1);
+    assert!(!swept.contains(&pfxs[1]));
+
+    // Muis in the loaded store are found through the node indexes.
+    assert_eq!(
+        loaded.iter_records_for_mui_v4(1, false, guard).count(),
+        store.iter_records_for_mui_v4(1, false, guard).count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_save_and_load_many() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    for a in 0..=255_u8 {
+        for b in 0..64_u8 {
+            for len in [16, 20, 24] {
+                let pfx = Prefix::new_relaxed(
+                    std::net::Ipv4Addr::new(a, b * 4, 0, 0).into(),
+                    len,
+                )?;
+                store.insert(
+                    &pfx,
+                    Record::new(
+                        (a % 4) as u32,
+                        b as u64,
+                        RouteStatus::Active,
+                        Asn::from(a as u32),
+                    ),
+                    None,
+                )?;
+            }
+        }
+    }
+
+    let mut buf = vec![];
+    store.save_to(&mut buf)?;
+    let loaded = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice())?;
+
+    assert_eq!(loaded.prefixes_count(), store.prefixes_count());
+    assert_eq!(sorted_contents(&store), sorted_contents(&loaded));
+
+    // Truncated after a number of chunks of prefixes were handed to the
+    // loading threads already.
+    buf.truncate(buf.len() / 2);
+    let res = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice());
+    assert!(matches!(res, Err(PersistError::Io(_))));
+
+    Ok(())
+}
+
+#[test]
+fn test_load_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let res = MultiThreadedStore::<Asn>::load_from(&mut &b"RTSX\0\x01"[..]);
+    assert!(matches!(res, Err(PersistError::InvalidMagic)));
+
+    let res = MultiThreadedStore::<Asn>::load_from(&mut &b"RTSS\0\x09"[..]);
+    assert!(matches!(res, Err(PersistError::UnsupportedVersion(9))));
+
+    // A truncated store.
+    let store = MultiThreadedStore::<Asn>::new()?;
+    store.insert(
+        &Prefix::from_str("185.34.0.0/16")?,
+        Record::new(1, 1, RouteStatus::Active, Asn::from(65401)),
+        None,
+    )?;
+    let mut buf = vec![];
+    store.save_to(&mut buf)?;
+    let full = buf.clone();
+    buf.truncate(buf.len() - 10);
+    let res = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice());
+    assert!(matches!(res, Err(PersistError::Io(_))));
+
+    // Counts and lengths that are way larger than the data that follows
+    // don't make loading allocate for them.
+    let find = |pattern: &[u8]| {
+        full.windows(pattern.len())
+            .position(|w| w == pattern)
+            .unwrap()
+    };
+    // The record count, followed by mui 1 and ltime 1.
+    let pos = find(b"\0\0\0\x01\0\0\0\x01\0\0\0\0\0\0\0\x01");
+    let mut buf = full.clone();
+    buf[pos..pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    let res = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice());
+    assert!(matches!(res, Err(PersistError::Io(_))));
+    // The length of the meta-data, followed by AS65401.
+    let pos = find(b"\0\0\0\x04\0\0\xff\x79");
+    let mut buf = full;
+    buf[pos..pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    let res = MultiThreadedStore::<Asn>::load_from(&mut buf.as_slice());
+    assert!(matches!(res, Err(PersistError::Io(_))));
+
+    Ok(())
+}
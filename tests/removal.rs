@@ -0,0 +1,504 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn exact_match() -> MatchOptions {
+    MatchOptions {
+        match_type: MatchType::ExactMatch,
+        include_withdrawn: true,
+        include_less_specifics: false,
+        include_more_specifics: false,
+        mui: None,
+    }
+}
+
+#[test]
+fn test_remove_record_for_mui() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfx = Prefix::from_str("185.34.0.0/16")?;
+
+    for mui in 1..=3 {
+        store.insert(
+            &pfx,
+            Record::new(mui, 0, RouteStatus::Active, Asn::from(65400 + mui)),
+            None,
+        )?;
+    }
+    assert_eq!(store.prefixes_count(), 1);
+
+    let removed = store.remove(&pfx, 2)?;
+    assert_eq!(removed.map(|r| r.meta), Some(Asn::from(65402)));
+    // Removing it again yields nothing.
+    assert!(store.remove(&pfx, 2)?.is_none());
+
+    let guard = &epoch::pin();
+    let res = store.match_prefix(&pfx, &exact_match(), guard);
+    assert_eq!(res.match_type, MatchType::ExactMatch);
+    let mut muis =
+        res.prefix_meta.iter().map(|r| r.multi_uniq_id).collect::<Vec<_>>();
+    muis.sort();
+    assert_eq!(muis, vec![1, 3]);
+    assert_eq!(store.prefixes_count(), 1);
+
+    // Removing the remaining records removes the prefix.
+    store.remove(&pfx, 1)?;
+    store.remove(&pfx, 3)?;
+    assert_eq!(store.prefixes_count(), 0);
+    assert_eq!(store.prefixes_iter(guard).count(), 0);
+    let res = store.match_prefix(&pfx, &exact_match(), guard);
+    assert_eq!(res.match_type, MatchType::EmptyMatch);
+
+    assert_eq!(
+        store.remove(&pfx, 1).err(),
+        Some(PrefixStoreError::PrefixNotFound)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_remove_prefix() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfxs = [
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("130.55.240.0/22")?,
+        Prefix::from_str("130.55.240.0/24")?,
+        Prefix::from_str("130.55.240.0/25")?,
+        Prefix::from_str("130.55.240.128/25")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    for pfx in pfxs.iter() {
+        for mui in 1..=2 {
+            store.insert(
+                pfx,
+                Record::new(mui, 0, RouteStatus::Active, Asn::from(65400)),
+                None,
+            )?;
+        }
+    }
+    assert_eq!(store.prefixes_count(), 6);
+
+    let removed = store.remove_prefix(&pfxs[2])?;
+    assert_eq!(removed.len(), 2);
+    assert_eq!(store.prefixes_count(), 5);
+    assert_eq!(
+        store.remove_prefix(&pfxs[2]).err(),
+        Some(PrefixStoreError::PrefixNotFound)
+    );
+
+    let guard = &epoch::pin();
+
+    // The removed prefix doesn't show up as a more-specific, the ones
+    // below it still do.
+    let res = store.match_prefix(
+        &pfxs[1],
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: true,
+            mui: None,
+        },
+        guard,
+    );
+    let more_specifics = res
+        .more_specifics
+        .unwrap()
+        .iter()
+        .map(|p| p.prefix)
+        .collect::<Vec<_>>();
+    assert_eq!(more_specifics.len(), 2);
+    assert!(!more_specifics.contains(&pfxs[2]));
+
+    // The removed prefix doesn't show up as a less-specific, and it does not
+    // hide the ones above it.
+    let res = store.match_prefix(
+        &pfxs[3],
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: true,
+            include_more_specifics: false,
+            mui: None,
+        },
+        guard,
+    );
+    let less_specifics = res
+        .less_specifics
+        .unwrap()
+        .iter()
+        .map(|p| p.prefix)
+        .collect::<Vec<_>>();
+    assert_eq!(less_specifics, vec![pfxs[1]]);
+
+    // The longest match for the removed prefix is its parent.
+    let res = store.match_prefix(
+        &pfxs[2],
+        &MatchOptions {
+            match_type: MatchType::LongestMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: None,
+        },
+        guard,
+    );
+    assert_eq!(res.match_type, MatchType::LongestMatch);
+    assert_eq!(res.prefix, Some(pfxs[1]));
+
+    store.remove_prefix(&pfxs[0])?;
+    store.remove_prefix(&pfxs[5])?;
+    assert_eq!(store.prefixes_count(), 3);
+    assert_eq!(store.prefixes_iter(guard).count(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_remove_and_reinsert() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+
+    // Enough prefixes of the same length to get collisions in the
+    // prefix buckets, so that removal has to deal with chains.
+    let pfxs = (0..=255_u8)
+        .flat_map(|a| {
+            (0..16_u8).map(move |b| {
+                Prefix::new(
+                    std::net::Ipv4Addr::new(a, b * 16, 0, 0).into(),
+                    20,
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for pfx in pfxs.iter() {
+        store.insert(
+            pfx,
+            Record::new(1, 0, RouteStatus::Active, Asn::from(65400)),
+            None,
+        )?;
+    }
+    assert_eq!(store.prefixes_count(), pfxs.len());
+
+    // Remove every other prefix.
+    for pfx in pfxs.iter().step_by(2) {
+        assert_eq!(store.remove(pfx, 1)?.map(|r| r.multi_uniq_id), Some(1));
+    }
+    assert_eq!(store.prefixes_count(), pfxs.len() / 2);
+
+    let guard = &epoch::pin();
+    let mut remaining =
+        store.prefixes_iter(guard).map(|p| p.prefix).collect::<Vec<_>>();
+    remaining.sort();
+    assert_eq!(
+        remaining,
+        pfxs.iter().skip(1).step_by(2).copied().collect::<Vec<_>>()
+    );
+
+    for pfx in pfxs.iter().skip(1).step_by(2) {
+        let res = store.match_prefix(pfx, &exact_match(), guard);
+        assert_eq!(res.match_type, MatchType::ExactMatch);
+    }
+
+    // Put the removed prefixes back in.
+    for pfx in pfxs.iter().step_by(2) {
+        store.insert(
+            pfx,
+            Record::new(2, 0, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+    }
+    assert_eq!(store.prefixes_count(), pfxs.len());
+    assert_eq!(store.prefixes_iter(guard).count(), pfxs.len());
+
+    for pfx in pfxs.iter().step_by(2) {
+        let res = store.match_prefix(pfx, &exact_match(), guard);
+        assert_eq!(res.match_type, MatchType::ExactMatch);
+        assert_eq!(res.prefix_meta[0].multi_uniq_id, 2);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_remove_and_insert_colliding(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = std::sync::Arc::new(MultiThreadedStore::<Asn>::new()?);
+
+    // Every thread inserts and removes its own prefixes over and over, and
+    // leaves them in at the end. The prefixes of all threads end up in the
+    // same prefix buckets, so inserts into the chain of a prefix that is
+    // being removed by another thread must not get lost.
+    let pfxs = (0..=255_u8)
+        .flat_map(|a| {
+            (0..16_u8).map(move |b| {
+                Prefix::new(
+                    std::net::Ipv4Addr::new(a, b * 16, 0, 0).into(),
+                    20,
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let threads = (0..4_u32)
+        .map(|n| {
+            let store = store.clone();
+            let pfxs = pfxs
+                .iter()
+                .skip(n as usize)
+                .step_by(4)
+                .copied()
+                .collect::<Vec<_>>();
+            std::thread::Builder::new()
+                .name(n.to_string())
+                .spawn(move || {
+                    for round in 0..20 {
+                        for pfx in pfxs.iter() {
+                            store
+                                .insert(
+                                    pfx,
+                                    Record::new(
+                                        n,
+                                        round,
+                                        RouteStatus::Active,
+                                        Asn::from(65400),
+                                    ),
+                                    None,
+                                )
+                                .unwrap();
+                        }
+                        if round < 19 {
+                            for pfx in pfxs.iter() {
+                                store.remove_prefix(pfx).unwrap();
+                            }
+                        }
+                    }
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(store.prefixes_count(), pfxs.len());
+    let guard = &epoch::pin();
+    let mut found = store
+        .prefixes_iter(guard)
+        .map(|p| p.prefix)
+        .collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(found, pfxs);
+
+    for (i, pfx) in pfxs.iter().enumerate() {
+        let res = store.match_prefix(pfx, &exact_match(), guard);
+        assert_eq!(res.match_type, MatchType::ExactMatch);
+        assert_eq!(res.prefix_meta.len(), 1);
+        assert_eq!(res.prefix_meta[0].multi_uniq_id, i as u32 % 4);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_remove_and_insert_same_prefix(
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = std::sync::Arc::new(MultiThreadedStore::<Asn>::new()?);
+
+    // Two threads insert and remove the same prefixes over and over, and
+    // both insert them once more at the end. An insert that races with a
+    // removal must leave the prefix visible for tree traversals, and the
+    // prefixes counted once.
+    let mut pfxs = (0..=255_u8)
+        .map(|a| {
+            Prefix::new(std::net::Ipv4Addr::new(10, a, 0, 0).into(), 24)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    pfxs.push(Prefix::from_str("0.0.0.0/0")?);
+
+    let threads = (0..2_u32)
+        .map(|n| {
+            let store = store.clone();
+            let pfxs = pfxs.clone();
+            std::thread::Builder::new()
+                .name(n.to_string())
+                .spawn(move || {
+                    for round in 0..=20 {
+                        for pfx in pfxs.iter() {
+                            store
+                                .insert(
+                                    pfx,
+                                    Record::new(
+                                        n,
+                                        round,
+                                        RouteStatus::Active,
+                                        Asn::from(65400),
+                                    ),
+                                    None,
+                                )
+                                .unwrap();
+                        }
+                        if round < 20 {
+                            for pfx in pfxs.iter() {
+                                // The other thread may have removed it.
+                                let _ = store.remove_prefix(pfx);
+                            }
+                        }
+                    }
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(store.prefixes_count(), pfxs.len());
+
+    let guard = &epoch::pin();
+    let res = store.match_prefix(
+        &Prefix::from_str("10.0.0.0/8")?,
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: true,
+            mui: None,
+        },
+        guard,
+    );
+    assert_eq!(res.more_specifics.map(|ms| ms.len()), Some(pfxs.len() - 1));
+
+    // The records of the last round of one thread may have been removed by
+    // the other thread, but not those of both.
+    for pfx in pfxs.iter() {
+        let res = store.match_prefix(pfx, &exact_match(), guard);
+        assert_eq!(res.match_type, MatchType::ExactMatch);
+        assert!(!res.prefix_meta.is_empty());
+    }
+
+    // Removing everything leaves nothing behind, also not in the counters.
+    for pfx in pfxs.iter() {
+        store.remove_prefix(pfx)?;
+    }
+    assert_eq!(store.prefixes_count(), 0);
+    assert_eq!(store.prefixes_iter(guard).count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_purge_mui() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+
+    let mut pfxs = (0..=255_u8)
+        .flat_map(|a| {
+            [8, 16, 24, 32].into_iter().map(move |len| {
+                Prefix::new_relaxed(
+                    std::net::Ipv4Addr::new(a, a, a, a).into(),
+                    len,
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for pfx in ["0.0.0.0/0", "2001:db8::/32", "2001:db8:1::/48"] {
+        pfxs.push(Prefix::from_str(pfx)?);
+    }
+
+    // mui 1 lives on all prefixes, mui 2 on every other prefix, and mui 3
+    // on every third prefix.
+    for (i, pfx) in pfxs.iter().enumerate() {
+        store.insert(
+            pfx,
+            Record::new(1, 0, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+        if i % 2 == 0 {
+            store.insert(
+                pfx,
+                Record::new(2, 0, RouteStatus::Active, Asn::from(65402)),
+                None,
+            )?;
+        }
+        if i % 3 == 0 {
+            store.insert(
+                pfx,
+                Record::new(3, 0, RouteStatus::Active, Asn::from(65403)),
+                None,
+            )?;
+        }
+    }
+
+    // Removing mui 1 from the prefixes that only carry mui 3 and mui 1, so
+    // that purging mui 3 will leave these prefixes empty.
+    let mut only_mui_3 = 0;
+    for (i, pfx) in pfxs.iter().enumerate() {
+        if i % 3 == 0 && i % 2 != 0 {
+            store.remove(pfx, 1)?;
+            only_mui_3 += 1;
+        }
+    }
+
+    store.mark_mui_as_withdrawn_v4(3)?;
+    assert!(store.mui_is_withdrawn_v4(3));
+
+    let report = store.purge_mui(3)?;
+    assert_eq!(report.prefixes_touched, pfxs.iter().step_by(3).count());
+    assert_eq!(report.prefixes_emptied, only_mui_3);
+    assert!(!store.mui_is_withdrawn_v4(3));
+    assert_eq!(store.prefixes_count(), pfxs.len() - only_mui_3);
+
+    let guard = &epoch::pin();
+    for p in store.prefixes_iter(guard) {
+        assert!(p.meta.iter().all(|r| r.multi_uniq_id != 3));
+    }
+    assert_eq!(store.iter_records_for_mui_v4(3, true, guard).count(), 0);
+    assert_eq!(store.iter_records_for_mui_v6(3, true, guard).count(), 0);
+    // The other muis are left alone. Note that the mui iterators do not
+    // return the default route (at index 1024).
+    assert_eq!(
+        store.iter_records_for_mui_v4(2, true, guard).count()
+            + store.iter_records_for_mui_v6(2, true, guard).count(),
+        pfxs.iter().step_by(2).count() - 1
+    );
+
+    // Purging a mui that is not in the store is a no-op.
+    let report = store.purge_mui(4)?;
+    assert_eq!(report.prefixes_touched, 0);
+    assert_eq!(report.prefixes_emptied, 0);
+
+    // The mui can be re-used after the purge.
+    store.insert(
+        &pfxs[1],
+        Record::new(3, 1, RouteStatus::Active, Asn::from(65403)),
+        None,
+    )?;
+    assert_eq!(store.iter_records_for_mui_v4(3, false, guard).count(), 1);
+
+    Ok(())
+}
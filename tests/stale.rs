@@ -0,0 +1,211 @@
+use std::str::FromStr;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+use rotonda_store::prelude::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn records_for(
+    store: &MultiThreadedStore<Asn>,
+    prefix: &Prefix,
+) -> Vec<Record<Asn>> {
+    let guard = &epoch::pin();
+    let mut recs = store
+        .match_prefix(
+            prefix,
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: true,
+                include_less_specifics: false,
+                include_more_specifics: false,
+                mui: None,
+            },
+            guard,
+        )
+        .prefix_meta;
+    recs.sort_by_key(|r| r.multi_uniq_id);
+    recs
+}
+
+// Inserts four prefixes with records for muis 1 and 2, marks mui 1 as
+// stale and then refreshes mui 1 for the first and the last prefix.
+fn create_store(
+) -> Result<(MultiThreadedStore<Asn>, Vec<Prefix>), Box<dyn std::error::Error>>
+{
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let pfxs = vec![
+        Prefix::from_str("0.0.0.0/0")?,
+        Prefix::from_str("185.34.0.0/16")?,
+        Prefix::from_str("185.34.10.0/24")?,
+        Prefix::from_str("2001:db8::/32")?,
+    ];
+
+    for pfx in pfxs.iter() {
+        for mui in 1..=2 {
+            store.insert(
+                pfx,
+                Record::new(mui, 1, RouteStatus::Active, Asn::from(65400)),
+                None,
+            )?;
+        }
+    }
+
+    store.mark_mui_as_stale(1)?;
+    assert!(store.mui_is_stale(1));
+    assert!(!store.mui_is_stale(2));
+
+    for pfx in [pfxs[0], pfxs[3]] {
+        store.insert(
+            &pfx,
+            Record::new(1, 2, RouteStatus::Active, Asn::from(65401)),
+            None,
+        )?;
+    }
+
+    Ok((store, pfxs))
+}
+
+#[test]
+fn test_sweep_stale_withdraw() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+
+    // Stale records are reported as such until they're swept.
+    assert_eq!(records_for(&store, &pfxs[1])[0].status, RouteStatus::Stale);
+
+    let mut swept = store.sweep_stale(1, false)?;
+    swept.sort();
+    let mut expected = vec![pfxs[1], pfxs[2]];
+    expected.sort();
+    assert_eq!(swept, expected);
+    assert!(!store.mui_is_stale(1));
+    assert_eq!(store.prefixes_count(), 4);
+
+    for (i, pfx) in pfxs.iter().enumerate() {
+        let recs = records_for(&store, pfx);
+        let expected = if i == 1 || i == 2 {
+            RouteStatus::Withdrawn
+        } else {
+            RouteStatus::Active
+        };
+        assert_eq!(recs[0].status, expected);
+        assert_eq!(recs[1].status, RouteStatus::Active);
+    }
+
+    // The mui is not stale anymore, so sweeping again does nothing.
+    assert!(store.sweep_stale(1, false)?.is_empty());
+
+    // The refreshed records go stale again the next time around.
+    store.mark_mui_as_stale(1)?;
+    let mut swept = store.sweep_stale(1, false)?;
+    swept.sort();
+    let mut expected = vec![pfxs[0], pfxs[3]];
+    expected.sort();
+    assert_eq!(swept, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_stale_status() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+    let guard = &epoch::pin();
+
+    // Only the records for mui 1 that were not refreshed are stale.
+    for (i, pfx) in pfxs.iter().enumerate() {
+        let recs = records_for(&store, pfx);
+        let expected = if i == 1 || i == 2 {
+            RouteStatus::Stale
+        } else {
+            RouteStatus::Active
+        };
+        assert_eq!(recs[0].status, expected);
+        assert_eq!(recs[1].status, RouteStatus::Active);
+    }
+
+    // Stale records are still in use, so they are not filtered out, also
+    // not in the more-specifics, or for a single mui.
+    let res = store.match_prefix(
+        &pfxs[1],
+        &MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: true,
+            mui: Some(1),
+        },
+        guard,
+    );
+    assert_eq!(res.prefix_meta.len(), 1);
+    assert_eq!(res.prefix_meta[0].status, RouteStatus::Stale);
+    let more_specifics = res.more_specifics.unwrap();
+    assert_eq!(more_specifics.len(), 1);
+    assert_eq!(more_specifics.v4[0].prefix, pfxs[2]);
+    assert_eq!(more_specifics.v4[0].meta[0].status, RouteStatus::Stale);
+
+    // A globally withdrawn mui is reported as withdrawn.
+    store.mark_mui_as_withdrawn_v4(1)?;
+    assert_eq!(
+        records_for(&store, &pfxs[1])[0].status,
+        RouteStatus::Withdrawn
+    );
+    store.mark_mui_as_active_v4(1)?;
+    assert_eq!(records_for(&store, &pfxs[1])[0].status, RouteStatus::Stale);
+
+    // After the sweep the records are no longer stale.
+    store.sweep_stale(1, false)?;
+    assert_eq!(
+        records_for(&store, &pfxs[1])[0].status,
+        RouteStatus::Withdrawn
+    );
+    assert_eq!(records_for(&store, &pfxs[0])[0].status, RouteStatus::Active);
+
+    Ok(())
+}
+
+#[test]
+fn test_sweep_stale_remove() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let (store, pfxs) = create_store()?;
+
+    // Leave the /24 with only the stale record for mui 1.
+    store.remove(&pfxs[2], 2)?;
+
+    let mut swept = store.sweep_stale(1, true)?;
+    swept.sort();
+    let mut expected = vec![pfxs[1], pfxs[2]];
+    expected.sort();
+    assert_eq!(swept, expected);
+    assert_eq!(store.prefixes_count(), 3);
+
+    assert!(records_for(&store, &pfxs[2]).is_empty());
+    assert_eq!(
+        records_for(&store, &pfxs[1])
+            .iter()
+            .map(|r| r.multi_uniq_id)
+            .collect::<Vec<_>>(),
+        vec![2]
+    );
+    for pfx in [pfxs[0], pfxs[3]] {
+        let recs = records_for(&store, &pfx);
+        assert_eq!(recs.len(), 2);
+        assert_eq!(recs[0].ltime, 2);
+    }
+
+    Ok(())
+}
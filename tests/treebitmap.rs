@@ -866,4 +866,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_prefixes_iter_default_route(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tree_bitmap = MultiThreadedStore::<PrefixAs>::new()?;
+        let pfxs = [
+            Prefix::from_str("0.0.0.0/0")?,
+            Prefix::from_str("10.0.0.0/8")?,
+            Prefix::from_str("::/0")?,
+            Prefix::from_str("2001:db8::/32")?,
+        ];
+        for pfx in pfxs.iter() {
+            tree_bitmap.insert(
+                pfx,
+                Record::new(1, 0, RouteStatus::Active, PrefixAs(1)),
+                None,
+            )?;
+        }
+
+        let guard = &epoch::pin();
+        let mut found = tree_bitmap
+            .prefixes_iter(guard)
+            .map(|rec| rec.prefix)
+            .collect::<Vec<_>>();
+        found.sort();
+        let mut expected = pfxs.to_vec();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        let found = tree_bitmap
+            .prefixes_iter_v4(guard)
+            .map(|rec| rec.prefix)
+            .collect::<Vec<_>>();
+        assert!(found.contains(&Prefix::from_str("0.0.0.0/0")?));
+        assert_eq!(found.len(), 2);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,161 @@
+use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use rotonda_store::prelude::multi::*;
+
+mod common {
+    use std::io::Write;
+
+    pub fn init() {
+        let _ = env_logger::builder()
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .is_test(true)
+            .try_init();
+    }
+}
+
+fn rec(mui: u32, ltime: u64) -> Record<Asn> {
+    Record::new(mui, ltime, RouteStatus::Active, Asn::from(65400 + mui))
+}
+
+#[test]
+fn test_watch_channel() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let watched4 = Prefix::from_str("185.34.0.0/16")?;
+    let watched6 = Prefix::from_str("2001:db8::/32")?;
+    let (_, rx4) = store.watch_channel(&watched4);
+    let (_, rx6) = store.watch_channel(&watched6);
+
+    let exact = watched4;
+    let more_specific = Prefix::from_str("185.34.10.0/24")?;
+    let less_specific = Prefix::from_str("185.0.0.0/8")?;
+    let unrelated = Prefix::from_str("185.35.0.0/16")?;
+    let more_specific6 = Prefix::from_str("2001:db8:1::/48")?;
+
+    store.insert(&exact, rec(1, 1), None)?;
+    store.insert(&more_specific, rec(1, 1), None)?;
+    store.insert(&more_specific, rec(2, 1), None)?;
+    store.insert(&less_specific, rec(1, 1), None)?;
+    store.insert(&unrelated, rec(1, 1), None)?;
+    store.insert(&more_specific6, rec(3, 1), None)?;
+    // Replacing a record is not a new more-specific.
+    store.insert(&more_specific, rec(1, 2), None)?;
+
+    let events = rx4.try_iter().collect::<Vec<_>>();
+    assert_eq!(
+        events
+            .iter()
+            .map(|e| (e.watched, e.prefix, e.record.multi_uniq_id))
+            .collect::<Vec<_>>(),
+        vec![
+            (watched4, exact, 1),
+            (watched4, more_specific, 1),
+            (watched4, more_specific, 2),
+        ]
+    );
+    assert_eq!(
+        events.iter().map(|e| e.prefix_new).collect::<Vec<_>>(),
+        vec![true, true, false]
+    );
+
+    let events = rx6.try_iter().collect::<Vec<_>>();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].prefix, more_specific6);
+    assert_eq!(events[0].record.meta, Asn::from(65403));
+
+    Ok(())
+}
+
+#[test]
+fn test_watch_callbacks() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    let store = MultiThreadedStore::<Asn>::new()?;
+    let outer = Prefix::from_str("10.0.0.0/8")?;
+    let inner = Prefix::from_str("10.1.0.0/16")?;
+
+    let outer_count = Arc::new(AtomicUsize::new(0));
+    let inner_count = Arc::new(AtomicUsize::new(0));
+    let count = outer_count.clone();
+    let outer_id = store.watch(&outer, move |_| {
+        count.fetch_add(1, Ordering::Relaxed);
+    });
+    let count = inner_count.clone();
+    let inner_id = store.watch(&inner, move |_| {
+        count.fetch_add(1, Ordering::Relaxed);
+    });
+
+    // Both watches cover this one.
+    store.insert(&Prefix::from_str("10.1.2.0/24")?, rec(1, 1), None)?;
+    // Only the outer watch covers this one.
+    store.insert(&Prefix::from_str("10.2.0.0/16")?, rec(1, 1), None)?;
+    assert_eq!(outer_count.load(Ordering::Relaxed), 2);
+    assert_eq!(inner_count.load(Ordering::Relaxed), 1);
+
+    assert!(store.unwatch(outer_id));
+    assert!(!store.unwatch(outer_id));
+    store.insert(&Prefix::from_str("10.1.3.0/24")?, rec(1, 1), None)?;
+    assert_eq!(outer_count.load(Ordering::Relaxed), 2);
+    assert_eq!(inner_count.load(Ordering::Relaxed), 2);
+
+    assert!(store.unwatch(inner_id));
+    store.insert(&Prefix::from_str("10.1.4.0/24")?, rec(1, 1), None)?;
+    assert_eq!(inner_count.load(Ordering::Relaxed), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_watch_nested() -> Result<(), Box<dyn std::error::Error>> {
+    crate::common::init();
+
+    // Watches on the default route, and on prefixes in the same stride of
+    // the index.
+    let store = Arc::new(MultiThreadedStore::<Asn>::new()?);
+    let watched = ["0.0.0.0/0", "10.0.0.0/8", "10.0.0.0/9", "10.0.0.0/10"]
+        .iter()
+        .map(|p| Prefix::from_str(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    let rxs = watched
+        .iter()
+        .map(|p| store.watch_channel(p).1)
+        .collect::<Vec<_>>();
+
+    // A callback that removes its own watch, on the first event.
+    let own_id = Arc::new(std::sync::Mutex::new(None));
+    let id = own_id.clone();
+    let unwatching = store.clone();
+    let once = Arc::new(AtomicUsize::new(0));
+    let count = once.clone();
+    *own_id.lock().unwrap() = Some(store.watch(&watched[2], move |_| {
+        count.fetch_add(1, Ordering::Relaxed);
+        if let Some(id) = id.lock().unwrap().take() {
+            assert!(unwatching.unwatch(id));
+        }
+    }));
+
+    store.insert(&Prefix::from_str("10.0.0.0/10")?, rec(1, 1), None)?;
+    store.insert(&Prefix::from_str("10.64.0.0/10")?, rec(1, 1), None)?;
+    store.insert(&Prefix::from_str("10.128.0.0/9")?, rec(1, 1), None)?;
+    store.insert(&Prefix::from_str("2001:db8::/32")?, rec(1, 1), None)?;
+
+    assert_eq!(
+        rxs.iter()
+            .map(|rx| rx.try_iter().map(|e| e.prefix.to_string()).collect())
+            .collect::<Vec<Vec<_>>>(),
+        vec![
+            vec!["10.0.0.0/10", "10.64.0.0/10", "10.128.0.0/9"],
+            vec!["10.0.0.0/10", "10.64.0.0/10", "10.128.0.0/9"],
+            vec!["10.0.0.0/10", "10.64.0.0/10"],
+            vec!["10.0.0.0/10"],
+        ]
+    );
+    assert_eq!(once.load(Ordering::Relaxed), 1);
+
+    Ok(())
+}